@@ -0,0 +1,38 @@
+//! Open a relay through the direct outbound behind a Tower timeout layer
+//!
+//! ```sh
+//! cargo run -p wind-core --features tower --example tower_timeout -- example.com 80
+//! ```
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tower::{ServiceBuilder, ServiceExt};
+use wind_core::{
+	DirectOutbound,
+	service::{ConnectRequest, OutboundService},
+	types::TargetAddr,
+};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> eyre::Result<()> {
+	let mut args = std::env::args().skip(1);
+	let host = args.next().unwrap_or_else(|| "example.com".to_string());
+	let port = args.next().map(|port| port.parse()).transpose()?.unwrap_or(80);
+
+	let service = ServiceBuilder::new()
+		.timeout(Duration::from_secs(5))
+		.service(OutboundService::new(DirectOutbound::new()));
+	let request = ConnectRequest {
+		target_addr: TargetAddr::Domain(host.clone(), port),
+	};
+	let mut stream = service.oneshot(request).await.map_err(eyre::Report::msg)?;
+
+	stream
+		.write_all(format!("HEAD / HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n").as_bytes())
+		.await?;
+	let mut response = String::new();
+	stream.read_to_string(&mut response).await?;
+	println!("{}", response.lines().next().unwrap_or_default());
+	Ok(())
+}
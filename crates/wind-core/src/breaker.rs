@@ -0,0 +1,202 @@
+//! Circuit breaker refusing dials to targets that keep failing, so clients
+//! get an immediate error instead of waiting out a connect timeout each time
+
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use crate::types::TargetAddr;
+
+/// Tracked targets beyond which stale entries are swept on the next failure
+const SWEEP_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerConfig {
+	/// Failures within `window` that open the circuit
+	pub threshold: u32,
+	pub window:    Duration,
+	/// How long an open circuit refuses dials before letting one probe through
+	pub cooldown:  Duration,
+}
+
+impl Default for BreakerConfig {
+	fn default() -> Self {
+		Self {
+			threshold: 5,
+			window:    Duration::from_secs(30),
+			cooldown:  Duration::from_secs(30),
+		}
+	}
+}
+
+#[derive(Debug)]
+struct TargetState {
+	failures:     u32,
+	window_start: Instant,
+	/// Set once the circuit opened, dials are refused until then
+	open_until:   Option<Instant>,
+}
+
+/// Dial outcomes per target, shared by the outbounds dialing them
+///
+/// Outbounds ask [`allow`](Self::allow) before dialing and report the result
+/// with [`record_success`](Self::record_success) or
+/// [`record_failure`](Self::record_failure). Once a cooldown ends a single
+/// dial is let through: success closes the circuit, failure opens it again.
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+	config:  BreakerConfig,
+	targets: Mutex<HashMap<TargetAddr, TargetState>>,
+}
+
+impl CircuitBreaker {
+	pub fn new(config: BreakerConfig) -> Self {
+		Self {
+			config,
+			targets: Mutex::default(),
+		}
+	}
+
+	/// Whether `target` may be dialed now
+	pub fn allow(&self, target: &TargetAddr) -> bool {
+		let mut targets = self.targets.lock().unwrap();
+		let Some(open_until) = targets.get_mut(target).and_then(|state| state.open_until.as_mut()) else {
+			return true;
+		};
+		let now = Instant::now();
+		if now < *open_until {
+			return false;
+		}
+		// Hold the others back while this probe is in flight, or until another
+		// cooldown passes should it never report back
+		*open_until = now + self.config.cooldown;
+		true
+	}
+
+	pub fn record_success(&self, target: &TargetAddr) {
+		self.targets.lock().unwrap().remove(target);
+	}
+
+	pub fn record_failure(&self, target: &TargetAddr) {
+		let now = Instant::now();
+		let mut targets = self.targets.lock().unwrap();
+		if targets.len() >= SWEEP_THRESHOLD && !targets.contains_key(target) {
+			targets.retain(|_, state| match state.open_until {
+				Some(open_until) => now < open_until,
+				None => now.duration_since(state.window_start) < self.config.window,
+			});
+		}
+		let state = targets.entry(target.clone()).or_insert(TargetState {
+			failures:     0,
+			window_start: now,
+			open_until:   None,
+		});
+		if state.open_until.is_some() {
+			// A failed probe
+			state.open_until = Some(now + self.config.cooldown);
+			return;
+		}
+		if now.duration_since(state.window_start) >= self.config.window {
+			state.failures = 0;
+			state.window_start = now;
+		}
+		state.failures += 1;
+		if state.failures >= self.config.threshold {
+			state.open_until = Some(now + self.config.cooldown);
+		}
+	}
+
+	/// Whether dials to `target` are currently refused
+	pub fn is_open(&self, target: &TargetAddr) -> bool {
+		self.targets
+			.lock()
+			.unwrap()
+			.get(target)
+			.and_then(|state| state.open_until)
+			.is_some_and(|open_until| Instant::now() < open_until)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+	use tokio::net::TcpListener;
+
+	use super::*;
+	use crate::{AbstractOutbound, DirectOutbound};
+
+	#[test]
+	fn test_probe_after_cooldown() {
+		let breaker = CircuitBreaker::new(BreakerConfig {
+			threshold: 2,
+			window:    Duration::from_secs(60),
+			cooldown:  Duration::from_millis(50),
+		});
+		let target = TargetAddr::Domain("dead.example".into(), 443);
+
+		breaker.record_failure(&target);
+		assert!(breaker.allow(&target));
+		breaker.record_failure(&target);
+		assert!(!breaker.allow(&target));
+
+		std::thread::sleep(Duration::from_millis(60));
+		// A single probe goes through
+		assert!(breaker.allow(&target));
+		assert!(!breaker.allow(&target));
+		breaker.record_success(&target);
+		assert!(breaker.allow(&target));
+		assert!(!breaker.is_open(&target));
+	}
+
+	#[test]
+	fn test_expired_circuits_swept() {
+		let breaker = CircuitBreaker::new(BreakerConfig {
+			threshold: 1,
+			window:    Duration::from_millis(10),
+			cooldown:  Duration::from_millis(10),
+		});
+		for port in 0..SWEEP_THRESHOLD as u16 {
+			breaker.record_failure(&TargetAddr::IPv4(Ipv4Addr::LOCALHOST, port));
+		}
+		std::thread::sleep(Duration::from_millis(20));
+
+		// Every circuit opened, but none is still refusing dials
+		breaker.record_failure(&TargetAddr::IPv4(Ipv4Addr::LOCALHOST, u16::MAX));
+		assert_eq!(breaker.targets.lock().unwrap().len(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_open_circuit_fails_fast() {
+		let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+		let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, port);
+		let breaker = Arc::new(CircuitBreaker::new(BreakerConfig {
+			threshold: 2,
+			..Default::default()
+		}));
+		let outbound = DirectOutbound::new().with_circuit_breaker(breaker.clone());
+
+		for _ in 0..2 {
+			let (_client, stream) = tokio::io::duplex(64);
+			let res = outbound.handle_tcp(target.clone(), stream, None::<DirectOutbound>).await;
+			assert!(res.is_err());
+		}
+		assert!(breaker.is_open(&target));
+
+		// The target is back up, but the circuit keeps refusing it without dialing
+		let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+		let (_client, stream) = tokio::io::duplex(64);
+		let err = outbound
+			.handle_tcp(target.clone(), stream, None::<DirectOutbound>)
+			.await
+			.unwrap_err();
+		assert!(err.to_string().contains("circuit open"), "{err}");
+		assert!(
+			tokio::time::timeout(Duration::from_millis(50), listener.accept())
+				.await
+				.is_err()
+		);
+	}
+}
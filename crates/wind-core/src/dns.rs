@@ -0,0 +1,506 @@
+use std::{
+	io,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpSocket, TcpStream};
+
+use crate::types::TargetAddr;
+
+mod query;
+
+pub use query::QUERY_TIMEOUT;
+
+/// Which address families are used, and in what order, when dialing a domain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpPolicy {
+	PreferV4,
+	PreferV6,
+	V4Only,
+	V6Only,
+	/// Alternate families starting with IPv6, the order Happy Eyeballs (RFC
+	/// 8305) sorts into. Addresses are still dialed one after another, not
+	/// raced
+	#[default]
+	Dual,
+}
+
+impl IpPolicy {
+	/// Filter and order resolved addresses for connecting
+	pub fn sort(self, addrs: impl IntoIterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+		let (v4, v6): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv4);
+		match self {
+			IpPolicy::PreferV4 => v4.into_iter().chain(v6).collect(),
+			IpPolicy::PreferV6 => v6.into_iter().chain(v4).collect(),
+			IpPolicy::V4Only => v4,
+			IpPolicy::V6Only => v6,
+			IpPolicy::Dual => {
+				let mut out = Vec::with_capacity(v4.len() + v6.len());
+				let (mut v4, mut v6) = (v4.into_iter(), v6.into_iter());
+				loop {
+					match (v6.next(), v4.next()) {
+						(None, None) => break out,
+						(a, b) => out.extend(a.into_iter().chain(b)),
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Resolve a target into the addresses `policy` allows, in dialing order
+pub async fn resolve(target: &TargetAddr, policy: IpPolicy) -> io::Result<Vec<SocketAddr>> {
+	let addrs: Vec<_> = match target {
+		TargetAddr::Domain(domain, port) => tokio::net::lookup_host((domain.as_str(), *port)).await?.collect(),
+		ip => ip.to_socket_addr().into_iter().collect(),
+	};
+	no_address(policy.sort(addrs), target)
+}
+
+/// Blocking variant of [`resolve`] for configuration time
+pub fn resolve_blocking(target: &TargetAddr, policy: IpPolicy) -> io::Result<Vec<SocketAddr>> {
+	let addrs: Vec<_> = match target {
+		TargetAddr::Domain(domain, port) => (domain.as_str(), *port).to_socket_addrs()?.collect(),
+		ip => ip.to_socket_addr().into_iter().collect(),
+	};
+	no_address(policy.sort(addrs), target)
+}
+
+/// Where domains are looked up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Resolver {
+	/// The resolver of the system, as configured on this host
+	#[default]
+	System,
+	/// This DNS server, asked directly over UDP, eg. for split DNS where one
+	/// outbound's targets are only known to a resolver of their own
+	Server(SocketAddr),
+}
+
+impl Resolver {
+	/// Resolve a target into the addresses `policy` allows, in dialing order
+	pub async fn resolve(self, target: &TargetAddr, policy: IpPolicy) -> io::Result<Vec<SocketAddr>> {
+		let (server, domain, port) = match (self, target) {
+			(Resolver::Server(server), TargetAddr::Domain(domain, port)) => (server, domain, *port),
+			_ => return resolve(target, policy).await,
+		};
+		let results =
+			futures::future::join_all(record_types(policy).iter().map(|rtype| query::query(server, domain, *rtype))).await;
+		server_addrs(results, port, target, policy)
+	}
+
+	/// Blocking variant of [`Resolver::resolve`] for configuration time
+	pub fn resolve_blocking(self, target: &TargetAddr, policy: IpPolicy) -> io::Result<Vec<SocketAddr>> {
+		let (server, domain, port) = match (self, target) {
+			(Resolver::Server(server), TargetAddr::Domain(domain, port)) => (server, domain, *port),
+			_ => return resolve_blocking(target, policy),
+		};
+		let results = record_types(policy)
+			.iter()
+			.map(|rtype| query::query_blocking(server, domain, *rtype))
+			.collect();
+		server_addrs(results, port, target, policy)
+	}
+}
+
+/// The records worth asking a server for under `policy`
+fn record_types(policy: IpPolicy) -> &'static [query::RecordType] {
+	match policy {
+		IpPolicy::V4Only => &[query::RecordType::A],
+		IpPolicy::V6Only => &[query::RecordType::Aaaa],
+		_ => &[query::RecordType::A, query::RecordType::Aaaa],
+	}
+}
+
+/// Merge the answers of each query, failing only if every query did
+fn server_addrs(
+	results: Vec<io::Result<Vec<IpAddr>>>,
+	port: u16,
+	target: &TargetAddr,
+	policy: IpPolicy,
+) -> io::Result<Vec<SocketAddr>> {
+	let mut addrs = Vec::new();
+	let mut error = None;
+	for result in results {
+		match result {
+			Ok(ips) => addrs.extend(ips.into_iter().map(|ip| SocketAddr::new(ip, port))),
+			Err(err) => error = Some(err),
+		}
+	}
+	match error {
+		Some(err) if addrs.is_empty() => Err(err),
+		_ => no_address(policy.sort(addrs), target),
+	}
+}
+
+fn no_address(addrs: Vec<SocketAddr>, target: &TargetAddr) -> io::Result<Vec<SocketAddr>> {
+	if addrs.is_empty() {
+		return Err(io::Error::new(
+			io::ErrorKind::HostUnreachable,
+			format!("no usable address for {target}"),
+		));
+	}
+	Ok(addrs)
+}
+
+/// Connect to the first reachable address, trying them in the given order
+///
+/// Fails only once every address has, with the kind of the last error and a
+/// message listing each attempt.
+pub async fn connect_dual_stack(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+	connect_in_order(addrs, false, None).await
+}
+
+async fn connect_in_order(
+	addrs: &[SocketAddr],
+	fast_open: bool,
+	local_port_range: Option<(u16, u16)>,
+) -> io::Result<TcpStream> {
+	let mut errors = Vec::with_capacity(addrs.len());
+	for addr in addrs {
+		match connect(*addr, fast_open, local_port_range).await {
+			Ok(stream) => return Ok(stream),
+			Err(err) => errors.push((addr, err)),
+		}
+	}
+	let Some((_, last)) = errors.last() else {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"));
+	};
+	let attempts = errors
+		.iter()
+		.map(|(addr, err)| format!("{addr}: {err}"))
+		.collect::<Vec<_>>()
+		.join("; ");
+	Err(io::Error::new(last.kind(), format!("all addresses failed ({attempts})")))
+}
+
+/// Resolve `target` and connect to the first of its addresses that accepts,
+/// with TCP Fast Open where the platform supports it if `fast_open` is set,
+/// from a port of `local_port_range` if any
+pub async fn dial(
+	target: &TargetAddr,
+	resolver: Resolver,
+	policy: IpPolicy,
+	fast_open: bool,
+	local_port_range: Option<(u16, u16)>,
+) -> io::Result<TcpStream> {
+	connect_in_order(&resolver.resolve(target, policy).await?, fast_open, local_port_range).await
+}
+
+/// Check that `range` holds at least one port, and not port 0
+pub fn check_port_range((first, last): (u16, u16)) -> io::Result<()> {
+	if first == 0 || first > last {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("invalid local port range {first}-{last}"),
+		));
+	}
+	Ok(())
+}
+
+/// Bind through `bind` to a port of `range`, both ends included, starting
+/// from a random one and moving on while they are taken
+///
+/// Fails with [`AddrInUse`](io::ErrorKind::AddrInUse) once the whole range
+/// is, or right away with any other error of `bind`.
+pub fn bind_in_port_range<T>(
+	ip: IpAddr,
+	range: (u16, u16),
+	mut bind: impl FnMut(SocketAddr) -> io::Result<T>,
+) -> io::Result<T> {
+	for port in ports_in_range(range)? {
+		match bind(SocketAddr::new(ip, port)) {
+			Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+			res => return res,
+		}
+	}
+	Err(range_taken(ip, range))
+}
+
+/// Every port of `range`, starting from a random one
+fn ports_in_range(range: (u16, u16)) -> io::Result<impl Iterator<Item = u16>> {
+	check_port_range(range)?;
+	let (first, last) = range;
+	let len = u32::from(last - first) + 1;
+	let start = rand::random_range(0..len);
+	Ok((0..len).map(move |i| first + ((start + i) % len) as u16))
+}
+
+fn range_taken(ip: IpAddr, (first, last): (u16, u16)) -> io::Error {
+	io::Error::new(
+		io::ErrorKind::AddrInUse,
+		format!("every local port in {first}-{last} is taken on {ip}"),
+	)
+}
+
+async fn connect(addr: SocketAddr, fast_open: bool, local_port_range: Option<(u16, u16)>) -> io::Result<TcpStream> {
+	let Some(range) = local_port_range else {
+		if !fast_open {
+			return TcpStream::connect(addr).await;
+		}
+		return connect_from(addr, fast_open, None).await;
+	};
+	let ip = if addr.is_ipv4() {
+		IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+	} else {
+		IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+	};
+	for port in ports_in_range(range)? {
+		match connect_from(addr, fast_open, Some(SocketAddr::new(ip, port))).await {
+			// A listener holds the port, or a connection from it to `addr` exists
+			Err(err) if matches!(err.kind(), io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable) => continue,
+			res => return res,
+		}
+	}
+	Err(range_taken(ip, range))
+}
+
+/// Connect to `addr`, from `local` if set
+async fn connect_from(addr: SocketAddr, fast_open: bool, local: Option<SocketAddr>) -> io::Result<TcpStream> {
+	let socket = if addr.is_ipv4() {
+		TcpSocket::new_v4()?
+	} else {
+		TcpSocket::new_v6()?
+	};
+	if let Some(local) = local {
+		// Or the ports of closed connections stay taken while in TIME_WAIT
+		socket.set_reuseaddr(true)?;
+		socket.bind(local)?;
+	}
+	#[cfg(target_os = "linux")]
+	if fast_open && let Err(err) = crate::tcp::set_fast_open_connect(&socket) {
+		crate::debug!(target: "[DIAL]", "TCP Fast Open refused for {addr}, connecting without it: {err}");
+	}
+	socket.connect(addr).await
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		net::{Ipv4Addr, Ipv6Addr},
+		sync::{Arc, Mutex},
+		time::Duration,
+	};
+
+	use tokio::{
+		io::{AsyncReadExt, AsyncWriteExt},
+		net::TcpListener,
+	};
+
+	use super::*;
+	use crate::{AbstractOutbound, DirectOutbound};
+
+	#[tokio::test]
+	async fn test_policy_picks_family() {
+		let v4 = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let v6 = TcpListener::bind((Ipv6Addr::LOCALHOST, 0)).await.unwrap();
+		// What a domain with both A and AAAA records resolves to
+		let resolved = [v4.local_addr().unwrap(), v6.local_addr().unwrap()];
+
+		for (policy, want_v4) in [
+			(IpPolicy::PreferV4, true),
+			(IpPolicy::PreferV6, false),
+			(IpPolicy::V4Only, true),
+			(IpPolicy::V6Only, false),
+			(IpPolicy::Dual, false),
+		] {
+			let addrs = policy.sort(resolved);
+			let stream = connect_dual_stack(&addrs).await.unwrap();
+			assert_eq!(stream.peer_addr().unwrap().is_ipv4(), want_v4, "{policy:?}");
+		}
+
+		assert_eq!(IpPolicy::V4Only.sort(resolved), vec![resolved[0]]);
+		assert_eq!(IpPolicy::V6Only.sort(resolved), vec![resolved[1]]);
+		assert!(resolve_blocking(&TargetAddr::from(resolved[0]), IpPolicy::V6Only).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_fall_through_refused_address() {
+		let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let accepting = listener.local_addr().unwrap();
+		// Bound and dropped, so connecting there is refused
+		let refusing = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+			.await
+			.unwrap()
+			.local_addr()
+			.unwrap();
+
+		let stream = connect_dual_stack(&[refusing, accepting]).await.unwrap();
+		assert_eq!(stream.peer_addr().unwrap(), accepting);
+
+		drop(listener);
+		let err = connect_dual_stack(&[refusing, accepting]).await.unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+		let msg = err.to_string();
+		assert!(
+			msg.contains(&refusing.to_string()) && msg.contains(&accepting.to_string()),
+			"{msg}"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_dial_from_port_range() {
+		let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, listener.local_addr().unwrap().port());
+		// A port just given up by the OS is free for the range
+		let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.port();
+
+		let stream = dial(&target, Resolver::System, IpPolicy::V4Only, false, Some((port, port)))
+			.await
+			.unwrap();
+		assert_eq!(stream.local_addr().unwrap().port(), port);
+		let (accepted, _) = listener.accept().await.unwrap();
+		assert_eq!(accepted.peer_addr().unwrap().port(), port);
+
+		// `stream` holds the only port of the range now
+		let err = dial(&target, Resolver::System, IpPolicy::V4Only, false, Some((port, port)))
+			.await
+			.unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+		assert!(err.to_string().contains(&format!("{port}-{port} is taken")), "{err}");
+
+		// Closing first leaves the port in TIME_WAIT, which doesn't keep it
+		// from other targets
+		drop(stream);
+		let mut accepted = accepted;
+		assert_eq!(accepted.read(&mut [0]).await.unwrap(), 0);
+		drop(accepted);
+		let other = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, other.local_addr().unwrap().port());
+		let stream = dial(&target, Resolver::System, IpPolicy::V4Only, false, Some((port, port)))
+			.await
+			.unwrap();
+		assert_eq!(stream.local_addr().unwrap().port(), port);
+
+		let err = bind_in_port_range(Ipv4Addr::LOCALHOST.into(), (2, 1), std::net::UdpSocket::bind).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+	}
+
+	/// A DNS server answering every A query with 127.0.0.1 and every other
+	/// with nothing, keeping the names asked for
+	async fn mock_dns_server() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+		let socket = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let addr = socket.local_addr().unwrap();
+		let asked = Arc::new(Mutex::new(Vec::new()));
+		let asked2 = asked.clone();
+		tokio::spawn(async move {
+			let mut buf = [0u8; 512];
+			loop {
+				let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+				let query = &buf[..n];
+				let (mut pos, mut labels) = (12, Vec::new());
+				while query[pos] != 0 {
+					let len = usize::from(query[pos]);
+					labels.push(String::from_utf8_lossy(&query[pos + 1..pos + 1 + len]).into_owned());
+					pos += 1 + len;
+				}
+				asked2.lock().unwrap().push(labels.join("."));
+				let is_a = query[pos + 1..pos + 3] == [0, 1];
+
+				let mut reply = query[..pos + 5].to_vec();
+				reply[2] = 0x81;
+				reply[3] = 0x80;
+				reply[7] = u8::from(is_a);
+				if is_a {
+					// Pointer to the question's name, type A, class IN, TTL, 4 bytes
+					reply.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]);
+				}
+				socket.send_to(&reply, from).await.unwrap();
+			}
+		});
+		(addr, asked)
+	}
+
+	/// A DNS server that truncates every answer over UDP and answers A
+	/// queries with 127.0.0.2 over TCP
+	async fn truncating_dns_server() -> SocketAddr {
+		let socket = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let addr = socket.local_addr().unwrap();
+		let listener = TcpListener::bind(addr).await.unwrap();
+		tokio::spawn(async move {
+			let mut buf = [0u8; 512];
+			loop {
+				let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+				let mut reply = buf[..n].to_vec();
+				reply[2] = 0x83;
+				reply[3] = 0x80;
+				socket.send_to(&reply, from).await.unwrap();
+			}
+		});
+		tokio::spawn(async move {
+			loop {
+				let (mut stream, _) = listener.accept().await.unwrap();
+				let len = stream.read_u16().await.unwrap();
+				let mut query = vec![0u8; usize::from(len)];
+				stream.read_exact(&mut query).await.unwrap();
+				let is_a = query[query.len() - 4..query.len() - 2] == [0, 1];
+
+				let mut reply = query;
+				reply[2] = 0x81;
+				reply[3] = 0x80;
+				reply[7] = u8::from(is_a);
+				if is_a {
+					reply.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 2]);
+				}
+				stream.write_u16(reply.len() as u16).await.unwrap();
+				stream.write_all(&reply).await.unwrap();
+			}
+		});
+		addr
+	}
+
+	#[tokio::test]
+	async fn test_truncated_answer_retried_over_tcp() {
+		let resolver = Resolver::Server(truncating_dns_server().await);
+		let target = TargetAddr::Domain("big.wind.test".into(), 80);
+		let expected = vec![SocketAddr::from((Ipv4Addr::new(127, 0, 0, 2), 80))];
+
+		let addrs = resolver.resolve(&target, IpPolicy::V4Only).await.unwrap();
+		assert_eq!(addrs, expected);
+		let addrs = tokio::task::spawn_blocking(move || resolver.resolve_blocking(&target, IpPolicy::V4Only))
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(addrs, expected);
+	}
+
+	#[tokio::test]
+	async fn test_direct_resolves_through_server() {
+		let (server, asked) = mock_dns_server().await;
+		let resolver = Resolver::Server(server);
+		let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		// Only the mock knows this one
+		let target = TargetAddr::Domain("backend.wind.test".into(), port);
+
+		let outbound = DirectOutbound::new().with_resolver(resolver);
+		let (_client, stream) = tokio::io::duplex(64);
+		let relay = tokio::spawn(async move { outbound.handle_tcp(target, stream, None::<DirectOutbound>).await });
+		let (accepted, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(accepted.peer_addr().unwrap().ip().is_loopback());
+		relay.abort();
+		assert!(asked.lock().unwrap().iter().all(|name| name == "backend.wind.test"));
+		assert!(!asked.lock().unwrap().is_empty());
+
+		let target = TargetAddr::Domain("backend.wind.test".into(), 80);
+		let addrs = tokio::task::spawn_blocking(move || resolver.resolve_blocking(&target, IpPolicy::V4Only))
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(addrs, vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 80))]);
+		let err = resolver
+			.resolve(&TargetAddr::Domain("backend.wind.test".into(), 80), IpPolicy::V6Only)
+			.await
+			.unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::HostUnreachable);
+	}
+}
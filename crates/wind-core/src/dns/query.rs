@@ -0,0 +1,219 @@
+//! Just enough of the DNS wire format (RFC 1035) to ask one server for the
+//! A and AAAA records of a domain over UDP, and again over TCP when the
+//! answer didn't fit
+
+use std::{
+	io::{self, Read, Write},
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	time::Duration,
+};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// How long a server has to answer one query
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Large enough for any answer without EDNS
+const MAX_MESSAGE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RecordType {
+	A    = 1,
+	Aaaa = 28,
+}
+
+/// Ask `server` for the `rtype` records of `domain`
+pub(super) async fn query(server: SocketAddr, domain: &str, rtype: RecordType) -> io::Result<Vec<IpAddr>> {
+	let id = rand::random();
+	let request = encode_query(id, domain, rtype)?;
+	let socket = tokio::net::UdpSocket::bind(unspecified(server)).await?;
+	socket.connect(server).await?;
+	socket.send(&request).await?;
+
+	let mut buf = [0u8; MAX_MESSAGE];
+	tokio::time::timeout(QUERY_TIMEOUT, async {
+		loop {
+			let n = socket.recv(&mut buf).await?;
+			match decode_answer(id, &buf[..n])? {
+				Answer::Unrelated => {}
+				Answer::Truncated => return query_tcp(server, id, &request).await,
+				Answer::Addrs(addrs) => return Ok(addrs),
+			}
+		}
+	})
+	.await
+	.map_err(|_| timed_out(server))?
+}
+
+/// Ask again over TCP, where the whole answer fits (RFC 7766)
+async fn query_tcp(server: SocketAddr, id: u16, request: &[u8]) -> io::Result<Vec<IpAddr>> {
+	let mut stream = tokio::net::TcpStream::connect(server).await?;
+	stream.write_all(&tcp_framed(request)).await?;
+	let len = stream.read_u16().await?;
+	let mut msg = vec![0u8; usize::from(len)];
+	stream.read_exact(&mut msg).await?;
+	tcp_answer(id, &msg)
+}
+
+/// Blocking variant of [`query`]
+pub(super) fn query_blocking(server: SocketAddr, domain: &str, rtype: RecordType) -> io::Result<Vec<IpAddr>> {
+	let id = rand::random();
+	let request = encode_query(id, domain, rtype)?;
+	let socket = std::net::UdpSocket::bind(unspecified(server))?;
+	socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+	socket.connect(server)?;
+	socket.send(&request)?;
+
+	let mut buf = [0u8; MAX_MESSAGE];
+	loop {
+		let n = socket.recv(&mut buf).map_err(|err| blocking_timed_out(server, err))?;
+		match decode_answer(id, &buf[..n])? {
+			Answer::Unrelated => {}
+			Answer::Truncated => return query_tcp_blocking(server, id, &request),
+			Answer::Addrs(addrs) => return Ok(addrs),
+		}
+	}
+}
+
+/// Blocking variant of [`query_tcp`]
+fn query_tcp_blocking(server: SocketAddr, id: u16, request: &[u8]) -> io::Result<Vec<IpAddr>> {
+	let mut stream = std::net::TcpStream::connect_timeout(&server, QUERY_TIMEOUT)?;
+	stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+	stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+	stream
+		.write_all(&tcp_framed(request))
+		.map_err(|err| blocking_timed_out(server, err))?;
+	let mut len = [0u8; 2];
+	stream.read_exact(&mut len).map_err(|err| blocking_timed_out(server, err))?;
+	let mut msg = vec![0u8; usize::from(u16::from_be_bytes(len))];
+	stream.read_exact(&mut msg).map_err(|err| blocking_timed_out(server, err))?;
+	tcp_answer(id, &msg)
+}
+
+/// `msg` prefixed with its length, as DNS over TCP sends it
+fn tcp_framed(msg: &[u8]) -> Vec<u8> {
+	let mut framed = Vec::with_capacity(2 + msg.len());
+	framed.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+	framed.extend_from_slice(msg);
+	framed
+}
+
+/// The addresses of an answer read over TCP, which carries nothing else
+fn tcp_answer(id: u16, msg: &[u8]) -> io::Result<Vec<IpAddr>> {
+	match decode_answer(id, msg)? {
+		Answer::Addrs(addrs) => Ok(addrs),
+		Answer::Unrelated | Answer::Truncated => Err(malformed()),
+	}
+}
+
+fn unspecified(server: SocketAddr) -> SocketAddr {
+	if server.is_ipv4() {
+		(Ipv4Addr::UNSPECIFIED, 0).into()
+	} else {
+		(Ipv6Addr::UNSPECIFIED, 0).into()
+	}
+}
+
+fn timed_out(server: SocketAddr) -> io::Error {
+	io::Error::new(io::ErrorKind::TimedOut, format!("DNS server {server} did not answer"))
+}
+
+/// Blocking sockets report their timeout as either kind, depending on the OS
+fn blocking_timed_out(server: SocketAddr, err: io::Error) -> io::Error {
+	match err.kind() {
+		io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => timed_out(server),
+		_ => err,
+	}
+}
+
+fn malformed() -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response")
+}
+
+/// A recursive query for `domain`
+fn encode_query(id: u16, domain: &str, rtype: RecordType) -> io::Result<Vec<u8>> {
+	let mut msg = Vec::with_capacity(18 + domain.len());
+	msg.extend_from_slice(&id.to_be_bytes());
+	// Recursion desired, one question
+	msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+	for label in domain.trim_end_matches('.').split('.') {
+		if label.is_empty() || label.len() > 63 {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("{domain} is not a valid domain name"),
+			));
+		}
+		msg.push(label.len() as u8);
+		msg.extend_from_slice(label.as_bytes());
+	}
+	msg.push(0);
+	msg.extend_from_slice(&(rtype as u16).to_be_bytes());
+	// Class IN
+	msg.extend_from_slice(&[0, 1]);
+	Ok(msg)
+}
+
+/// What a message received says about query `id`
+enum Answer {
+	/// It answers another query, or isn't an answer at all
+	Unrelated,
+	/// The answer didn't fit the datagram
+	Truncated,
+	Addrs(Vec<IpAddr>),
+}
+
+fn decode_answer(id: u16, msg: &[u8]) -> io::Result<Answer> {
+	let header = msg.get(..12).ok_or_else(malformed)?;
+	if u16::from_be_bytes([header[0], header[1]]) != id || header[2] & 0x80 == 0 {
+		return Ok(Answer::Unrelated);
+	}
+	if header[2] & 0x02 != 0 {
+		return Ok(Answer::Truncated);
+	}
+	match header[3] & 0x0f {
+		0 => {}
+		// NXDOMAIN
+		3 => return Ok(Answer::Addrs(Vec::new())),
+		rcode => return Err(io::Error::other(format!("DNS server failed the query with rcode {rcode}"))),
+	}
+	let questions = u16::from_be_bytes([header[4], header[5]]);
+	let answers = u16::from_be_bytes([header[6], header[7]]);
+
+	let mut pos = 12;
+	for _ in 0..questions {
+		// Type and class follow the name
+		pos = skip_name(msg, pos)? + 4;
+	}
+	let mut addrs = Vec::new();
+	for _ in 0..answers {
+		pos = skip_name(msg, pos)?;
+		let record = msg.get(pos..pos + 10).ok_or_else(malformed)?;
+		let rtype = u16::from_be_bytes([record[0], record[1]]);
+		let len = usize::from(u16::from_be_bytes([record[8], record[9]]));
+		let data = msg.get(pos + 10..pos + 10 + len).ok_or_else(malformed)?;
+		// Anything else, eg. the CNAMEs leading to the addresses, is skipped
+		if let Ok(octets) = <[u8; 4]>::try_from(data)
+			&& rtype == RecordType::A as u16
+		{
+			addrs.push(IpAddr::from(octets));
+		} else if let Ok(octets) = <[u8; 16]>::try_from(data)
+			&& rtype == RecordType::Aaaa as u16
+		{
+			addrs.push(IpAddr::from(octets));
+		}
+		pos += 10 + len;
+	}
+	Ok(Answer::Addrs(addrs))
+}
+
+/// Position right after the name starting at `pos`
+fn skip_name(msg: &[u8], mut pos: usize) -> io::Result<usize> {
+	loop {
+		match *msg.get(pos).ok_or_else(malformed)? {
+			0 => return Ok(pos + 1),
+			// A compression pointer ends the name
+			len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+			len => pos += 1 + usize::from(len),
+		}
+	}
+}
@@ -0,0 +1,103 @@
+//! Typed events of the proxy, broadcast to whoever subscribes, eg. metrics
+//! or an audit log
+
+use std::net::SocketAddr;
+
+use tokio::sync::broadcast;
+
+use crate::{registry::ConnectionId, types::TargetAddr};
+
+/// Events kept for a subscriber that falls behind before the oldest are
+/// dropped
+pub const EVENT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+	/// A relay to `target` through `outbound` was registered
+	ConnectionOpened {
+		id:       ConnectionId,
+		target:   TargetAddr,
+		outbound: String,
+	},
+	/// A relay ended, `bytes_up` read from the client and `bytes_down`
+	/// written to it
+	ConnectionClosed {
+		id:         ConnectionId,
+		bytes_up:   u64,
+		bytes_down: u64,
+	},
+	/// A client of `inbound` failed to authenticate, `client_addr` is `None`
+	/// for Unix domain sockets
+	AuthFailed {
+		inbound:     &'static str,
+		client_addr: Option<SocketAddr>,
+	},
+	/// An outbound started replacing its connection to `peer_addr`
+	ReconnectStarted { peer_addr: SocketAddr },
+}
+
+/// Sending side of the [`Event`]s, cloned into whatever publishes them
+///
+/// Publishing never waits: a subscriber lagging more than the capacity behind
+/// misses the oldest events, and is told how many through
+/// [`RecvError::Lagged`](broadcast::error::RecvError::Lagged).
+#[derive(Debug, Clone)]
+pub struct EventBus {
+	tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+	fn default() -> Self {
+		Self::new(EVENT_CAPACITY)
+	}
+}
+
+impl EventBus {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			tx: broadcast::channel(capacity).0,
+		}
+	}
+
+	/// Hand `event` to the current subscribers, if any
+	pub fn publish(&self, event: Event) {
+		let _ = self.tx.send(event);
+	}
+
+	/// Receive the events published from now on
+	pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+		self.tx.subscribe()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+
+	use tokio::sync::broadcast::error::RecvError;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_slow_subscriber_lags() {
+		let events = EventBus::new(2);
+		// Nobody listening yet, which is fine
+		events.publish(Event::ReconnectStarted {
+			peer_addr: (Ipv4Addr::LOCALHOST, 1).into(),
+		});
+
+		let mut rx = events.subscribe();
+		for port in 2..=4 {
+			events.publish(Event::ReconnectStarted {
+				peer_addr: (Ipv4Addr::LOCALHOST, port).into(),
+			});
+		}
+		assert_eq!(rx.recv().await, Err(RecvError::Lagged(1)));
+		assert_eq!(
+			rx.recv().await,
+			Ok(Event::ReconnectStarted {
+				peer_addr: (Ipv4Addr::LOCALHOST, 3).into(),
+			})
+		);
+	}
+}
@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use crate::{tcp::AbstractTcpStream, types::TargetAddr, udp::AbstractUdpSocket};
 
 pub trait FutResult<T> = Future<Output = eyre::Result<T>> + Send + Sync;
@@ -7,7 +9,44 @@ pub trait AbstractInbound {
 	fn listen(&self, cb: &impl InboundCallback) -> impl FutResult<()>;
 }
 
+/// What an inbound knows of a connection before relaying it
+#[derive(Debug, Clone, Default)]
+pub struct ConnInfo {
+	pub client_addr: Option<SocketAddr>,
+	/// `None` for UDP associations, whose packets each name their own target
+	pub target:      Option<TargetAddr>,
+	/// The user the client authenticated as: the username, or the UUID for
+	/// TUIC
+	pub user:        Option<String>,
+}
+
+/// Whether a connection may be relayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+	Allow,
+	Deny,
+}
+
 pub trait InboundCallback: Send + Sync + Clone + 'static {
-	fn handle_tcpstream(&self, target_addr: TargetAddr, stream: impl AbstractTcpStream) -> impl FutResult<()>;
+	/// Asked before [`Self::handle_tcpstream`] and [`Self::handle_udpsocket`].
+	/// On [`Decision::Deny`] the inbound refuses the client the way its
+	/// protocol does and hands nothing over
+	fn authorize(&self, _info: &ConnInfo) -> impl Future<Output = Decision> + Send + Sync {
+		async { Decision::Allow }
+	}
+
+	/// Relay `stream` to `target_addr`. The callback owns the stream, so it may
+	/// spawn the relay and return right away, letting the inbound go on
+	/// accepting. It is then up to the callback to answer the client through
+	/// [`AbstractTcpStream::on_connect`] when the outbound fails, see
+	/// [`report_failure`](crate::tcp::report_failure)
+	fn handle_tcpstream(&self, target_addr: TargetAddr, stream: impl AbstractTcpStream + 'static) -> impl FutResult<()>;
 	fn handle_udpsocket(&self, socket: impl AbstractUdpSocket + 'static) -> impl FutResult<()>;
+
+	/// Look `target_addr` up the way relaying to it would, for clients that
+	/// ask for an address without connecting. Refused unless the callback
+	/// knows a resolver that doesn't leak the query past its routing
+	fn resolve(&self, target_addr: TargetAddr) -> impl FutResult<SocketAddr> {
+		async move { eyre::bail!("resolving {target_addr} is not supported") }
+	}
 }
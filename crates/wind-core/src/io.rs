@@ -2,6 +2,14 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 const BUFFER_SIZE: usize = 16 * 1024;
 
+/// Relay between `a` and `b` until either side closes, returning the bytes
+/// copied each way and the error that ended it, if any
+///
+/// The counts are of bytes the other side accepted, so a write failing
+/// halfway through a buffer counts only what went through before it.
+///
+/// Payloads are dumped while [`trace_payloads`](crate::log::trace_payloads) is
+/// on.
 pub async fn copy_io<A, B>(a: &mut A, b: &mut B) -> (usize, usize, Option<std::io::Error>)
 where
 	A: AsyncRead + AsyncWrite + Unpin + ?Sized,
@@ -23,8 +31,8 @@ where
 				 if num == 0 {
 					break;
 				 }
-				 a2b_num += num;
-				 if let Err(err) = b.write_all(&a2b[..num]).await {
+				 crate::log::trace_payload("a->b", a2b_num, &a2b[..num]);
+				 if let Err(err) = write_counted(b, &a2b[..num], &mut a2b_num).await {
 					last_err = Some(err);
 					break;
 				 }
@@ -40,8 +48,8 @@ where
 				 if num == 0 {
 					break;
 				 }
-				 b2a_num += num;
-				 if let Err(err) = a.write_all(&b2a[..num]).await {
+				 crate::log::trace_payload("b->a", b2a_num, &b2a[..num]);
+				 if let Err(err) = write_counted(a, &b2a[..num], &mut b2a_num).await {
 					last_err = Some(err);
 					break;
 				 }
@@ -57,6 +65,22 @@ where
 	(a2b_num, b2a_num, last_err)
 }
 
+/// `write_all` adding each accepted chunk to `written` as it goes
+async fn write_counted<W>(w: &mut W, mut buf: &[u8], written: &mut usize) -> std::io::Result<()>
+where
+	W: AsyncWrite + Unpin + ?Sized,
+{
+	while !buf.is_empty() {
+		let num = w.write(buf).await?;
+		if num == 0 {
+			return Err(std::io::ErrorKind::WriteZero.into());
+		}
+		*written += num;
+		buf = &buf[num..];
+	}
+	Ok(())
+}
+
 #[cfg(feature = "quic")]
 pub mod quinn {
 	use std::{
@@ -121,4 +145,66 @@ pub mod quinn {
 			Pin::new(&mut self.recv).poll_read(cx, buf)
 		}
 	}
+
+	impl crate::tcp::AbstractTcpStream for QuinnCompat {}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		io,
+		pin::Pin,
+		task::{Context, Poll},
+	};
+
+	use tokio::io::ReadBuf;
+
+	use super::*;
+
+	/// Takes up to `accept` bytes per write and fails once `limit` are in
+	struct FailingWriter {
+		accept:  usize,
+		limit:   usize,
+		written: usize,
+	}
+
+	impl AsyncRead for FailingWriter {
+		fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+			Poll::Pending
+		}
+	}
+
+	impl AsyncWrite for FailingWriter {
+		fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+			if self.written >= self.limit {
+				return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+			}
+			let num = buf.len().min(self.accept).min(self.limit - self.written);
+			self.written += num;
+			Poll::Ready(Ok(num))
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	#[tokio::test]
+	async fn test_copy_io_counts_flushed_bytes() {
+		let (mut client, mut a) = tokio::io::duplex(BUFFER_SIZE);
+		let mut b = FailingWriter {
+			accept:  100,
+			limit:   250,
+			written: 0,
+		};
+		client.write_all(&[7u8; 1000]).await.unwrap();
+		let (a2b, b2a, err) = copy_io(&mut a, &mut b).await;
+		assert_eq!(a2b, 250);
+		assert_eq!(b2a, 0);
+		assert_eq!(err.unwrap().kind(), io::ErrorKind::BrokenPipe);
+	}
 }
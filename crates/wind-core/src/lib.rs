@@ -2,17 +2,44 @@
 #![feature(type_alias_impl_trait)]
 #![feature(trait_alias)]
 
+pub mod breaker;
+pub mod dns;
+pub mod events;
 pub mod inbound;
 mod interface;
 pub mod io;
+pub mod metrics;
 mod outbound;
+pub mod proxy_protocol;
+pub mod registry;
+#[cfg(feature = "tower")]
+pub mod service;
+#[cfg(unix)]
+pub mod systemd;
+pub mod task;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod throttle;
 pub mod types;
 
+use std::{
+	future::Future,
+	sync::{Arc, atomic::AtomicBool},
+	time::Duration,
+};
+
 pub use inbound::*;
 pub use interface::*;
 pub use outbound::*;
+use tokio::task::JoinHandle;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
+use crate::{
+	events::EventBus,
+	registry::ConnectionRegistry,
+	task::{OnFailure, ShutdownReport, TaskLabels},
+};
+
 pub mod log;
 
 pub mod tcp;
@@ -22,15 +49,83 @@ pub mod udp;
 mod udp_tests;
 
 pub struct AppContext {
-	pub tasks: TaskTracker,
-	pub token: CancellationToken,
+	pub tasks:        TaskTracker,
+	pub token:        CancellationToken,
+	/// Cancelled to stop accepting new connections while existing ones drain,
+	/// a child of `token`
+	pub listen_token: CancellationToken,
+	/// Connections currently relayed, across all inbounds
+	pub connections:  ConnectionRegistry,
+	/// Labels of the tasks spawned through [`spawn`](Self::spawn)
+	pub labels:       TaskLabels,
+	/// Whether inbounds take new UDP relays, toggled at runtime while TCP
+	/// carries on either way
+	pub udp_enabled:  Arc<AtomicBool>,
+	/// Connection, authentication and reconnection events, for observers to
+	/// [`subscribe`](EventBus::subscribe) to
+	pub events:       EventBus,
+}
+
+impl AppContext {
+	/// Spawn `task` on [`tasks`](Self::tasks), counted under `label` until it
+	/// ends
+	pub fn spawn<F>(&self, label: &'static str, task: F) -> JoinHandle<F::Output>
+	where
+		F: Future + Send + 'static,
+		F::Output: Send + 'static,
+	{
+		self.tasks.spawn(self.labels.track(label, task))
+	}
+
+	/// Spawn `task` like [`spawn`](Self::spawn), logging the error it may end
+	/// with and reacting to it as `on_failure` says
+	pub fn spawn_critical<F>(&self, label: &'static str, on_failure: OnFailure, task: F) -> JoinHandle<eyre::Result<()>>
+	where
+		F: Future<Output = eyre::Result<()>> + Send + 'static,
+	{
+		let token = self.token.clone();
+		self.spawn(label, async move {
+			let res = task.await;
+			if let Err(err) = &res {
+				match on_failure {
+					OnFailure::Log => crate::error!(target: "[TASK]", "{label} failed: {err:#}"),
+					OnFailure::Shutdown => {
+						crate::error!(target: "[TASK]", "{label} failed, shutting down: {err:#}");
+						token.cancel();
+					}
+				}
+			}
+			res
+		})
+	}
+
+	/// Close [`tasks`](Self::tasks) and wait up to `timeout` for them to
+	/// finish, reporting the ones that didn't
+	pub async fn drain_tasks(&self, timeout: Duration) -> ShutdownReport {
+		self.tasks.close();
+		let before = self.tasks.len();
+		let _ = tokio::time::timeout(timeout, self.tasks.wait()).await;
+		let running = self.tasks.len();
+		ShutdownReport {
+			completed: before - running,
+			running,
+			stuck: if running == 0 { Vec::new() } else { self.labels.running() },
+		}
+	}
 }
 
 impl Default for AppContext {
 	fn default() -> Self {
+		let token = CancellationToken::new();
+		let events = EventBus::default();
 		Self {
 			tasks: TaskTracker::new(),
-			token: CancellationToken::new(),
+			listen_token: token.child_token(),
+			token,
+			connections: ConnectionRegistry::default().with_events(events.clone()),
+			labels: TaskLabels::default(),
+			udp_enabled: Arc::new(AtomicBool::new(true)),
+			events,
 		}
 	}
 }
@@ -1,6 +1,50 @@
+use std::{
+	fmt::{self, Write as _},
+	sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+	time::{Duration, Instant},
+};
+
 pub use const_str::concat;
 pub use tracing;
 
+#[macro_export]
+macro_rules! trace {
+    (target: $target:expr, $($arg:tt)*) => {
+
+		$crate::log::tracing::trace!(target: $crate::log::concat!($crate::extract_crate_name!(), " ", $target), $($arg)*)
+    };
+    (name: $name:expr, target: $target:expr, $($arg:tt)*) => {
+
+		$crate::log::tracing::trace!(
+            name: $name,
+            target: $crate::log::concat!($crate::extract_crate_name!(), " ", $target),
+            $($arg)*
+        )
+    };
+    ($($arg:tt)*) => {
+		$crate::log::tracing::trace!(target: $crate::extract_crate_name!(), $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    (target: $target:expr, $($arg:tt)*) => {
+
+		$crate::log::tracing::debug!(target: $crate::log::concat!($crate::extract_crate_name!(), " ", $target), $($arg)*)
+    };
+    (name: $name:expr, target: $target:expr, $($arg:tt)*) => {
+
+		$crate::log::tracing::debug!(
+            name: $name,
+            target: $crate::log::concat!($crate::extract_crate_name!(), " ", $target),
+            $($arg)*
+        )
+    };
+    ($($arg:tt)*) => {
+		$crate::log::tracing::debug!(target: $crate::extract_crate_name!(), $($arg)*)
+    };
+}
+
 #[macro_export]
 macro_rules! info {
     (target: $target:expr, $($arg:tt)*) => {
@@ -58,6 +102,179 @@ macro_rules! error {
     };
 }
 
+/// Lets a repeating log line through at most once per period
+///
+/// ```
+/// # use std::time::Duration;
+/// # use wind_core::{log::LogLimiter, warn};
+/// let limiter = LogLimiter::new(Duration::from_secs(5));
+/// if let Some(suppressed) = limiter.check() {
+///     warn!("send failed ({suppressed} more suppressed)");
+/// }
+/// ```
+pub struct LogLimiter {
+	period:     Duration,
+	started:    Instant,
+	/// Milliseconds since `started` of the last line let through, plus one so
+	/// zero means never
+	last:       AtomicU64,
+	suppressed: AtomicU64,
+}
+
+impl LogLimiter {
+	pub fn new(period: Duration) -> Self {
+		Self {
+			period,
+			started: Instant::now(),
+			last: AtomicU64::new(0),
+			suppressed: AtomicU64::new(0),
+		}
+	}
+
+	/// Returns how many lines were suppressed since the last one if this one
+	/// may be logged
+	pub fn check(&self) -> Option<u64> {
+		let now = self.started.elapsed().as_millis() as u64 + 1;
+		let last = self.last.load(Ordering::Relaxed);
+		if (last == 0 || now - last >= self.period.as_millis() as u64)
+			&& self
+				.last
+				.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+				.is_ok()
+		{
+			return Some(self.suppressed.swap(0, Ordering::Relaxed));
+		}
+		self.suppressed.fetch_add(1, Ordering::Relaxed);
+		None
+	}
+}
+
+/// Counts packets and bytes, handing out the totals once per period so hot
+/// paths can log an aggregate instead of every packet
+pub struct PacketTally {
+	limiter: LogLimiter,
+	packets: AtomicU64,
+	bytes:   AtomicU64,
+}
+
+impl PacketTally {
+	pub fn new(period: Duration) -> Self {
+		let limiter = LogLimiter::new(period);
+		// The first report covers a full period too
+		limiter.last.store(1, Ordering::Relaxed);
+		Self {
+			limiter,
+			packets: AtomicU64::new(0),
+			bytes: AtomicU64::new(0),
+		}
+	}
+
+	/// Count a packet, returning `(packets, bytes)` since the last report when
+	/// one is due
+	pub fn record(&self, bytes: usize) -> Option<(u64, u64)> {
+		self.packets.fetch_add(1, Ordering::Relaxed);
+		self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+		self.limiter.check()?;
+		Some((self.packets.swap(0, Ordering::Relaxed), self.bytes.swap(0, Ordering::Relaxed)))
+	}
+}
+
+/// Short id tying together the log lines of one accepted connection
+///
+/// Inbounds run each connection inside [`span`](Self::span), so every line
+/// logged while serving it carries `conn{id=...}`. 48 bits of counter keep ids
+/// unique within a run, 16 random bits make a repeat after a restart
+/// unlikely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnId(u64);
+
+impl ConnId {
+	pub fn next() -> Self {
+		static COUNTER: AtomicU64 = AtomicU64::new(0);
+		let count = COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff_ffff_ffff;
+		Self((count << 16) | rand::random::<u16>() as u64)
+	}
+
+	pub fn span(self) -> tracing::Span {
+		tracing::info_span!("conn", id = %self)
+	}
+}
+
+impl fmt::Display for ConnId {
+	/// Thirteen characters of lowercase Crockford base32
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		const ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+		for shift in (0..65).step_by(5).rev() {
+			f.write_char(ALPHABET[(self.0 >> shift) as usize & 31] as char)?;
+		}
+		Ok(())
+	}
+}
+
+/// Bytes of each relayed stream direction to dump, zero while tracing is off
+static TRACE_PAYLOADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Log a hex dump of the first `max_bytes` each way of every stream relayed
+/// through [`copy_io`](crate::io::copy_io), and a sample of the TUIC commands
+/// decoded
+///
+/// Meant for protocol debugging only, as it logs what clients send. Zero
+/// turns it back off.
+pub fn trace_payloads(max_bytes: usize) {
+	TRACE_PAYLOADS.store(max_bytes, Ordering::Relaxed);
+}
+
+/// Whether [`trace_payloads`] is on
+pub fn tracing_payloads() -> bool {
+	TRACE_PAYLOADS.load(Ordering::Relaxed) > 0
+}
+
+/// Dump `data`, read `offset` bytes into one direction of a stream, up to
+/// the [`trace_payloads`] cap
+pub fn trace_payload(direction: &str, offset: usize, data: &[u8]) {
+	let max_bytes = TRACE_PAYLOADS.load(Ordering::Relaxed);
+	if offset >= max_bytes {
+		return;
+	}
+	let data = &data[..data.len().min(max_bytes - offset)];
+	crate::info!(
+		target: "[TRACE]",
+		"{direction} bytes {offset}..{}\n{}",
+		offset + data.len(),
+		HexDump { offset, data }
+	);
+}
+
+/// Rows of 16 bytes: offset, hex, then the printable ASCII
+struct HexDump<'a> {
+	offset: usize,
+	data:   &'a [u8],
+}
+
+impl fmt::Display for HexDump<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (row, chunk) in self.data.chunks(16).enumerate() {
+			if row > 0 {
+				f.write_char('\n')?;
+			}
+			write!(f, "{:08x} ", self.offset + row * 16)?;
+			for column in 0..16 {
+				match chunk.get(column) {
+					Some(byte) => write!(f, " {byte:02x}")?,
+					None => f.write_str("   ")?,
+				}
+			}
+			f.write_str("  |")?;
+			for byte in chunk {
+				let printable = byte.is_ascii_graphic() || *byte == b' ';
+				f.write_char(if printable { *byte as char } else { '.' })?;
+			}
+			f.write_char('|')?;
+		}
+		Ok(())
+	}
+}
+
 /// Extract the crate name from the module path at compile time.
 ///
 /// This macro parses `module_path!()` to extract the crate name (the part
@@ -109,6 +326,87 @@ macro_rules! extract_crate_name {
 
 #[cfg(test)]
 mod tests {
+	use std::{thread::sleep, time::Duration};
+
+	use super::{ConnId, HexDump, LogLimiter, PacketTally};
+
+	#[test]
+	fn test_log_limiter() {
+		let limiter = LogLimiter::new(Duration::from_millis(50));
+		assert_eq!(limiter.check(), Some(0));
+		assert_eq!(limiter.check(), None);
+		assert_eq!(limiter.check(), None);
+		sleep(Duration::from_millis(60));
+		assert_eq!(limiter.check(), Some(2));
+	}
+
+	#[test]
+	fn test_packet_tally() {
+		let tally = PacketTally::new(Duration::from_millis(50));
+		assert_eq!(tally.record(100), None);
+		assert_eq!(tally.record(200), None);
+		sleep(Duration::from_millis(60));
+		assert_eq!(tally.record(300), Some((3, 600)));
+		assert_eq!(tally.record(1), None);
+	}
+
+	#[test]
+	fn test_conn_id() {
+		let (a, b) = (ConnId::next(), ConnId::next());
+		assert_ne!(a, b);
+		let id = a.to_string();
+		assert_eq!(id.len(), 13);
+		assert!(id.bytes().all(|c| c.is_ascii_digit() || c.is_ascii_lowercase()));
+	}
+
+	#[test]
+	fn test_hex_dump() {
+		let dump = HexDump {
+			offset: 16,
+			data:   b"GET / HTTP/1.1\r\nHost",
+		};
+		let dump = dump.to_string();
+		let rows = dump.lines().collect::<Vec<_>>();
+		assert_eq!(
+			rows[0],
+			"00000010  47 45 54 20 2f 20 48 54 54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|"
+		);
+		assert_eq!(rows[1], format!("00000020  48 6f 73 74{}  |Host|", " ".repeat(36)));
+		assert_eq!(rows.len(), 2);
+	}
+
+	#[tokio::test]
+	async fn test_trace_payloads() {
+		use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+		let (logs, _guard) = crate::test_util::LogCapture::install(tracing::Level::INFO);
+		super::trace_payloads(8);
+
+		let (mut client, mut a) = tokio::io::duplex(64);
+		let (mut b, mut server) = tokio::io::duplex(64);
+		let relay = async {
+			crate::io::copy_io(&mut a, &mut b).await;
+		};
+		let exchange = async {
+			client.write_all(b"hello, server").await.unwrap();
+			let mut buf = [0; 13];
+			server.read_exact(&mut buf).await.unwrap();
+			server.write_all(b"hi").await.unwrap();
+			client.read_exact(&mut buf[..2]).await.unwrap();
+			drop(client);
+		};
+		tokio::join!(relay, exchange);
+		super::trace_payloads(0);
+
+		let logs = logs.contents();
+		assert!(logs.contains("a->b bytes 0..8"), "{logs}");
+		assert!(logs.contains("68 65 6c 6c 6f 2c 20 73"), "{logs}");
+		assert!(logs.contains("|hello, s|"), "{logs}");
+		assert!(!logs.contains("65 72 76"), "{logs}");
+		assert!(logs.contains("b->a bytes 0..2"), "{logs}");
+		assert!(logs.contains("|hi|"), "{logs}");
+	}
+
 	#[test]
 	fn test_extract_crate_name() {
 		// Test from root module
@@ -0,0 +1,279 @@
+//! Counters of the proxy, fed from its [`Event`]s and pushed out by a
+//! [`MetricsExporter`]
+//!
+//! [`Metrics`] only counts, the wire format is the exporter's business.
+//! [`StatsdExporter`] pushes them to a StatsD server over UDP.
+
+use std::{collections::HashMap, fmt::Write as _, future::Future, io, net::SocketAddr, time::Duration};
+
+use tokio::{
+	net::UdpSocket,
+	sync::broadcast::{self, error::RecvError},
+	time::MissedTickBehavior,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::events::Event;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+	/// Only grows, totals since startup
+	Counter,
+	/// Current level, can go either way
+	Gauge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+	pub name:  &'static str,
+	pub kind:  MetricKind,
+	pub value: u64,
+}
+
+/// Totals of the [`Event`]s recorded
+#[derive(Debug, Default)]
+pub struct Metrics {
+	connections_opened: u64,
+	connections_closed: u64,
+	bytes_up:           u64,
+	bytes_down:         u64,
+	auth_failures:      u64,
+	reconnects:         u64,
+}
+
+impl Metrics {
+	pub fn record(&mut self, event: &Event) {
+		match event {
+			Event::ConnectionOpened { .. } => self.connections_opened += 1,
+			Event::ConnectionClosed {
+				bytes_up, bytes_down, ..
+			} => {
+				self.connections_closed += 1;
+				self.bytes_up += bytes_up;
+				self.bytes_down += bytes_down;
+			}
+			Event::AuthFailed { .. } => self.auth_failures += 1,
+			Event::ReconnectStarted { .. } => self.reconnects += 1,
+		}
+	}
+
+	/// Current value of every metric, in a fixed order
+	pub fn samples(&self) -> Vec<Sample> {
+		let counter = |name, value| Sample {
+			name,
+			kind: MetricKind::Counter,
+			value,
+		};
+		vec![
+			Sample {
+				name:  "connections.active",
+				kind:  MetricKind::Gauge,
+				value: self.connections_opened.saturating_sub(self.connections_closed),
+			},
+			counter("connections.opened", self.connections_opened),
+			counter("connections.closed", self.connections_closed),
+			counter("bytes.up", self.bytes_up),
+			counter("bytes.down", self.bytes_down),
+			counter("auth.failures", self.auth_failures),
+			counter("reconnects", self.reconnects),
+		]
+	}
+}
+
+/// Ships [`Sample`]s somewhere, called at every push interval
+pub trait MetricsExporter: Send {
+	fn export(&mut self, samples: &[Sample]) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Where and how often metrics are pushed over StatsD
+#[derive(Debug, Clone)]
+pub struct StatsdConfig {
+	pub addr:     SocketAddr,
+	/// Put before every metric name with a dot, none when empty
+	pub prefix:   String,
+	pub interval: Duration,
+}
+
+/// Pushes samples to a StatsD server as a datagram of `name:value|type`
+/// lines
+///
+/// Counters go out as the increase since the previous push, skipped while
+/// unchanged, gauges as their value every time.
+pub struct StatsdExporter {
+	socket: UdpSocket,
+	prefix: String,
+	pushed: HashMap<&'static str, u64>,
+}
+
+impl StatsdExporter {
+	pub async fn connect(addr: SocketAddr, prefix: impl Into<String>) -> io::Result<Self> {
+		let bind: SocketAddr = if addr.is_ipv4() {
+			([0, 0, 0, 0], 0).into()
+		} else {
+			([0u16; 8], 0).into()
+		};
+		let socket = UdpSocket::bind(bind).await?;
+		socket.connect(addr).await?;
+		Ok(Self {
+			socket,
+			prefix: prefix.into(),
+			pushed: HashMap::new(),
+		})
+	}
+
+	fn lines(&mut self, samples: &[Sample]) -> String {
+		let mut lines = String::new();
+		for sample in samples {
+			let (value, kind) = match sample.kind {
+				MetricKind::Gauge => (sample.value, "g"),
+				MetricKind::Counter => {
+					let pushed = self.pushed.insert(sample.name, sample.value).unwrap_or(0);
+					match sample.value.saturating_sub(pushed) {
+						0 => continue,
+						delta => (delta, "c"),
+					}
+				}
+			};
+			if !lines.is_empty() {
+				lines.push('\n');
+			}
+			if !self.prefix.is_empty() {
+				lines.push_str(&self.prefix);
+				lines.push('.');
+			}
+			let _ = write!(lines, "{}:{value}|{kind}", sample.name);
+		}
+		lines
+	}
+}
+
+impl MetricsExporter for StatsdExporter {
+	async fn export(&mut self, samples: &[Sample]) -> io::Result<()> {
+		let lines = self.lines(samples);
+		if !lines.is_empty() {
+			self.socket.send(lines.as_bytes()).await?;
+		}
+		Ok(())
+	}
+}
+
+/// Record `events` and export them every `interval` until `token` is
+/// cancelled, once more on the way out
+///
+/// A failed push is logged and retried with the next one, the counts aren't
+/// lost.
+pub async fn run(
+	mut exporter: impl MetricsExporter,
+	mut events: broadcast::Receiver<Event>,
+	interval: Duration,
+	token: CancellationToken,
+) {
+	let mut metrics = Metrics::default();
+	let mut ticks = tokio::time::interval(interval);
+	ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+	loop {
+		tokio::select! {
+			_ = token.cancelled() => break,
+			event = events.recv() => match event {
+				Ok(event) => metrics.record(&event),
+				Err(RecvError::Lagged(missed)) => {
+					crate::warn!(target: "[METRICS]", "Missed {missed} events, the counts are short of them");
+				}
+				Err(RecvError::Closed) => break,
+			},
+			_ = ticks.tick() => {
+				if let Err(err) = exporter.export(&metrics.samples()).await {
+					crate::warn!(target: "[METRICS]", "Failed to push metrics: {err}");
+				}
+			}
+		}
+	}
+	if let Err(err) = exporter.export(&metrics.samples()).await {
+		crate::warn!(target: "[METRICS]", "Failed to push metrics: {err}");
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+
+	use super::*;
+	use crate::{events::EventBus, types::TargetAddr};
+
+	async fn recv_lines(sink: &UdpSocket) -> String {
+		let mut buf = [0u8; 1024];
+		let len = tokio::time::timeout(Duration::from_secs(5), sink.recv(&mut buf))
+			.await
+			.unwrap()
+			.unwrap();
+		String::from_utf8(buf[..len].to_vec()).unwrap()
+	}
+
+	#[tokio::test]
+	async fn test_statsd_lines() {
+		let sink = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let mut exporter = StatsdExporter::connect(sink.local_addr().unwrap(), "wind").await.unwrap();
+		let mut metrics = Metrics::default();
+		let opened = |id| Event::ConnectionOpened {
+			id,
+			target: TargetAddr::Domain("example.com".into(), 443),
+			outbound: "proxy".into(),
+		};
+		metrics.record(&opened(0));
+		metrics.record(&opened(1));
+		metrics.record(&Event::ConnectionClosed {
+			id:         0,
+			bytes_up:   4,
+			bytes_down: 5,
+		});
+		metrics.record(&Event::AuthFailed {
+			inbound:     "socks",
+			client_addr: None,
+		});
+
+		exporter.export(&metrics.samples()).await.unwrap();
+		assert_eq!(
+			recv_lines(&sink).await,
+			[
+				"wind.connections.active:1|g",
+				"wind.connections.opened:2|c",
+				"wind.connections.closed:1|c",
+				"wind.bytes.up:4|c",
+				"wind.bytes.down:5|c",
+				"wind.auth.failures:1|c",
+			]
+			.join("\n")
+		);
+
+		// Counters only send what they grew by since
+		metrics.record(&Event::ConnectionClosed {
+			id:         1,
+			bytes_up:   10,
+			bytes_down: 0,
+		});
+		exporter.export(&metrics.samples()).await.unwrap();
+		assert_eq!(
+			recv_lines(&sink).await,
+			"wind.connections.active:0|g\nwind.connections.closed:1|c\nwind.bytes.up:10|c"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_run_pushes_events() {
+		let sink = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let exporter = StatsdExporter::connect(sink.local_addr().unwrap(), "").await.unwrap();
+		let events = EventBus::default();
+		let rx = events.subscribe();
+		let task = tokio::spawn(run(exporter, rx, Duration::from_secs(3600), CancellationToken::new()));
+		// The first tick is immediate and finds nothing recorded yet
+		assert_eq!(recv_lines(&sink).await, "connections.active:0|g");
+
+		events.publish(Event::ReconnectStarted {
+			peer_addr: (Ipv4Addr::LOCALHOST, 1).into(),
+		});
+		// Closing the bus ends the run once the events left are recorded
+		drop(events);
+		task.await.unwrap();
+		assert_eq!(recv_lines(&sink).await, "connections.active:0|g\nreconnects:1|c");
+	}
+}
@@ -1,5 +1,18 @@
 use crate::{tcp::AbstractTcpStream, types::TargetAddr, udp::AbstractUdpSocket};
 
+mod balance;
+mod blackhole;
+mod direct;
+mod dynamic;
+mod fallback;
+#[cfg(test)]
+mod testing;
+pub use balance::*;
+pub use blackhole::*;
+pub use direct::*;
+pub use dynamic::*;
+pub use fallback::*;
+
 pub trait AbstractOutbound {
 	/// TCP traffic which needs handled by outbound
 	fn handle_tcp(
@@ -0,0 +1,157 @@
+use std::{
+	hash::{DefaultHasher, Hash, Hasher},
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AbstractOutbound, info, tcp::AbstractTcpStream, types::TargetAddr, udp::AbstractUdpSocket};
+
+/// How [`LoadBalanceOutbound`] picks a member for a new connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BalanceStrategy {
+	#[default]
+	RoundRobin,
+	LeastConnections,
+	/// Rendezvous hashing on the destination, so the same target sticks to the
+	/// same member and only moves when that member is removed.
+	///
+	/// UDP associations carry no destination up front and fall back to
+	/// round-robin.
+	ConsistentHash,
+}
+
+/// Spreads connections across a group of outbounds
+pub struct LoadBalanceOutbound<O> {
+	members:  Vec<O>,
+	active:   Vec<AtomicUsize>,
+	strategy: BalanceStrategy,
+	cursor:   AtomicUsize,
+}
+
+impl<O: AbstractOutbound + Send + Sync> LoadBalanceOutbound<O> {
+	pub fn new(members: Vec<O>, strategy: BalanceStrategy) -> eyre::Result<Self> {
+		if members.is_empty() {
+			eyre::bail!("load balance group needs at least one member");
+		}
+		Ok(Self {
+			active: members.iter().map(|_| AtomicUsize::new(0)).collect(),
+			members,
+			strategy,
+			cursor: AtomicUsize::new(0),
+		})
+	}
+
+	pub fn members(&self) -> &[O] {
+		&self.members
+	}
+
+	/// Connections currently relayed by each member, in member order
+	pub fn active_counts(&self) -> Vec<usize> {
+		self.active.iter().map(|v| v.load(Ordering::Relaxed)).collect()
+	}
+
+	fn round_robin(&self) -> usize {
+		self.cursor.fetch_add(1, Ordering::Relaxed) % self.members.len()
+	}
+
+	fn select(&self, target_addr: Option<&TargetAddr>) -> usize {
+		match (self.strategy, target_addr) {
+			(BalanceStrategy::RoundRobin, _) | (BalanceStrategy::ConsistentHash, None) => self.round_robin(),
+			(BalanceStrategy::LeastConnections, _) => {
+				// Rotate the starting point so ties don't all land on the first member
+				let start = self.round_robin();
+				(0..self.members.len())
+					.map(|i| (start + i) % self.members.len())
+					.min_by_key(|&i| self.active[i].load(Ordering::Relaxed))
+					.unwrap_or(start)
+			}
+			(BalanceStrategy::ConsistentHash, Some(target_addr)) => (0..self.members.len())
+				.max_by_key(|&i| {
+					let mut hasher = DefaultHasher::new();
+					target_addr.hash(&mut hasher);
+					i.hash(&mut hasher);
+					hasher.finish()
+				})
+				.unwrap_or_default(),
+		}
+	}
+}
+
+/// Keeps a member's active count raised while its connection is relayed
+struct ActiveGuard<'a>(&'a AtomicUsize);
+
+impl<'a> ActiveGuard<'a> {
+	fn new(counter: &'a AtomicUsize) -> Self {
+		counter.fetch_add(1, Ordering::Relaxed);
+		Self(counter)
+	}
+}
+
+impl Drop for ActiveGuard<'_> {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
+impl<O: AbstractOutbound + Send + Sync> AbstractOutbound for LoadBalanceOutbound<O> {
+	async fn handle_tcp(
+		&self,
+		target_addr: TargetAddr,
+		stream: impl AbstractTcpStream,
+		via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let idx = self.select(Some(&target_addr));
+		info!(target: "[OUT] BALANCE", "{target_addr} via member #{idx}");
+		let _guard = ActiveGuard::new(&self.active[idx]);
+		self.members[idx].handle_tcp(target_addr, stream, via).await
+	}
+
+	async fn handle_udp(
+		&self,
+		socket: impl AbstractUdpSocket + 'static,
+		via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let idx = self.select(None);
+		info!(target: "[OUT] BALANCE", "UDP association via member #{idx}");
+		let _guard = ActiveGuard::new(&self.active[idx]);
+		self.members[idx].handle_udp(socket, via).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::outbound::testing::CountingOutbound;
+
+	fn target(port: u16) -> TargetAddr {
+		TargetAddr::Domain("example.com".into(), port)
+	}
+
+	#[tokio::test]
+	async fn test_round_robin_even() {
+		let members = vec![CountingOutbound::default(), CountingOutbound::default()];
+		let lb = LoadBalanceOutbound::new(members.clone(), BalanceStrategy::RoundRobin).unwrap();
+		for port in 0..4 {
+			let (stream, _peer) = tokio::io::duplex(64);
+			lb.handle_tcp(target(port), stream, None::<CountingOutbound>).await.unwrap();
+		}
+		assert_eq!(members[0].tcp.load(Ordering::Relaxed), 2);
+		assert_eq!(members[1].tcp.load(Ordering::Relaxed), 2);
+		assert_eq!(lb.active_counts(), vec![0, 0]);
+	}
+
+	#[test]
+	fn test_least_connections_and_hash() {
+		let members = vec![CountingOutbound::default(), CountingOutbound::default()];
+		let lb = LoadBalanceOutbound::new(members.clone(), BalanceStrategy::LeastConnections).unwrap();
+		let _busy = ActiveGuard::new(&lb.active[0]);
+		assert_eq!(lb.select(Some(&target(1))), 1);
+		assert_eq!(lb.select(Some(&target(2))), 1);
+
+		let lb = LoadBalanceOutbound::new(members, BalanceStrategy::ConsistentHash).unwrap();
+		let idx = lb.select(Some(&target(443)));
+		assert!((0..8).all(|_| lb.select(Some(&target(443))) == idx));
+	}
+}
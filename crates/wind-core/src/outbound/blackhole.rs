@@ -0,0 +1,81 @@
+use std::io::IoSliceMut;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+	AbstractOutbound,
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+	udp::{AbstractUdpSocket, RecvMeta},
+};
+
+/// Drops all traffic, for destinations that should be blocked
+///
+/// TCP clients are told the connection is not allowed and closed right away
+/// instead of being left to time out.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlackholeOutbound;
+
+impl AbstractOutbound for BlackholeOutbound {
+	async fn handle_tcp(
+		&self,
+		_target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		stream.on_connect(Err(ConnectError::NotAllowed)).await?;
+		stream.shutdown().await?;
+		Ok(())
+	}
+
+	async fn handle_udp(
+		&self,
+		socket: impl AbstractUdpSocket + 'static,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let mut buf = vec![0u8; u16::MAX as usize];
+		let mut meta = RecvMeta::default();
+		let closed = socket.association_token();
+		// Swallow datagrams until the association goes away. Polled by hand since the
+		// `recv` future isn't `Sync`
+		let drain = async {
+			while std::future::poll_fn(|cx| {
+				socket.poll_recv(cx, &mut [IoSliceMut::new(&mut buf)], std::slice::from_mut(&mut meta))
+			})
+			.await
+			.is_ok()
+			{}
+		};
+		tokio::select! {
+			_ = closed.cancelled() => {}
+			_ = drain => {}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use tokio::io::AsyncReadExt;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_tcp_closed_promptly() {
+		let (mut client, stream) = tokio::io::duplex(64);
+		let target = TargetAddr::Domain("ads.example.com".into(), 443);
+		BlackholeOutbound
+			.handle_tcp(target, stream, None::<BlackholeOutbound>)
+			.await
+			.unwrap();
+
+		let mut buf = [0u8; 16];
+		let n = tokio::time::timeout(Duration::from_millis(100), client.read(&mut buf))
+			.await
+			.expect("blackhole left the connection open")
+			.unwrap();
+		assert_eq!(n, 0);
+	}
+}
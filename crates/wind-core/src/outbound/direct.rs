@@ -0,0 +1,159 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+	AbstractOutbound,
+	breaker::CircuitBreaker,
+	dns::{IpPolicy, Resolver, dial},
+	proxy_protocol,
+	tcp::{AbstractTcpStream, ConnectError, KeepaliveConfig, set_keepalive},
+	types::TargetAddr,
+	udp::AbstractUdpSocket,
+	warn,
+};
+
+/// Connects straight to the target from this host
+///
+/// Domains are dialed address by address until one accepts. Only TCP is
+/// relayed, UDP associations are refused.
+#[derive(Debug, Clone)]
+pub struct DirectOutbound {
+	tcp_fast_open:       bool,
+	tcp_keepalive:       Option<KeepaliveConfig>,
+	breaker:             Option<Arc<CircuitBreaker>>,
+	send_proxy_protocol: bool,
+	local_port_range:    Option<(u16, u16)>,
+	resolver:            Resolver,
+}
+
+impl Default for DirectOutbound {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl DirectOutbound {
+	pub const fn new() -> Self {
+		Self {
+			tcp_fast_open:       false,
+			tcp_keepalive:       None,
+			breaker:             None,
+			send_proxy_protocol: false,
+			local_port_range:    None,
+			resolver:            Resolver::System,
+		}
+	}
+
+	/// Connect with TCP Fast Open, Linux only. Falls back to the regular
+	/// handshake where the kernel refuses it
+	pub fn with_tcp_fast_open(mut self, tcp_fast_open: bool) -> Self {
+		self.tcp_fast_open = tcp_fast_open;
+		self
+	}
+
+	/// Probe idle connections to the target, `None` leaves the OS default
+	pub fn with_tcp_keepalive(mut self, tcp_keepalive: Option<KeepaliveConfig>) -> Self {
+		self.tcp_keepalive = tcp_keepalive;
+		self
+	}
+
+	/// Refuse targets `breaker` considers dead without dialing, and report
+	/// every dial to it
+	pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+		self.breaker = Some(breaker);
+		self
+	}
+
+	/// Open each connection with a PROXY protocol v2 header carrying the
+	/// address the inbound accepted the client from, for backends that want
+	/// the real client address
+	pub fn with_proxy_protocol(mut self, send_proxy_protocol: bool) -> Self {
+		self.send_proxy_protocol = send_proxy_protocol;
+		self
+	}
+
+	/// Connect from a port of `local_port_range`, both ends included, for
+	/// egress firewalls that only let some source ports out. `None` leaves
+	/// the port to the OS
+	pub fn with_local_port_range(mut self, local_port_range: Option<(u16, u16)>) -> Self {
+		self.local_port_range = local_port_range;
+		self
+	}
+
+	/// Look up domain targets through `resolver` instead of the system's
+	pub fn with_resolver(mut self, resolver: Resolver) -> Self {
+		self.resolver = resolver;
+		self
+	}
+
+	/// The address a connection to `target_addr` would dial first
+	pub async fn resolve(&self, target_addr: &TargetAddr) -> std::io::Result<SocketAddr> {
+		self.resolver
+			.resolve(target_addr, IpPolicy::default())
+			.await?
+			.into_iter()
+			.next()
+			.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{target_addr} has no address")))
+	}
+}
+
+impl AbstractOutbound for DirectOutbound {
+	async fn handle_tcp(
+		&self,
+		target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		if let Some(breaker) = &self.breaker
+			&& !breaker.allow(&target_addr)
+		{
+			stream.on_connect(Err(ConnectError::HostUnreachable)).await?;
+			eyre::bail!("circuit open for {target_addr}, not dialing");
+		}
+		let result = dial(
+			&target_addr,
+			self.resolver,
+			IpPolicy::default(),
+			self.tcp_fast_open,
+			self.local_port_range,
+		)
+		.await;
+		if let Some(breaker) = &self.breaker {
+			match &result {
+				Ok(_) => breaker.record_success(&target_addr),
+				Err(_) => breaker.record_failure(&target_addr),
+			}
+		}
+		let mut target = match result {
+			Ok(target) => target,
+			Err(err) => {
+				stream.on_connect(Err(ConnectError::from(&err))).await?;
+				return Err(err.into());
+			}
+		};
+		if let Some(keepalive) = &self.tcp_keepalive
+			&& let Err(err) = set_keepalive(&target, keepalive)
+		{
+			warn!(target: "[OUT] DIRECT", "Failed to enable keepalive to {target_addr}: {err}");
+		}
+		if self.send_proxy_protocol {
+			let header = proxy_protocol::encode_v2(stream.client_addr(), target.peer_addr()?);
+			if let Err(err) = target.write_all(&header).await {
+				stream.on_connect(Err(ConnectError::General)).await?;
+				return Err(err.into());
+			}
+		}
+		stream.on_connect(Ok(())).await?;
+		tokio::io::copy_bidirectional(&mut stream, &mut target).await?;
+		Ok(())
+	}
+
+	async fn handle_udp(
+		&self,
+		_socket: impl AbstractUdpSocket + 'static,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		eyre::bail!("direct outbound does not relay UDP")
+	}
+}
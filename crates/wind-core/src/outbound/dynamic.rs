@@ -0,0 +1,340 @@
+use std::{
+	future::Future,
+	io::{IoSliceMut, Result as IoResult},
+	net::SocketAddr,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+	AbstractOutbound,
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+	udp::{AbstractUdpSocket, EcnCodepoint, RecvMeta, Transmit, UdpPoller},
+};
+
+/// Object-safe counterpart of [`AbstractOutbound`], so outbounds of different
+/// types can be held as `Arc<dyn DynOutbound>` and picked at runtime
+///
+/// Every [`AbstractOutbound`] implements it, and `Arc<dyn DynOutbound>` is an
+/// [`AbstractOutbound`] in turn, so it fits wherever a concrete outbound does.
+pub trait DynOutbound: Send + Sync {
+	fn dyn_handle_tcp<'a>(
+		&'a self,
+		target_addr: TargetAddr,
+		stream: BoxTcpStream<'a>,
+		via: Option<Arc<dyn DynOutbound>>,
+	) -> BoxFuture<'a, eyre::Result<()>>;
+
+	fn dyn_handle_udp(&self, socket: BoxUdpSocket, via: Option<Arc<dyn DynOutbound>>) -> BoxFuture<'_, eyre::Result<()>>;
+}
+
+impl<O: AbstractOutbound + Send + Sync> DynOutbound for O {
+	fn dyn_handle_tcp<'a>(
+		&'a self,
+		target_addr: TargetAddr,
+		stream: BoxTcpStream<'a>,
+		via: Option<Arc<dyn DynOutbound>>,
+	) -> BoxFuture<'a, eyre::Result<()>> {
+		Box::pin(self.handle_tcp(target_addr, stream, via))
+	}
+
+	fn dyn_handle_udp(&self, socket: BoxUdpSocket, via: Option<Arc<dyn DynOutbound>>) -> BoxFuture<'_, eyre::Result<()>> {
+		Box::pin(self.handle_udp(socket, via))
+	}
+}
+
+/// A generic `via` can't be boxed without being `'static` and `Sync`, so
+/// chaining is only available through [`DynOutbound`] itself
+impl AbstractOutbound for Arc<dyn DynOutbound> {
+	async fn handle_tcp(
+		&self,
+		target_addr: TargetAddr,
+		stream: impl AbstractTcpStream,
+		via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		if via.is_some() {
+			eyre::bail!("dynamic outbounds can't be chained through a generic `via`");
+		}
+		(**self).dyn_handle_tcp(target_addr, Box::new(stream), None).await
+	}
+
+	async fn handle_udp(
+		&self,
+		socket: impl AbstractUdpSocket + 'static,
+		via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		if via.is_some() {
+			eyre::bail!("dynamic outbounds can't be chained through a generic `via`");
+		}
+		(**self).dyn_handle_udp(BoxUdpSocket::new(socket), None).await
+	}
+}
+
+/// Object-safe counterpart of [`AbstractTcpStream`]
+pub trait DynTcpStream: AsyncRead + AsyncWrite + Send + Sync + Unpin {
+	fn dyn_on_connect(
+		&mut self,
+		result: Result<(), ConnectError>,
+	) -> Pin<Box<dyn Future<Output = IoResult<()>> + Send + Sync + '_>>;
+
+	fn dyn_client_addr(&self) -> Option<SocketAddr>;
+
+	fn dyn_local_addr(&self) -> Option<SocketAddr>;
+}
+
+impl<S: AbstractTcpStream> DynTcpStream for S {
+	fn dyn_on_connect(
+		&mut self,
+		result: Result<(), ConnectError>,
+	) -> Pin<Box<dyn Future<Output = IoResult<()>> + Send + Sync + '_>> {
+		Box::pin(self.on_connect(result))
+	}
+
+	fn dyn_client_addr(&self) -> Option<SocketAddr> {
+		self.client_addr()
+	}
+
+	fn dyn_local_addr(&self) -> Option<SocketAddr> {
+		self.local_addr()
+	}
+}
+
+/// Inbound stream of any type, as handed to a [`DynOutbound`]
+pub type BoxTcpStream<'a> = Box<dyn DynTcpStream + 'a>;
+
+impl AbstractTcpStream for BoxTcpStream<'_> {
+	fn on_connect(&mut self, result: Result<(), ConnectError>) -> impl Future<Output = IoResult<()>> + Send + Sync {
+		(**self).dyn_on_connect(result)
+	}
+
+	fn client_addr(&self) -> Option<SocketAddr> {
+		(**self).dyn_client_addr()
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		(**self).dyn_local_addr()
+	}
+}
+
+/// Object-safe counterpart of [`AbstractUdpSocket`]
+///
+/// Mirrors the provided methods sockets override, so they behave the same
+/// once boxed. The rest are built on these as usual.
+pub trait DynUdpSocket: Send + Sync {
+	fn dyn_create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>>;
+	fn dyn_try_send(&self, transmit: &Transmit) -> IoResult<()>;
+	fn dyn_poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<IoResult<usize>>;
+	fn dyn_local_addr(&self) -> IoResult<SocketAddr>;
+	fn dyn_max_transmit_segments(&self) -> usize;
+	fn dyn_max_receive_segments(&self) -> usize;
+	fn dyn_may_fragment(&self) -> bool;
+	fn dyn_poll_recv_ready(&self, cx: &mut Context) -> Poll<IoResult<()>>;
+	fn dyn_association_token(&self) -> CancellationToken;
+	fn dyn_poll_send_ecn(
+		&self,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> Poll<IoResult<usize>>;
+	fn dyn_send_ecn<'a>(
+		&'a self,
+		buf: &'a [u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> BoxFuture<'a, IoResult<usize>>;
+}
+
+impl<S: AbstractUdpSocket + 'static> DynUdpSocket for S {
+	fn dyn_create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+		self.create_io_poller()
+	}
+
+	fn dyn_try_send(&self, transmit: &Transmit) -> IoResult<()> {
+		self.try_send(transmit)
+	}
+
+	fn dyn_poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<IoResult<usize>> {
+		self.poll_recv(cx, bufs, meta)
+	}
+
+	fn dyn_local_addr(&self) -> IoResult<SocketAddr> {
+		self.local_addr()
+	}
+
+	fn dyn_max_transmit_segments(&self) -> usize {
+		self.max_transmit_segments()
+	}
+
+	fn dyn_max_receive_segments(&self) -> usize {
+		self.max_receive_segments()
+	}
+
+	fn dyn_may_fragment(&self) -> bool {
+		self.may_fragment()
+	}
+
+	fn dyn_poll_recv_ready(&self, cx: &mut Context) -> Poll<IoResult<()>> {
+		self.poll_recv_ready(cx)
+	}
+
+	fn dyn_association_token(&self) -> CancellationToken {
+		self.association_token()
+	}
+
+	fn dyn_poll_send_ecn(
+		&self,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> Poll<IoResult<usize>> {
+		self.poll_send_ecn(cx, buf, target, ecn)
+	}
+
+	fn dyn_send_ecn<'a>(
+		&'a self,
+		buf: &'a [u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> BoxFuture<'a, IoResult<usize>> {
+		Box::pin(self.send_ecn(buf, target, ecn))
+	}
+}
+
+/// UDP socket of any type, as handed to a [`DynOutbound`]
+#[derive(Clone)]
+pub struct BoxUdpSocket(Arc<dyn DynUdpSocket>);
+
+impl BoxUdpSocket {
+	pub fn new(socket: impl AbstractUdpSocket + 'static) -> Self {
+		Self(Arc::new(socket))
+	}
+}
+
+impl AbstractUdpSocket for BoxUdpSocket {
+	fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+		self.0.clone().dyn_create_io_poller()
+	}
+
+	fn try_send(&self, transmit: &Transmit) -> IoResult<()> {
+		self.0.dyn_try_send(transmit)
+	}
+
+	fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<IoResult<usize>> {
+		self.0.dyn_poll_recv(cx, bufs, meta)
+	}
+
+	fn local_addr(&self) -> IoResult<SocketAddr> {
+		self.0.dyn_local_addr()
+	}
+
+	fn max_transmit_segments(&self) -> usize {
+		self.0.dyn_max_transmit_segments()
+	}
+
+	fn max_receive_segments(&self) -> usize {
+		self.0.dyn_max_receive_segments()
+	}
+
+	fn may_fragment(&self) -> bool {
+		self.0.dyn_may_fragment()
+	}
+
+	fn poll_recv_ready(&self, cx: &mut Context) -> Poll<IoResult<()>> {
+		self.0.dyn_poll_recv_ready(cx)
+	}
+
+	fn association_token(&self) -> CancellationToken {
+		self.0.dyn_association_token()
+	}
+
+	fn poll_send_ecn(
+		&self,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> Poll<IoResult<usize>> {
+		self.0.dyn_poll_send_ecn(cx, buf, target, ecn)
+	}
+
+	fn send_ecn<'a>(
+		&'a self,
+		buf: &'a [u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> impl Future<Output = IoResult<usize>> + Send + 'a {
+		self.0.dyn_send_ecn(buf, target, ecn)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{sync::atomic::Ordering, time::Duration};
+
+	use tokio::{
+		io::{AsyncReadExt, AsyncWriteExt},
+		net::TcpListener,
+	};
+
+	use super::*;
+	use crate::{BlackholeOutbound, DirectOutbound, outbound::testing::CountingOutbound, udp::TokioUdpSocket};
+
+	#[tokio::test]
+	async fn test_outbounds_behind_trait_object() {
+		let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let backend_addr = backend.local_addr().unwrap();
+		let counting = Arc::new(CountingOutbound::default());
+		let outbounds: Vec<Arc<dyn DynOutbound>> =
+			vec![Arc::new(DirectOutbound::new()), Arc::new(BlackholeOutbound), counting.clone()];
+
+		// Direct relays to the backend
+		let (mut client, stream) = tokio::io::duplex(64);
+		let direct = outbounds[0].clone();
+		let relay = tokio::spawn(async move {
+			direct
+				.handle_tcp(TargetAddr::from(backend_addr), stream, None::<DirectOutbound>)
+				.await
+		});
+		let (mut upstream, _) = backend.accept().await.unwrap();
+		client.write_all(b"hello").await.unwrap();
+		let mut buf = [0u8; 5];
+		upstream.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"hello");
+		drop((client, upstream));
+		relay.await.unwrap().unwrap();
+
+		// Blackhole closes right away
+		let (mut client, stream) = tokio::io::duplex(64);
+		let target = TargetAddr::Domain("ads.example.com".into(), 443);
+		outbounds[1]
+			.handle_tcp(target.clone(), stream, None::<DirectOutbound>)
+			.await
+			.unwrap();
+		let n = tokio::time::timeout(Duration::from_millis(100), client.read(&mut [0u8; 16]))
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(n, 0);
+
+		// A custom outbound sees both the stream and the UDP socket it was given
+		let (mut client, stream) = tokio::io::duplex(64);
+		outbounds[2].handle_tcp(target, stream, None::<DirectOutbound>).await.unwrap();
+		let mut received = Vec::new();
+		client.read_to_end(&mut received).await.unwrap();
+		assert_eq!(received, b"counted");
+		assert_eq!(counting.tcp.load(Ordering::Relaxed), 1);
+
+		let socket = TokioUdpSocket::new(std::net::UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+		let local = socket.local_addr().unwrap();
+		outbounds[2].handle_udp(socket, None::<DirectOutbound>).await.unwrap();
+		assert_eq!(*counting.udp_addr.lock().unwrap(), Some(local));
+	}
+}
@@ -0,0 +1,304 @@
+use std::{
+	pin::Pin,
+	sync::Mutex,
+	task::{Context, Poll},
+	time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+	AbstractOutbound, info,
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+	udp::AbstractUdpSocket,
+	warn,
+};
+
+pub struct FallbackOpts {
+	/// Endpoint every member opens a probe relay to
+	pub probe_target: TargetAddr,
+	/// Time between two probe rounds
+	pub interval:     Duration,
+	/// Probes slower than this count as failed
+	pub timeout:      Duration,
+	/// Members within this much of the fastest are considered equal, so an
+	/// earlier member wins over a marginally faster later one
+	pub tolerance:    Duration,
+	/// How long a failed member stays demoted
+	pub cooldown:     Duration,
+}
+
+#[derive(Default)]
+struct MemberState {
+	latency:    Option<Duration>,
+	down_until: Option<Instant>,
+}
+
+impl MemberState {
+	fn is_healthy(&self, now: Instant) -> bool {
+		self.down_until.is_none_or(|until| until <= now)
+	}
+}
+
+/// Routes to the first healthy, lowest-latency member of a group
+pub struct FallbackOutbound<O> {
+	members: Vec<O>,
+	states:  Vec<Mutex<MemberState>>,
+	current: Mutex<usize>,
+	opts:    FallbackOpts,
+}
+
+impl<O: AbstractOutbound + Send + Sync> FallbackOutbound<O> {
+	pub fn new(members: Vec<O>, opts: FallbackOpts) -> eyre::Result<Self> {
+		if members.is_empty() {
+			eyre::bail!("fallback group needs at least one member");
+		}
+		Ok(Self {
+			states: members.iter().map(|_| Mutex::default()).collect(),
+			members,
+			current: Mutex::new(0),
+			opts,
+		})
+	}
+
+	pub fn members(&self) -> &[O] {
+		&self.members
+	}
+
+	/// Index of the member new connections are routed to
+	pub fn current(&self) -> usize {
+		*self.current.lock().unwrap()
+	}
+
+	/// Probe members every `interval` until cancelled
+	pub async fn run_probes(&self, cancel: CancellationToken) {
+		let mut interval = tokio::time::interval(self.opts.interval);
+		loop {
+			tokio::select! {
+				_ = cancel.cancelled() => return,
+				_ = interval.tick() => self.probe_all().await,
+			}
+		}
+	}
+
+	/// Run one probe round and re-elect the current member
+	pub async fn probe_all(&self) {
+		for idx in 0..self.members.len() {
+			let now = Instant::now();
+			if !self.states[idx].lock().unwrap().is_healthy(now) {
+				// Still cooling down
+				continue;
+			}
+			let result = self.probe(idx).await;
+			let mut state = self.states[idx].lock().unwrap();
+			match result {
+				Ok(latency) => {
+					if state.down_until.take().is_some() {
+						info!(target: "[OUT] FALLBACK", "member #{idx} is back up ({latency:?})");
+					}
+					state.latency = Some(latency);
+				}
+				Err(err) => {
+					warn!(target: "[OUT] FALLBACK", "member #{idx} failed probe: {err}");
+					state.latency = None;
+					state.down_until = Some(Instant::now() + self.opts.cooldown);
+				}
+			}
+		}
+		self.elect();
+	}
+
+	async fn probe(&self, idx: usize) -> eyre::Result<Duration> {
+		let (stream, peer) = tokio::io::duplex(64);
+		// Nothing is sent through the probe, the relay ends once the upstream is
+		// reached
+		drop(peer);
+		let mut probe = ProbeStream {
+			inner:     stream,
+			started:   Instant::now(),
+			connected: None,
+		};
+		let result = tokio::time::timeout(
+			self.opts.timeout,
+			self.members[idx].handle_tcp(self.opts.probe_target.clone(), &mut probe, None::<O>),
+		)
+		.await;
+		match (probe.connected, result) {
+			(Some(Ok(latency)), _) => Ok(latency),
+			(Some(Err(err)), _) => Err(eyre::eyre!("upstream unreachable: {err:?}")),
+			// Members that don't report the upstream state are judged by the relay result
+			(None, Ok(Ok(()))) => Ok(probe.started.elapsed()),
+			(None, Ok(Err(err))) => Err(err),
+			(None, Err(_)) => Err(eyre::eyre!("timed out after {:?}", self.opts.timeout)),
+		}
+	}
+
+	fn elect(&self) {
+		let now = Instant::now();
+		let healthy: Vec<_> = self
+			.states
+			.iter()
+			.enumerate()
+			.filter_map(|(idx, state)| {
+				let state = state.lock().unwrap();
+				state.is_healthy(now).then_some((idx, state.latency))
+			})
+			.collect();
+		let fastest = healthy.iter().filter_map(|(_, latency)| *latency).min();
+		let next = healthy
+			.iter()
+			.find(|(_, latency)| match (latency, fastest) {
+				(Some(latency), Some(fastest)) => *latency <= fastest + self.opts.tolerance,
+				// Nothing measured yet, keep member order
+				(_, None) => true,
+				(None, Some(_)) => false,
+			})
+			.map(|(idx, _)| *idx)
+			// Everyone is down, keep trying the primary
+			.unwrap_or(0);
+
+		let mut current = self.current.lock().unwrap();
+		if *current != next {
+			info!(target: "[OUT] FALLBACK", "switching from member #{} to #{next}", *current);
+			*current = next;
+		}
+	}
+}
+
+impl<O: AbstractOutbound + Send + Sync> AbstractOutbound for FallbackOutbound<O> {
+	async fn handle_tcp(
+		&self,
+		target_addr: TargetAddr,
+		stream: impl AbstractTcpStream,
+		via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let idx = self.current();
+		self.members[idx].handle_tcp(target_addr, stream, via).await
+	}
+
+	async fn handle_udp(
+		&self,
+		socket: impl AbstractUdpSocket + 'static,
+		via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let idx = self.current();
+		self.members[idx].handle_udp(socket, via).await
+	}
+}
+
+/// Records when the member reports reaching the probe target
+struct ProbeStream {
+	inner:     DuplexStream,
+	started:   Instant,
+	connected: Option<Result<Duration, ConnectError>>,
+}
+
+impl AsyncRead for ProbeStream {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for ProbeStream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+		Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+	}
+}
+
+impl AbstractTcpStream for ProbeStream {
+	async fn on_connect(&mut self, result: Result<(), ConnectError>) -> std::io::Result<()> {
+		self.connected = Some(result.map(|()| self.started.elapsed()));
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{
+		Arc,
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+	};
+
+	use super::*;
+
+	#[derive(Clone)]
+	struct FlakyOutbound {
+		up:  Arc<AtomicBool>,
+		tcp: Arc<AtomicUsize>,
+	}
+
+	impl FlakyOutbound {
+		fn new() -> Self {
+			Self {
+				up:  Arc::new(AtomicBool::new(true)),
+				tcp: Arc::default(),
+			}
+		}
+	}
+
+	impl AbstractOutbound for FlakyOutbound {
+		async fn handle_tcp(
+			&self,
+			target_addr: TargetAddr,
+			mut stream: impl AbstractTcpStream,
+			_via: Option<impl AbstractOutbound + Sized + Send>,
+		) -> eyre::Result<()> {
+			if !self.up.load(Ordering::Relaxed) {
+				stream.on_connect(Err(ConnectError::ConnectionRefused)).await?;
+				eyre::bail!("connection refused");
+			}
+			stream.on_connect(Ok(())).await?;
+			if target_addr.to_string() != "probe.test:80" {
+				self.tcp.fetch_add(1, Ordering::Relaxed);
+			}
+			Ok(())
+		}
+
+		async fn handle_udp(
+			&self,
+			_socket: impl AbstractUdpSocket + 'static,
+			_via: Option<impl AbstractOutbound + Sized + Send>,
+		) -> eyre::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[tokio::test]
+	async fn test_failover_to_secondary() {
+		let (primary, secondary) = (FlakyOutbound::new(), FlakyOutbound::new());
+		let fallback = FallbackOutbound::new(
+			vec![primary.clone(), secondary.clone()],
+			FallbackOpts {
+				probe_target: TargetAddr::Domain("probe.test".into(), 80),
+				interval:     Duration::from_secs(30),
+				timeout:      Duration::from_secs(1),
+				tolerance:    Duration::from_secs(1),
+				cooldown:     Duration::from_secs(60),
+			},
+		)
+		.unwrap();
+
+		fallback.probe_all().await;
+		assert_eq!(fallback.current(), 0);
+
+		primary.up.store(false, Ordering::Relaxed);
+		fallback.probe_all().await;
+		assert_eq!(fallback.current(), 1);
+
+		let (stream, _peer) = tokio::io::duplex(64);
+		let target = TargetAddr::Domain("example.com".into(), 443);
+		fallback.handle_tcp(target, stream, None::<FlakyOutbound>).await.unwrap();
+		assert_eq!(primary.tcp.load(Ordering::Relaxed), 0);
+		assert_eq!(secondary.tcp.load(Ordering::Relaxed), 1);
+	}
+}
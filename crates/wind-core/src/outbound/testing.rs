@@ -0,0 +1,42 @@
+use std::{
+	net::SocketAddr,
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicUsize, Ordering},
+	},
+};
+
+use tokio::io::AsyncWriteExt as _;
+
+use crate::{AbstractOutbound, tcp::AbstractTcpStream, types::TargetAddr, udp::AbstractUdpSocket};
+
+/// Counts the streams it's handed, answering each with `counted`, and
+/// remembers the address of the last UDP socket. Clones share their counts
+#[derive(Clone, Default)]
+pub(super) struct CountingOutbound {
+	pub tcp:      Arc<AtomicUsize>,
+	pub udp_addr: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl AbstractOutbound for CountingOutbound {
+	async fn handle_tcp(
+		&self,
+		_target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		self.tcp.fetch_add(1, Ordering::Relaxed);
+		stream.on_connect(Ok(())).await?;
+		stream.write_all(b"counted").await?;
+		Ok(())
+	}
+
+	async fn handle_udp(
+		&self,
+		socket: impl AbstractUdpSocket + 'static,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		*self.udp_addr.lock().unwrap() = Some(socket.local_addr()?);
+		Ok(())
+	}
+}
@@ -0,0 +1,231 @@
+//! HAProxy PROXY protocol headers, telling a backend the address of the
+//! client a relayed connection came from
+
+use std::{
+	io,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest v1 header, CRLF included
+const V1_MAX_LEN: usize = 107;
+
+/// Version 2, the connection is relayed for the addresses that follow
+const CMD_PROXY: u8 = 0x21;
+/// Version 2, the connection was made by the proxy itself
+const CMD_LOCAL: u8 = 0x20;
+const FAMILY_UNSPEC: u8 = 0x00;
+const FAMILY_TCP4: u8 = 0x11;
+const FAMILY_TCP6: u8 = 0x21;
+
+/// Encode a v2 header for a TCP connection from `source` to `destination`
+///
+/// Without a source, say for Unix socket clients, the header is `LOCAL` and
+/// carries no addresses. An IPv4 address paired with an IPv6 one is sent
+/// v4-mapped.
+pub fn encode_v2(source: Option<SocketAddr>, destination: SocketAddr) -> Vec<u8> {
+	let mut buf = SIGNATURE.to_vec();
+	let Some(source) = source else {
+		buf.extend_from_slice(&[CMD_LOCAL, FAMILY_UNSPEC, 0, 0]);
+		return buf;
+	};
+	buf.push(CMD_PROXY);
+	match (source.ip(), destination.ip()) {
+		(IpAddr::V4(src), IpAddr::V4(dst)) => {
+			buf.push(FAMILY_TCP4);
+			buf.extend_from_slice(&12u16.to_be_bytes());
+			buf.extend_from_slice(&src.octets());
+			buf.extend_from_slice(&dst.octets());
+		}
+		(src, dst) => {
+			let v6 = |ip: IpAddr| match ip {
+				IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+				IpAddr::V6(ip) => ip,
+			};
+			buf.push(FAMILY_TCP6);
+			buf.extend_from_slice(&36u16.to_be_bytes());
+			buf.extend_from_slice(&v6(src).octets());
+			buf.extend_from_slice(&v6(dst).octets());
+		}
+	}
+	buf.extend_from_slice(&source.port().to_be_bytes());
+	buf.extend_from_slice(&destination.port().to_be_bytes());
+	buf
+}
+
+/// Read the v1 or v2 header a load balancer put in front of a connection
+/// and return the client address it conveys
+///
+/// `None` means the balancer made the connection itself (`LOCAL`, `UNKNOWN`)
+/// or relayed a client without an IP address. A connection that doesn't start
+/// with a header is refused, as the address would otherwise come from whoever
+/// connected.
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+	let mut head = [0u8; 5];
+	stream.read_exact(&mut head).await?;
+	if &head == b"PROXY" {
+		read_v1(stream).await
+	} else if head == SIGNATURE[..5] {
+		read_v2(stream).await
+	} else {
+		Err(invalid("missing PROXY protocol header"))
+	}
+}
+
+/// Read the rest of a `PROXY TCP4 <src> <dst> <src port> <dst port>\r\n` line
+async fn read_v1<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+	// Byte by byte, so nothing past the header is consumed
+	let mut line = b"PROXY".to_vec();
+	while !line.ends_with(b"\r\n") {
+		if line.len() >= V1_MAX_LEN {
+			return Err(invalid("PROXY v1 header too long"));
+		}
+		line.push(stream.read_u8().await?);
+	}
+	let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("malformed PROXY v1 header"))?;
+	let fields: Vec<&str> = line.split(' ').collect();
+	let source = match fields[..] {
+		["PROXY", "UNKNOWN", ..] => return Ok(None),
+		["PROXY", family @ ("TCP4" | "TCP6"), src, _, src_port, _] => src
+			.parse::<IpAddr>()
+			.ok()
+			.filter(|ip| ip.is_ipv4() == (family == "TCP4"))
+			.zip(src_port.parse::<u16>().ok())
+			.map(|(ip, port)| SocketAddr::new(ip, port)),
+		_ => None,
+	};
+	source.map(Some).ok_or_else(|| invalid("malformed PROXY v1 header"))
+}
+
+/// Read the rest of a binary header, whose first 5 bytes were the signature's
+async fn read_v2<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+	let mut head = [0u8; 11];
+	stream.read_exact(&mut head).await?;
+	if head[..7] != SIGNATURE[5..] {
+		return Err(invalid("malformed PROXY v2 signature"));
+	}
+	let (ver_cmd, family) = (head[7], head[8]);
+	let mut body = vec![0u8; u16::from_be_bytes([head[9], head[10]]) as usize];
+	stream.read_exact(&mut body).await?;
+	if ver_cmd >> 4 != 2 {
+		return Err(invalid("unsupported PROXY protocol version"));
+	}
+	match ver_cmd & 0x0f {
+		0 => return Ok(None),
+		1 => {}
+		_ => return Err(invalid("unknown PROXY v2 command")),
+	}
+	// Any TLVs past the addresses are ignored
+	match family >> 4 {
+		1 if body.len() >= 12 => {
+			let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&body[..4]).unwrap());
+			Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([body[8], body[9]]))))
+		}
+		2 if body.len() >= 36 => {
+			let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&body[..16]).unwrap());
+			Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([body[32], body[33]]))))
+		}
+		1 | 2 => Err(invalid("PROXY v2 address block too short")),
+		// Unspecified or Unix addresses
+		_ => Ok(None),
+	}
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::{
+		io::{AsyncReadExt, AsyncWriteExt},
+		net::{TcpListener, TcpStream},
+	};
+
+	use super::*;
+	use crate::{AbstractOutbound, DirectOutbound, types::TargetAddr};
+
+	#[test]
+	fn test_encode_v2() {
+		let header = encode_v2(Some("192.0.2.1:40000".parse().unwrap()), "198.51.100.7:443".parse().unwrap());
+		assert_eq!(&header[..12], &SIGNATURE);
+		assert_eq!(
+			&header[12..],
+			&[0x21, 0x11, 0, 12, 192, 0, 2, 1, 198, 51, 100, 7, 0x9c, 0x40, 0x01, 0xbb]
+		);
+
+		let header = encode_v2(Some("192.0.2.1:1".parse().unwrap()), "[2001:db8::1]:2".parse().unwrap());
+		assert_eq!(&header[12..16], &[0x21, 0x21, 0, 36]);
+		assert_eq!(&header[16..32], &Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped().octets());
+		assert_eq!(header.len(), 16 + 36);
+
+		let header = encode_v2(None, "198.51.100.7:443".parse().unwrap());
+		assert_eq!(&header[12..], &[0x20, 0x00, 0, 0]);
+	}
+
+	#[tokio::test]
+	async fn test_read_header() {
+		let client: SocketAddr = "[2001:db8::7]:50000".parse().unwrap();
+		let mut stream = [encode_v2(Some(client), "[::1]:1080".parse().unwrap()), b"rest".to_vec()].concat();
+		// A TLV after the addresses
+		stream[15] += 3;
+		stream.splice(52..52, [0x04, 0, 0]);
+		let mut stream = &stream[..];
+		assert_eq!(read_header(&mut stream).await.unwrap(), Some(client));
+		assert_eq!(stream, b"rest");
+
+		let mut stream = &b"PROXY TCP4 192.0.2.1 198.51.100.7 40000 443\r\nrest"[..];
+		assert_eq!(
+			read_header(&mut stream).await.unwrap(),
+			Some("192.0.2.1:40000".parse().unwrap())
+		);
+		assert_eq!(stream, b"rest");
+
+		let mut stream = &b"PROXY UNKNOWN\r\n"[..];
+		assert_eq!(read_header(&mut stream).await.unwrap(), None);
+		let local = encode_v2(None, "127.0.0.1:1".parse().unwrap());
+		assert_eq!(read_header(&mut &local[..]).await.unwrap(), None);
+
+		for malformed in [
+			&b"\x05\x01\x00\x05\x01"[..],
+			b"PROXY TCP4 2001:db8::1 198.51.100.7 40000 443\r\n",
+			b"PROXY TCP4 192.0.2.1 198.51.100.7 40000\r\n",
+			&[&SIGNATURE[..], &[0x21, 0x11, 0, 4, 192, 0, 2, 1]].concat()[..],
+			&[&SIGNATURE[..], &[0x11, 0x11, 0, 0]].concat()[..],
+		] {
+			let err = read_header(&mut &malformed[..]).await.unwrap_err();
+			assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{malformed:?}");
+		}
+		let endless = [&b"PROXY "[..], &[b'x'; 200]].concat();
+		assert!(read_header(&mut &endless[..]).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_direct_outbound_sends_header() {
+		let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let backend_addr = backend.local_addr().unwrap();
+		let inbound = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let mut client = TcpStream::connect(inbound.local_addr().unwrap()).await.unwrap();
+		let (stream, client_addr) = inbound.accept().await.unwrap();
+
+		let outbound = DirectOutbound::new().with_proxy_protocol(true);
+		let relay = tokio::spawn(async move {
+			outbound
+				.handle_tcp(TargetAddr::from(backend_addr), stream, None::<DirectOutbound>)
+				.await
+		});
+		client.write_all(b"hello").await.unwrap();
+		client.shutdown().await.unwrap();
+
+		let (mut upstream, _) = backend.accept().await.unwrap();
+		let mut received = Vec::new();
+		upstream.read_to_end(&mut received).await.unwrap();
+		let header = encode_v2(Some(client_addr), backend_addr);
+		assert_eq!(&received[..header.len()], &header[..]);
+		assert_eq!(&received[header.len()..], b"hello");
+		drop(upstream);
+		relay.await.unwrap().unwrap();
+	}
+}
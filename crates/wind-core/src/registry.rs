@@ -0,0 +1,338 @@
+//! Table of the connections currently relayed, for introspection and a
+//! global concurrency cap
+
+use std::{
+	collections::HashMap,
+	io,
+	net::SocketAddr,
+	pin::Pin,
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+	},
+	task::{Context, Poll},
+	time::Instant,
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+	events::{Event, EventBus},
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+};
+
+pub type ConnectionId = u64;
+
+/// Snapshot of one registered connection
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+	pub id:         ConnectionId,
+	pub target:     TargetAddr,
+	pub outbound:   String,
+	pub started:    Instant,
+	/// Bytes read from the client
+	pub uploaded:   u64,
+	/// Bytes written to the client
+	pub downloaded: u64,
+}
+
+struct Entry {
+	target:     TargetAddr,
+	outbound:   String,
+	started:    Instant,
+	uploaded:   AtomicU64,
+	downloaded: AtomicU64,
+	cancel:     CancellationToken,
+}
+
+type Entries = Arc<Mutex<HashMap<ConnectionId, Arc<Entry>>>>;
+
+/// Active connections by id, refusing new ones beyond the configured maximum
+///
+/// Entries live as long as the [`ConnectionGuard`] returned by
+/// [`register`](Self::register), so the table never outgrows the cap. Both
+/// ends of an entry are published as [`Event`]s.
+pub struct ConnectionRegistry {
+	max:     AtomicUsize,
+	next_id: AtomicU64,
+	entries: Entries,
+	events:  EventBus,
+}
+
+impl Default for ConnectionRegistry {
+	fn default() -> Self {
+		Self::new(None)
+	}
+}
+
+impl ConnectionRegistry {
+	pub fn new(max_connections: Option<usize>) -> Self {
+		Self {
+			max:     AtomicUsize::new(max_connections.unwrap_or(usize::MAX)),
+			next_id: AtomicU64::new(0),
+			entries: Entries::default(),
+			events:  EventBus::default(),
+		}
+	}
+
+	/// Publish openings and closings on `events` rather than a bus of its own
+	pub fn with_events(mut self, events: EventBus) -> Self {
+		self.events = events;
+		self
+	}
+
+	/// Change the cap, `None` lifts it. Open connections are kept either way
+	pub fn set_max_connections(&self, max_connections: Option<usize>) {
+		self.max.store(max_connections.unwrap_or(usize::MAX), Ordering::Relaxed);
+	}
+
+	/// Record a connection to `target` through `outbound`, failing when the
+	/// cap is reached
+	pub fn register(&self, target: TargetAddr, outbound: impl Into<String>) -> eyre::Result<ConnectionGuard> {
+		let mut entries = self.entries.lock().unwrap();
+		let max = self.max.load(Ordering::Relaxed);
+		if entries.len() >= max {
+			eyre::bail!("connection limit of {max} reached");
+		}
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let entry = Arc::new(Entry {
+			target,
+			outbound: outbound.into(),
+			started: Instant::now(),
+			uploaded: AtomicU64::new(0),
+			downloaded: AtomicU64::new(0),
+			cancel: CancellationToken::new(),
+		});
+		entries.insert(id, entry.clone());
+		self.events.publish(Event::ConnectionOpened {
+			id,
+			target: entry.target.clone(),
+			outbound: entry.outbound.clone(),
+		});
+		Ok(ConnectionGuard {
+			id,
+			entry,
+			entries: self.entries.clone(),
+			events: self.events.clone(),
+		})
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.lock().unwrap().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn get(&self, id: ConnectionId) -> Option<ConnectionInfo> {
+		self.entries.lock().unwrap().get(&id).map(|entry| entry.info(id))
+	}
+
+	/// Every open connection, in no particular order
+	pub fn snapshot(&self) -> Vec<ConnectionInfo> {
+		self.entries
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|(id, entry)| entry.info(*id))
+			.collect()
+	}
+
+	/// Ask the relay of connection `id` to stop, returning whether it was open
+	pub fn kill(&self, id: ConnectionId) -> bool {
+		match self.entries.lock().unwrap().get(&id) {
+			Some(entry) => {
+				entry.cancel.cancel();
+				true
+			}
+			None => false,
+		}
+	}
+}
+
+impl Entry {
+	fn info(&self, id: ConnectionId) -> ConnectionInfo {
+		ConnectionInfo {
+			id,
+			target: self.target.clone(),
+			outbound: self.outbound.clone(),
+			started: self.started,
+			uploaded: self.uploaded.load(Ordering::Relaxed),
+			downloaded: self.downloaded.load(Ordering::Relaxed),
+		}
+	}
+}
+
+/// A registered connection, removed from the registry on drop
+pub struct ConnectionGuard {
+	id:      ConnectionId,
+	entry:   Arc<Entry>,
+	entries: Entries,
+	events:  EventBus,
+}
+
+impl ConnectionGuard {
+	pub fn id(&self) -> ConnectionId {
+		self.id
+	}
+
+	/// Cancelled when the connection is killed through the registry
+	pub fn token(&self) -> CancellationToken {
+		self.entry.cancel.clone()
+	}
+
+	/// Wrap the client stream so the bytes relayed over it are counted
+	pub fn track<S>(&self, inner: S) -> TrackedStream<S> {
+		TrackedStream {
+			inner,
+			entry: self.entry.clone(),
+		}
+	}
+}
+
+impl Drop for ConnectionGuard {
+	fn drop(&mut self) {
+		self.entries.lock().unwrap().remove(&self.id);
+		self.events.publish(Event::ConnectionClosed {
+			id:         self.id,
+			bytes_up:   self.entry.uploaded.load(Ordering::Relaxed),
+			bytes_down: self.entry.downloaded.load(Ordering::Relaxed),
+		});
+	}
+}
+
+/// Client stream counting into its registry entry
+pub struct TrackedStream<S> {
+	inner: S,
+	entry: Arc<Entry>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TrackedStream<S> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		let before = buf.filled().len();
+		let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+		let read = buf.filled().len() - before;
+		this.entry.uploaded.fetch_add(read as u64, Ordering::Relaxed);
+		res
+	}
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TrackedStream<S> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+		if let Poll::Ready(Ok(written)) = res {
+			this.entry.downloaded.fetch_add(written as u64, Ordering::Relaxed);
+		}
+		res
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+	}
+}
+
+impl<S: AbstractTcpStream> AbstractTcpStream for TrackedStream<S> {
+	fn on_connect(&mut self, result: Result<(), ConnectError>) -> impl Future<Output = io::Result<()>> + Send + Sync {
+		self.inner.on_connect(result)
+	}
+
+	fn client_addr(&self) -> Option<SocketAddr> {
+		self.inner.client_addr()
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		self.inner.local_addr()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	use super::*;
+
+	#[test]
+	fn test_reject_beyond_max_connections() {
+		let registry = ConnectionRegistry::new(Some(2));
+		let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 80);
+		let first = registry.register(target.clone(), "proxy").unwrap();
+		let _second = registry.register(target.clone(), "direct").unwrap();
+
+		let err = registry.register(target.clone(), "proxy").err().unwrap();
+		assert!(err.to_string().contains("limit of 2"), "{err}");
+		assert_eq!(registry.len(), 2);
+
+		// Closing one makes room again
+		assert!(registry.kill(first.id()));
+		assert!(first.token().is_cancelled());
+		drop(first);
+		assert_eq!(registry.len(), 1);
+		registry.register(target, "proxy").unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_track_counts_bytes() {
+		let registry = ConnectionRegistry::default();
+		let guard = registry
+			.register(TargetAddr::Domain("example.com".into(), 443), "proxy")
+			.unwrap();
+		let (mut client, server) = tokio::io::duplex(64);
+		let mut tracked = guard.track(server);
+
+		client.write_all(b"hello").await.unwrap();
+		let mut buf = [0u8; 5];
+		tracked.read_exact(&mut buf).await.unwrap();
+		tracked.write_all(b"hi").await.unwrap();
+
+		let info = registry.get(guard.id()).unwrap();
+		assert_eq!((info.uploaded, info.downloaded), (5, 2));
+		assert_eq!(info.outbound, "proxy");
+	}
+
+	#[tokio::test]
+	async fn test_relay_publishes_events() {
+		let events = EventBus::default();
+		let mut rx = events.subscribe();
+		let registry = ConnectionRegistry::default().with_events(events);
+		let target = TargetAddr::Domain("example.com".into(), 443);
+		let guard = registry.register(target.clone(), "proxy").unwrap();
+		let id = guard.id();
+		assert_eq!(
+			rx.recv().await.unwrap(),
+			Event::ConnectionOpened {
+				id,
+				target,
+				outbound: "proxy".into(),
+			}
+		);
+
+		let (mut client, server) = tokio::io::duplex(64);
+		let mut tracked = guard.track(server);
+		client.write_all(b"ping").await.unwrap();
+		let mut buf = [0u8; 4];
+		tracked.read_exact(&mut buf).await.unwrap();
+		tracked.write_all(b"pong!").await.unwrap();
+		drop(tracked);
+		drop(guard);
+
+		assert_eq!(
+			rx.recv().await.unwrap(),
+			Event::ConnectionClosed {
+				id,
+				bytes_up: 4,
+				bytes_down: 5,
+			}
+		);
+	}
+}
@@ -0,0 +1,211 @@
+//! [`tower::Service`] adapter for outbounds, so they compose with Tower
+//! middleware such as timeouts, retries or rate limits
+
+use std::{
+	io,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use tokio::{
+	io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf},
+	sync::oneshot,
+};
+use tower::Service;
+
+use crate::{
+	AbstractOutbound,
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+};
+
+/// Buffer between the caller and the relay task, per direction
+const DEFAULT_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Asks the outbound to open a TCP relay to `target_addr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectRequest {
+	pub target_addr: TargetAddr,
+}
+
+impl From<TargetAddr> for ConnectRequest {
+	fn from(target_addr: TargetAddr) -> Self {
+		Self { target_addr }
+	}
+}
+
+/// Exposes an outbound as a `Service<ConnectRequest>`
+///
+/// Each call spawns the outbound's `handle_tcp` on one end of an in-memory
+/// pipe and resolves with the other end once the outbound reports the
+/// upstream through [`AbstractTcpStream::on_connect`]. Outbounds that never
+/// report only resolve when their relay ends.
+pub struct OutboundService<O> {
+	outbound:    Arc<O>,
+	buffer_size: usize,
+}
+
+impl<O> OutboundService<O> {
+	pub fn new(outbound: O) -> Self {
+		Self::from_arc(Arc::new(outbound))
+	}
+
+	pub fn from_arc(outbound: Arc<O>) -> Self {
+		Self {
+			outbound,
+			buffer_size: DEFAULT_BUFFER_SIZE,
+		}
+	}
+
+	pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+		self.buffer_size = buffer_size;
+		self
+	}
+}
+
+impl<O> Clone for OutboundService<O> {
+	fn clone(&self) -> Self {
+		Self {
+			outbound:    self.outbound.clone(),
+			buffer_size: self.buffer_size,
+		}
+	}
+}
+
+impl<O: AbstractOutbound + Send + Sync + 'static> Service<ConnectRequest> for OutboundService<O> {
+	type Error = eyre::Report;
+	type Future = Pin<Box<dyn Future<Output = eyre::Result<RelayStream>> + Send>>;
+	type Response = RelayStream;
+
+	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, req: ConnectRequest) -> Self::Future {
+		let (client, inner) = tokio::io::duplex(self.buffer_size);
+		let (connected, on_connect) = oneshot::channel();
+		let outbound = self.outbound.clone();
+		let relay = tokio::spawn(async move {
+			let stream = SignalStream {
+				inner,
+				connected: Some(connected),
+			};
+			outbound.handle_tcp(req.target_addr, stream, None::<O>).await
+		});
+
+		Box::pin(async move {
+			match on_connect.await {
+				Ok(Ok(())) => Ok(RelayStream { inner: client }),
+				Ok(Err(err)) => Err(eyre::eyre!("upstream unreachable: {err:?}")),
+				// The outbound dropped the stream without reporting
+				Err(_) => match relay.await? {
+					Ok(()) => Err(eyre::eyre!("outbound closed the relay before connecting")),
+					Err(err) => Err(err),
+				},
+			}
+		})
+	}
+}
+
+/// Caller side of a relay opened through [`OutboundService`]
+///
+/// Dropping it closes the relay.
+#[derive(Debug)]
+pub struct RelayStream {
+	inner: DuplexStream,
+}
+
+impl AsyncRead for RelayStream {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for RelayStream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+	}
+}
+
+impl AbstractTcpStream for RelayStream {}
+
+/// Outbound side of the relay, handing the connect result to the caller
+struct SignalStream {
+	inner:     DuplexStream,
+	connected: Option<oneshot::Sender<Result<(), ConnectError>>>,
+}
+
+impl AsyncRead for SignalStream {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for SignalStream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+	}
+}
+
+impl AbstractTcpStream for SignalStream {
+	async fn on_connect(&mut self, result: Result<(), ConnectError>) -> io::Result<()> {
+		if let Some(connected) = self.connected.take() {
+			// The caller may have given up already
+			let _ = connected.send(result);
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::{
+		io::{AsyncReadExt, AsyncWriteExt},
+		net::TcpListener,
+	};
+	use tower::ServiceExt;
+
+	use super::*;
+	use crate::DirectOutbound;
+
+	#[tokio::test]
+	async fn test_relay_through_service() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut upstream, _) = listener.accept().await.unwrap();
+			let mut buf = [0u8; 4];
+			upstream.read_exact(&mut buf).await.unwrap();
+			upstream.write_all(&buf).await.unwrap();
+		});
+
+		let service = OutboundService::new(DirectOutbound::new());
+		let mut stream = service.clone().oneshot(TargetAddr::from(addr).into()).await.unwrap();
+		stream.write_all(b"ping").await.unwrap();
+		let mut buf = [0u8; 4];
+		stream.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"ping");
+
+		// Nobody listens there anymore
+		let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let err = service.oneshot(TargetAddr::from(closed).into()).await.unwrap_err();
+		assert!(err.to_string().contains("ConnectionRefused"), "{err}");
+	}
+}
@@ -0,0 +1,118 @@
+//! systemd socket activation: listeners opened by the service manager and
+//! passed down through `LISTEN_FDS`, so restarts never drop the port
+
+use std::{
+	io,
+	net::SocketAddr,
+	os::fd::{FromRawFd as _, RawFd},
+	sync::{
+		Mutex,
+		atomic::{AtomicBool, Ordering},
+	},
+};
+
+use socket2::{Socket, Type};
+
+/// First inherited descriptor, after stdin, stdout and stderr
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Inherited sockets no inbound has claimed yet
+static INHERITED: Mutex<Vec<Socket>> = Mutex::new(Vec::new());
+/// Set once the inherited descriptors are owned by [`INHERITED`]
+static ACTIVATED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the service manager passed sockets to this process
+pub fn is_activated() -> bool {
+	is_this_process(std::env::var("LISTEN_PID").ok().as_deref())
+}
+
+fn is_this_process(listen_pid: Option<&str>) -> bool {
+	listen_pid.is_some_and(|pid| pid == std::process::id().to_string())
+}
+
+/// Take over the sockets passed by the service manager, returning how many
+/// there are
+///
+/// Inbounds then claim them by address through [`take_tcp_listener`] and
+/// [`take_udp_socket`] instead of binding. Only the first call takes them,
+/// later ones fail. The environment is left alone: `LISTEN_PID` doesn't name
+/// children, and the sockets are closed on exec.
+pub fn activate() -> io::Result<usize> {
+	activate_from(
+		SD_LISTEN_FDS_START,
+		std::env::var("LISTEN_PID").ok().as_deref(),
+		std::env::var("LISTEN_FDS").ok().as_deref(),
+	)
+}
+
+fn activate_from(start: RawFd, listen_pid: Option<&str>, listen_fds: Option<&str>) -> io::Result<usize> {
+	if !is_this_process(listen_pid) {
+		return Err(io::Error::new(io::ErrorKind::NotFound, "LISTEN_PID is not this process"));
+	}
+	let count: RawFd = listen_fds
+		.and_then(|count| count.parse().ok())
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "LISTEN_FDS is missing or malformed"))?;
+	if ACTIVATED.swap(true, Ordering::AcqRel) {
+		return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already activated"));
+	}
+
+	let mut inherited = INHERITED.lock().unwrap();
+	for fd in start..start + count {
+		// SAFETY: systemd hands these descriptors over to this process, nothing
+		// else owns them, and `ACTIVATED` keeps them from being taken twice
+		let socket = unsafe { Socket::from_raw_fd(fd) };
+		// Children must not take the sockets for their own
+		socket.set_cloexec(true)?;
+		inherited.push(socket);
+	}
+	Ok(count as usize)
+}
+
+fn take(ty: Type, addr: SocketAddr) -> io::Result<Option<Socket>> {
+	let mut inherited = INHERITED.lock().unwrap();
+	let Some(index) = inherited.iter().position(|socket| {
+		socket.r#type().is_ok_and(|socket_ty| socket_ty == ty)
+			&& socket.local_addr().ok().and_then(|local| local.as_socket()) == Some(addr)
+	}) else {
+		return Ok(None);
+	};
+	let socket = inherited.swap_remove(index);
+	socket.set_nonblocking(true)?;
+	Ok(Some(socket))
+}
+
+/// The inherited stream socket bound to `addr`, if any, set non-blocking
+pub fn take_tcp_listener(addr: SocketAddr) -> io::Result<Option<std::net::TcpListener>> {
+	Ok(take(Type::STREAM, addr)?.map(Into::into))
+}
+
+/// The inherited datagram socket bound to `addr`, if any, set non-blocking
+pub fn take_udp_socket(addr: SocketAddr) -> io::Result<Option<std::net::UdpSocket>> {
+	Ok(take(Type::DGRAM, addr)?.map(Into::into))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::fd::IntoRawFd as _;
+
+	use super::*;
+
+	#[test]
+	fn test_adopt_inherited_listener() {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let fd = listener.into_raw_fd();
+		let pid = std::process::id().to_string();
+
+		assert!(activate_from(fd, Some("1"), Some("1")).is_err());
+		assert_eq!(activate_from(fd, Some(&pid), Some("1")).unwrap(), 1);
+		assert!(activate_from(fd, Some(&pid), Some("1")).is_err());
+
+		// Only a stream socket on that address qualifies
+		assert!(take_udp_socket(addr).unwrap().is_none());
+		let adopted = take_tcp_listener(addr).unwrap().unwrap();
+		assert_eq!(adopted.local_addr().unwrap(), addr);
+		std::net::TcpStream::connect(addr).unwrap();
+		assert!(take_tcp_listener(addr).unwrap().is_none());
+	}
+}
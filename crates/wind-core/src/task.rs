@@ -0,0 +1,175 @@
+//! Labels for the tasks spawned on [`AppContext::tasks`](crate::AppContext),
+//! so a shutdown that times out can tell which subsystems didn't drain
+
+use std::{
+	collections::BTreeMap,
+	fmt,
+	future::Future,
+	sync::{Arc, Mutex},
+};
+
+type Running = Arc<Mutex<BTreeMap<&'static str, usize>>>;
+
+/// How many tasks are running under each label
+#[derive(Debug, Default)]
+pub struct TaskLabels {
+	running: Running,
+}
+
+impl TaskLabels {
+	/// Count `task` under `label` until it finishes or is dropped
+	pub fn track<F: Future>(&self, label: &'static str, task: F) -> impl Future<Output = F::Output> + use<F> {
+		*self.running.lock().unwrap().entry(label).or_default() += 1;
+		let guard = LabelGuard {
+			running: self.running.clone(),
+			label,
+		};
+		async move {
+			let _guard = guard;
+			task.await
+		}
+	}
+
+	/// Labels with tasks still running, and how many of each
+	pub fn running(&self) -> Vec<(&'static str, usize)> {
+		self.running
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|(label, count)| (*label, *count))
+			.collect()
+	}
+}
+
+struct LabelGuard {
+	running: Running,
+	label:   &'static str,
+}
+
+impl Drop for LabelGuard {
+	fn drop(&mut self) {
+		let mut running = self.running.lock().unwrap();
+		if let Some(count) = running.get_mut(self.label) {
+			*count -= 1;
+			if *count == 0 {
+				running.remove(self.label);
+			}
+		}
+	}
+}
+
+/// How [`AppContext::spawn_critical`](crate::AppContext::spawn_critical)
+/// reacts to its task ending with an error, which it logs either way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFailure {
+	/// Carry on without the task
+	Log,
+	/// Cancel [`AppContext::token`](crate::AppContext::token), stopping
+	/// everything rather than running half-dead
+	Shutdown,
+}
+
+/// What became of the tasks when shutting down
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+	/// Tasks that finished while waiting
+	pub completed: usize,
+	/// Tasks still running when the wait timed out
+	pub running:   usize,
+	/// Labels of the tasks still running, with how many of each. Tasks spawned
+	/// without a label are only part of `running`
+	pub stuck:     Vec<(&'static str, usize)>,
+}
+
+impl ShutdownReport {
+	/// Whether every task finished in time
+	pub fn is_clean(&self) -> bool {
+		self.running == 0
+	}
+}
+
+impl fmt::Display for ShutdownReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} task(s) finished, {} still running", self.completed, self.running)?;
+		if self.is_clean() {
+			return Ok(());
+		}
+		let mut unlabelled = self.running;
+		let mut sep = ": ";
+		for (label, count) in &self.stuck {
+			write!(f, "{sep}{label} ({count})")?;
+			unlabelled = unlabelled.saturating_sub(*count);
+			sep = ", ";
+		}
+		if unlabelled > 0 {
+			write!(f, "{sep}unlabelled ({unlabelled})")?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+	use crate::{AppContext, test_util::LogCapture};
+
+	#[tokio::test]
+	async fn test_report_names_stuck_tasks() {
+		let ctx = AppContext::default();
+		ctx.spawn("finishes", async {});
+		ctx.spawn("hangs", std::future::pending::<()>());
+		ctx.spawn("hangs", std::future::pending::<()>());
+		ctx.tasks.spawn(std::future::pending::<()>());
+		tokio::task::yield_now().await;
+		ctx.spawn("finishes", tokio::time::sleep(Duration::from_millis(10)));
+
+		let report = ctx.drain_tasks(Duration::from_millis(100)).await;
+		assert!(!report.is_clean());
+		assert_eq!(report.completed, 1);
+		assert_eq!(report.running, 3);
+		assert_eq!(report.stuck, vec![("hangs", 2)]);
+		assert_eq!(
+			report.to_string(),
+			"1 task(s) finished, 3 still running: hangs (2), unlabelled (1)"
+		);
+
+		let ctx = AppContext::default();
+		ctx.spawn("finishes", async {});
+		let report = ctx.drain_tasks(Duration::from_millis(100)).await;
+		assert!(report.is_clean());
+		assert_eq!(report.to_string(), "1 task(s) finished, 0 still running");
+	}
+
+	#[tokio::test]
+	async fn test_failed_critical_task() {
+		let (captured, _guard) = LogCapture::install(tracing::Level::INFO);
+
+		let ctx = AppContext::default();
+		let res = ctx
+			.spawn_critical("poller", OnFailure::Log, async { eyre::bail!("connection lost") })
+			.await
+			.unwrap();
+		assert!(res.is_err());
+		assert!(!ctx.token.is_cancelled());
+		ctx.spawn_critical("quiet", OnFailure::Shutdown, async { Ok(()) })
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(!ctx.token.is_cancelled());
+
+		let res = ctx
+			.spawn_critical("poller", OnFailure::Shutdown, async { eyre::bail!("endpoint closed") })
+			.await
+			.unwrap();
+		assert!(res.is_err());
+		assert!(ctx.token.is_cancelled());
+		assert!(ctx.listen_token.is_cancelled());
+
+		let logged = captured.contents();
+		assert!(logged.contains("poller failed: connection lost"), "{logged}");
+		assert!(logged.contains("poller failed, shutting down: endpoint closed"), "{logged}");
+		assert!(!logged.contains("quiet"), "{logged}");
+	}
+}
@@ -1,5 +1,427 @@
-use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::os::fd::AsRawFd;
+use std::{
+	io,
+	net::SocketAddr,
+	pin::Pin,
+	task::{Context, Poll},
+	time::Duration,
+};
 
-pub trait AbstractTcpStream: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+use tokio::{
+	io::{AsyncRead, AsyncWrite, ReadBuf},
+	time::{Instant, Sleep},
+};
 
-impl<T> AbstractTcpStream for T where T: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+pub trait AbstractTcpStream: AsyncRead + AsyncWrite + Send + Sync + Unpin {
+	/// Reports whether the outbound reached the upstream.
+	///
+	/// Outbounds should call this once the upstream is established (or has
+	/// failed) and before relaying any payload, so inbounds whose handshake
+	/// answers the client (e.g. the SOCKS5 reply) can reflect the real outcome.
+	/// Streams without such a handshake ignore it.
+	fn on_connect(&mut self, _result: Result<(), ConnectError>) -> impl Future<Output = io::Result<()>> + Send + Sync {
+		async { Ok(()) }
+	}
+
+	/// Address of the client the inbound accepted this stream from, when it
+	/// has one to tell
+	fn client_addr(&self) -> Option<SocketAddr> {
+		None
+	}
+
+	/// Local address the inbound accepted this stream on, when it has one to
+	/// tell
+	fn local_addr(&self) -> Option<SocketAddr> {
+		None
+	}
+}
+
+impl AbstractTcpStream for tokio::net::TcpStream {
+	fn client_addr(&self) -> Option<SocketAddr> {
+		self.peer_addr().ok()
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		tokio::net::TcpStream::local_addr(self).ok()
+	}
+}
+
+impl AbstractTcpStream for tokio::io::DuplexStream {}
+
+#[cfg(unix)]
+impl AbstractTcpStream for tokio::net::UnixStream {}
+
+impl<T: AbstractTcpStream + ?Sized> AbstractTcpStream for &mut T {
+	fn on_connect(&mut self, result: Result<(), ConnectError>) -> impl Future<Output = io::Result<()>> + Send + Sync {
+		(**self).on_connect(result)
+	}
+
+	fn client_addr(&self) -> Option<SocketAddr> {
+		(**self).client_addr()
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		(**self).local_addr()
+	}
+}
+
+/// Takes any stream where an [`AbstractTcpStream`] is wanted, as one with no
+/// client to answer and no addresses to tell. Streams that have either
+/// implement the trait themselves
+#[derive(Debug)]
+pub struct PlainStream<S>(pub S);
+
+impl<S: AsyncRead + Unpin> AsyncRead for PlainStream<S> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+	}
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PlainStream<S> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().0).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+	}
+}
+
+impl<S: AsyncRead + AsyncWrite + Send + Sync + Unpin> AbstractTcpStream for PlainStream<S> {}
+
+/// Answer the client of `stream` with the failure in `res`, then return it
+///
+/// Callbacks pass what their relay returned through this, so clients of
+/// inbounds with a handshake, eg. SOCKS, get a reply even when the relay
+/// failed before the outbound reported through
+/// [`AbstractTcpStream::on_connect`]. Streams already answered ignore it.
+pub async fn report_failure(stream: &mut impl AbstractTcpStream, res: eyre::Result<()>) -> eyre::Result<()> {
+	if let Err(err) = &res
+		&& let Err(reply_err) = stream.on_connect(Err(ConnectError::from_report(err))).await
+	{
+		crate::debug!(target: "[TCP]", "Failed to report the error to the client: {reply_err}");
+	}
+	res
+}
+
+/// Reason an outbound failed to reach the upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectError {
+	General,
+	NotAllowed,
+	NetworkUnreachable,
+	HostUnreachable,
+	ConnectionRefused,
+	TimedOut,
+}
+
+impl ConnectError {
+	/// Classify an outbound error by the first I/O error in its chain
+	pub fn from_report(err: &eyre::Report) -> Self {
+		err.chain()
+			.find_map(|e| e.downcast_ref::<io::Error>())
+			.map(Self::from)
+			.unwrap_or(Self::General)
+	}
+}
+
+impl From<&io::Error> for ConnectError {
+	fn from(err: &io::Error) -> Self {
+		match err.kind() {
+			io::ErrorKind::ConnectionRefused => Self::ConnectionRefused,
+			io::ErrorKind::HostUnreachable => Self::HostUnreachable,
+			io::ErrorKind::NetworkUnreachable => Self::NetworkUnreachable,
+			io::ErrorKind::TimedOut => Self::TimedOut,
+			io::ErrorKind::PermissionDenied => Self::NotAllowed,
+			_ => Self::General,
+		}
+	}
+}
+
+/// Fails reads or writes on the inner stream that make no progress within a
+/// deadline
+///
+/// The deadline is per operation: it starts when a poll first returns pending
+/// and is cleared as soon as the operation completes.
+pub struct DeadlineStream<S> {
+	inner:         S,
+	read_timeout:  Option<Duration>,
+	write_timeout: Option<Duration>,
+	read_sleep:    Option<Pin<Box<Sleep>>>,
+	write_sleep:   Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> DeadlineStream<S> {
+	pub fn new(inner: S) -> Self {
+		Self {
+			inner,
+			read_timeout: None,
+			write_timeout: None,
+			read_sleep: None,
+			write_sleep: None,
+		}
+	}
+
+	pub fn with_read_timeout(inner: S, timeout: Duration) -> Self {
+		let mut stream = Self::new(inner);
+		stream.set_read_timeout(Some(timeout));
+		stream
+	}
+
+	pub fn with_write_timeout(inner: S, timeout: Duration) -> Self {
+		let mut stream = Self::new(inner);
+		stream.set_write_timeout(Some(timeout));
+		stream
+	}
+
+	pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+		self.read_timeout = timeout;
+		self.read_sleep = None;
+	}
+
+	pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+		self.write_timeout = timeout;
+		self.write_sleep = None;
+	}
+
+	pub fn get_ref(&self) -> &S {
+		&self.inner
+	}
+
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.inner
+	}
+
+	pub fn into_inner(self) -> S {
+		self.inner
+	}
+}
+
+/// Resolve an inner poll against the deadline in `sleep`, arming it on the
+/// first pending poll
+fn poll_deadline<T>(
+	cx: &mut Context<'_>,
+	timeout: Option<Duration>,
+	sleep: &mut Option<Pin<Box<Sleep>>>,
+	poll: Poll<io::Result<T>>,
+) -> Poll<io::Result<T>> {
+	if poll.is_ready() {
+		*sleep = None;
+		return poll;
+	}
+	let Some(timeout) = timeout else {
+		return Poll::Pending;
+	};
+	let deadline = sleep.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(Instant::now() + timeout)));
+	match deadline.as_mut().poll(cx) {
+		Poll::Ready(()) => {
+			*sleep = None;
+			Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "stream operation timed out")))
+		}
+		Poll::Pending => Poll::Pending,
+	}
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeadlineStream<S> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+		poll_deadline(cx, this.read_timeout, &mut this.read_sleep, poll)
+	}
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeadlineStream<S> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+		poll_deadline(cx, this.write_timeout, &mut this.write_sleep, poll)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		let poll = Pin::new(&mut this.inner).poll_flush(cx);
+		poll_deadline(cx, this.write_timeout, &mut this.write_sleep, poll)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		let poll = Pin::new(&mut this.inner).poll_shutdown(cx);
+		poll_deadline(cx, this.write_timeout, &mut this.write_sleep, poll)
+	}
+}
+
+impl<S: AbstractTcpStream> AbstractTcpStream for DeadlineStream<S> {
+	fn on_connect(&mut self, result: Result<(), ConnectError>) -> impl Future<Output = io::Result<()>> + Send + Sync {
+		self.inner.on_connect(result)
+	}
+
+	fn client_addr(&self) -> Option<SocketAddr> {
+		self.inner.client_addr()
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		self.inner.local_addr()
+	}
+}
+
+/// OS-level probing of idle connections, so a silently dead peer ends the
+/// relay instead of holding it open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+	/// Idle time before the first probe
+	pub idle:     Duration,
+	/// Time between unanswered probes
+	pub interval: Duration,
+	/// Unanswered probes after which the connection is dropped
+	pub retries:  u32,
+}
+
+impl Default for KeepaliveConfig {
+	fn default() -> Self {
+		Self {
+			idle:     Duration::from_secs(60),
+			interval: Duration::from_secs(10),
+			retries:  6,
+		}
+	}
+}
+
+/// Enable keepalive on `socket`. Platforms without a knob for the interval
+/// or retry count keep their defaults for it
+pub fn set_keepalive(socket: &tokio::net::TcpStream, config: &KeepaliveConfig) -> io::Result<()> {
+	let keepalive = socket2::TcpKeepalive::new().with_time(config.idle);
+	#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+	let keepalive = keepalive.with_interval(config.interval).with_retries(config.retries);
+	socket2::SockRef::from(socket).set_tcp_keepalive(&keepalive)
+}
+
+/// Handshakes carrying data a TCP Fast Open listener keeps pending
+#[cfg(target_os = "linux")]
+const FAST_OPEN_QUEUE: libc::c_int = 256;
+
+/// Accept TCP Fast Open handshakes on a listening socket
+///
+/// Kernels with TFO disabled (`net.ipv4.tcp_fastopen`) still accept the option
+/// but fall back to the regular handshake.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn set_fast_open_listener(socket: &impl AsRawFd) -> io::Result<()> {
+	#[cfg(target_os = "linux")]
+	let value = FAST_OPEN_QUEUE;
+	// Darwin only takes an on/off switch
+	#[cfg(target_os = "macos")]
+	let value = 1;
+	set_tcp_option(socket, libc::TCP_FASTOPEN, value)
+}
+
+/// Send the first write with the SYN when connecting this socket, once the
+/// peer has handed out a cookie
+///
+/// macOS only supports client TFO through `connectx`, so it is Linux only.
+#[cfg(target_os = "linux")]
+pub fn set_fast_open_connect(socket: &impl AsRawFd) -> io::Result<()> {
+	set_tcp_option(socket, libc::TCP_FASTOPEN_CONNECT, 1)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn set_tcp_option(socket: &impl AsRawFd, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+	// SAFETY: the value outlives the call and its size is passed along
+	let ret = unsafe {
+		libc::setsockopt(
+			socket.as_raw_fd(),
+			libc::IPPROTO_TCP,
+			name,
+			(&raw const value).cast(),
+			size_of::<libc::c_int>() as libc::socklen_t,
+		)
+	};
+	if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::AsyncReadExt;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_stalled_read_times_out() {
+		// The peer is kept alive but never writes
+		let (_peer, stream) = tokio::io::duplex(64);
+		let mut stream = DeadlineStream::with_read_timeout(stream, Duration::from_millis(50));
+
+		let started = Instant::now();
+		let mut buf = [0u8; 8];
+		let err = stream.read(&mut buf).await.unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+		assert!(started.elapsed() >= Duration::from_millis(50));
+	}
+
+	#[cfg(any(target_os = "linux", target_os = "macos"))]
+	#[tokio::test]
+	async fn test_plain_stream_relays() {
+		use crate::{AbstractOutbound, DirectOutbound, types::TargetAddr};
+
+		let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target = TargetAddr::from(upstream.local_addr().unwrap());
+		tokio::spawn(async move {
+			let (mut stream, _) = upstream.accept().await.unwrap();
+			tokio::io::AsyncWriteExt::write_all(&mut stream, b"pong").await.unwrap();
+		});
+
+		// A pair of halves has no impl of its own
+		let (mut client, relayed) = tokio::io::duplex(64);
+		let (read, write) = tokio::io::split(relayed);
+		let stream = PlainStream(tokio::io::join(read, write));
+		assert_eq!(stream.client_addr(), None);
+		tokio::spawn(async move { DirectOutbound::new().handle_tcp(target, stream, None::<DirectOutbound>).await });
+
+		let mut buf = [0u8; 4];
+		client.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"pong");
+	}
+
+	#[tokio::test]
+	async fn test_keepalive_parameters_applied() {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let stream = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+		let config = KeepaliveConfig {
+			idle:     Duration::from_secs(42),
+			interval: Duration::from_secs(7),
+			retries:  3,
+		};
+		set_keepalive(&stream, &config).unwrap();
+
+		let socket = socket2::SockRef::from(&stream);
+		assert!(socket.keepalive().unwrap());
+		assert_eq!(socket.tcp_keepalive_time().unwrap(), config.idle);
+		assert_eq!(socket.tcp_keepalive_interval().unwrap(), config.interval);
+		assert_eq!(socket.tcp_keepalive_retries().unwrap(), config.retries);
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_fast_open_sockopt_set() {
+		fn get(socket: &impl AsRawFd, name: libc::c_int) -> libc::c_int {
+			let mut value: libc::c_int = 0;
+			let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+			// SAFETY: `value` and `len` outlive the call, `len` holds its size
+			let ret =
+				unsafe { libc::getsockopt(socket.as_raw_fd(), libc::IPPROTO_TCP, name, (&raw mut value).cast(), &mut len) };
+			assert_eq!(ret, 0, "{}", io::Error::last_os_error());
+			value
+		}
+
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		set_fast_open_listener(&listener).unwrap();
+		assert_eq!(get(&listener, libc::TCP_FASTOPEN), FAST_OPEN_QUEUE);
+
+		let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+		set_fast_open_connect(&socket).unwrap();
+		assert_eq!(get(&socket, libc::TCP_FASTOPEN_CONNECT), 1);
+	}
+}
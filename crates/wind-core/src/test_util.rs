@@ -0,0 +1,142 @@
+//! Helpers shared by the tests of the wind crates, with the `test-util`
+//! feature
+
+use std::{
+	io::{self, IoSliceMut},
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+};
+
+use tokio::{
+	io::{AsyncReadExt as _, AsyncWriteExt as _},
+	net::{TcpStream, UdpSocket},
+};
+use tracing::{Level, subscriber::DefaultGuard};
+
+use crate::{
+	AbstractOutbound, DirectOutbound, InboundCallback,
+	tcp::AbstractTcpStream,
+	types::TargetAddr,
+	udp::{AbstractUdpSocket, RecvMeta},
+};
+
+/// Dials TCP targets with [`DirectOutbound`] and relays UDP datagrams from a
+/// loopback socket
+#[derive(Debug, Clone, Default)]
+pub struct DirectCallback;
+
+impl InboundCallback for DirectCallback {
+	async fn handle_tcpstream(&self, target_addr: TargetAddr, stream: impl AbstractTcpStream + 'static) -> eyre::Result<()> {
+		DirectOutbound::new()
+			.handle_tcp(target_addr, stream, None::<DirectOutbound>)
+			.await
+	}
+
+	async fn handle_udpsocket(&self, socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+		// Relay each datagram to its target, and what comes back to the client.
+		// Spawned since the callback's future must be `Sync`
+		tokio::spawn(async move {
+			let upstream = UdpSocket::bind("127.0.0.1:0").await?;
+			let (mut buf, mut reply) = (vec![0u8; 65536], vec![0u8; 65536]);
+			let mut meta = [RecvMeta::default()];
+			let closed = socket.association_token();
+			loop {
+				let mut bufs = [IoSliceMut::new(&mut buf)];
+				tokio::select! {
+					_ = closed.cancelled() => return Ok(()),
+					received = socket.recv(&mut bufs, &mut meta) => {
+						received?;
+						let target: SocketAddr = meta[0].destination.as_ref().unwrap().to_string().parse()?;
+						upstream.send_to(&buf[..meta[0].len], target).await?;
+					}
+					received = upstream.recv_from(&mut reply) => {
+						let (len, from) = received?;
+						socket.send(&reply[..len], from).await?;
+					}
+				}
+			}
+		})
+		.await?
+	}
+}
+
+/// Collects the formatted log lines of the thread it is installed on
+#[derive(Clone, Default)]
+pub struct LogCapture(Arc<Mutex<Vec<u8>>>);
+
+impl LogCapture {
+	/// Capture the events up to `level` until the guard is dropped
+	pub fn install(level: Level) -> (Self, DefaultGuard) {
+		let logs = Self::default();
+		let subscriber = tracing_subscriber::fmt()
+			.with_max_level(level)
+			.with_ansi(false)
+			.with_writer({
+				let logs = logs.clone();
+				move || logs.clone()
+			})
+			.finish();
+		(logs, tracing::subscriber::set_default(subscriber))
+	}
+
+	/// Everything logged so far
+	pub fn contents(&self) -> String {
+		String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+	}
+}
+
+impl io::Write for LogCapture {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.lock().unwrap().extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Greet the SOCKS5 proxy at `proxy` without authentication and send it
+/// `command` for `target`, returning the stream and the reply code
+pub async fn socks5_request(proxy: SocketAddr, command: u8, target: SocketAddr) -> io::Result<(TcpStream, u8)> {
+	let mut stream = TcpStream::connect(proxy).await?;
+	stream.write_all(&[5, 1, 0]).await?;
+	let mut method = [0u8; 2];
+	stream.read_exact(&mut method).await?;
+	if method != [5, 0] {
+		return Err(io::Error::other(format!("proxy chose method {method:?}")));
+	}
+
+	let mut req = vec![5, command, 0];
+	match target {
+		SocketAddr::V4(addr) => {
+			req.push(1);
+			req.extend_from_slice(&addr.ip().octets());
+		}
+		SocketAddr::V6(addr) => {
+			req.push(4);
+			req.extend_from_slice(&addr.ip().octets());
+		}
+	}
+	req.extend_from_slice(&target.port().to_be_bytes());
+	stream.write_all(&req).await?;
+
+	// VER, REP, RSV, ATYP, then the bound address and port
+	let mut head = [0u8; 4];
+	stream.read_exact(&mut head).await?;
+	let addr_len = match head[3] {
+		1 => 4,
+		4 => 16,
+		3 => stream.read_u8().await? as usize,
+		atyp => return Err(io::Error::other(format!("unknown address type {atyp} in reply"))),
+	};
+	let mut bound = vec![0u8; addr_len + 2];
+	stream.read_exact(&mut bound).await?;
+	Ok((stream, head[1]))
+}
+
+/// Open a stream to `target` through the SOCKS5 proxy at `proxy`, returning
+/// it with the reply code
+pub async fn socks5_connect(proxy: SocketAddr, target: SocketAddr) -> io::Result<(TcpStream, u8)> {
+	socks5_request(proxy, 1, target).await
+}
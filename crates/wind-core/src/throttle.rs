@@ -0,0 +1,230 @@
+//! Bandwidth caps on relayed connections, enforced by delaying I/O once a
+//! token bucket runs dry
+
+use std::{
+	future::Future,
+	io,
+	net::SocketAddr,
+	pin::Pin,
+	sync::{Arc, Mutex},
+	task::{Context, Poll, ready},
+	time::Duration,
+};
+
+use tokio::{
+	io::{AsyncRead, AsyncWrite, ReadBuf},
+	time::{Instant, Sleep},
+};
+
+use crate::tcp::{AbstractTcpStream, ConnectError};
+
+/// Token bucket refilled at `rate` bytes per second, holding up to a second's
+/// worth
+///
+/// Bytes are charged after they were moved, so the bucket can go into debt
+/// by a buffer's worth. Whoever charged next waits the debt off, which keeps
+/// the average at `rate` however many streams share the bucket.
+#[derive(Debug)]
+pub struct RateLimiter {
+	rate:   f64,
+	bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+	tokens: f64,
+	filled: Instant,
+}
+
+impl RateLimiter {
+	pub fn new(bytes_per_sec: u64) -> Self {
+		let rate = bytes_per_sec.max(1) as f64;
+		Self {
+			rate,
+			bucket: Mutex::new(Bucket {
+				tokens: rate,
+				filled: Instant::now(),
+			}),
+		}
+	}
+
+	/// Charge `bytes`, returning how long to hold off before moving more
+	pub fn consume(&self, bytes: usize) -> Duration {
+		let mut bucket = self.bucket.lock().unwrap();
+		let now = Instant::now();
+		let refill = now.duration_since(bucket.filled).as_secs_f64() * self.rate;
+		bucket.tokens = (bucket.tokens + refill).min(self.rate) - bytes as f64;
+		bucket.filled = now;
+		if bucket.tokens >= 0.0 {
+			Duration::ZERO
+		} else {
+			Duration::from_secs_f64(-bucket.tokens / self.rate)
+		}
+	}
+}
+
+/// Limits in bytes per second, each direction counted on its own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+	/// Shared by all connections
+	pub global:         Option<u64>,
+	pub per_connection: Option<u64>,
+}
+
+/// Hands out [`ThrottledStream`]s drawing on the global buckets
+#[derive(Debug, Default)]
+pub struct Throttle {
+	per_connection: Option<u64>,
+	upload:         Option<Arc<RateLimiter>>,
+	download:       Option<Arc<RateLimiter>>,
+}
+
+impl Throttle {
+	pub fn new(config: RateLimitConfig) -> Self {
+		Self {
+			per_connection: config.per_connection,
+			upload:         config.global.map(|rate| Arc::new(RateLimiter::new(rate))),
+			download:       config.global.map(|rate| Arc::new(RateLimiter::new(rate))),
+		}
+	}
+
+	/// Throttle a client stream, whose reads are uploads and writes downloads
+	pub fn wrap<S>(&self, inner: S) -> ThrottledStream<S> {
+		let own = |global: &Option<Arc<RateLimiter>>| {
+			let mut limiters: Vec<_> = global.iter().cloned().collect();
+			limiters.extend(self.per_connection.map(|rate| Arc::new(RateLimiter::new(rate))));
+			limiters
+		};
+		ThrottledStream {
+			inner,
+			read: own(&self.upload),
+			write: own(&self.download),
+			read_sleep: None,
+			write_sleep: None,
+		}
+	}
+}
+
+/// Stream whose reads and writes pause while any of their buckets is in debt
+pub struct ThrottledStream<S> {
+	inner:       S,
+	read:        Vec<Arc<RateLimiter>>,
+	write:       Vec<Arc<RateLimiter>>,
+	read_sleep:  Option<Pin<Box<Sleep>>>,
+	write_sleep: Option<Pin<Box<Sleep>>>,
+}
+
+/// Wait out a pending delay before the next operation
+fn poll_delay(cx: &mut Context<'_>, sleep: &mut Option<Pin<Box<Sleep>>>) -> Poll<()> {
+	if let Some(delay) = sleep {
+		ready!(delay.as_mut().poll(cx));
+		*sleep = None;
+	}
+	Poll::Ready(())
+}
+
+/// Charge `bytes` to every limiter and arm the longest delay they ask for
+fn charge(limiters: &[Arc<RateLimiter>], bytes: usize, sleep: &mut Option<Pin<Box<Sleep>>>) {
+	if bytes == 0 {
+		return;
+	}
+	let delay = limiters
+		.iter()
+		.map(|limiter| limiter.consume(bytes))
+		.max()
+		.unwrap_or_default();
+	if !delay.is_zero() {
+		*sleep = Some(Box::pin(tokio::time::sleep(delay)));
+	}
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(poll_delay(cx, &mut this.read_sleep));
+		let before = buf.filled().len();
+		ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+		charge(&this.read, buf.filled().len() - before, &mut this.read_sleep);
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		ready!(poll_delay(cx, &mut this.write_sleep));
+		let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+		charge(&this.write, written, &mut this.write_sleep);
+		Poll::Ready(Ok(written))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+	}
+}
+
+impl<S: AbstractTcpStream> AbstractTcpStream for ThrottledStream<S> {
+	fn on_connect(&mut self, result: Result<(), ConnectError>) -> impl Future<Output = io::Result<()>> + Send + Sync {
+		self.inner.on_connect(result)
+	}
+
+	fn client_addr(&self) -> Option<SocketAddr> {
+		self.inner.client_addr()
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		self.inner.local_addr()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	use super::*;
+
+	#[test]
+	fn test_limiter_debt() {
+		let limiter = RateLimiter::new(1000);
+		assert_eq!(limiter.consume(1000), Duration::ZERO);
+		let delay = limiter.consume(500);
+		assert!(
+			delay > Duration::from_millis(450) && delay <= Duration::from_millis(500),
+			"{delay:?}"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_transfer_takes_expected_time() {
+		const RATE: u64 = 100_000;
+		let throttle = Throttle::new(RateLimitConfig {
+			global:         Some(RATE * 4),
+			per_connection: Some(RATE),
+		});
+		let (client, server) = tokio::io::duplex(8192);
+		let mut server = throttle.wrap(server);
+
+		// The first second's worth comes out of the full bucket, the second
+		// has to wait for it to refill
+		let started = Instant::now();
+		let writer = tokio::spawn(async move {
+			let mut client = client;
+			client.write_all(&vec![7u8; 2 * RATE as usize]).await.unwrap();
+		});
+		let mut received = 0;
+		let mut buf = [0u8; 8192];
+		while received < 2 * RATE as usize {
+			received += server.read(&mut buf).await.unwrap();
+		}
+		writer.await.unwrap();
+		let elapsed = started.elapsed();
+		assert!(
+			elapsed > Duration::from_millis(850) && elapsed < Duration::from_millis(1500),
+			"{elapsed:?}"
+		);
+	}
+}
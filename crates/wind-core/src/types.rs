@@ -1,10 +1,14 @@
 use std::{
 	fmt::Display,
-	net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	str::FromStr,
 };
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+/// Longest domain, once ASCII-encoded, the address codecs can carry
+pub const MAX_DOMAIN_LEN: usize = u8::MAX as usize;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum TargetAddr {
 	Domain(String, u16),
@@ -12,6 +16,29 @@ pub enum TargetAddr {
 	IPv6(Ipv6Addr, u16),
 }
 
+impl TargetAddr {
+	/// Target `host` on `port`, `host` being an IP address (IPv6 optionally in
+	/// brackets) or else a domain, which gets normalized
+	pub fn new(host: impl AsRef<str>, port: u16) -> Result<Self, InvalidDomain> {
+		let host = host.as_ref();
+		let ip = host.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')).unwrap_or(host);
+		Ok(match ip.parse::<IpAddr>() {
+			Ok(IpAddr::V4(ip)) => TargetAddr::IPv4(ip, port),
+			Ok(IpAddr::V6(ip)) => TargetAddr::IPv6(ip, port),
+			Err(_) => TargetAddr::Domain(normalize_domain(host)?, port),
+		})
+	}
+
+	/// The socket address of an IP target, `None` for a domain
+	pub fn to_socket_addr(&self) -> Option<SocketAddr> {
+		match self {
+			TargetAddr::Domain(..) => None,
+			TargetAddr::IPv4(ip, port) => Some(SocketAddr::from((*ip, *port))),
+			TargetAddr::IPv6(ip, port) => Some(SocketAddr::from((*ip, *port))),
+		}
+	}
+}
+
 impl From<SocketAddr> for TargetAddr {
 	fn from(addr: SocketAddr) -> Self {
 		match addr {
@@ -31,6 +58,129 @@ impl Display for TargetAddr {
 	}
 }
 
+/// `addr` with an IPv4-mapped IPv6 address turned back into IPv4, as
+/// dual-stack sockets report their IPv4 peers
+pub fn unmap_v4(addr: SocketAddr) -> SocketAddr {
+	match addr {
+		SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+			Some(ip) => SocketAddr::from((ip, v6.port())),
+			None => addr,
+		},
+		SocketAddr::V4(_) => addr,
+	}
+}
+
+/// Encode `domain` the way it's matched and sent: IDNA to ASCII (Punycode),
+/// lowercased, without a trailing dot
+pub fn normalize_domain(domain: &str) -> Result<String, InvalidDomain> {
+	let trimmed = domain.strip_suffix('.').unwrap_or(domain);
+	if trimmed.is_empty() {
+		return Err(InvalidDomain::Empty);
+	}
+	// The URL deny list keeps out what can't be in a host, still allowing `_`
+	let ascii = idna::domain_to_ascii_cow(trimmed.as_bytes(), idna::AsciiDenyList::URL)
+		.map_err(|_| InvalidDomain::Idna(domain.to_owned()))?
+		.into_owned();
+	if ascii.len() > MAX_DOMAIN_LEN {
+		return Err(InvalidDomain::TooLong {
+			domain: domain.to_owned(),
+			len:    ascii.len(),
+		});
+	}
+	Ok(ascii)
+}
+
+/// Why a domain can't be a target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidDomain {
+	Empty,
+	/// Not a valid domain name under IDNA (UTS #46)
+	Idna(String),
+	/// Longer than [`MAX_DOMAIN_LEN`] bytes once ASCII-encoded
+	TooLong {
+		domain: String,
+		len:    usize,
+	},
+}
+
+impl Display for InvalidDomain {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			InvalidDomain::Empty => f.write_str("Empty domain"),
+			InvalidDomain::Idna(domain) => write!(f, "Invalid domain name {domain:?}"),
+			InvalidDomain::TooLong { domain, len } => write!(
+				f,
+				"Domain {domain:?} is {len} bytes once encoded, at most {MAX_DOMAIN_LEN} fit"
+			),
+		}
+	}
+}
+
+impl std::error::Error for InvalidDomain {}
+
+/// Why a string isn't a `host:port` or `[IPv6]:port` target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseTargetAddrError {
+	Format(&'static str),
+	Domain(InvalidDomain),
+}
+
+impl Display for ParseTargetAddrError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ParseTargetAddrError::Format(reason) => f.write_str(reason),
+			ParseTargetAddrError::Domain(err) => err.fmt(f),
+		}
+	}
+}
+
+impl std::error::Error for ParseTargetAddrError {}
+
+impl From<InvalidDomain> for ParseTargetAddrError {
+	fn from(err: InvalidDomain) -> Self {
+		ParseTargetAddrError::Domain(err)
+	}
+}
+
+impl FromStr for TargetAddr {
+	type Err = ParseTargetAddrError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let parse_port = |port: &str| {
+			port.parse::<u16>()
+				.map_err(|_| ParseTargetAddrError::Format("Invalid port number"))
+		};
+
+		// IPv6 addresses come with brackets, [IPv6]:port
+		if let Some(rest) = s.strip_prefix('[') {
+			let (ip, port) = rest.split_once(']').ok_or(ParseTargetAddrError::Format(
+				"Invalid IPv6 address format, missing closing bracket",
+			))?;
+			let port = port.strip_prefix(':').ok_or(ParseTargetAddrError::Format(
+				"Invalid IPv6 address format, expected [IPv6]:port",
+			))?;
+			let ip = ip
+				.parse::<Ipv6Addr>()
+				.map_err(|_| ParseTargetAddrError::Format("Invalid IPv6 address"))?;
+			return Ok(TargetAddr::IPv6(ip, parse_port(port)?));
+		}
+
+		// Otherwise an IPv4 address or a domain
+		match s.split_once(':') {
+			Some((host, port)) if !host.is_empty() && !port.contains(':') => Ok(TargetAddr::new(host, parse_port(port)?)?),
+			_ => Err(ParseTargetAddrError::Format("Invalid address format, expected host:port")),
+		}
+	}
+}
+
+impl TryFrom<&str> for TargetAddr {
+	type Error = ParseTargetAddrError;
+
+	fn try_from(s: &str) -> Result<Self, Self::Error> {
+		s.parse()
+	}
+}
+
 impl Serialize for TargetAddr {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
 	where
@@ -45,63 +195,10 @@ impl<'de> Deserialize<'de> for TargetAddr {
 	where
 		D: Deserializer<'de>,
 	{
-		use serde::de::Error;
-
-		let s = String::deserialize(deserializer)?;
-
-		// Check if this is an IPv6 address with brackets [IPv6]:port
-		if s.starts_with('[') {
-			let end_bracket = match s.find(']') {
-				Some(pos) => pos,
-				None => {
-					return Err(Error::custom("Invalid IPv6 address format, missing closing bracket"));
-				}
-			};
-
-			// Ensure there's a colon after the closing bracket
-			if end_bracket + 1 >= s.len() || !s[end_bracket + 1..].starts_with(':') {
-				return Err(Error::custom("Invalid IPv6 address format, expected [IPv6]:port"));
-			}
-
-			let ipv6_str = &s[1..end_bracket];
-			let port_str = &s[end_bracket + 2..];
-
-			let ipv6_addr = match ipv6_str.parse::<Ipv6Addr>() {
-				Ok(addr) => addr,
-				Err(_) => return Err(Error::custom("Invalid IPv6 address")),
-			};
-
-			let port = match port_str.parse::<u16>() {
-				Ok(p) => p,
-				Err(_) => return Err(Error::custom("Invalid port number")),
-			};
-
-			return Ok(TargetAddr::IPv6(ipv6_addr, port));
-		}
-
-		// Split the string into host and port parts for IPv4 or domain
-		let parts: Vec<&str> = s.split(':').collect();
-		if parts.len() != 2 {
-			return Err(Error::custom("Invalid address format, expected host:port"));
-		}
-
-		// Parse the port
-		let port = match parts[1].parse::<u16>() {
-			Ok(p) => p,
-			Err(_) => return Err(Error::custom("Invalid port number")),
-		};
-
-		// Try to parse as IPv4 first
-		if let Ok(ipv4) = parts[0].parse::<Ipv4Addr>() {
-			Ok(TargetAddr::IPv4(ipv4, port))
-		} else {
-			// Otherwise treat as domain
-			Ok(TargetAddr::Domain(parts[0].to_string(), port))
-		}
+		String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
 	}
 }
 
-
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -172,6 +269,65 @@ mod tests {
 		assert!(result.is_err());
 	}
 
+	#[test]
+	fn test_from_str() {
+		assert_eq!(
+			"[2001:db8::1]:443".parse(),
+			Ok(TargetAddr::IPv6("2001:db8::1".parse().unwrap(), 443))
+		);
+		assert_eq!("10.0.0.1:53".parse(), Ok(TargetAddr::IPv4(Ipv4Addr::new(10, 0, 0, 1), 53)));
+		assert_eq!(
+			TargetAddr::try_from("example.com:80"),
+			Ok(TargetAddr::Domain("example.com".to_string(), 80))
+		);
+		assert!("2001:db8::1:443".parse::<TargetAddr>().is_err());
+		assert!("[::1]443".parse::<TargetAddr>().is_err());
+		assert!(":80".parse::<TargetAddr>().is_err());
+		assert!("example.com:65536".parse::<TargetAddr>().is_err());
+	}
+
+	#[test]
+	fn test_new_detects_host_kind() {
+		assert_eq!(TargetAddr::new("127.0.0.1", 1), Ok(TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 1)));
+		assert_eq!(TargetAddr::new("::1", 2), Ok(TargetAddr::IPv6(Ipv6Addr::LOCALHOST, 2)));
+		assert_eq!(TargetAddr::new("[::1]", 3), Ok(TargetAddr::IPv6(Ipv6Addr::LOCALHOST, 3)));
+		assert_eq!(
+			TargetAddr::new("localhost", 4),
+			Ok(TargetAddr::Domain("localhost".to_string(), 4))
+		);
+		assert_eq!(
+			TargetAddr::new("::1", 5).unwrap().to_socket_addr(),
+			Some(SocketAddr::from((Ipv6Addr::LOCALHOST, 5)))
+		);
+		assert_eq!(TargetAddr::new("localhost", 6).unwrap().to_socket_addr(), None);
+	}
+
+	#[test]
+	fn test_normalize_domain() {
+		assert_eq!(normalize_domain("Example.COM."), Ok("example.com".to_string()));
+		assert_eq!(normalize_domain("Bücher.example"), Ok("xn--bcher-kva.example".to_string()));
+		assert_eq!(
+			normalize_domain("xn--bcher-kva.example"),
+			Ok("xn--bcher-kva.example".to_string())
+		);
+		assert_eq!(normalize_domain("_srv.Example"), Ok("_srv.example".to_string()));
+		assert_eq!(normalize_domain("."), Err(InvalidDomain::Empty));
+		assert!(matches!(normalize_domain("bad domain.example"), Err(InvalidDomain::Idna(_))));
+
+		let long = format!("{}.example", "a.".repeat(124));
+		assert_eq!(
+			normalize_domain(&long),
+			Err(InvalidDomain::TooLong {
+				domain: long.clone(),
+				len:    long.len(),
+			})
+		);
+		assert_eq!(
+			"Bücher.example.:443".parse(),
+			Ok(TargetAddr::Domain("xn--bcher-kva.example".to_string(), 443))
+		);
+	}
+
 	#[test]
 	fn test_deserialize_invalid_format() {
 		let s = "justastring";
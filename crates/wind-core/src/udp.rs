@@ -3,8 +3,12 @@ use std::{
 	future::Future,
 	io::{IoSliceMut, Result as IoResult},
 	net::{IpAddr, Ipv6Addr, SocketAddr},
+	ops::{Deref, DerefMut},
 	pin::Pin,
-	sync::Arc,
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicUsize, Ordering},
+	},
 	task::{Context, Poll, ready},
 };
 
@@ -16,9 +20,11 @@ pub use quinn_udp::{EcnCodepoint, RecvMeta as QuinnRecvMeta, Transmit, UdpSocket
 // Re-export quinn-udp's RecvMeta directly
 // pub use quinn_udp::RecvMeta;
 use tokio::io::Interest;
+use tokio_util::sync::CancellationToken;
 
 use crate::types::TargetAddr;
 
+/// Stand-in for quinn's `UdpPoller` in builds without the `quic` feature
 #[cfg(not(feature = "quic"))]
 pub trait UdpPoller: Send + Sync + Debug + 'static {
 	fn poll_writable(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>>;
@@ -87,6 +93,8 @@ pub struct UdpPacket {
 	pub source:  Option<TargetAddr>,
 	pub target:  TargetAddr,
 	pub payload: Bytes,
+	/// ECN codepoint the packet arrived with, to be carried onto its send
+	pub ecn:     Option<EcnCodepoint>,
 }
 
 // TODO impl quinn::AsyncUdpSocket for AbstractUdpSocket
@@ -120,6 +128,12 @@ pub trait AbstractUdpSocket: Send + Sync {
 		true
 	}
 
+	/// Poll until a datagram is ready to be received. Sockets that can't tell
+	/// report ready right away, so callers fall back to waiting in `poll_recv`.
+	fn poll_recv_ready(&self, _cx: &mut Context) -> Poll<IoResult<()>> {
+		Poll::Ready(Ok(()))
+	}
+
 	/// Supplied methods
 	/// Receive a UDP datagram.
 	/// `meta` is the returned metadata for each buffer in `bufs`.
@@ -128,13 +142,24 @@ pub trait AbstractUdpSocket: Send + Sync {
 	}
 
 	/// Sends data on the socket to the given address.
-	fn poll_send(&self, _cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<IoResult<usize>> {
+	fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<IoResult<usize>> {
+		self.poll_send_ecn(cx, buf, target, None)
+	}
+
+	/// Sends data on the socket to the given address, marked with `ecn`.
+	fn poll_send_ecn(
+		&self,
+		_cx: &mut Context<'_>,
+		buf: &[u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> Poll<IoResult<usize>> {
 		let transmit = Transmit {
-			destination:  target,
-			contents:     buf,
-			ecn:          None,
+			destination: target,
+			contents: buf,
+			ecn,
 			segment_size: None,
-			src_ip:       None,
+			src_ip: None,
 		};
 		match self.try_send(&transmit) {
 			Ok(_) => Poll::Ready(Ok(buf.len())),
@@ -146,20 +171,169 @@ pub trait AbstractUdpSocket: Send + Sync {
 	fn send<'a>(&'a self, buf: &'a [u8], target: SocketAddr) -> impl Future<Output = IoResult<usize>> + Send + 'a {
 		poll_fn(move |cx| self.poll_send(cx, buf, target))
 	}
+
+	/// Sends data on the socket to the given address, marked with `ecn`.
+	fn send_ecn<'a>(
+		&'a self,
+		buf: &'a [u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> impl Future<Output = IoResult<usize>> + Send + 'a {
+		poll_fn(move |cx| self.poll_send_ecn(cx, buf, target, ecn))
+	}
+
+	/// Cancelled once the inbound ends the association, e.g. when a SOCKS
+	/// client closes its control connection, so the outbound can dissociate.
+	/// Sockets without such a signal are never cancelled.
+	fn association_token(&self) -> CancellationToken {
+		CancellationToken::new()
+	}
+}
+
+/// Receive buffers shared between UDP relays
+///
+/// Relays borrow a buffer only while a datagram is being received, so idle
+/// ones hold no memory.
+#[derive(Debug)]
+pub struct BufferPool {
+	free:      Mutex<Vec<Vec<u8>>>,
+	allocated: AtomicUsize,
+	max_idle:  usize,
+}
+
+impl BufferPool {
+	/// Keep at most `max_idle` returned buffers around for reuse
+	pub fn new(max_idle: usize) -> Arc<Self> {
+		Arc::new(Self {
+			free: Mutex::default(),
+			allocated: AtomicUsize::new(0),
+			max_idle,
+		})
+	}
+
+	/// Borrow a zeroed buffer of `len` bytes
+	pub fn take(self: &Arc<Self>, len: usize) -> PooledBuffer {
+		let mut buf = self.free.lock().unwrap().pop().unwrap_or_else(|| {
+			self.allocated.fetch_add(1, Ordering::Relaxed);
+			Vec::new()
+		});
+		buf.clear();
+		buf.resize(len, 0);
+		PooledBuffer { buf, pool: self.clone() }
+	}
+
+	/// Number of buffers currently alive, borrowed or idle
+	pub fn allocated(&self) -> usize {
+		self.allocated.load(Ordering::Relaxed)
+	}
+
+	/// Wait for a datagram on `socket`, then receive it into a buffer sized for
+	/// `datagram_size` bytes per GRO segment
+	pub async fn recv(
+		self: &Arc<Self>,
+		socket: &impl AbstractUdpSocket,
+		datagram_size: usize,
+	) -> IoResult<(PooledBuffer, RecvMeta)> {
+		poll_fn(|cx| socket.poll_recv_ready(cx)).await?;
+		// GRO never coalesces more than a maximum UDP payload
+		let len = (datagram_size * socket.max_receive_segments()).min(u16::MAX as usize);
+		let mut buf = self.take(len.max(datagram_size));
+		let mut meta = RecvMeta::default();
+		let count = socket
+			.recv(&mut [IoSliceMut::new(&mut buf)], std::slice::from_mut(&mut meta))
+			.await?;
+		if count != 1 {
+			return Err(std::io::Error::other(format!("Expected to receive 1 datagram, got {count}")));
+		}
+		Ok((buf, meta))
+	}
+}
+
+/// A buffer borrowed from a [`BufferPool`], returned to it on drop
+#[derive(Debug)]
+pub struct PooledBuffer {
+	buf:  Vec<u8>,
+	pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		&self.buf
+	}
+}
+
+impl DerefMut for PooledBuffer {
+	fn deref_mut(&mut self) -> &mut [u8] {
+		&mut self.buf
+	}
+}
+
+impl Drop for PooledBuffer {
+	fn drop(&mut self) {
+		let mut free = self.pool.free.lock().unwrap();
+		if free.len() < self.pool.max_idle {
+			free.push(std::mem::take(&mut self.buf));
+		} else {
+			self.pool.allocated.fetch_sub(1, Ordering::Relaxed);
+		}
+	}
+}
+
+/// Turn off the UDP GRO [`UdpSocketState::new`] enables wherever the kernel
+/// supports it, so each receive returns a single datagram
+///
+/// Only Linux has GRO, elsewhere this does nothing.
+pub fn disable_gro(socket: &tokio::net::UdpSocket) -> IoResult<()> {
+	#[cfg(target_os = "linux")]
+	{
+		use std::os::fd::AsRawFd;
+
+		let value: libc::c_int = 0;
+		// SAFETY: the value outlives the call and its size is passed along
+		let ret = unsafe {
+			libc::setsockopt(
+				socket.as_raw_fd(),
+				libc::SOL_UDP,
+				libc::UDP_GRO,
+				(&raw const value).cast(),
+				size_of::<libc::c_int>() as libc::socklen_t,
+			)
+		};
+		if ret != 0 {
+			return Err(std::io::Error::last_os_error());
+		}
+	}
+	#[cfg(not(target_os = "linux"))]
+	let _ = socket;
+	Ok(())
 }
 
 #[derive(Debug)]
 pub struct TokioUdpSocket {
-	io:    tokio::net::UdpSocket,
-	inner: UdpSocketState,
+	io:      tokio::net::UdpSocket,
+	inner:   UdpSocketState,
+	/// Segmentation offload is used in either direction, see
+	/// [`without_offload`](Self::without_offload)
+	offload: bool,
 }
 impl TokioUdpSocket {
 	pub fn new(sock: std::net::UdpSocket) -> std::io::Result<Self> {
 		Ok(Self {
-			inner: UdpSocketState::new((&sock).into())?,
-			io:    tokio::net::UdpSocket::from_std(sock)?,
+			inner:   UdpSocketState::new((&sock).into())?,
+			io:      tokio::net::UdpSocket::from_std(sock)?,
+			offload: true,
 		})
 	}
+
+	/// Send and receive one datagram at a time, for NICs and drivers that
+	/// mangle GSO/GRO batches
+	pub fn without_offload(mut self) -> std::io::Result<Self> {
+		disable_gro(&self.io)?;
+		self.offload = false;
+		Ok(self)
+	}
 }
 impl AbstractUdpSocket for TokioUdpSocket {
 	fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
@@ -206,12 +380,16 @@ impl AbstractUdpSocket for TokioUdpSocket {
 		self.inner.may_fragment()
 	}
 
+	fn poll_recv_ready(&self, cx: &mut Context) -> Poll<IoResult<()>> {
+		self.io.poll_recv_ready(cx)
+	}
+
 	fn max_transmit_segments(&self) -> usize {
-		self.inner.max_gso_segments()
+		if self.offload { self.inner.max_gso_segments() } else { 1 }
 	}
 
 	fn max_receive_segments(&self) -> usize {
-		self.inner.gro_segments()
+		if self.offload { self.inner.gro_segments() } else { 1 }
 	}
 }
 
@@ -201,6 +201,114 @@ mod tests {
 		assert_eq!(datagrams, expected_datagrams);
 	}
 
+	#[tokio::test]
+	async fn ecn_relayed_onto_transmit() {
+		use std::sync::Arc;
+
+		use quinn_udp::EcnCodepoint;
+
+		use crate::udp::{AbstractUdpSocket, RecvMeta, TokioUdpSocket};
+
+		let bind = || Arc::new(TokioUdpSocket::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap());
+		// Sends go straight to `try_send`, so wait for tokio to see the socket writable
+		let writable = |socket: &Arc<TokioUdpSocket>| {
+			let mut poller = socket.clone().create_io_poller();
+			futures::future::poll_fn(move |cx| poller.as_mut().poll_writable(cx))
+		};
+		let (client, relay, upstream) = (bind(), bind(), bind());
+
+		// Client sends a marked packet to the relay
+		writable(&client).await.unwrap();
+		client
+			.try_send(&Transmit {
+				destination:  relay.local_addr().unwrap(),
+				ecn:          Some(EcnCodepoint::Ect0),
+				contents:     b"ping",
+				segment_size: None,
+				src_ip:       None,
+			})
+			.unwrap();
+
+		let mut buf = [0u8; 64];
+		let mut meta = RecvMeta::default();
+		relay.recv(&mut [IoSliceMut::new(&mut buf)], slice::from_mut(&mut meta)).await.unwrap();
+		assert_eq!(meta.ecn, Some(EcnCodepoint::Ect0));
+
+		// The relay forwards it with the codepoint it arrived with
+		writable(&relay).await.unwrap();
+		relay
+			.send_ecn(&buf[..meta.len], upstream.local_addr().unwrap(), meta.ecn)
+			.await
+			.unwrap();
+
+		let mut meta = RecvMeta::default();
+		upstream.recv(&mut [IoSliceMut::new(&mut buf)], slice::from_mut(&mut meta)).await.unwrap();
+		assert_eq!(&buf[..meta.len], b"ping");
+		assert_eq!(meta.ecn, Some(EcnCodepoint::Ect0));
+	}
+
+	#[tokio::test]
+	async fn without_offload_receives_single_datagrams() {
+		use crate::udp::{AbstractUdpSocket, RecvMeta, TokioUdpSocket};
+
+		let socket = TokioUdpSocket::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap())
+			.unwrap()
+			.without_offload()
+			.unwrap();
+		assert_eq!(socket.max_transmit_segments(), 1);
+		assert_eq!(socket.max_receive_segments(), 1);
+
+		let send = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+		for _ in 0..2 {
+			send.send_to(&[7; 100], socket.local_addr().unwrap()).unwrap();
+		}
+		let mut buf = [0u8; 1024];
+		for _ in 0..2 {
+			let mut meta = RecvMeta::default();
+			socket
+				.recv(&mut [IoSliceMut::new(&mut buf)], slice::from_mut(&mut meta))
+				.await
+				.unwrap();
+			assert_eq!((meta.len, meta.stride), (100, 100));
+		}
+	}
+
+	#[tokio::test]
+	async fn idle_relays_hold_no_buffer() {
+		use std::{sync::Arc, time::Duration};
+
+		use crate::udp::{AbstractUdpSocket, BufferPool, TokioUdpSocket};
+
+		const RELAYS: usize = 100;
+		let pool = BufferPool::new(8);
+		let sockets: Vec<_> = (0..RELAYS)
+			.map(|_| Arc::new(TokioUdpSocket::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap()))
+			.collect();
+		let relays: Vec<_> = sockets
+			.iter()
+			.map(|socket| {
+				let (pool, socket) = (pool.clone(), socket.clone());
+				tokio::spawn(async move {
+					let (buf, meta) = pool.recv(socket.as_ref(), 1500).await.unwrap();
+					buf[..meta.len].to_vec()
+				})
+			})
+			.collect();
+
+		// A dedicated 64 KiB buffer each would be 6.4 MiB while nothing arrives
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(pool.allocated(), 0);
+
+		let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+		for socket in &sockets {
+			sender.send_to(b"ping", socket.local_addr().unwrap()).unwrap();
+		}
+		for relay in relays {
+			assert_eq!(relay.await.unwrap(), b"ping");
+		}
+		assert!(pool.allocated() <= 8, "{} buffers kept", pool.allocated());
+	}
+
 	fn ip_to_v6_mapped(x: IpAddr) -> IpAddr {
 		match x {
 			IpAddr::V4(x) => IpAddr::V6(x.to_ipv6_mapped()),
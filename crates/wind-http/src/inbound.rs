@@ -0,0 +1,460 @@
+use std::net::SocketAddr;
+
+use base64::prelude::*;
+use futures_util::{StreamExt as _, stream::FuturesUnordered};
+use snafu::{ResultExt, ensure};
+use tokio::{
+	io::{AsyncBufReadExt, AsyncReadExt as _, AsyncWriteExt, BufReader},
+	net::{TcpListener, TcpStream},
+};
+use tokio_util::sync::CancellationToken;
+use wind_core::{
+	AbstractInbound, ConnInfo, Decision, InboundCallback, error,
+	events::{Event, EventBus},
+	info,
+	log::{ConnId, tracing::Instrument as _},
+	tcp::{KeepaliveConfig, set_keepalive},
+	types::{TargetAddr, unmap_v4},
+	warn,
+};
+
+use crate::{
+	CallbackSnafu, Error, IoSnafu, RequestSnafu,
+	stream::{HttpTcpStream, conn_id_header, encode_response},
+};
+
+/// Longest request head accepted before the client is turned away
+const MAX_HEAD_LEN: usize = 8192;
+
+pub struct HttpInboundOpt {
+	/// Bind on address address. eg. `127.0.0.1:8080`
+	pub listen_addr: SocketAddr,
+
+	/// Choose authentication type
+	pub auth: AuthMode,
+
+	/// Accept TCP Fast Open handshakes, Linux and macOS only
+	pub tcp_fast_open: bool,
+
+	/// Probe idle clients so dead ones are dropped
+	pub tcp_keepalive: Option<KeepaliveConfig>,
+
+	/// Tell clients the id their connection is logged under, in the
+	/// [`conn_id_header`]. Off by default as it marks the proxy as wind
+	pub conn_id_header: bool,
+}
+
+pub enum AuthMode {
+	NoAuth,
+	/// HTTP Basic through `Proxy-Authorization`
+	Password {
+		username: String,
+		password: String,
+	},
+}
+
+/// HTTP/1.1 proxy inbound, serving `CONNECT` tunnels only
+pub struct HttpInbound {
+	opts:   HttpInboundOpt,
+	cancel: CancellationToken,
+	/// Where failed authentications are reported
+	events: EventBus,
+}
+
+impl AbstractInbound for HttpInbound {
+	async fn listen(&self, cb: &impl InboundCallback) -> eyre::Result<()> {
+		let listener = TcpListener::bind(self.opts.listen_addr).await?;
+		#[cfg(any(target_os = "linux", target_os = "macos"))]
+		if self.opts.tcp_fast_open
+			&& let Err(err) = wind_core::tcp::set_fast_open_listener(&listener)
+		{
+			warn!(target: "[IN] REACTOR", "TCP Fast Open unavailable, accepting regular handshakes only: {err}");
+		}
+		// Clients still in their handshake, so a slow one doesn't hold up the next
+		let mut clients = FuturesUnordered::new();
+		loop {
+			tokio::select! {
+				_ = self.cancel.cancelled() => {
+					info!(target: "[IN] REACTOR", "Cancellation received, shutting down");
+					break;
+				}
+				Some(()) = clients.next() => {}
+				res = listener.accept() => {
+					let (stream, client_addr) = match res {
+						Err(err) => {
+							error!(target:"[IN] REACTOR", "{:}", err);
+							continue;
+						}
+						Ok(accepted) => accepted,
+					};
+					if let Some(keepalive) = &self.opts.tcp_keepalive
+						&& let Err(err) = set_keepalive(&stream, keepalive)
+					{
+						warn!(target: "[IN] REACTOR", "Failed to enable keepalive: {err}");
+					}
+
+					let conn_id = ConnId::next();
+					clients.push(async move {
+						if let Err(err) = self.handle_income(stream, client_addr, conn_id, cb).await {
+							error!(target: "[IN] HANDLER" , "{:}", err);
+						}
+					}
+					.instrument(conn_id.span()));
+				}
+			};
+		}
+		// Clients already taken in are let finish, the drain bounds how long
+		while clients.next().await.is_some() {}
+		Ok(())
+	}
+}
+
+impl HttpInbound {
+	pub async fn new(opts: HttpInboundOpt, cancel: CancellationToken) -> Self {
+		Self {
+			opts,
+			cancel,
+			events: EventBus::default(),
+		}
+	}
+
+	/// Report failed authentications on `events`, eg.
+	/// [`AppContext::events`](wind_core::AppContext::events)
+	pub fn with_events(mut self, events: EventBus) -> Self {
+		self.events = events;
+		self
+	}
+
+	async fn handle_income(
+		&self,
+		stream: TcpStream,
+		client_addr: SocketAddr,
+		conn_id: ConnId,
+		cb: &impl InboundCallback,
+	) -> Result<(), Error> {
+		let local_addr = stream.local_addr().ok().map(unmap_v4);
+		let client_addr = unmap_v4(client_addr);
+		// Payload pipelined behind the request head stays buffered in the reader
+		let mut stream = BufReader::new(stream);
+		let request = match self.read_request(&mut stream).await {
+			Ok(target_addr) => self.authorize(target_addr, client_addr, cb).await,
+			Err(err) => Err(err),
+		};
+		let target_addr = match request {
+			Ok(target_addr) => target_addr,
+			Err(err) => {
+				if let Error::Request { status, reason, .. } = &err {
+					let header = self.opts.conn_id_header.then(|| conn_id_header(conn_id));
+					let mut headers: Vec<&str> = header.as_deref().into_iter().collect();
+					if *status == 407 {
+						headers.push("Proxy-Authenticate: Basic realm=\"wind\"");
+						self.events.publish(Event::AuthFailed {
+							inbound:     "http",
+							client_addr: Some(client_addr),
+						});
+					}
+					stream
+						.write_all(&encode_response(*status, reason, &headers))
+						.await
+						.context(IoSnafu)?;
+				}
+				return Err(err);
+			}
+		};
+
+		let mut inner = HttpTcpStream::new(stream)
+			.with_client_addr(Some(client_addr))
+			.with_local_addr(local_addr);
+		if self.opts.conn_id_header {
+			inner = inner.with_conn_id(conn_id);
+		}
+		cb.handle_tcpstream(target_addr, inner).await.context(CallbackSnafu)
+	}
+
+	/// Ask `cb` whether `client_addr` may reach `target_addr`
+	async fn authorize(
+		&self,
+		target_addr: TargetAddr,
+		client_addr: SocketAddr,
+		cb: &impl InboundCallback,
+	) -> Result<TargetAddr, Error> {
+		let info = ConnInfo {
+			client_addr: Some(client_addr),
+			target:      Some(target_addr.clone()),
+			user:        match &self.opts.auth {
+				AuthMode::Password { username, .. } => Some(username.clone()),
+				AuthMode::NoAuth => None,
+			},
+		};
+		ensure!(
+			cb.authorize(&info).await == Decision::Allow,
+			RequestSnafu {
+				status: 403u16,
+				reason: "Forbidden",
+			}
+		);
+		Ok(target_addr)
+	}
+
+	/// Read the request head up to its blank line and return the `CONNECT`
+	/// target
+	async fn read_request(&self, stream: &mut BufReader<TcpStream>) -> Result<TargetAddr, Error> {
+		let mut head = Vec::new();
+		let mut lines = Vec::new();
+		loop {
+			let start = head.len();
+			// Bounded while reading, a line that never ends can't grow `head` past it
+			let limit = (MAX_HEAD_LEN + 1 - head.len()) as u64;
+			let n = (&mut *stream)
+				.take(limit)
+				.read_until(b'\n', &mut head)
+				.await
+				.context(IoSnafu)?;
+			ensure!(n > 0, RequestSnafu { status: 400u16, reason: "Bad Request" });
+			ensure!(
+				head.len() <= MAX_HEAD_LEN,
+				RequestSnafu {
+					status: 431u16,
+					reason: "Request Header Fields Too Large",
+				}
+			);
+			let line = String::from_utf8_lossy(&head[start..]).trim_end().to_owned();
+			if line.is_empty() {
+				break;
+			}
+			lines.push(line);
+		}
+
+		let mut request_line = lines.first().map(String::as_str).unwrap_or_default().split(' ');
+		let (method, authority) = (request_line.next(), request_line.next());
+		ensure!(
+			method == Some("CONNECT"),
+			RequestSnafu {
+				status: 405u16,
+				reason: "Method Not Allowed",
+			}
+		);
+		let target_addr = authority.and_then(parse_authority).ok_or_else(|| {
+			RequestSnafu {
+				status: 400u16,
+				reason: "Bad Request",
+			}
+			.build()
+		})?;
+
+		if let AuthMode::Password { username, password } = &self.opts.auth {
+			let expected = BASE64_STANDARD.encode(format!("{username}:{password}"));
+			let authorized = lines[1..].iter().any(|line| {
+				let Some((name, value)) = line.split_once(':') else {
+					return false;
+				};
+				let mut value = value.split_whitespace();
+				name.eq_ignore_ascii_case("Proxy-Authorization")
+					&& value.next().is_some_and(|scheme| scheme.eq_ignore_ascii_case("Basic"))
+					&& value.next() == Some(expected.as_str())
+			});
+			ensure!(
+				authorized,
+				RequestSnafu {
+					status: 407u16,
+					reason: "Proxy Authentication Required",
+				}
+			);
+		}
+		Ok(target_addr)
+	}
+}
+
+/// Parse a `CONNECT` authority, `host:port` or `[v6]:port`
+fn parse_authority(authority: &str) -> Option<TargetAddr> {
+	if let Ok(addr) = authority.parse::<SocketAddr>() {
+		return Some(addr.into());
+	}
+	let (host, port) = authority.rsplit_once(':')?;
+	if host.is_empty() || host.contains([':', '[', ']']) {
+		return None;
+	}
+	TargetAddr::new(host, port.parse().ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::AsyncReadExt;
+	use wind_core::{tcp::AbstractTcpStream, udp::AbstractUdpSocket};
+
+	use super::*;
+
+	/// Answers with the requested domain, then echoes
+	#[derive(Clone)]
+	struct EchoCallback;
+
+	impl InboundCallback for EchoCallback {
+		async fn handle_tcpstream(
+			&self,
+			target_addr: TargetAddr,
+			mut stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			stream.on_connect(Ok(())).await?;
+			if let TargetAddr::Domain(domain, _) = target_addr {
+				stream.write_all(domain.as_bytes()).await?;
+			}
+			let mut buf = [0u8; 4];
+			stream.read_exact(&mut buf).await?;
+			stream.write_all(&buf).await?;
+			Ok(())
+		}
+
+		async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			Ok(())
+		}
+	}
+
+	/// Answers with the addresses the stream reports
+	#[derive(Clone)]
+	struct AddrCallback;
+
+	impl InboundCallback for AddrCallback {
+		async fn handle_tcpstream(
+			&self,
+			_target_addr: TargetAddr,
+			mut stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			stream.on_connect(Ok(())).await?;
+			let addrs = format!("{:?} {:?}", stream.client_addr(), stream.local_addr());
+			stream.write_all(addrs.as_bytes()).await?;
+			Ok(())
+		}
+
+		async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn test_parse_authority() {
+		assert_eq!(
+			parse_authority("example.com:443"),
+			Some(TargetAddr::Domain("example.com".into(), 443))
+		);
+		assert_eq!(
+			parse_authority("[::1]:80"),
+			Some(TargetAddr::IPv6(std::net::Ipv6Addr::LOCALHOST, 80))
+		);
+		assert_eq!(
+			parse_authority("Example.COM.:443"),
+			Some(TargetAddr::Domain("example.com".into(), 443))
+		);
+		assert_eq!(parse_authority("example.com"), None);
+		assert_eq!(parse_authority("::1:80"), None);
+	}
+
+	#[tokio::test]
+	async fn test_connect_with_auth() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = HttpInbound::new(
+			HttpInboundOpt {
+				listen_addr,
+				auth: AuthMode::Password {
+					username: "u".into(),
+					password: "p".into(),
+				},
+				tcp_fast_open: false,
+				tcp_keepalive: None,
+				conn_id_header: true,
+			},
+			cancel.clone(),
+		)
+		.await;
+		tokio::spawn(async move { inbound.listen(&EchoCallback).await });
+		tokio::task::yield_now().await;
+
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client
+			.write_all(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n")
+			.await
+			.unwrap();
+		let mut response = String::new();
+		client.read_to_string(&mut response).await.unwrap();
+		assert!(response.starts_with("HTTP/1.1 407 "), "{response}");
+
+		// The payload may follow the head before the response arrives
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client
+			.write_all(b"CONNECT example.com:443 HTTP/1.1\r\nProxy-Authorization: Basic dTpw\r\n\r\nping")
+			.await
+			.unwrap();
+		// Status line, the 13 character connection id header, blank line
+		let mut response = vec![0u8; 70];
+		client.read_exact(&mut response).await.unwrap();
+		let response = String::from_utf8(response).unwrap();
+		let conn_id = response
+			.strip_prefix("HTTP/1.1 200 Connection Established\r\nX-Wind-Conn-Id: ")
+			.and_then(|rest| rest.strip_suffix("\r\n\r\n"))
+			.unwrap_or_else(|| panic!("{response}"));
+		assert_eq!(conn_id.len(), 13);
+		let mut body = Vec::new();
+		client.read_to_end(&mut body).await.unwrap();
+		assert_eq!(body, b"example.comping");
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_stream_addrs_unmapped() {
+		let listen_addr = TcpListener::bind("[::]:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = HttpInbound::new(
+			HttpInboundOpt {
+				listen_addr,
+				auth: AuthMode::NoAuth,
+				tcp_fast_open: false,
+				tcp_keepalive: None,
+				conn_id_header: false,
+			},
+			cancel.clone(),
+		)
+		.await;
+		tokio::spawn(async move { inbound.listen(&AddrCallback).await });
+		tokio::task::yield_now().await;
+
+		// An IPv4 client of a dual-stack listener is told as IPv4
+		let mut client = TcpStream::connect(("127.0.0.1", listen_addr.port())).await.unwrap();
+		let client_addr = client.local_addr().unwrap();
+		client.write_all(b"CONNECT example.com:443 HTTP/1.1\r\n\r\n").await.unwrap();
+		let mut response = String::new();
+		client.read_to_string(&mut response).await.unwrap();
+		let local_addr = SocketAddr::from(([127, 0, 0, 1], listen_addr.port()));
+		assert_eq!(
+			response.strip_prefix("HTTP/1.1 200 Connection Established\r\n\r\n"),
+			Some(format!("{:?} {:?}", Some(client_addr), Some(local_addr)).as_str())
+		);
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_endless_head_line_refused() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = HttpInbound::new(
+			HttpInboundOpt {
+				listen_addr,
+				auth: AuthMode::NoAuth,
+				tcp_fast_open: false,
+				tcp_keepalive: None,
+				conn_id_header: false,
+			},
+			cancel.clone(),
+		)
+		.await;
+		tokio::spawn(async move { inbound.listen(&EchoCallback).await });
+		tokio::task::yield_now().await;
+
+		// Refused once past the limit, without waiting for a newline
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client.write_all(&vec![b'a'; MAX_HEAD_LEN + 1]).await.unwrap();
+		let mut response = String::new();
+		client.read_to_string(&mut response).await.unwrap();
+		assert!(response.starts_with("HTTP/1.1 431 "), "{response}");
+		cancel.cancel();
+	}
+}
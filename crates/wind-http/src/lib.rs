@@ -0,0 +1,35 @@
+use std::backtrace::Backtrace;
+
+use snafu::Snafu;
+
+pub mod inbound;
+pub mod outbound;
+pub mod stream;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum Error {
+	Io {
+		source:    std::io::Error,
+		backtrace: Backtrace,
+	},
+	/// The client sent something that isn't an acceptable proxy request, it
+	/// has been answered with `status`
+	Request {
+		status:    u16,
+		reason:    String,
+		backtrace: Backtrace,
+	},
+	/// The upstream proxy answered a `CONNECT` with `status` instead of
+	/// success
+	#[snafu(display("upstream proxy answered {status} {reason}"))]
+	Upstream {
+		status:    u16,
+		reason:    String,
+		backtrace: Backtrace,
+	},
+	Callback {
+		source:    eyre::Report,
+		backtrace: Backtrace,
+	},
+}
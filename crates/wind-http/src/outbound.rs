@@ -0,0 +1,266 @@
+use std::net::SocketAddr;
+
+use base64::prelude::*;
+use snafu::{IntoError as _, ResultExt, ensure};
+use tokio::{
+	io::{AsyncBufReadExt, AsyncReadExt as _, AsyncWriteExt, BufReader},
+	net::TcpStream,
+};
+use wind_core::{
+	AbstractOutbound, debug,
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+	udp::AbstractUdpSocket,
+};
+
+use crate::{Error, IoSnafu, UpstreamSnafu};
+
+/// Longest response head accepted from the upstream proxy
+const MAX_HEAD_LEN: usize = 8192;
+
+pub struct HttpConnectOutboundOpts {
+	/// The upstream HTTP proxy
+	pub proxy_addr: SocketAddr,
+
+	/// Username and password sent as `Proxy-Authorization: Basic`
+	pub auth: Option<(String, String)>,
+}
+
+/// Relays through an upstream HTTP proxy, opening each stream with a
+/// `CONNECT` tunnel
+///
+/// HTTP proxies can't carry UDP, so associations are refused.
+pub struct HttpConnectOutbound {
+	opts: HttpConnectOutboundOpts,
+}
+
+impl HttpConnectOutbound {
+	pub fn new(opts: HttpConnectOutboundOpts) -> Self {
+		Self { opts }
+	}
+
+	/// Open a tunnel to `target_addr`, bytes the proxy sent past its response
+	/// stay buffered in the reader
+	async fn connect(&self, target_addr: &TargetAddr) -> Result<BufReader<TcpStream>, Error> {
+		let mut stream = TcpStream::connect(self.opts.proxy_addr).await.context(IoSnafu)?;
+		stream
+			.write_all(&encode_request(target_addr, self.opts.auth.as_ref()))
+			.await
+			.context(IoSnafu)?;
+
+		let mut stream = BufReader::new(stream);
+		let mut head = Vec::new();
+		let mut status_line = None;
+		loop {
+			let start = head.len();
+			// Bounded while reading, a line that never ends can't grow `head` past it
+			let limit = (MAX_HEAD_LEN + 1 - head.len()) as u64;
+			let n = (&mut stream)
+				.take(limit)
+				.read_until(b'\n', &mut head)
+				.await
+				.context(IoSnafu)?;
+			if n == 0 {
+				return Err(invalid_response("connection closed before the response ended"));
+			}
+			if head.len() > MAX_HEAD_LEN {
+				return Err(invalid_response("response head too long"));
+			}
+			let line = String::from_utf8_lossy(&head[start..]).trim_end().to_owned();
+			if line.is_empty() {
+				break;
+			}
+			status_line.get_or_insert(line);
+		}
+
+		let (status, reason) = parse_status_line(status_line.as_deref().unwrap_or_default())
+			.ok_or_else(|| invalid_response("malformed status line"))?;
+		ensure!(
+			(200..300).contains(&status),
+			UpstreamSnafu {
+				status,
+				reason: reason.to_owned(),
+			}
+		);
+		Ok(stream)
+	}
+}
+
+/// The upstream proxy's response can't be made sense of
+fn invalid_response(reason: &'static str) -> Error {
+	IoSnafu.into_error(std::io::Error::new(std::io::ErrorKind::InvalidData, reason))
+}
+
+/// Encode the `CONNECT` request head for `target_addr`
+fn encode_request(target_addr: &TargetAddr, auth: Option<&(String, String)>) -> Vec<u8> {
+	let mut buf = format!("CONNECT {target_addr} HTTP/1.1\r\nHost: {target_addr}\r\n");
+	if let Some((username, password)) = auth {
+		let credentials = BASE64_STANDARD.encode(format!("{username}:{password}"));
+		buf.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+	}
+	buf.push_str("\r\n");
+	buf.into_bytes()
+}
+
+/// Split `HTTP/1.x <status> <reason>` into the status and reason
+fn parse_status_line(line: &str) -> Option<(u16, &str)> {
+	let (version, rest) = line.split_once(' ')?;
+	if !version.starts_with("HTTP/1.") {
+		return None;
+	}
+	let (status, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+	Some((status.parse().ok()?, reason))
+}
+
+/// Classify a failed tunnel for the inbound's reply
+fn connect_error(err: &Error) -> ConnectError {
+	match err {
+		Error::Io { source, .. } => ConnectError::from(source),
+		Error::Upstream { status: 403 | 407, .. } => ConnectError::NotAllowed,
+		Error::Upstream { status: 408 | 504, .. } => ConnectError::TimedOut,
+		Error::Upstream { status: 502, .. } => ConnectError::HostUnreachable,
+		_ => ConnectError::General,
+	}
+}
+
+impl AbstractOutbound for HttpConnectOutbound {
+	async fn handle_tcp(
+		&self,
+		target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let mut upstream = match self.connect(&target_addr).await {
+			Ok(upstream) => upstream,
+			Err(err) => {
+				stream.on_connect(Err(connect_error(&err))).await?;
+				return Err(eyre::eyre!(
+					"upstream HTTP proxy {} failed to connect {target_addr}: {err}",
+					self.opts.proxy_addr
+				));
+			}
+		};
+		debug!(target: "[OUT] HTTP", "Tunnel to {target_addr} opened through {}", self.opts.proxy_addr);
+		stream.on_connect(Ok(())).await?;
+		let (_, _, err) = wind_core::io::copy_io(&mut stream, &mut upstream).await;
+		if let Some(err) = err {
+			return Err(err.into());
+		}
+		Ok(())
+	}
+
+	async fn handle_udp(
+		&self,
+		_socket: impl AbstractUdpSocket + 'static,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		eyre::bail!("HTTP CONNECT outbound does not relay UDP")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::{io::AsyncReadExt, net::TcpListener};
+	use tokio_util::sync::CancellationToken;
+	use wind_core::{AbstractInbound, test_util::DirectCallback};
+
+	use super::*;
+	use crate::inbound::{AuthMode, HttpInbound, HttpInboundOpt};
+
+	/// Serve an HTTP proxy with the user `u`/`p` on a free port
+	async fn proxy() -> SocketAddr {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let inbound = HttpInbound::new(
+			HttpInboundOpt {
+				listen_addr,
+				auth: AuthMode::Password {
+					username: "u".into(),
+					password: "p".into(),
+				},
+				tcp_fast_open: false,
+				tcp_keepalive: None,
+				conn_id_header: false,
+			},
+			CancellationToken::new(),
+		)
+		.await;
+		tokio::spawn(async move { inbound.listen(&DirectCallback).await });
+		tokio::task::yield_now().await;
+		listen_addr
+	}
+
+	#[test]
+	fn test_parse_status_line() {
+		assert_eq!(
+			parse_status_line("HTTP/1.1 200 Connection established"),
+			Some((200, "Connection established"))
+		);
+		assert_eq!(parse_status_line("HTTP/1.0 407"), Some((407, "")));
+		assert_eq!(parse_status_line("SSH-2.0-OpenSSH"), None);
+	}
+
+	#[tokio::test]
+	async fn test_relay_through_connect_tunnel() {
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target_addr = target.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut stream, _) = target.accept().await.unwrap();
+			let mut buf = [0u8; 4];
+			stream.read_exact(&mut buf).await.unwrap();
+			stream.write_all(&buf).await.unwrap();
+		});
+
+		let outbound = HttpConnectOutbound::new(HttpConnectOutboundOpts {
+			proxy_addr: proxy().await,
+			auth:       Some(("u".into(), "p".into())),
+		});
+		let (mut client, stream) = tokio::io::duplex(1024);
+		let relay = tokio::spawn(async move {
+			outbound
+				.handle_tcp(target_addr.into(), stream, None::<HttpConnectOutbound>)
+				.await
+		});
+		client.write_all(b"ping").await.unwrap();
+		let mut buf = [0u8; 4];
+		client.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"ping");
+		drop(client);
+		relay.await.unwrap().unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_rejected_connect_is_typed() {
+		let outbound = HttpConnectOutbound::new(HttpConnectOutboundOpts {
+			proxy_addr: proxy().await,
+			auth:       Some(("u".into(), "wrong".into())),
+		});
+		let err = outbound
+			.connect(&TargetAddr::Domain("example.com".into(), 443))
+			.await
+			.unwrap_err();
+		assert!(matches!(err, Error::Upstream { status: 407, .. }), "{err:?}");
+		assert_eq!(connect_error(&err), ConnectError::NotAllowed);
+	}
+
+	#[tokio::test]
+	async fn test_endless_response_line_refused() {
+		let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let proxy_addr = upstream.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut stream, _) = upstream.accept().await.unwrap();
+			stream.write_all(&vec![b'a'; MAX_HEAD_LEN + 1]).await.unwrap();
+			// Never a newline, nor the end of the connection
+			std::future::pending::<()>().await;
+		});
+
+		let outbound = HttpConnectOutbound::new(HttpConnectOutboundOpts { proxy_addr, auth: None });
+		let err = outbound
+			.connect(&TargetAddr::Domain("example.com".into(), 443))
+			.await
+			.unwrap_err();
+		assert!(
+			matches!(&err, Error::Io { source, .. } if source.kind() == std::io::ErrorKind::InvalidData),
+			"{err:?}"
+		);
+	}
+}
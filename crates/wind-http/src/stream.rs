@@ -0,0 +1,178 @@
+use std::{
+	io,
+	net::SocketAddr,
+	pin::Pin,
+	task::{Context, Poll, ready},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use wind_core::{
+	log::ConnId,
+	tcp::{AbstractTcpStream, ConnectError},
+};
+
+/// Response header telling clients the id their connection is logged under
+pub fn conn_id_header(conn_id: ConnId) -> String {
+	format!("X-Wind-Conn-Id: {conn_id}")
+}
+
+/// Encode a bodyless HTTP/1.1 response with extra header lines
+pub fn encode_response(status: u16, reason: &str, headers: &[&str]) -> Vec<u8> {
+	let mut buf = format!("HTTP/1.1 {status} {reason}\r\n");
+	for header in headers {
+		buf.push_str(header);
+		buf.push_str("\r\n");
+	}
+	if status != 200 {
+		buf.push_str("Content-Length: 0\r\nConnection: close\r\n");
+	}
+	buf.push_str("\r\n");
+	buf.into_bytes()
+}
+
+pub fn status_from(err: ConnectError) -> (u16, &'static str) {
+	match err {
+		ConnectError::NotAllowed => (403, "Forbidden"),
+		ConnectError::TimedOut => (504, "Gateway Timeout"),
+		ConnectError::General
+		| ConnectError::NetworkUnreachable
+		| ConnectError::HostUnreachable
+		| ConnectError::ConnectionRefused => (502, "Bad Gateway"),
+	}
+}
+
+/// An HTTP CONNECT tunnel whose response is deferred until the outbound
+/// reports the upstream state through [`AbstractTcpStream::on_connect`].
+///
+/// Outbounds that never report are treated as successful on first I/O, so
+/// the response always precedes any relayed payload.
+pub struct HttpTcpStream<T> {
+	inner:       T,
+	/// Success response still to be written and how much of it already is
+	pending:     Option<(Vec<u8>, usize)>,
+	conn_id:     Option<ConnId>,
+	client_addr: Option<SocketAddr>,
+	local_addr:  Option<SocketAddr>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> HttpTcpStream<T> {
+	pub fn new(inner: T) -> Self {
+		Self {
+			inner,
+			pending: Some((encode_response(200, "Connection Established", &[]), 0)),
+			conn_id: None,
+			client_addr: None,
+			local_addr: None,
+		}
+	}
+
+	/// Tell outbounds the client is at `client_addr`, see
+	/// [`AbstractTcpStream::client_addr`]
+	pub fn with_client_addr(mut self, client_addr: Option<SocketAddr>) -> Self {
+		self.client_addr = client_addr;
+		self
+	}
+
+	/// Tell outbounds the client reached us at `local_addr`, see
+	/// [`AbstractTcpStream::local_addr`]
+	pub fn with_local_addr(mut self, local_addr: Option<SocketAddr>) -> Self {
+		self.local_addr = local_addr;
+		self
+	}
+
+	/// Send the [`conn_id_header`] along with the response
+	pub fn with_conn_id(mut self, conn_id: ConnId) -> Self {
+		let header = conn_id_header(conn_id);
+		self.pending = Some((encode_response(200, "Connection Established", &[&header]), 0));
+		self.conn_id = Some(conn_id);
+		self
+	}
+
+	fn poll_reply(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		if let Some((reply, written)) = &mut self.pending {
+			while *written < reply.len() {
+				let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &reply[*written..]))?;
+				if n == 0 {
+					return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+				}
+				*written += n;
+			}
+			ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+			self.pending = None;
+		}
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for HttpTcpStream<T> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_reply(cx))?;
+		Pin::new(&mut this.inner).poll_read(cx, buf)
+	}
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for HttpTcpStream<T> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		ready!(this.poll_reply(cx))?;
+		Pin::new(&mut this.inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_reply(cx))?;
+		Pin::new(&mut this.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_reply(cx))?;
+		Pin::new(&mut this.inner).poll_shutdown(cx)
+	}
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> AbstractTcpStream for HttpTcpStream<T> {
+	async fn on_connect(&mut self, result: Result<(), ConnectError>) -> io::Result<()> {
+		match result {
+			Ok(()) => std::future::poll_fn(|cx| self.poll_reply(cx)).await,
+			// Only a response that hasn't started can still be turned into a failure
+			Err(err) if matches!(self.pending, Some((_, 0))) => {
+				self.pending = None;
+				let (status, reason) = status_from(err);
+				let header = self.conn_id.map(conn_id_header);
+				let headers: Vec<&str> = header.as_deref().into_iter().collect();
+				self.inner.write_all(&encode_response(status, reason, &headers)).await?;
+				self.inner.flush().await
+			}
+			Err(_) => Ok(()),
+		}
+	}
+
+	fn client_addr(&self) -> Option<SocketAddr> {
+		self.client_addr
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		self.local_addr
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::{AsyncReadExt, duplex};
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_connect_error_response() {
+		let (mut client, server) = duplex(128);
+		let mut stream = HttpTcpStream::new(server);
+		stream.on_connect(Err(ConnectError::ConnectionRefused)).await.unwrap();
+		drop(stream);
+
+		let mut response = String::new();
+		client.read_to_string(&mut response).await.unwrap();
+		assert!(response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+	}
+}
@@ -1,23 +1,24 @@
 use std::{
 	io,
 	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	time::Duration,
 };
 
 use fast_socks5::{
 	server::{ErrorContext as _, Socks5ServerProtocol, SocksServerError, states, wait_on_tcp},
 	util::target_addr::TargetAddr,
 };
-use futures_util::TryFutureExt;
 use socket2::{Domain, Socket, Type};
-use tokio::{
-	io::{AsyncRead, AsyncWrite},
-	try_join,
-};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::sync::CancellationToken;
 use tracing::debug;
 use wind_core::{error, warn};
 
 use crate::Error;
 
+/// How long the outbound gets to dissociate once the control connection closed
+const DISSOCIATE_TIMEOUT: Duration = Duration::from_secs(5);
+
 macro_rules! try_notify {
     ($proto:expr, $e:expr) => {
         match $e {
@@ -36,6 +37,11 @@ macro_rules! try_notify {
     };
 }
 
+const V4_UNSPEC: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+const V6_UNSPEC: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0);
+
+/// Bind a UDP socket on a random port of `addr`, or of a dual-stack `[::]`
+/// falling back to `0.0.0.0` when `addr` is `None`
 fn udp_bind_random_port(addr: Option<IpAddr>) -> io::Result<Socket> {
 	// Early return pattern: handle the Some case first
 	if let Some(addr) = addr {
@@ -44,17 +50,30 @@ fn udp_bind_random_port(addr: Option<IpAddr>) -> io::Result<Socket> {
 		socket.bind(&sock_addr.into())?;
 		return socket.set_nonblocking(true).map(|_| socket);
 	}
-	
-	// Handle None case (trying IPv6 first, then IPv4)
-	const V4_UNSPEC: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
-	const V6_UNSPEC: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0);
-	Socket::new(Domain::IPV6, Type::DGRAM, None)
-		.and_then(|socket| socket.set_only_v6(false).map(|_| socket))
-		.and_then(|socket| socket.bind(&V6_UNSPEC.into()).map(|_| socket))
-		.or_else(|_| {
-			Socket::new(Domain::IPV4, Type::DGRAM, None).and_then(|socket| socket.bind(&V4_UNSPEC.into()).map(|_| socket))
-		})
-		.and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+
+	let socket = match udp_bind_dual_stack() {
+		Ok(socket) => socket,
+		Err(err) => {
+			debug!("No usable dual-stack UDP socket, binding IPv4 only: {err}");
+			let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+			socket.bind(&V4_UNSPEC.into())?;
+			socket
+		}
+	};
+	socket.set_nonblocking(true).map(|_| socket)
+}
+
+/// An IPv6 socket also taking IPv4, refused when the host would leave it
+/// IPv6-only or unbound, as some with IPv6 disabled do
+fn udp_bind_dual_stack() -> io::Result<Socket> {
+	let socket = Socket::new(Domain::IPV6, Type::DGRAM, None)?;
+	socket.set_only_v6(false)?;
+	socket.bind(&V6_UNSPEC.into())?;
+	if socket.only_v6()? {
+		return Err(io::Error::new(io::ErrorKind::Unsupported, "socket stayed IPv6-only"));
+	}
+	socket.local_addr()?;
+	Ok(socket)
 }
 
 pub async fn run_udp_proxy<T, F, R>(
@@ -66,7 +85,7 @@ pub async fn run_udp_proxy<T, F, R>(
 ) -> Result<T, Error>
 where
 	T: AsyncRead + AsyncWrite + Unpin,
-	F: FnOnce(Socket) -> R,
+	F: FnOnce(Socket, CancellationToken) -> R,
 	R: Future<Output = Result<(), Error>>,
 {
 	let peer_sock = try_notify!(
@@ -81,11 +100,24 @@ where
 	// Respect the pre-populated reply IP address.
 	let mut inner = proto.reply_success(SocketAddr::new(reply_ip, reply_port)).await?;
 
-	let udp_fut = transfer(peer_sock);
-	let tcp_fut = wait_on_tcp(&mut inner).map_err(Error::from);
+	// Cancelled when the control connection closes, so the outbound can end its
+	// side of the association instead of being dropped mid-relay
+	let token = CancellationToken::new();
+	let udp_fut = transfer(peer_sock, token.clone());
+	tokio::pin!(udp_fut);
+	let res = tokio::select! {
+		res = &mut udp_fut => res,
+		res = wait_on_tcp(&mut inner) => {
+			token.cancel();
+			if tokio::time::timeout(DISSOCIATE_TIMEOUT, &mut udp_fut).await.is_err() {
+				warn!("UDP relay still running {:?} after its control connection closed", DISSOCIATE_TIMEOUT);
+			}
+			res.map_err(Error::from)
+		}
+	};
 
-	match try_join!(udp_fut, tcp_fut) {
-		Ok(_) => warn!("unreachable"),
+	match res {
+		Ok(_) => debug!("UDP relay finished, closing its control connection"),
 		Err(Error::Socks {
 			source: SocksServerError::EOF,
 			backtrace: _,
@@ -96,3 +128,23 @@ where
 	}
 	Ok(inner)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::inbound::UdpBindFamily;
+
+	#[test]
+	fn test_force_ipv4_udp_bind() {
+		// Whether or not this host could go dual-stack
+		let ipv6 = udp_bind_dual_stack().is_ok();
+		let socket = udp_bind_random_port(UdpBindFamily::Ipv4.bind_ip()).unwrap();
+		let local_addr = socket.local_addr().unwrap().as_socket().unwrap();
+		assert!(local_addr.is_ipv4(), "{local_addr} with IPv6 available: {ipv6}");
+		assert_ne!(local_addr.port(), 0);
+
+		let socket = udp_bind_random_port(UdpBindFamily::Auto.bind_ip()).unwrap();
+		let local_addr = socket.local_addr().unwrap().as_socket().unwrap();
+		assert_eq!(local_addr.is_ipv6(), ipv6);
+	}
+}
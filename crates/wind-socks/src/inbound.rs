@@ -1,18 +1,56 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::{
+	io,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	path::PathBuf,
+	sync::{
+		Arc,
+		atomic::{AtomicBool, Ordering},
+	},
+};
 
-use fast_socks5::{ReplyError, Socks5Command, server::Socks5ServerProtocol, util::target_addr::TargetAddr as SocksTargetAddr};
+use fast_socks5::{
+	ReplyError, Socks5Command,
+	server::{Socks5ServerProtocol, SocksServerError},
+	util::target_addr::{TargetAddr as SocksTargetAddr, read_address},
+};
+use futures_util::{StreamExt as _, stream::FuturesUnordered};
 use snafu::ResultExt;
-use tokio::net::{TcpListener, TcpStream};
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+};
 use tokio_util::sync::CancellationToken;
-use wind_core::{AbstractInbound, InboundCallback, error, info, types::TargetAddr};
+use wind_core::{
+	AbstractInbound, ConnInfo, Decision, InboundCallback, error,
+	events::{Event, EventBus},
+	info,
+	log::{ConnId, tracing::Instrument as _},
+	proxy_protocol,
+	tcp::{KeepaliveConfig, set_keepalive},
+	types::{TargetAddr, normalize_domain, unmap_v4},
+	warn,
+};
 
-use crate::{CallbackSnafu, Error, IoSnafu, SocksSnafu};
+use crate::{
+	CallbackSnafu, Error, IoSnafu, MissingPublicAddrSnafu, ProxyProtocolSnafu, SocksSnafu, socks4,
+	stream::{PeekStream, SocksTcpStream, encode_reply},
+};
+
+/// Tor extension: resolve a hostname, see `socks-extensions.txt` in the Tor
+/// spec
+const SOCKS5_CMD_TOR_RESOLVE: u8 = 0xF0;
+/// Tor extension: reverse-resolve an IP address
+const SOCKS5_CMD_TOR_RESOLVE_PTR: u8 = 0xF1;
 
 pub struct SocksInboundOpt {
-	/// Bind on address address. eg. `127.0.0.1:1080`
-	pub listen_addr: SocketAddr,
+	/// Where clients connect, eg. `127.0.0.1:1080`
+	pub listen: Listen,
 
-	/// Our external IP address to be sent in reply packets (required for UDP)
+	/// Our external IP address to be sent in reply packets (required for
+	/// UDP), CONNECT replies only carry it to clients of the same family
 	pub public_addr: Option<std::net::IpAddr>,
 
 	/// Choose authentication type
@@ -21,8 +59,32 @@ pub struct SocksInboundOpt {
 	/// Don't perform the auth handshake, send directly the command request
 	pub skip_auth: bool,
 
-	/// Allow UDP proxying, requires public-addr to be set
+	/// Allow UDP proxying, requires `public_addr` unless listening on
+	/// loopback
 	pub allow_udp: bool,
+
+	/// Answer Tor's `RESOLVE` extension command through
+	/// [`InboundCallback::resolve`]. `RESOLVE_PTR` is always refused
+	pub allow_resolve: bool,
+
+	/// Listen on `[::]` at the port of `listen`, accepting both IPv4 and
+	/// IPv6 clients
+	pub dual_stack: bool,
+
+	/// Also accept SOCKS4 and SOCKS4a CONNECT. SOCKS4 can't authenticate, so
+	/// its requests are rejected under password auth
+	pub allow_socks4:          bool,
+	/// Accept TCP Fast Open handshakes, Linux and macOS only
+	pub tcp_fast_open:         bool,
+	/// Probe idle clients so dead ones are dropped
+	pub tcp_keepalive:         Option<KeepaliveConfig>,
+	/// Relay UDP without GSO/GRO, for NICs and drivers that mangle batches
+	pub disable_offload:       bool,
+	/// Expect every connection to open with a PROXY protocol v1 or v2 header,
+	/// as sent by load balancers, and take the client address from it
+	pub accept_proxy_protocol: bool,
+	/// Family of the socket relaying each UDP association to the client
+	pub udp_bind_family:       UdpBindFamily,
 }
 
 pub enum AuthMode {
@@ -30,75 +92,356 @@ pub enum AuthMode {
 	Password { username: String, password: String },
 }
 
+/// Family of the socket a UDP association is relayed to the client on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UdpBindFamily {
+	/// Dual-stack IPv6 where the host can, IPv4 otherwise
+	#[default]
+	Auto,
+	/// IPv4 only, for hosts where IPv6 sockets open but aren't usable
+	Ipv4,
+}
+
+impl UdpBindFamily {
+	/// Address to bind to, `None` to pick one as [`Auto`](Self::Auto) does
+	pub fn bind_ip(self) -> Option<IpAddr> {
+		match self {
+			Self::Auto => None,
+			Self::Ipv4 => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listen {
+	Tcp(SocketAddr),
+	/// A Unix domain socket, replacing whatever is at the path. Not available
+	/// on other platforms
+	Unix(PathBuf),
+}
+
+impl From<SocketAddr> for Listen {
+	fn from(addr: SocketAddr) -> Self {
+		Listen::Tcp(addr)
+	}
+}
+
+enum Listener {
+	Tcp(TcpListener),
+	#[cfg(unix)]
+	Unix(UnixListener),
+}
+
+enum Accepted {
+	Tcp(TcpStream, SocketAddr),
+	#[cfg(unix)]
+	Unix(UnixStream),
+}
+
+impl Listener {
+	async fn bind(opts: &SocksInboundOpt) -> io::Result<Self> {
+		match &opts.listen {
+			Listen::Tcp(addr) => {
+				// Under socket activation the listener is already bound
+				#[cfg(unix)]
+				{
+					let addr = if opts.dual_stack {
+						SocketAddr::from((Ipv6Addr::UNSPECIFIED, addr.port()))
+					} else {
+						*addr
+					};
+					if let Some(listener) = wind_core::systemd::take_tcp_listener(addr)? {
+						return Ok(Listener::Tcp(TcpListener::from_std(listener)?));
+					}
+				}
+				let listener = if opts.dual_stack {
+					bind_dual_stack(addr.port())?
+				} else {
+					TcpListener::bind(addr).await?
+				};
+				#[cfg(any(target_os = "linux", target_os = "macos"))]
+				if opts.tcp_fast_open
+					&& let Err(err) = wind_core::tcp::set_fast_open_listener(&listener)
+				{
+					wind_core::warn!(target: "[IN] REACTOR", "TCP Fast Open unavailable, accepting regular handshakes only: {err}");
+				}
+				Ok(Listener::Tcp(listener))
+			}
+			#[cfg(unix)]
+			Listen::Unix(path) => {
+				// A socket left behind by a previous run would fail the bind
+				remove_socket_file(path)?;
+				Ok(Listener::Unix(UnixListener::bind(path)?))
+			}
+			#[cfg(not(unix))]
+			Listen::Unix(_) => Err(io::Error::new(
+				io::ErrorKind::Unsupported,
+				"Unix domain sockets are not supported on this platform",
+			)),
+		}
+	}
+
+	async fn accept(&self) -> io::Result<Accepted> {
+		match self {
+			Listener::Tcp(listener) => {
+				let (stream, client_addr) = listener.accept().await?;
+				Ok(Accepted::Tcp(stream, unmap_v4(client_addr)))
+			}
+			#[cfg(unix)]
+			Listener::Unix(listener) => Ok(Accepted::Unix(listener.accept().await?.0)),
+		}
+	}
+}
+
 pub struct SocksInbound {
-	opts:   SocksInboundOpt,
-	cancel: CancellationToken,
+	opts:        SocksInboundOpt,
+	cancel:      CancellationToken,
+	/// Runtime switch over `opts.allow_udp`, new UDP associations are refused
+	/// while it's off
+	udp_enabled: Arc<AtomicBool>,
+	/// Where failed authentications are reported
+	events:      EventBus,
 }
 
 impl AbstractInbound for SocksInbound {
 	async fn listen(&self, cb: &impl InboundCallback) -> eyre::Result<()> {
-		let listener = TcpListener::bind(self.opts.listen_addr).await?;
+		let listener = Listener::bind(&self.opts).await?;
+		// Clients still in their handshake, so a slow one doesn't hold up the next
+		let mut clients = FuturesUnordered::new();
 		loop {
 			tokio::select! {
 				_ = self.cancel.cancelled() => {
 					info!(target: "[IN] REACTOR", "Cancellation received, shutting down");
 					break;
 				}
+				Some(()) = clients.next() => {}
 				res = listener.accept() => {
-					let (stream, client_addr) = match res {
+					let accepted = match res {
 						Err(err) => {
 							error!(target:"[IN] REACTOR", "{:}", err);
 							continue;
 						}
-						Ok(conn) => conn,
+						Ok(accepted) => accepted,
 					};
-					
-					if let Err(err) = self.handle_income(stream, client_addr, cb).await {
-						error!(target: "[IN] HANDLER" , "{:}", err);
+					clients.push(async move {
+						let res = match accepted {
+							Accepted::Tcp(stream, client_addr) => {
+								if let Some(keepalive) = &self.opts.tcp_keepalive
+									&& let Err(err) = set_keepalive(&stream, keepalive)
+								{
+									warn!(target: "[IN] REACTOR", "Failed to enable keepalive for {client_addr}: {err}");
+								}
+								let local_addr = stream.local_addr().ok().map(unmap_v4);
+								self.handle_income(stream, Some(client_addr), local_addr, cb).await
+							}
+							#[cfg(unix)]
+							Accepted::Unix(stream) => self.handle_income(stream, None, None, cb).await,
+						};
+						if let Err(err) = res {
+							error!(target: "[IN] HANDLER" , "{:}", err);
+						}
 					}
+					.instrument(ConnId::next().span()));
 				}
 			};
 		}
+		// Clients already taken in are let finish, the drain bounds how long
+		while clients.next().await.is_some() {}
+		#[cfg(unix)]
+		if let Listen::Unix(path) = &self.opts.listen {
+			let _ = remove_socket_file(path);
+		}
 		Ok(())
 	}
 }
 
+/// Remove the socket at `path`, refusing to touch anything else found there
+#[cfg(unix)]
+fn remove_socket_file(path: &std::path::Path) -> io::Result<()> {
+	use std::os::unix::fs::FileTypeExt;
+
+	match std::fs::symlink_metadata(path) {
+		Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+		Ok(_) => Err(io::Error::new(
+			io::ErrorKind::AlreadyExists,
+			format!("{} exists and is not a socket, not replacing it", path.display()),
+		)),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+		Err(err) => Err(err),
+	}
+}
+
+/// Bind `[::]:port` with `IPV6_V6ONLY` off, IPv4 clients then show up as
+/// v4-mapped addresses
+fn bind_dual_stack(port: u16) -> std::io::Result<TcpListener> {
+	let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+	socket.set_only_v6(false)?;
+	socket.set_reuse_address(true)?;
+	socket.set_nonblocking(true)?;
+	socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+	socket.listen(1024)?;
+	TcpListener::from_std(socket.into())
+}
+
 impl SocksInbound {
-	pub async fn new(opts: SocksInboundOpt, cancel: CancellationToken) -> Self {
-		Self { opts, cancel }
+	/// Fails when UDP is allowed for remote clients without a `public_addr`,
+	/// as they would be told to send datagrams to 127.0.0.1
+	pub async fn new(opts: SocksInboundOpt, cancel: CancellationToken) -> Result<Self, Error> {
+		if let Listen::Tcp(listen_addr) = opts.listen
+			&& opts.allow_udp
+			&& opts.public_addr.is_none()
+		{
+			// What is actually bound, `dual_stack` takes every address of the port
+			let listen_addr = if opts.dual_stack {
+				SocketAddr::from((Ipv6Addr::UNSPECIFIED, listen_addr.port()))
+			} else {
+				listen_addr
+			};
+			if !listen_addr.ip().is_loopback() {
+				return MissingPublicAddrSnafu { listen_addr }.fail();
+			}
+		}
+		Ok(Self {
+			opts,
+			cancel,
+			udp_enabled: Arc::new(AtomicBool::new(true)),
+			events: EventBus::default(),
+		})
 	}
 
-	async fn handle_income(&self, stream: TcpStream, _client_addr: SocketAddr, cb: &impl InboundCallback) -> Result<(), Error> {
-		let proto = match &self.opts.auth {
-			AuthMode::NoAuth => Socks5ServerProtocol::accept_no_auth(stream).await.context(SocksSnafu)?,
+	/// Consult `udp_enabled` before each UDP association, eg.
+	/// [`AppContext::udp_enabled`](wind_core::AppContext::udp_enabled)
+	pub fn with_udp_enabled(mut self, udp_enabled: Arc<AtomicBool>) -> Self {
+		self.udp_enabled = udp_enabled;
+		self
+	}
+
+	/// Report failed authentications on `events`, eg.
+	/// [`AppContext::events`](wind_core::AppContext::events)
+	pub fn with_events(mut self, events: EventBus) -> Self {
+		self.events = events;
+		self
+	}
+
+	/// BND.ADDR of CONNECT replies, `public_addr` when the client reached us
+	/// over its family and the address it reached otherwise. The outbound
+	/// dials from a port of its own, which isn't known here
+	fn reply_bind_addr(&self, local_addr: Option<SocketAddr>) -> SocketAddr {
+		let Some(local_addr) = local_addr else {
+			return SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+		};
+		let ip = match self.opts.public_addr {
+			Some(public_addr) if public_addr.is_ipv4() == local_addr.is_ipv4() => public_addr,
+			_ => local_addr.ip(),
+		};
+		SocketAddr::new(ip, 0)
+	}
+
+	/// Serve one client, `client_addr` and `local_addr` are `None` for Unix
+	/// domain sockets
+	async fn handle_income(
+		&self,
+		stream: impl AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
+		client_addr: Option<SocketAddr>,
+		local_addr: Option<SocketAddr>,
+		cb: &impl InboundCallback,
+	) -> Result<(), Error> {
+		let mut stream = PeekStream::new(stream);
+		// The balancer's own connections carry no client address
+		let client_addr = if self.opts.accept_proxy_protocol {
+			proxy_protocol::read_header(&mut stream)
+				.await
+				.context(ProxyProtocolSnafu)?
+				.or(client_addr)
+		} else {
+			client_addr
+		};
+		let mut version = [0u8; 1];
+		stream.peek_exact(&mut version).await.context(IoSnafu)?;
+		if version[0] == socks4::SOCKS4_VERSION && self.opts.allow_socks4 {
+			return self.handle_socks4(stream, client_addr, local_addr, cb).await;
+		}
+
+		// The handshake only borrows the stream, so the command can be read and
+		// answered on it directly
+		let negotiated = match &self.opts.auth {
+			AuthMode::NoAuth => Socks5ServerProtocol::accept_no_auth(&mut stream).await.map(|_| ()),
 			AuthMode::Password { username, password } => {
-				Socks5ServerProtocol::accept_password_auth(stream, |user, pass| user == *username && pass == *password)
+				Socks5ServerProtocol::accept_password_auth(&mut stream, |user, pass| user == *username && pass == *password)
 					.await
-					.context(SocksSnafu)?
-					.0
+					.map(|_| ())
 			}
 		};
+		match negotiated {
+			// Already answered with "no acceptable methods" (0xFF), which is all RFC
+			// 1928 asks before closing
+			Err(SocksServerError::AuthMethodUnacceptable(methods)) => {
+				info!(target: "[IN] HANDLER", "Client offered no supported auth method: {methods:02x?}");
+				stream.shutdown().await.context(IoSnafu)?;
+				return Ok(());
+			}
+			Err(err @ SocksServerError::AuthenticationRejected) => {
+				self.events.publish(Event::AuthFailed {
+					inbound: "socks",
+					client_addr,
+				});
+				return Err(err).context(SocksSnafu);
+			}
+			res => res.context(SocksSnafu)?,
+		}
+
+		// Tor's resolve extensions aren't known to fast_socks5, so look at the command
+		// byte before handing the request over
+		let mut head = [0u8; 2];
+		stream.peek_exact(&mut head).await.context(IoSnafu)?;
+		if matches!(head[1], SOCKS5_CMD_TOR_RESOLVE | SOCKS5_CMD_TOR_RESOLVE_PTR) {
+			return self.handle_resolve(&mut stream, cb).await;
+		}
+
+		let proto = Socks5ServerProtocol::skip_auth_this_is_not_rfc_compliant(&mut stream);
 		let (proto, cmd, target_addr) = proto.read_command().await?;
 
 		match cmd {
 			Socks5Command::TCPConnect => {
-				let inner = proto
-					.reply_success(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0))
-					.await?;
 				let target_addr = match target_addr {
 					SocksTargetAddr::Ip(socket_addr) => match socket_addr {
 						SocketAddr::V4(socket_addr) => TargetAddr::IPv4(*socket_addr.ip(), socket_addr.port()),
 						SocketAddr::V6(socket_addr) => TargetAddr::IPv6(*socket_addr.ip(), socket_addr.port()),
 					},
-					SocksTargetAddr::Domain(domain, port) => TargetAddr::Domain(domain, port),
+					SocksTargetAddr::Domain(domain, port) => match normalize_domain(&domain) {
+						Ok(domain) => TargetAddr::Domain(domain, port),
+						Err(err) => {
+							info!(target: "[IN] HANDLER", "Client requested an invalid domain: {err}");
+							proto.reply_error(&ReplyError::AddressTypeNotSupported).await?;
+							return Err(ReplyError::AddressTypeNotSupported.into());
+						}
+					},
 				};
+				if cb.authorize(&self.conn_info(client_addr, Some(target_addr.clone()))).await == Decision::Deny {
+					proto.reply_error(&ReplyError::ConnectionNotAllowed).await?;
+					return Err(ReplyError::ConnectionNotAllowed.into());
+				}
+				let inner = SocksTcpStream::new(stream, self.reply_bind_addr(local_addr))
+					.with_client_addr(client_addr)
+					.with_local_addr(local_addr);
 				cb.handle_tcpstream(target_addr, inner).await.context(CallbackSnafu)?;
 			}
-			Socks5Command::UDPAssociate if self.opts.allow_udp => {
-				let reply_ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
-				crate::ext::run_udp_proxy(proto, &target_addr, None, reply_ip, move |inbound| async move {
+			Socks5Command::UDPAssociate if self.opts.allow_udp && self.udp_enabled.load(Ordering::Relaxed) => {
+				if cb.authorize(&self.conn_info(client_addr, None)).await == Decision::Deny {
+					proto.reply_error(&ReplyError::ConnectionNotAllowed).await?;
+					return Err(ReplyError::ConnectionNotAllowed.into());
+				}
+				let reply_ip = self.opts.public_addr.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+				let disable_offload = self.opts.disable_offload;
+				let bind_ip = self.opts.udp_bind_family.bind_ip();
+				crate::ext::run_udp_proxy(proto, &target_addr, bind_ip, reply_ip, move |inbound, token| async move {
 					// Create a virtual UDP socket that handles SOCKS5 UDP headers
-					let virtual_socket = crate::udp::Socks5UdpSocket::new(inbound.into()).context(IoSnafu)?;
+					let mut virtual_socket = crate::udp::Socks5UdpSocket::new(inbound.into())
+						.context(IoSnafu)?
+						.with_token(token);
+					if disable_offload {
+						virtual_socket = virtual_socket.without_offload().context(IoSnafu)?;
+					}
 					cb.handle_udpsocket(virtual_socket).await.context(CallbackSnafu)
 				})
 				.await?;
@@ -110,4 +453,988 @@ impl SocksInbound {
 		};
 		Ok(())
 	}
+
+	async fn handle_socks4<S>(
+		&self,
+		mut stream: S,
+		client_addr: Option<SocketAddr>,
+		local_addr: Option<SocketAddr>,
+		cb: &impl InboundCallback,
+	) -> Result<(), Error>
+	where
+		S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
+	{
+		stream.read_u8().await.context(IoSnafu)?;
+		let request = socks4::read_request(&mut stream).await?;
+		let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+		let refusal = match &self.opts.auth {
+			_ if request.command != socks4::SOCKS4_CMD_CONNECT => Some(ReplyError::CommandNotSupported),
+			AuthMode::Password { .. } => Some(ReplyError::ConnectionNotAllowed),
+			AuthMode::NoAuth => None,
+		};
+		let refusal = match refusal {
+			None if cb.authorize(&self.conn_info(client_addr, Some(request.target.clone()))).await == Decision::Deny => {
+				Some(ReplyError::ConnectionNotAllowed)
+			}
+			refusal => refusal,
+		};
+		if let Some(err) = refusal {
+			stream
+				.write_all(&socks4::encode_reply(&err, bind_addr))
+				.await
+				.context(IoSnafu)?;
+			return Err(err.into());
+		}
+
+		let inner = SocksTcpStream::socks4(stream, bind_addr)
+			.with_client_addr(client_addr)
+			.with_local_addr(local_addr);
+		cb.handle_tcpstream(request.target, inner).await.context(CallbackSnafu)
+	}
+
+	/// What [`InboundCallback::authorize`] is told of a client
+	fn conn_info(&self, client_addr: Option<SocketAddr>, target: Option<TargetAddr>) -> ConnInfo {
+		let user = match &self.opts.auth {
+			AuthMode::Password { username, .. } => Some(username.clone()),
+			AuthMode::NoAuth => None,
+		};
+		ConnInfo {
+			client_addr,
+			target,
+			user,
+		}
+	}
+
+	async fn handle_resolve(
+		&self,
+		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+		cb: &impl InboundCallback,
+	) -> Result<(), Error> {
+		let mut head = [0u8; 4];
+		stream.read_exact(&mut head).await.context(IoSnafu)?;
+		let [_, cmd, _, atyp] = head;
+		let target_addr = read_address(stream, atyp).await.map_err(SocksServerError::AddrError)?;
+
+		let reply = match (cmd, target_addr) {
+			(SOCKS5_CMD_TOR_RESOLVE, _) if !self.opts.allow_resolve => Err(ReplyError::CommandNotSupported),
+			(SOCKS5_CMD_TOR_RESOLVE, SocksTargetAddr::Ip(addr)) => Ok(addr),
+			(SOCKS5_CMD_TOR_RESOLVE, SocksTargetAddr::Domain(domain, port)) => match normalize_domain(&domain) {
+				// Through the callback, so the query leaves the way a connection would
+				Ok(domain) => cb.resolve(TargetAddr::Domain(domain.clone(), port)).await.map_err(|err| {
+					info!(target: "[IN] RESOLVE", "failed to resolve {domain}: {err}");
+					ReplyError::HostUnreachable
+				}),
+				Err(err) => {
+					info!(target: "[IN] RESOLVE", "Client requested an invalid domain: {err}");
+					Err(ReplyError::AddressTypeNotSupported)
+				}
+			},
+			// No outbound can answer reverse lookups, so RESOLVE_PTR is always refused
+			_ => Err(ReplyError::CommandNotSupported),
+		};
+
+		let buf = match &reply {
+			Ok(addr) => encode_reply(&ReplyError::Succeeded, *addr),
+			Err(err) => encode_reply(err, SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)),
+		};
+		stream.write_all(&buf).await.context(IoSnafu)?;
+		stream.flush().await.context(IoSnafu)?;
+		reply.map(|_| ()).map_err(Into::into)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{sync::Mutex, time::Duration};
+
+	use wind_core::{
+		tcp::AbstractTcpStream,
+		test_util::{DirectCallback, socks5_connect, socks5_request},
+		udp::AbstractUdpSocket,
+	};
+
+	use super::*;
+
+	#[derive(Clone)]
+	struct NoopCallback;
+
+	impl InboundCallback for NoopCallback {
+		async fn handle_tcpstream(
+			&self,
+			_target_addr: TargetAddr,
+			_stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			Ok(())
+		}
+
+		async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			Ok(())
+		}
+	}
+
+	/// Resolves every name to loopback
+	#[derive(Clone)]
+	struct LoopbackResolver;
+
+	impl InboundCallback for LoopbackResolver {
+		async fn handle_tcpstream(
+			&self,
+			_target_addr: TargetAddr,
+			_stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			Ok(())
+		}
+
+		async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			Ok(())
+		}
+
+		async fn resolve(&self, target_addr: TargetAddr) -> eyre::Result<SocketAddr> {
+			match target_addr {
+				TargetAddr::Domain(_, port) => Ok(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)),
+				target_addr => eyre::bail!("{target_addr} is not a name"),
+			}
+		}
+	}
+
+	/// Answers with the requested domain, then echoes
+	#[derive(Clone)]
+	struct EchoCallback;
+
+	impl InboundCallback for EchoCallback {
+		async fn handle_tcpstream(
+			&self,
+			target_addr: TargetAddr,
+			mut stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			stream.on_connect(Ok(())).await?;
+			if let TargetAddr::Domain(domain, _) = target_addr {
+				stream.write_all(domain.as_bytes()).await?;
+			}
+			let mut buf = [0u8; 4];
+			stream.read_exact(&mut buf).await?;
+			stream.write_all(&buf).await?;
+			Ok(())
+		}
+
+		async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			Ok(())
+		}
+	}
+
+	/// Client and local address a stream reported
+	type StreamAddrs = (Option<SocketAddr>, Option<SocketAddr>);
+
+	/// Remembers the addresses each stream reports
+	#[derive(Clone, Default)]
+	struct ClientAddrCallback(Arc<Mutex<Vec<StreamAddrs>>>);
+
+	impl InboundCallback for ClientAddrCallback {
+		async fn handle_tcpstream(
+			&self,
+			_target_addr: TargetAddr,
+			mut stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			self.0.lock().unwrap().push((stream.client_addr(), stream.local_addr()));
+			stream.on_connect(Ok(())).await?;
+			Ok(())
+		}
+
+		async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			Ok(())
+		}
+	}
+
+	/// Refuses every connection, remembering what it was asked about
+	#[derive(Clone, Default)]
+	struct DenyCallback(Arc<Mutex<Vec<ConnInfo>>>);
+
+	impl InboundCallback for DenyCallback {
+		async fn authorize(&self, info: &ConnInfo) -> Decision {
+			self.0.lock().unwrap().push(info.clone());
+			Decision::Deny
+		}
+
+		async fn handle_tcpstream(
+			&self,
+			_target_addr: TargetAddr,
+			_stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			panic!("denied connections must not be relayed")
+		}
+
+		async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			panic!("denied associations must not be relayed")
+		}
+	}
+
+	/// Holds each UDP association until the inbound ends it
+	#[derive(Clone)]
+	struct AssocCallback(Arc<AtomicBool>);
+
+	impl InboundCallback for AssocCallback {
+		async fn handle_tcpstream(
+			&self,
+			_target_addr: TargetAddr,
+			_stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			Ok(())
+		}
+
+		async fn handle_udpsocket(&self, socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			socket.association_token().cancelled().await;
+			self.0.store(true, Ordering::Relaxed);
+			Ok(())
+		}
+	}
+
+	/// Also what a build of wind-core without `quic` is checked with
+	#[tokio::test]
+	async fn test_connect_through_direct_outbound() {
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target_addr = target.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut stream, _) = target.accept().await.unwrap();
+			let mut buf = [0u8; 4];
+			stream.read_exact(&mut buf).await.unwrap();
+			stream.write_all(&buf).await.unwrap();
+		});
+
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { inbound.listen(&DirectCallback).await });
+		tokio::task::yield_now().await;
+
+		let (mut client, reply) = socks5_connect(listen_addr, target_addr).await.unwrap();
+		assert_eq!(reply, 0);
+		client.write_all(b"ping").await.unwrap();
+		let mut buf = [0u8; 4];
+		client.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"ping");
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_connect_refused_reply() {
+		// Bound and dropped, so nothing listens there
+		let target_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { inbound.listen(&DirectCallback).await });
+		tokio::task::yield_now().await;
+
+		let (mut client, reply) = socks5_connect(listen_addr, target_addr).await.unwrap();
+		// Connection refused, and nothing follows
+		assert_eq!(reply, 5);
+		assert_eq!(client.read(&mut [0u8; 1]).await.unwrap(), 0);
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_tor_resolve() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         true,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { inbound.listen(&LoopbackResolver).await });
+		tokio::task::yield_now().await;
+
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client.write_all(&[5, 1, 0]).await.unwrap();
+		let mut method = [0u8; 2];
+		client.read_exact(&mut method).await.unwrap();
+		assert_eq!(method, [5, 0]);
+
+		let mut req = vec![5, SOCKS5_CMD_TOR_RESOLVE, 0, 3, 9];
+		req.extend_from_slice(b"localhost");
+		req.extend_from_slice(&0u16.to_be_bytes());
+		client.write_all(&req).await.unwrap();
+
+		let mut head = [0u8; 4];
+		client.read_exact(&mut head).await.unwrap();
+		assert_eq!(head[..2], [5, 0]);
+		let ip = match head[3] {
+			1 => {
+				let mut ip = [0u8; 4];
+				client.read_exact(&mut ip).await.unwrap();
+				IpAddr::from(ip)
+			}
+			4 => {
+				let mut ip = [0u8; 16];
+				client.read_exact(&mut ip).await.unwrap();
+				IpAddr::from(ip)
+			}
+			atyp => panic!("unexpected address type {atyp}"),
+		};
+		assert!(ip.is_loopback());
+
+		// A callback without a resolver refuses
+		let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let refusing = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         true,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { refusing.listen(&NoopCallback).await });
+		tokio::task::yield_now().await;
+		let mut client = TcpStream::connect(addr).await.unwrap();
+		client.write_all(&[5, 1, 0]).await.unwrap();
+		client.read_exact(&mut method).await.unwrap();
+		client.write_all(&req).await.unwrap();
+		client.read_exact(&mut head).await.unwrap();
+		assert_eq!(head[..2], [5, 4]);
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_unsupported_auth_methods() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::Password {
+					username: "user".to_string(),
+					password: "pass".to_string(),
+				},
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { inbound.listen(&NoopCallback).await });
+		tokio::task::yield_now().await;
+
+		// GSSAPI only
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client.write_all(&[5, 1, 1]).await.unwrap();
+		let mut method = [0u8; 2];
+		client.read_exact(&mut method).await.unwrap();
+		assert_eq!(method, [5, 0xFF]);
+		let mut rest = Vec::new();
+		assert_eq!(client.read_to_end(&mut rest).await.unwrap(), 0);
+
+		// GSSAPI or username/password
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client.write_all(&[5, 2, 1, 2]).await.unwrap();
+		client.read_exact(&mut method).await.unwrap();
+		assert_eq!(method, [5, 2]);
+		client.write_all(b"\x01\x04user\x04pass").await.unwrap();
+		let mut status = [0u8; 2];
+		client.read_exact(&mut status).await.unwrap();
+		assert_eq!(status, [1, 0]);
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_denied_connect() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::Password {
+					username: "user".to_string(),
+					password: "pass".to_string(),
+				},
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		let cb = DenyCallback::default();
+		let cb_clone = cb.clone();
+		tokio::spawn(async move { inbound.listen(&cb_clone).await });
+		tokio::task::yield_now().await;
+
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client.write_all(&[5, 1, 2]).await.unwrap();
+		let mut method = [0u8; 2];
+		client.read_exact(&mut method).await.unwrap();
+		client.write_all(b"\x01\x04user\x04pass").await.unwrap();
+		let mut status = [0u8; 2];
+		client.read_exact(&mut status).await.unwrap();
+		assert_eq!(status, [1, 0]);
+
+		let mut req = vec![5, 1, 0, 3, 11];
+		req.extend_from_slice(b"example.com");
+		req.extend_from_slice(&443u16.to_be_bytes());
+		client.write_all(&req).await.unwrap();
+		let mut reply = [0u8; 2];
+		client.read_exact(&mut reply).await.unwrap();
+		// Connection not allowed by ruleset
+		assert_eq!(reply, [5, 2]);
+
+		let infos = cb.0.lock().unwrap().clone();
+		assert_eq!(infos.len(), 1);
+		assert_eq!(infos[0].client_addr, Some(client.local_addr().unwrap()));
+		assert_eq!(infos[0].target, Some(TargetAddr::Domain("example.com".into(), 443)));
+		assert_eq!(infos[0].user.as_deref(), Some("user"));
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_dual_stack() {
+		let port = bind_dual_stack(0).unwrap().local_addr().unwrap().port();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                SocketAddr::from((Ipv4Addr::LOCALHOST, port)).into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            true,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { inbound.listen(&NoopCallback).await });
+		tokio::task::yield_now().await;
+
+		for ip in [IpAddr::from(Ipv4Addr::LOCALHOST), IpAddr::from(Ipv6Addr::LOCALHOST)] {
+			let mut client = TcpStream::connect((ip, port)).await.unwrap();
+			client.write_all(&[5, 1, 0]).await.unwrap();
+			let mut method = [0u8; 2];
+			client.read_exact(&mut method).await.unwrap();
+			assert_eq!(method, [5, 0], "{ip}");
+		}
+		cancel.cancel();
+
+		let mapped = SocketAddr::from((Ipv4Addr::LOCALHOST.to_ipv6_mapped(), 1080));
+		assert_eq!(unmap_v4(mapped), SocketAddr::from((Ipv4Addr::LOCALHOST, 1080)));
+	}
+
+	#[tokio::test]
+	async fn test_bind_addr_matches_client_family() {
+		let port = bind_dual_stack(0).unwrap().local_addr().unwrap().port();
+		let public_addr = Ipv4Addr::new(203, 0, 113, 1);
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                SocketAddr::from((Ipv4Addr::LOCALHOST, port)).into(),
+				public_addr:           Some(public_addr.into()),
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            true,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { inbound.listen(&ClientAddrCallback::default()).await });
+		tokio::task::yield_now().await;
+
+		let connect = async |ip: IpAddr, reply_len: usize| {
+			let mut client = TcpStream::connect((ip, port)).await.unwrap();
+			client.write_all(&[5, 1, 0]).await.unwrap();
+			let mut method = [0u8; 2];
+			client.read_exact(&mut method).await.unwrap();
+			client.write_all(&[5, 1, 0, 1, 127, 0, 0, 1, 0, 80]).await.unwrap();
+			let mut reply = vec![0u8; reply_len];
+			client.read_exact(&mut reply).await.unwrap();
+			reply
+		};
+
+		let reply = connect(Ipv6Addr::LOCALHOST.into(), 22).await;
+		assert_eq!(reply[..4], [5, 0, 0, 4]);
+		assert_eq!(reply[4..20], Ipv6Addr::LOCALHOST.octets());
+		assert_eq!(reply[20..], [0, 0]);
+
+		// IPv4 clients are told the public address
+		let reply = connect(Ipv4Addr::LOCALHOST.into(), 10).await;
+		assert_eq!(reply[..4], [5, 0, 0, 1]);
+		assert_eq!(reply[4..8], public_addr.octets());
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_udp_requires_public_addr() {
+		let opts = |listen: SocketAddr, public_addr: Option<IpAddr>| SocksInboundOpt {
+			listen: listen.into(),
+			public_addr,
+			auth: AuthMode::NoAuth,
+			skip_auth: false,
+			allow_udp: true,
+			allow_resolve: false,
+			dual_stack: false,
+			allow_socks4: false,
+			tcp_fast_open: false,
+			tcp_keepalive: None,
+			disable_offload: false,
+			accept_proxy_protocol: false,
+			udp_bind_family: UdpBindFamily::Auto,
+		};
+		let remote = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 1080));
+
+		let err = SocksInbound::new(opts(remote, None), CancellationToken::new())
+			.await
+			.err()
+			.unwrap();
+		assert!(matches!(err, Error::MissingPublicAddr { listen_addr, .. } if listen_addr == remote));
+
+		let public_addr = Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)));
+		assert!(
+			SocksInbound::new(opts(remote, public_addr), CancellationToken::new())
+				.await
+				.is_ok()
+		);
+		let local = SocketAddr::from((Ipv4Addr::LOCALHOST, 1080));
+		assert!(SocksInbound::new(opts(local, None), CancellationToken::new()).await.is_ok());
+
+		// Dual stack binds `[::]` whatever address is configured
+		let dual_stack = SocksInboundOpt {
+			dual_stack: true,
+			..opts(local, None)
+		};
+		let err = SocksInbound::new(dual_stack, CancellationToken::new()).await.err().unwrap();
+		let bound = SocketAddr::from((Ipv6Addr::UNSPECIFIED, 1080));
+		assert!(matches!(err, Error::MissingPublicAddr { listen_addr, .. } if listen_addr == bound));
+	}
+
+	#[tokio::test]
+	async fn test_socks4a_connect() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          true,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { inbound.listen(&EchoCallback).await });
+		tokio::task::yield_now().await;
+
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		let mut req = vec![4, 1, 0, 80, 0, 0, 0, 1, b'u', 0];
+		req.extend_from_slice(b"example.com\0");
+		client.write_all(&req).await.unwrap();
+		let mut reply = [0u8; 8];
+		client.read_exact(&mut reply).await.unwrap();
+		assert_eq!(reply[..2], [0, 0x5A]);
+		let mut domain = [0u8; 11];
+		client.read_exact(&mut domain).await.unwrap();
+		assert_eq!(&domain, b"example.com");
+		client.write_all(b"ping").await.unwrap();
+		let mut echo = [0u8; 4];
+		client.read_exact(&mut echo).await.unwrap();
+		assert_eq!(&echo, b"ping");
+		drop(client);
+
+		// SOCKS5 clients are still told apart by their version byte
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client.write_all(&[5, 1, 0]).await.unwrap();
+		let mut method = [0u8; 2];
+		client.read_exact(&mut method).await.unwrap();
+		assert_eq!(method, [5, 0]);
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_stalled_handshake_does_not_block_others() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		tokio::spawn(async move { inbound.listen(&EchoCallback).await });
+		tokio::task::yield_now().await;
+
+		// Stops halfway through its greeting
+		let mut stalled = TcpStream::connect(listen_addr).await.unwrap();
+		stalled.write_all(&[5]).await.unwrap();
+		tokio::time::sleep(Duration::from_millis(50)).await;
+
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client.write_all(&[5, 1, 0]).await.unwrap();
+		let mut method = [0u8; 2];
+		tokio::time::timeout(Duration::from_secs(1), client.read_exact(&mut method))
+			.await
+			.expect("the stalled client held up the handshake")
+			.unwrap();
+		assert_eq!(method, [5, 0]);
+		drop(stalled);
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_proxy_protocol_client_addr() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: true,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		let cb = ClientAddrCallback::default();
+		let seen = cb.0.clone();
+		tokio::spawn(async move { inbound.listen(&cb).await });
+		tokio::task::yield_now().await;
+
+		// The balancer's header, then the client's SOCKS5 handshake
+		let real_client: SocketAddr = "203.0.113.9:4242".parse().unwrap();
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		let mut req = proxy_protocol::encode_v2(Some(real_client), listen_addr);
+		req.extend_from_slice(&[5, 1, 0]);
+		client.write_all(&req).await.unwrap();
+		let mut method = [0u8; 2];
+		client.read_exact(&mut method).await.unwrap();
+		assert_eq!(method, [5, 0]);
+		client.write_all(&[5, 1, 0, 1, 127, 0, 0, 1, 0, 80]).await.unwrap();
+		let mut reply = [0u8; 10];
+		client.read_exact(&mut reply).await.unwrap();
+		assert_eq!(reply[1], 0);
+		drop(client);
+
+		// Without the header the client is dropped before any reply
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		client.write_all(&[5, 1, 0, 0, 0]).await.unwrap();
+		let mut rest = Vec::new();
+		client.read_to_end(&mut rest).await.unwrap();
+		assert!(rest.is_empty());
+
+		assert_eq!(*seen.lock().unwrap(), [(Some(real_client), Some(listen_addr))]);
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_callback_sees_stream_addrs() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          true,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		let cb = ClientAddrCallback::default();
+		let seen = cb.0.clone();
+		tokio::spawn(async move { inbound.listen(&cb).await });
+		tokio::task::yield_now().await;
+
+		let (client, reply) = socks5_connect(listen_addr, SocketAddr::from((Ipv4Addr::LOCALHOST, 80)))
+			.await
+			.unwrap();
+		assert_eq!(reply, 0);
+		let socks5_client = client.local_addr().unwrap();
+
+		// SOCKS4 CONNECT to 127.0.0.1:80 with an empty user id
+		let mut client = TcpStream::connect(listen_addr).await.unwrap();
+		let socks4_client = client.local_addr().unwrap();
+		client.write_all(&[4, 1, 0, 80, 127, 0, 0, 1, 0]).await.unwrap();
+		let mut reply = [0u8; 8];
+		client.read_exact(&mut reply).await.unwrap();
+		assert_eq!(reply[1], 0x5a);
+
+		assert_eq!(
+			*seen.lock().unwrap(),
+			[
+				(Some(socks5_client), Some(listen_addr)),
+				(Some(socks4_client), Some(listen_addr))
+			]
+		);
+		cancel.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_udp_association_ends_with_control() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             true,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		let ended = Arc::new(AtomicBool::new(false));
+		let cb = AssocCallback(ended.clone());
+		tokio::spawn(async move { inbound.listen(&cb).await });
+		tokio::task::yield_now().await;
+
+		let (client, reply) = socks5_request(listen_addr, 3, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+			.await
+			.unwrap();
+		assert_eq!(reply, 0);
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert!(!ended.load(Ordering::Relaxed));
+
+		drop(client);
+		tokio::time::timeout(Duration::from_secs(1), async {
+			while !ended.load(Ordering::Relaxed) {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await
+		.unwrap();
+		cancel.cancel();
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_unix_socket_connect() {
+		let path = std::env::temp_dir().join(format!("wind-socks-{}.sock", std::process::id()));
+		// Left over from an earlier run, the bind replaces it
+		drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+		let cancel = CancellationToken::new();
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                Listen::Unix(path.clone()),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			cancel.clone(),
+		)
+		.await
+		.unwrap();
+		let server = tokio::spawn(async move { inbound.listen(&EchoCallback).await });
+		tokio::time::sleep(Duration::from_millis(50)).await;
+
+		let mut client = UnixStream::connect(&path).await.unwrap();
+		client.write_all(&[5, 1, 0]).await.unwrap();
+		let mut method = [0u8; 2];
+		client.read_exact(&mut method).await.unwrap();
+		assert_eq!(method, [5, 0]);
+		let mut req = vec![5, 1, 0, 3, 11];
+		req.extend_from_slice(b"example.com");
+		req.extend_from_slice(&80u16.to_be_bytes());
+		client.write_all(&req).await.unwrap();
+		let mut reply = [0u8; 10];
+		client.read_exact(&mut reply).await.unwrap();
+		assert_eq!(reply[1], 0);
+		let mut domain = [0u8; 11];
+		client.read_exact(&mut domain).await.unwrap();
+		assert_eq!(&domain, b"example.com");
+		client.write_all(b"ping").await.unwrap();
+		let mut echo = [0u8; 4];
+		client.read_exact(&mut echo).await.unwrap();
+		assert_eq!(&echo, b"ping");
+
+		cancel.cancel();
+		server.await.unwrap().unwrap();
+		assert!(!path.exists());
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_unix_socket_keeps_other_files() {
+		let path = std::env::temp_dir().join(format!("wind-socks-{}.file", std::process::id()));
+		std::fs::write(&path, b"data").unwrap();
+		let err = Listener::bind(&SocksInboundOpt {
+			listen:                Listen::Unix(path.clone()),
+			public_addr:           None,
+			auth:                  AuthMode::NoAuth,
+			skip_auth:             false,
+			allow_udp:             false,
+			allow_resolve:         false,
+			dual_stack:            false,
+			allow_socks4:          false,
+			tcp_fast_open:         false,
+			tcp_keepalive:         None,
+			disable_offload:       false,
+			accept_proxy_protocol: false,
+			udp_bind_family:       UdpBindFamily::Auto,
+		})
+		.await
+		.err()
+		.unwrap();
+		assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+		assert_eq!(std::fs::read(&path).unwrap(), b"data");
+		std::fs::remove_file(&path).unwrap();
+	}
 }
@@ -8,6 +8,9 @@ use wind_core::types::TargetAddr;
 
 pub mod ext;
 pub mod inbound;
+pub mod outbound;
+pub mod socks4;
+pub mod stream;
 pub mod udp;
 
 #[derive(Debug, Snafu)]
@@ -38,6 +41,16 @@ pub enum Error {
 		source:    eyre::Report,
 		backtrace: Backtrace,
 	},
+	#[snafu(display("Invalid PROXY protocol header"))]
+	ProxyProtocol {
+		source:    std::io::Error,
+		backtrace: Backtrace,
+	},
+	#[snafu(display("UDP is allowed on {listen_addr} but public_addr is unset, remote clients would be sent to 127.0.0.1"))]
+	MissingPublicAddr {
+		listen_addr: SocketAddr,
+		backtrace:   Backtrace,
+	},
 }
 
 impl From<SocksServerError> for Error {
@@ -63,3 +76,11 @@ pub fn convert_addr(addr: &SocksTargetAddr) -> TargetAddr {
 		},
 	}
 }
+
+pub fn socks_addr(addr: &TargetAddr) -> SocksTargetAddr {
+	match addr {
+		TargetAddr::Domain(domain, port) => SocksTargetAddr::Domain(domain.clone(), *port),
+		TargetAddr::IPv4(ip, port) => SocksTargetAddr::Ip(SocketAddr::new((*ip).into(), *port)),
+		TargetAddr::IPv6(ip, port) => SocksTargetAddr::Ip(SocketAddr::new((*ip).into(), *port)),
+	}
+}
@@ -0,0 +1,285 @@
+use std::{
+	io::IoSliceMut,
+	net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+	sync::Arc,
+};
+
+use fast_socks5::{
+	AuthenticationMethod, ReplyError, Socks5Command, SocksError,
+	client::{Config, Socks5Datagram, Socks5Stream},
+	util::target_addr::TargetAddr as SocksTargetAddr,
+};
+use tokio::net::{TcpStream, UdpSocket};
+use wind_core::{
+	AbstractOutbound, debug,
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+	udp::{AbstractUdpSocket, BufferPool, RecvMeta},
+	warn,
+};
+
+use crate::socks_addr;
+
+/// Relays through an upstream SOCKS5 proxy, chaining this proxy in front of
+/// another one
+///
+/// TCP streams are opened with CONNECT. UDP associations get an ASSOCIATE of
+/// their own on the upstream, which lasts as long as the local association.
+#[derive(Debug, Clone)]
+pub struct SocksOutbound {
+	server:   SocketAddr,
+	/// Username and password, when the upstream asks for them
+	auth:     Option<(String, String)>,
+	/// Relay buffers of the UDP associations, reused by the next ones
+	udp_pool: Arc<BufferPool>,
+}
+
+impl SocksOutbound {
+	pub fn new(server: SocketAddr) -> Self {
+		Self {
+			server,
+			auth: None,
+			udp_pool: BufferPool::new(64),
+		}
+	}
+
+	/// Authenticate to the upstream with a username and password
+	pub fn with_password(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+		self.auth = Some((username.into(), password.into()));
+		self
+	}
+
+	async fn connect(&self, target_addr: &TargetAddr) -> Result<Socks5Stream<TcpStream>, SocksError> {
+		let socket = TcpStream::connect(self.server).await?;
+		let auth = self
+			.auth
+			.clone()
+			.map(|(username, password)| AuthenticationMethod::Password { username, password });
+		let mut stream = Socks5Stream::use_stream(socket, auth, Config::default()).await?;
+		stream.request(Socks5Command::TCPConnect, socks_addr(target_addr)).await?;
+		Ok(stream)
+	}
+
+	async fn associate(&self) -> Result<Socks5Datagram<TcpStream>, SocksError> {
+		let socket = TcpStream::connect(self.server).await?;
+		let bind_addr = match self.server {
+			SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+			SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+		};
+		let out_sock = UdpSocket::bind(bind_addr).await?;
+		match &self.auth {
+			Some((username, password)) => Socks5Datagram::use_socket_with_password(socket, out_sock, username, password).await,
+			None => Socks5Datagram::use_socket(socket, out_sock).await,
+		}
+	}
+}
+
+/// Classify a failed upstream handshake for the inbound's reply
+fn connect_error(err: &SocksError) -> ConnectError {
+	match err {
+		SocksError::Io(err) => ConnectError::from(err),
+		SocksError::ReplyError(ReplyError::ConnectionNotAllowed) | SocksError::AuthenticationRejected(_) => {
+			ConnectError::NotAllowed
+		}
+		SocksError::ReplyError(ReplyError::NetworkUnreachable) => ConnectError::NetworkUnreachable,
+		SocksError::ReplyError(ReplyError::HostUnreachable) => ConnectError::HostUnreachable,
+		SocksError::ReplyError(ReplyError::ConnectionRefused) => ConnectError::ConnectionRefused,
+		SocksError::ReplyError(ReplyError::ConnectionTimeout | ReplyError::TtlExpired) => ConnectError::TimedOut,
+		_ => ConnectError::General,
+	}
+}
+
+impl AbstractOutbound for SocksOutbound {
+	async fn handle_tcp(
+		&self,
+		target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let mut upstream = match self.connect(&target_addr).await {
+			Ok(upstream) => upstream,
+			Err(err) => {
+				stream.on_connect(Err(connect_error(&err))).await?;
+				return Err(eyre::eyre!(
+					"upstream SOCKS5 {} failed to connect {target_addr}: {err}",
+					self.server
+				));
+			}
+		};
+		stream.on_connect(Ok(())).await?;
+		let (_, _, err) = wind_core::io::copy_io(&mut stream, &mut upstream).await;
+		if let Some(err) = err {
+			return Err(err.into());
+		}
+		Ok(())
+	}
+
+	async fn handle_udp(
+		&self,
+		socket: impl AbstractUdpSocket + 'static,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let datagram = self
+			.associate()
+			.await
+			.map_err(|err| eyre::eyre!("upstream SOCKS5 {} refused the UDP association: {err}", self.server))?;
+		debug!(target: "[OUT] SOCKS", "UDP associated through {}", self.server);
+		let closed = socket.association_token();
+
+		let to_upstream = async {
+			let mut buf = self.udp_pool.take(u16::MAX as usize);
+			let mut meta = RecvMeta::default();
+			loop {
+				// Polled by hand since the `recv` future isn't `Sync`
+				std::future::poll_fn(|cx| {
+					socket.poll_recv(cx, &mut [IoSliceMut::new(&mut buf)], std::slice::from_mut(&mut meta))
+				})
+				.await?;
+				let Some(target_addr) = &meta.destination else {
+					warn!(target: "[OUT] SOCKS", "Dropping UDP packet from {} without a destination", meta.addr);
+					continue;
+				};
+				datagram.send_to(&buf[..meta.len], socks_addr(target_addr)).await?;
+			}
+		};
+		let to_local = async {
+			let mut buf = self.udp_pool.take(u16::MAX as usize);
+			loop {
+				let (len, source) = match datagram.recv_from(&mut buf).await {
+					Ok(received) => received,
+					Err(SocksError::Io(err)) => return Err(err.into()),
+					Err(err) => {
+						warn!(target: "[OUT] SOCKS", "Dropping malformed UDP packet from {}: {err}", self.server);
+						continue;
+					}
+				};
+				let source = match source {
+					SocksTargetAddr::Ip(addr) => addr,
+					SocksTargetAddr::Domain(..) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+				};
+				// Polled by hand as well, callbacks' futures must be `Sync`
+				std::future::poll_fn(|cx| socket.poll_send(cx, &buf[..len], source)).await?;
+			}
+		};
+		tokio::select! {
+			_ = closed.cancelled() => Ok(()),
+			res = to_upstream => res,
+			res = to_local => res,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use fast_socks5::server::{Socks5ServerProtocol, run_tcp_proxy, run_udp_proxy};
+	use tokio::{
+		io::{AsyncReadExt, AsyncWriteExt},
+		net::TcpListener,
+	};
+
+	use super::*;
+	use crate::udp::Socks5UdpSocket;
+
+	/// Upstream SOCKS5 proxy allowing CONNECT and UDP ASSOCIATE without auth
+	async fn spawn_upstream() -> SocketAddr {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move {
+			loop {
+				let (socket, _) = listener.accept().await.unwrap();
+				tokio::spawn(async move {
+					let (proto, cmd, target_addr) = Socks5ServerProtocol::accept_no_auth(socket).await?.read_command().await?;
+					match cmd {
+						Socks5Command::TCPConnect => {
+							run_tcp_proxy(proto, &target_addr, 5, false).await?;
+						}
+						Socks5Command::UDPAssociate => {
+							run_udp_proxy(proto, &target_addr, None, Ipv4Addr::LOCALHOST.into(), None).await?;
+						}
+						Socks5Command::TCPBind => proto.reply_error(&ReplyError::CommandNotSupported).await?,
+					}
+					Ok::<_, SocksError>(())
+				});
+			}
+		});
+		addr
+	}
+
+	#[tokio::test]
+	async fn test_tcp_through_upstream() {
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target_addr = target.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut conn, _) = target.accept().await.unwrap();
+			let (mut rx, mut tx) = conn.split();
+			tokio::io::copy(&mut rx, &mut tx).await.unwrap();
+		});
+		let outbound = SocksOutbound::new(spawn_upstream().await);
+
+		let (mut client, stream) = tokio::io::duplex(64);
+		let target_addr = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, target_addr.port());
+		let relay = tokio::spawn(async move { outbound.handle_tcp(target_addr, stream, None::<SocksOutbound>).await });
+		client.write_all(b"hello").await.unwrap();
+		let mut buf = [0u8; 5];
+		client.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"hello");
+		// The relay ends once either side hangs up
+		drop(client);
+		relay.await.unwrap().unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_dns_query_through_upstream() {
+		// Answers every query for an A record with 192.0.2.1
+		let resolver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let resolver_port = resolver.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			let mut buf = [0u8; 512];
+			loop {
+				let (len, from) = resolver.recv_from(&mut buf).await.unwrap();
+				let mut answer = buf[..len].to_vec();
+				// QR and RD/RA set, one answer
+				answer[2..4].copy_from_slice(&[0x81, 0x80]);
+				answer[6..8].copy_from_slice(&[0, 1]);
+				answer.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+				resolver.send_to(&answer, from).await.unwrap();
+			}
+		});
+
+		// The local association, as the SOCKS inbound hands it to outbounds
+		let socket = Socks5UdpSocket::new(std::net::UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+		let socket_addr = socket.local_addr().unwrap();
+		let outbound = SocksOutbound::new(spawn_upstream().await);
+		let pool = outbound.udp_pool.clone();
+		let relay = tokio::spawn(async move { outbound.handle_udp(socket, None::<SocksOutbound>).await });
+
+		let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let query = [
+			&[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0][..],
+			b"\x07example\x03com\x00",
+			&[0, 1, 0, 1],
+		]
+		.concat();
+		// RSV, FRAG, ATYP IPv4, then the resolver's address
+		let header = [&[0, 0, 0, 1, 127, 0, 0, 1][..], &resolver_port.to_be_bytes()].concat();
+		client.send_to(&[&header[..], &query].concat(), socket_addr).await.unwrap();
+
+		let mut buf = [0u8; 512];
+		let len = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf))
+			.await
+			.expect("no DNS answer came back")
+			.unwrap();
+		let answer = &buf[header.len()..len];
+		assert_eq!(&answer[..2], &[0x12, 0x34]);
+		assert_eq!(answer[2] & 0x80, 0x80);
+		assert_eq!(&answer[answer.len() - 4..], &[192, 0, 2, 1]);
+		relay.abort();
+		let _ = relay.await;
+
+		// The association's buffers went back for the next one
+		let _reused = (pool.take(1), pool.take(1));
+		assert_eq!(pool.allocated(), 2);
+	}
+}
@@ -0,0 +1,98 @@
+//! SOCKS4 and SOCKS4a CONNECT, for legacy clients
+//!
+//! See <https://www.openssh.com/txt/socks4.protocol> and
+//! <https://www.openssh.com/txt/socks4a.protocol>.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use fast_socks5::ReplyError;
+use snafu::ResultExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use wind_core::types::{TargetAddr, normalize_domain};
+
+use crate::{Error, IoSnafu};
+
+pub const SOCKS4_VERSION: u8 = 0x04;
+pub const SOCKS4_CMD_CONNECT: u8 = 0x01;
+/// Reply version byte, which is not the protocol version
+const SOCKS4_REPLY_VERSION: u8 = 0x00;
+const SOCKS4_GRANTED: u8 = 0x5A;
+const SOCKS4_REJECTED: u8 = 0x5B;
+/// Longest user id or domain accepted before giving up on the terminator
+const MAX_FIELD_LEN: usize = 255;
+
+/// A parsed SOCKS4 request, the version byte already consumed
+pub struct Request {
+	pub command: u8,
+	pub target:  TargetAddr,
+}
+
+/// Read a request after its version byte. A destination of `0.0.0.x` with a
+/// non-zero `x` is the SOCKS4a marker for a domain following the user id.
+pub async fn read_request<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Request, Error> {
+	let mut head = [0u8; 7];
+	reader.read_exact(&mut head).await.context(IoSnafu)?;
+	let [command, port_hi, port_lo, a, b, c, d] = head;
+	let port = u16::from_be_bytes([port_hi, port_lo]);
+	let ip = Ipv4Addr::new(a, b, c, d);
+
+	// The user id is only meaningful for identd, which isn't supported
+	read_nul_terminated(reader).await?;
+	let target = if a == 0 && b == 0 && c == 0 && d != 0 {
+		let domain = read_nul_terminated(reader).await?;
+		let domain = String::from_utf8(domain).map_err(|_| ReplyError::AddressTypeNotSupported)?;
+		let domain = normalize_domain(&domain).map_err(|_| ReplyError::AddressTypeNotSupported)?;
+		TargetAddr::Domain(domain, port)
+	} else {
+		TargetAddr::IPv4(ip, port)
+	};
+	Ok(Request { command, target })
+}
+
+async fn read_nul_terminated<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, Error> {
+	let mut field = Vec::new();
+	loop {
+		match reader.read_u8().await.context(IoSnafu)? {
+			0 => return Ok(field),
+			_ if field.len() == MAX_FIELD_LEN => return Err(ReplyError::GeneralFailure.into()),
+			byte => field.push(byte),
+		}
+	}
+}
+
+/// Encode a SOCKS4 reply, which only tells granted from rejected
+pub fn encode_reply(reply: &ReplyError, bind_addr: SocketAddr) -> Vec<u8> {
+	let code = match reply {
+		ReplyError::Succeeded => SOCKS4_GRANTED,
+		_ => SOCKS4_REJECTED,
+	};
+	let ip = match bind_addr {
+		SocketAddr::V4(addr) => *addr.ip(),
+		SocketAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+	};
+	let mut buf = Vec::with_capacity(8);
+	buf.extend_from_slice(&[SOCKS4_REPLY_VERSION, code]);
+	buf.extend_from_slice(&bind_addr.port().to_be_bytes());
+	buf.extend_from_slice(&ip.octets());
+	buf
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_read_request() {
+		let mut req: &[u8] = &[1, 0, 80, 10, 0, 0, 1, b'u', 0];
+		let request = read_request(&mut req).await.unwrap();
+		assert_eq!(request.command, SOCKS4_CMD_CONNECT);
+		assert_eq!(request.target, TargetAddr::IPv4(Ipv4Addr::new(10, 0, 0, 1), 80));
+
+		let mut req: &[u8] = &[1, 1, 187, 0, 0, 0, 1, 0, b'e', b'x', 0];
+		let request = read_request(&mut req).await.unwrap();
+		assert_eq!(request.target, TargetAddr::Domain("ex".into(), 443));
+
+		let mut req: &[u8] = &[1, 0, 80, 10, 0, 0, 1];
+		assert!(read_request(&mut req).await.is_err());
+	}
+}
@@ -0,0 +1,274 @@
+use std::{
+	io,
+	net::SocketAddr,
+	pin::Pin,
+	task::{Context, Poll, ready},
+};
+
+use fast_socks5::{ReplyError, consts};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use wind_core::tcp::{AbstractTcpStream, ConnectError};
+
+/// Encode a SOCKS5 reply as described in RFC 1928 section 6
+pub fn encode_reply(reply: &ReplyError, bind_addr: SocketAddr) -> Vec<u8> {
+	let mut buf = Vec::with_capacity(22);
+	buf.extend_from_slice(&[consts::SOCKS5_VERSION, reply.as_u8(), 0x00]);
+	match bind_addr {
+		SocketAddr::V4(addr) => {
+			buf.push(consts::SOCKS5_ADDR_TYPE_IPV4);
+			buf.extend_from_slice(&addr.ip().octets());
+		}
+		SocketAddr::V6(addr) => {
+			buf.push(consts::SOCKS5_ADDR_TYPE_IPV6);
+			buf.extend_from_slice(&addr.ip().octets());
+		}
+	}
+	buf.extend_from_slice(&bind_addr.port().to_be_bytes());
+	buf
+}
+
+pub fn reply_error_from(err: ConnectError) -> ReplyError {
+	match err {
+		ConnectError::General => ReplyError::GeneralFailure,
+		ConnectError::NotAllowed => ReplyError::ConnectionNotAllowed,
+		ConnectError::NetworkUnreachable => ReplyError::NetworkUnreachable,
+		ConnectError::HostUnreachable => ReplyError::HostUnreachable,
+		ConnectError::ConnectionRefused => ReplyError::ConnectionRefused,
+		ConnectError::TimedOut => ReplyError::ConnectionTimeout,
+	}
+}
+
+/// A SOCKS CONNECT stream whose reply is deferred until the outbound
+/// reports the upstream state through [`AbstractTcpStream::on_connect`].
+///
+/// Outbounds that never report are treated as successful on first I/O, so
+/// the reply always precedes any relayed payload.
+pub struct SocksTcpStream<T> {
+	inner:       T,
+	bind_addr:   SocketAddr,
+	/// Success reply still to be written and how much of it already is
+	pending:     Option<(Vec<u8>, usize)>,
+	encode:      fn(&ReplyError, SocketAddr) -> Vec<u8>,
+	client_addr: Option<SocketAddr>,
+	local_addr:  Option<SocketAddr>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> SocksTcpStream<T> {
+	pub fn new(inner: T, bind_addr: SocketAddr) -> Self {
+		Self::with_encoder(inner, bind_addr, encode_reply)
+	}
+
+	/// Answer with SOCKS4 replies instead
+	pub fn socks4(inner: T, bind_addr: SocketAddr) -> Self {
+		Self::with_encoder(inner, bind_addr, crate::socks4::encode_reply)
+	}
+
+	fn with_encoder(inner: T, bind_addr: SocketAddr, encode: fn(&ReplyError, SocketAddr) -> Vec<u8>) -> Self {
+		Self {
+			inner,
+			bind_addr,
+			pending: Some((encode(&ReplyError::Succeeded, bind_addr), 0)),
+			encode,
+			client_addr: None,
+			local_addr: None,
+		}
+	}
+
+	/// Tell outbounds the client is at `client_addr`, see
+	/// [`AbstractTcpStream::client_addr`]
+	pub fn with_client_addr(mut self, client_addr: Option<SocketAddr>) -> Self {
+		self.client_addr = client_addr;
+		self
+	}
+
+	/// Tell outbounds the client reached us at `local_addr`, see
+	/// [`AbstractTcpStream::local_addr`]
+	pub fn with_local_addr(mut self, local_addr: Option<SocketAddr>) -> Self {
+		self.local_addr = local_addr;
+		self
+	}
+
+	pub fn into_inner(self) -> T {
+		self.inner
+	}
+
+	fn poll_reply(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		if let Some((reply, written)) = &mut self.pending {
+			while *written < reply.len() {
+				let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &reply[*written..]))?;
+				if n == 0 {
+					return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+				}
+				*written += n;
+			}
+			ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+			self.pending = None;
+		}
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for SocksTcpStream<T> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_reply(cx))?;
+		Pin::new(&mut this.inner).poll_read(cx, buf)
+	}
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for SocksTcpStream<T> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		ready!(this.poll_reply(cx))?;
+		Pin::new(&mut this.inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_reply(cx))?;
+		Pin::new(&mut this.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_reply(cx))?;
+		Pin::new(&mut this.inner).poll_shutdown(cx)
+	}
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> AbstractTcpStream for SocksTcpStream<T> {
+	async fn on_connect(&mut self, result: Result<(), ConnectError>) -> io::Result<()> {
+		match result {
+			Ok(()) => std::future::poll_fn(|cx| self.poll_reply(cx)).await,
+			// Only a reply that hasn't started can still be turned into a failure
+			Err(err) if matches!(self.pending, Some((_, 0))) => {
+				self.pending = None;
+				let reply = (self.encode)(&reply_error_from(err), self.bind_addr);
+				self.inner.write_all(&reply).await?;
+				self.inner.flush().await
+			}
+			Err(_) => Ok(()),
+		}
+	}
+
+	fn client_addr(&self) -> Option<SocketAddr> {
+		self.client_addr
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		self.local_addr
+	}
+}
+
+/// A client stream that can look at upcoming bytes before they are read,
+/// for streams without a native `peek`
+pub struct PeekStream<T> {
+	inner:  T,
+	/// Bytes read ahead from `inner`, handed out again before reading on
+	peeked: Vec<u8>,
+	pos:    usize,
+}
+
+impl<T: AsyncRead + Unpin> PeekStream<T> {
+	pub fn new(inner: T) -> Self {
+		Self {
+			inner,
+			peeked: Vec::new(),
+			pos: 0,
+		}
+	}
+
+	/// Fill `buf` with the next bytes of the stream without consuming them
+	pub async fn peek_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+		self.peeked.drain(..self.pos);
+		self.pos = 0;
+		while self.peeked.len() < buf.len() {
+			let mut chunk = [0u8; 64];
+			let want = (buf.len() - self.peeked.len()).min(chunk.len());
+			let n = self.inner.read(&mut chunk[..want]).await?;
+			if n == 0 {
+				return Err(io::ErrorKind::UnexpectedEof.into());
+			}
+			self.peeked.extend_from_slice(&chunk[..n]);
+		}
+		buf.copy_from_slice(&self.peeked[..buf.len()]);
+		Ok(())
+	}
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for PeekStream<T> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		if this.pos < this.peeked.len() {
+			let n = buf.remaining().min(this.peeked.len() - this.pos);
+			buf.put_slice(&this.peeked[this.pos..this.pos + n]);
+			this.pos += n;
+			return Poll::Ready(Ok(()));
+		}
+		Pin::new(&mut this.inner).poll_read(cx, buf)
+	}
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for PeekStream<T> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::{AsyncReadExt, duplex};
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_peek_then_read() {
+		let (mut client, server) = duplex(64);
+		let mut stream = PeekStream::new(server);
+		client.write_all(b"he").await.unwrap();
+		let write = tokio::spawn(async move {
+			tokio::task::yield_now().await;
+			client.write_all(b"llo").await.unwrap();
+			client
+		});
+
+		let mut head = [0u8; 3];
+		stream.peek_exact(&mut head).await.unwrap();
+		assert_eq!(&head, b"hel");
+		drop(write.await.unwrap());
+		let mut buf = Vec::new();
+		stream.read_to_end(&mut buf).await.unwrap();
+		assert_eq!(buf, b"hello");
+	}
+
+	#[tokio::test]
+	async fn test_connect_error_reply() {
+		let (mut client, server) = duplex(64);
+		let mut stream = SocksTcpStream::new(server, "127.0.0.1:0".parse().unwrap());
+		stream.on_connect(Err(ConnectError::ConnectionRefused)).await.unwrap();
+
+		let mut reply = [0u8; 10];
+		client.read_exact(&mut reply).await.unwrap();
+		assert_eq!(reply, [5, 5, 0, 1, 127, 0, 0, 1, 0, 0]);
+	}
+
+	#[tokio::test]
+	async fn test_success_reply_before_payload() {
+		let (mut client, server) = duplex(64);
+		let mut stream = SocksTcpStream::new(server, "127.0.0.1:1080".parse().unwrap());
+		stream.write_all(b"hi").await.unwrap();
+		stream.flush().await.unwrap();
+
+		let mut buf = [0u8; 12];
+		client.read_exact(&mut buf).await.unwrap();
+		assert_eq!(buf, [5, 0, 0, 1, 127, 0, 0, 1, 0x04, 0x38, b'h', b'i']);
+	}
+}
@@ -1,38 +1,116 @@
 use std::{
+	collections::HashMap,
 	io::IoSliceMut,
 	net::{Ipv4Addr, SocketAddr},
 	pin::Pin,
-	sync::Arc,
+	sync::{Arc, Mutex},
 	task::{Context, Poll, ready},
 };
 
-use arc_swap::ArcSwap;
 use fast_socks5::{new_udp_header, util::target_addr::TargetAddr as SocksTargetAddr};
 use tokio::io::Interest;
+use tokio_util::sync::CancellationToken;
 use wind_core::{
 	types::TargetAddr,
 	udp::{AbstractUdpSocket, QuinnRecvMeta, RecvMeta, Transmit, UdpPollHelper, UdpPoller, UdpSocketState},
+	warn,
 };
 
+/// Targets a socket remembers the client of, beyond which the least recently
+/// used is forgotten
+const MAX_TRACKED_TARGETS: usize = 256;
+
+/// Which client each target was last sent to from, so replies find their way
+/// back when several clients share one socket
+///
+/// Two clients talking to the same target can't be told apart, its replies go
+/// to whichever sent last.
+#[derive(Debug, Default)]
+struct ClientTable {
+	/// Target -> client, and when the pair was last used
+	clients:     HashMap<SocketAddr, (SocketAddr, u64)>,
+	clock:       u64,
+	/// Sender of the latest datagram, for replies from targets not tracked
+	last_client: Option<SocketAddr>,
+}
+
+impl ClientTable {
+	fn record(&mut self, client: SocketAddr, target: Option<SocketAddr>) {
+		self.last_client = Some(client);
+		let Some(target) = target else {
+			return;
+		};
+		self.clock += 1;
+		if self.clients.len() >= MAX_TRACKED_TARGETS
+			&& !self.clients.contains_key(&target)
+			&& let Some(oldest) = self
+				.clients
+				.iter()
+				.min_by_key(|(_, (_, used))| *used)
+				.map(|(target, _)| *target)
+		{
+			self.clients.remove(&oldest);
+		}
+		self.clients.insert(target, (client, self.clock));
+	}
+
+	/// The client a reply from `origin` goes to
+	fn client_for(&mut self, origin: SocketAddr) -> Option<SocketAddr> {
+		self.clock += 1;
+		match self.clients.get_mut(&origin) {
+			Some((client, used)) => {
+				*used = self.clock;
+				Some(*client)
+			}
+			None => self.last_client,
+		}
+	}
+}
+
 /// A virtual UDP socket that handles SOCKS5 UDP headers
 /// It parses incoming SOCKS5 UDP packets and strips the headers,
 /// and adds SOCKS5 headers to outgoing packets
+///
+/// Fragmented datagrams (FRAG != 0) are dropped with a warning rather than
+/// reassembled: RFC 1928 makes reassembly optional and clients in practice
+/// never fragment.
 #[derive(Debug)]
 pub struct Socks5UdpSocket {
-	io:          tokio::net::UdpSocket,
-	inner:       UdpSocketState,
-	source_addr: ArcSwap<SocketAddr>,
+	io:      tokio::net::UdpSocket,
+	inner:   UdpSocketState,
+	clients: Mutex<ClientTable>,
+	token:   CancellationToken,
+	/// Segmentation offload is used in either direction, see
+	/// [`without_offload`](Self::without_offload)
+	offload: bool,
 }
 
 impl Socks5UdpSocket {
 	pub fn new(sock: std::net::UdpSocket) -> std::io::Result<Self> {
 		Ok(Self {
-			inner:       UdpSocketState::new((&sock).into())?,
-			io:          tokio::net::UdpSocket::from_std(sock)?,
-			source_addr: ArcSwap::new(Arc::new(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0))),
+			inner:   UdpSocketState::new((&sock).into())?,
+			io:      tokio::net::UdpSocket::from_std(sock)?,
+			clients: Mutex::default(),
+			token:   CancellationToken::new(),
+			offload: true,
 		})
 	}
 
+	/// Send and receive one datagram at a time, for NICs and drivers that
+	/// mangle GSO/GRO batches
+	pub fn without_offload(mut self) -> std::io::Result<Self> {
+		wind_core::udp::disable_gro(&self.io)?;
+		self.offload = false;
+		Ok(self)
+	}
+
+	/// End the association when `token` is cancelled, see
+	/// [`AbstractUdpSocket::association_token`]
+	pub fn with_token(mut self, token: CancellationToken) -> Self {
+		self.token = token;
+		self
+	}
+
 	/// Convert SOCKS target address to our TargetAddr
 	fn convert_target_addr(socks_addr: &SocksTargetAddr) -> TargetAddr {
 		match socks_addr {
@@ -44,9 +122,13 @@ impl Socks5UdpSocket {
 		}
 	}
 
-	/// Get the currently stored source address
+	/// Client that sent the latest datagram, unspecified before any
 	pub fn source_addr(&self) -> SocketAddr {
-		**self.source_addr.load()
+		self.clients
+			.lock()
+			.unwrap()
+			.last_client
+			.unwrap_or(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0))
 	}
 
 	/// Synchronously parse SOCKS5 UDP request header
@@ -126,10 +208,22 @@ impl AbstractUdpSocket for Socks5UdpSocket {
 		}))
 	}
 
+	fn association_token(&self) -> CancellationToken {
+		self.token.clone()
+	}
+
 	fn try_send(&self, transmit: &Transmit) -> std::io::Result<()> {
-		// For outgoing packets in SOCKS5 UDP proxy, we need to add SOCKS5 headers
-		// Create SOCKS5 target address from the destination
-		let socks_target = self.source_addr();
+		// For outgoing packets in SOCKS5 UDP proxy, we need to add SOCKS5 headers.
+		// The destination is the reply's origin, unspecified when the outbound can't
+		// tell, then the header names the client itself
+		let origin = transmit.destination;
+		let client = self
+			.clients
+			.lock()
+			.unwrap()
+			.client_for(origin)
+			.ok_or(std::io::ErrorKind::NotConnected)?;
+		let socks_target = if origin.ip().is_unspecified() { client } else { origin };
 
 		// Add SOCKS5 UDP header to the packet
 		if let Ok(mut packet_with_header) = new_udp_header(socks_target) {
@@ -137,7 +231,7 @@ impl AbstractUdpSocket for Socks5UdpSocket {
 
 			// Create new transmit with the header-wrapped packet
 			let new_transmit = Transmit {
-				destination:  socks_target,
+				destination:  client,
 				contents:     &packet_with_header,
 				ecn:          transmit.ecn,
 				segment_size: transmit.segment_size,
@@ -182,12 +276,21 @@ impl AbstractUdpSocket for Socks5UdpSocket {
 					if temp_meta[i].len > 0 {
 						let packet_data = &temp_bufs[i][..temp_meta[i].len];
 
-						// Record the source address from the received packet
-						self.source_addr.store(Arc::new(temp_meta[i].addr));
-
 						// Try to parse SOCKS5 UDP header synchronously
-						match Self::parse_udp_request_sync(packet_data) {
-							Ok((_frag, target_addr, payload)) => {
+						let parsed = Self::parse_udp_request_sync(packet_data);
+
+						// Record who sent to the target, for its replies
+						let target = match &parsed {
+							Ok((_, SocksTargetAddr::Ip(target), _)) => Some(*target),
+							_ => None,
+						};
+						self.clients.lock().unwrap().record(temp_meta[i].addr, target);
+
+						match parsed {
+							Ok((frag, ..)) if frag != 0 => {
+								warn!(target: "[IN] UDP", "Dropping fragmented datagram (FRAG {frag}) from {}, fragments are not reassembled", temp_meta[i].addr);
+							}
+							Ok((_, target_addr, payload)) => {
 								// Successfully parsed SOCKS5 header, copy payload to output buffer
 								let payload_len = payload.len().min(bufs[processed_count].len());
 								bufs[processed_count][..payload_len].copy_from_slice(&payload[..payload_len]);
@@ -211,7 +314,10 @@ impl AbstractUdpSocket for Socks5UdpSocket {
 						}
 					}
 				}
-				return Poll::Ready(Ok(processed_count));
+				// Every datagram may have been dropped, then wait for more
+				if processed_count > 0 {
+					return Poll::Ready(Ok(processed_count));
+				}
 			}
 		}
 	}
@@ -225,10 +331,122 @@ impl AbstractUdpSocket for Socks5UdpSocket {
 	}
 
 	fn max_transmit_segments(&self) -> usize {
-		self.inner.max_gso_segments()
+		if self.offload { self.inner.max_gso_segments() } else { 1 }
+	}
+
+	fn poll_recv_ready(&self, cx: &mut Context) -> Poll<std::io::Result<()>> {
+		self.io.poll_recv_ready(cx)
 	}
 
 	fn max_receive_segments(&self) -> usize {
-		self.inner.gro_segments()
+		if self.offload { self.inner.gro_segments() } else { 1 }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_fragmented_datagram_dropped() {
+		let socket = Socks5UdpSocket::new(std::net::UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+		let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let server_addr = socket.local_addr().unwrap();
+
+		// RSV, FRAG, ATYP IPv4, 127.0.0.1:53, then the payload
+		let header = |frag: u8| [0, 0, frag, 1, 127, 0, 0, 1, 0, 53];
+		client
+			.send_to(&[&header(1)[..], b"first"].concat(), server_addr)
+			.await
+			.unwrap();
+		client
+			.send_to(&[&header(0)[..], b"whole"].concat(), server_addr)
+			.await
+			.unwrap();
+
+		let mut buf = [0u8; 64];
+		let mut meta = [RecvMeta::default()];
+		let n = tokio::time::timeout(
+			Duration::from_secs(1),
+			socket.recv(&mut [IoSliceMut::new(&mut buf)], &mut meta),
+		)
+		.await
+		.unwrap()
+		.unwrap();
+		assert_eq!(n, 1);
+		assert_eq!(&buf[..meta[0].len], b"whole");
+		assert_eq!(meta[0].destination, Some(TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 53)));
+	}
+
+	#[tokio::test]
+	async fn test_replies_reach_their_client() {
+		let socket = Socks5UdpSocket::new(std::net::UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+		let server_addr = socket.local_addr().unwrap();
+		let first = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let second = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let first_target = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 53));
+		let second_target = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 2), 53));
+
+		// The second client sends last, so it's no longer the only one replies go to
+		for (client, target) in [(&first, first_target), (&second, second_target)] {
+			let mut datagram = new_udp_header(target).unwrap();
+			datagram.extend_from_slice(b"query");
+			client.send_to(&datagram, server_addr).await.unwrap();
+			let mut buf = [0u8; 64];
+			let mut meta = [RecvMeta::default()];
+			tokio::time::timeout(
+				Duration::from_secs(1),
+				socket.recv(&mut [IoSliceMut::new(&mut buf)], &mut meta),
+			)
+			.await
+			.unwrap()
+			.unwrap();
+			assert_eq!(meta[0].destination, Some(TargetAddr::from(target)));
+		}
+
+		socket.send(b"first", first_target).await.unwrap();
+		socket.send(b"second", second_target).await.unwrap();
+		for (client, target, payload) in [
+			(&first, first_target, b"first".as_slice()),
+			(&second, second_target, b"second"),
+		] {
+			let mut buf = [0u8; 64];
+			let n = tokio::time::timeout(Duration::from_secs(1), client.recv(&mut buf))
+				.await
+				.unwrap()
+				.unwrap();
+			let mut expected = new_udp_header(target).unwrap();
+			expected.extend_from_slice(payload);
+			assert_eq!(&buf[..n], expected.as_slice());
+		}
+	}
+
+	#[test]
+	fn test_client_table_forgets_least_recent() {
+		let addr = |n: u16| SocketAddr::from((Ipv4Addr::LOCALHOST, n));
+		let mut table = ClientTable::default();
+		for n in 0..MAX_TRACKED_TARGETS as u16 {
+			table.record(addr(1), Some(addr(1000 + n)));
+		}
+		// Target 1000 was used last, leaving 1001 the least recent
+		assert_eq!(table.client_for(addr(1000)), Some(addr(1)));
+		table.record(addr(2), Some(addr(5000)));
+		assert_eq!(table.clients.len(), MAX_TRACKED_TARGETS);
+		assert!(table.clients.contains_key(&addr(1000)));
+		assert!(!table.clients.contains_key(&addr(1001)));
+		// Unknown origins go to the latest sender
+		assert_eq!(table.client_for(addr(1001)), Some(addr(2)));
+	}
+
+	#[tokio::test]
+	async fn test_without_offload_reports_single_segments() {
+		let socket = Socks5UdpSocket::new(std::net::UdpSocket::bind("127.0.0.1:0").unwrap())
+			.unwrap()
+			.without_offload()
+			.unwrap();
+		assert_eq!(socket.max_transmit_segments(), 1);
+		assert_eq!(socket.max_receive_segments(), 1);
 	}
 }
@@ -0,0 +1,85 @@
+use std::io::IoSliceMut;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use wind_core::{
+	AbstractOutbound,
+	tcp::AbstractTcpStream,
+	types::TargetAddr,
+	udp::{AbstractUdpSocket, RecvMeta},
+};
+
+/// Loops all traffic back to where it came from, for exercising an inbound's
+/// whole datapath without a network
+///
+/// TCP streams get their bytes echoed until the client closes its side. UDP
+/// datagrams are sent straight back to their source through the inbound
+/// socket.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EchoOutbound;
+
+impl AbstractOutbound for EchoOutbound {
+	async fn handle_tcp(
+		&self,
+		_target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		stream.on_connect(Ok(())).await?;
+		let mut buf = vec![0u8; 16 * 1024];
+		loop {
+			let n = stream.read(&mut buf).await?;
+			if n == 0 {
+				break;
+			}
+			stream.write_all(&buf[..n]).await?;
+		}
+		stream.shutdown().await?;
+		Ok(())
+	}
+
+	async fn handle_udp(
+		&self,
+		socket: impl AbstractUdpSocket + 'static,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		let mut buf = vec![0u8; u16::MAX as usize];
+		let mut meta = RecvMeta::default();
+		let closed = socket.association_token();
+		// Polled by hand since the `recv` and `send` futures aren't `Sync`
+		let echo = async {
+			while std::future::poll_fn(|cx| {
+				socket.poll_recv(cx, &mut [IoSliceMut::new(&mut buf)], std::slice::from_mut(&mut meta))
+			})
+			.await
+			.is_ok()
+			{
+				let payload = &buf[..meta.len];
+				std::future::poll_fn(|cx| socket.poll_send_ecn(cx, payload, meta.addr, meta.ecn)).await?;
+			}
+			eyre::Ok(())
+		};
+		tokio::select! {
+			_ = closed.cancelled() => Ok(()),
+			res = echo => res,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_tcp_echo() {
+		let (mut client, stream) = tokio::io::duplex(64);
+		let target = TargetAddr::Domain("echo.invalid".into(), 7);
+		let relay = tokio::spawn(async move { EchoOutbound.handle_tcp(target, stream, None::<EchoOutbound>).await });
+
+		client.write_all(b"ping").await.unwrap();
+		let mut buf = [0u8; 4];
+		client.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"ping");
+		client.shutdown().await.unwrap();
+		relay.await.unwrap().unwrap();
+	}
+}
@@ -1,3 +1,6 @@
+pub mod echo;
+pub mod record;
+pub mod resolve;
 pub mod socks5;
 
 pub mod benches {
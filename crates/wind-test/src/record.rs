@@ -0,0 +1,165 @@
+use std::{
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use tokio::{io::AsyncReadExt, sync::Notify};
+use wind_core::{InboundCallback, tcp::AbstractTcpStream, types::TargetAddr, udp::AbstractUdpSocket};
+
+/// How long a client may stay silent before its first bytes are taken as
+/// complete
+const FIRST_BYTES_IDLE: Duration = Duration::from_millis(200);
+
+/// What a client asked an inbound to relay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRequest {
+	pub target:      TargetAddr,
+	/// Up to the callback's byte limit, fewer if the client closed or went
+	/// quiet first
+	pub first_bytes: Vec<u8>,
+}
+
+/// Records the target and first bytes of each TCP connection an inbound
+/// hands over, for asserting on what clients requested without an upstream
+///
+/// Connections are reported as established, then closed once recorded. UDP
+/// associations are refused.
+#[derive(Debug, Clone)]
+pub struct RecordingCallback {
+	max_bytes: usize,
+	recorded:  Arc<Mutex<Vec<RecordedRequest>>>,
+	notify:    Arc<Notify>,
+}
+
+impl RecordingCallback {
+	/// Keep up to `max_bytes` of what each client sends
+	pub fn new(max_bytes: usize) -> Self {
+		Self {
+			max_bytes,
+			recorded: Arc::default(),
+			notify: Arc::default(),
+		}
+	}
+
+	/// Requests recorded so far, in the order they completed
+	pub fn recorded(&self) -> Vec<RecordedRequest> {
+		self.recorded.lock().unwrap().clone()
+	}
+
+	/// Wait until `count` requests are recorded
+	pub async fn wait_for(&self, count: usize) -> Vec<RecordedRequest> {
+		loop {
+			let notified = self.notify.notified();
+			let recorded = self.recorded();
+			if recorded.len() >= count {
+				return recorded;
+			}
+			notified.await;
+		}
+	}
+}
+
+impl InboundCallback for RecordingCallback {
+	async fn handle_tcpstream(
+		&self,
+		target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream + 'static,
+	) -> eyre::Result<()> {
+		stream.on_connect(Ok(())).await?;
+		let (recorded, notify) = (self.recorded.clone(), self.notify.clone());
+		let mut first_bytes = vec![0u8; self.max_bytes];
+		// Recorded on its own task, so a quiet client doesn't hold up the next
+		tokio::spawn(async move {
+			let mut len = 0;
+			while len < first_bytes.len() {
+				match tokio::time::timeout(FIRST_BYTES_IDLE, stream.read(&mut first_bytes[len..])).await {
+					Ok(Ok(0)) | Err(_) => break,
+					Ok(Ok(n)) => len += n,
+					Ok(Err(_)) => return,
+				}
+			}
+			first_bytes.truncate(len);
+
+			recorded.lock().unwrap().push(RecordedRequest {
+				target: target_addr,
+				first_bytes,
+			});
+			notify.notify_waiters();
+		});
+		Ok(())
+	}
+
+	async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+		eyre::bail!("RecordingCallback records TCP connections only")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use fast_socks5::client::{Config, Socks5Stream};
+	use tokio::io::AsyncWriteExt;
+	use wind_core::{AppContext, inbound::AbstractInbound};
+	use wind_socks::inbound::{AuthMode, SocksInbound, SocksInboundOpt, UdpBindFamily};
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_socks_connect_recorded() {
+		let listen_addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+			.await
+			.unwrap()
+			.local_addr()
+			.unwrap();
+		let ctx = Arc::new(AppContext::default());
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			ctx.listen_token.child_token(),
+		)
+		.await
+		.unwrap();
+		let recorder = RecordingCallback::new(5);
+		let cb = recorder.clone();
+		ctx.tasks.spawn(async move { inbound.listen(&cb).await });
+		tokio::task::yield_now().await;
+
+		// A client that says nothing is recorded once it goes quiet, without
+		// holding up the one behind it
+		let _quiet = Socks5Stream::connect(listen_addr, "quiet.example".to_string(), 443, Config::default())
+			.await
+			.unwrap();
+		// Never reaches example.com, the recorder stands in for the upstream
+		let mut stream = Socks5Stream::connect(listen_addr, "example.com".to_string(), 443, Config::default())
+			.await
+			.unwrap();
+		stream.write_all(b"\x16\x03\x01 hello").await.unwrap();
+
+		let recorded = tokio::time::timeout(Duration::from_secs(5), recorder.wait_for(1))
+			.await
+			.unwrap();
+		assert_eq!(
+			recorded,
+			[RecordedRequest {
+				target:      TargetAddr::Domain("example.com".into(), 443),
+				first_bytes: b"\x16\x03\x01 h".to_vec(),
+			}]
+		);
+
+		ctx.token.cancel();
+		ctx.tasks.close();
+		tokio::time::timeout(Duration::from_secs(5), ctx.tasks.wait()).await.unwrap();
+	}
+}
@@ -0,0 +1,103 @@
+//! Domain resolution for the UDP relays of the test proxy
+
+use std::{
+	collections::HashMap,
+	io,
+	net::{IpAddr, SocketAddr},
+	sync::{
+		Mutex,
+		atomic::{AtomicUsize, Ordering},
+	},
+	time::{Duration, Instant},
+};
+
+use wind_core::{
+	dns::{IpPolicy, resolve},
+	types::TargetAddr,
+};
+
+/// How long a UDP association keeps sending to a domain's address before
+/// resolving it again, unless told otherwise
+pub const UDP_RESOLVE_TTL: Duration = Duration::from_secs(30);
+
+/// The addresses of the domains one UDP association sends to
+///
+/// Each is resolved on the first packet and reused for `ttl`, rather than
+/// going through the resolver for every packet. A `ttl` of zero resolves
+/// every time.
+#[derive(Debug)]
+pub struct UdpResolveCache {
+	ttl:         Duration,
+	policy:      IpPolicy,
+	/// Domain -> first address `policy` allows, and when it was resolved
+	entries:     Mutex<HashMap<String, (IpAddr, Instant)>>,
+	resolutions: AtomicUsize,
+}
+
+impl UdpResolveCache {
+	pub fn new(ttl: Duration, policy: IpPolicy) -> Self {
+		Self {
+			ttl,
+			policy,
+			entries: Mutex::default(),
+			resolutions: AtomicUsize::new(0),
+		}
+	}
+
+	/// Where to send a packet for `target`
+	pub async fn resolve(&self, target: &TargetAddr) -> io::Result<SocketAddr> {
+		let (domain, port) = match target {
+			TargetAddr::Domain(domain, port) => (domain, *port),
+			ip => return Ok(ip.to_socket_addr().expect("IP targets have a socket address")),
+		};
+		if let Some((ip, resolved)) = self.entries.lock().unwrap().get(domain)
+			&& resolved.elapsed() < self.ttl
+		{
+			return Ok(SocketAddr::new(*ip, port));
+		}
+
+		self.resolutions.fetch_add(1, Ordering::Relaxed);
+		let addr = resolve(target, self.policy).await?[0];
+		if !self.ttl.is_zero() {
+			let mut entries = self.entries.lock().unwrap();
+			entries.retain(|_, (_, resolved)| resolved.elapsed() < self.ttl);
+			entries.insert(domain.clone(), (addr.ip(), Instant::now()));
+		}
+		Ok(addr)
+	}
+
+	/// How many times a domain went to the resolver
+	pub fn resolutions(&self) -> usize {
+		self.resolutions.load(Ordering::Relaxed)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_udp_resolve_cache() {
+		let cache = UdpResolveCache::new(Duration::from_millis(200), IpPolicy::V4Only);
+		for port in [53, 53, 5353] {
+			let addr = cache.resolve(&TargetAddr::Domain("localhost".into(), port)).await.unwrap();
+			assert_eq!(addr, SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+		}
+		assert_eq!(cache.resolutions(), 1);
+		let ip = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 9));
+		assert_eq!(cache.resolve(&ip.into()).await.unwrap(), ip);
+		assert_eq!(cache.resolutions(), 1);
+
+		tokio::time::sleep(Duration::from_millis(250)).await;
+		cache.resolve(&TargetAddr::Domain("localhost".into(), 53)).await.unwrap();
+		assert_eq!(cache.resolutions(), 2);
+
+		let uncached = UdpResolveCache::new(Duration::ZERO, IpPolicy::V4Only);
+		for _ in 0..2 {
+			uncached.resolve(&TargetAddr::Domain("localhost".into(), 53)).await.unwrap();
+		}
+		assert_eq!(uncached.resolutions(), 2);
+	}
+}
@@ -421,18 +421,26 @@ pub async fn test_socks5_udp_large_packet(
 /// Returns the AppContext and server task handle
 #[allow(dead_code)]
 async fn start_test_proxy(socks_port: u16) -> eyre::Result<(Arc<wind_core::AppContext>, tokio::task::JoinHandle<()>)> {
-	use wind_socks::inbound::SocksInboundOpt;
+	use wind_socks::inbound::{SocksInboundOpt, UdpBindFamily};
 
 	let ctx = Arc::new(wind_core::AppContext::default());
 
 	// Create test configuration with dynamic port
 	let config = TestConfig {
 		socks_opt: SocksInboundOpt {
-			listen_addr: format!("127.0.0.1:{}", socks_port).parse()?,
-			public_addr: None,
-			auth:        wind_socks::inbound::AuthMode::NoAuth,
-			skip_auth:   false,
-			allow_udp:   true,
+			listen:                SocketAddr::from(([127, 0, 0, 1], socks_port)).into(),
+			public_addr:           None,
+			auth:                  wind_socks::inbound::AuthMode::NoAuth,
+			skip_auth:             false,
+			allow_udp:             true,
+			allow_resolve:         false,
+			dual_stack:            false,
+			allow_socks4:          false,
+			tcp_fast_open:         false,
+			tcp_keepalive:         None,
+			disable_offload:       false,
+			accept_proxy_protocol: false,
+			udp_bind_family:       UdpBindFamily::Auto,
 		},
 		tuic_port: 0, // Let OS assign a port
 	};
@@ -460,7 +468,11 @@ async fn run_test_proxy(ctx: Arc<wind_core::AppContext>, config: TestConfig) ->
 	use std::collections::HashMap;
 
 	use wind_core::{
-		InboundCallback, inbound::AbstractInbound, tcp::AbstractTcpStream, types::TargetAddr, udp::AbstractUdpSocket,
+		InboundCallback,
+		inbound::AbstractInbound,
+		tcp::{AbstractTcpStream, report_failure},
+		types::TargetAddr,
+		udp::AbstractUdpSocket,
 	};
 
 	// Generate self-signed certificate for testing
@@ -495,10 +507,14 @@ async fn run_test_proxy(ctx: Arc<wind_core::AppContext>, config: TestConfig) ->
 	}
 
 	impl InboundCallback for TestManager {
-		async fn handle_tcpstream(&self, target_addr: TargetAddr, stream: impl AbstractTcpStream) -> eyre::Result<()> {
+		async fn handle_tcpstream(
+			&self,
+			target_addr: TargetAddr,
+			mut stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
 			// Direct connection - connect directly to target
-			handle_tcp_direct(target_addr, stream).await?;
-			Ok(())
+			let res = handle_tcp_direct(target_addr, &mut stream).await;
+			report_failure(&mut stream, res).await
 		}
 
 		async fn handle_udpsocket(&self, socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
@@ -541,11 +557,16 @@ async fn run_test_proxy(ctx: Arc<wind_core::AppContext>, config: TestConfig) ->
 		use std::{collections::HashMap, io::IoSliceMut, sync::Arc};
 
 		use tokio::sync::Mutex;
-		use wind_core::udp::RecvMeta;
+		use wind_core::{dns::IpPolicy, udp::RecvMeta};
+
+		use crate::resolve::{UDP_RESOLVE_TTL, UdpResolveCache};
 
 		// Use Arc<Mutex> to share the socket across tasks
 		let socket = Arc::new(socket);
 		let target_sockets: Arc<Mutex<HashMap<String, Arc<tokio::net::UdpSocket>>>> = Arc::new(Mutex::new(HashMap::new()));
+		// The target sockets are IPv4, and domains would otherwise be looked up
+		// on every packet
+		let resolver = UdpResolveCache::new(UDP_RESOLVE_TTL, IpPolicy::PreferV4);
 
 		// Spawn task to relay packets from inbound to targets
 		let socket_clone = socket.clone();
@@ -611,7 +632,9 @@ async fn run_test_proxy(ctx: Arc<wind_core::AppContext>, config: TestConfig) ->
 							};
 
 							// Send to target
-							let _ = target_socket.send_to(data, target_key).await;
+							if let Ok(addr) = resolver.resolve(&target_addr).await {
+								let _ = target_socket.send_to(data, addr).await;
+							}
 						}
 					}
 					_ => break,
@@ -625,7 +648,8 @@ async fn run_test_proxy(ctx: Arc<wind_core::AppContext>, config: TestConfig) ->
 
 	// Initialize inbound servers
 	let tuic_inbound = Arc::new(wind_tuic::inbound::TuicInbound::new(ctx.clone(), tuic_opts));
-	let socks_inbound = Arc::new(wind_socks::inbound::SocksInbound::new(config.socks_opt, ctx.token.child_token()).await);
+	let socks_inbound =
+		Arc::new(wind_socks::inbound::SocksInbound::new(config.socks_opt, ctx.listen_token.child_token()).await?);
 
 	let manager = Arc::new(TestManager {
 		socks_inbound: socks_inbound.clone(),
@@ -778,6 +802,82 @@ mod tests {
 		}
 	}
 
+	#[tokio::test]
+	async fn test_udp_through_proxy_echo_outbound() {
+		use fast_socks5::client::Socks5Datagram;
+		use wind_core::{
+			AbstractOutbound, InboundCallback, inbound::AbstractInbound, tcp::AbstractTcpStream, types::TargetAddr,
+			udp::AbstractUdpSocket,
+		};
+		use wind_socks::inbound::{AuthMode, SocksInbound, SocksInboundOpt, UdpBindFamily};
+
+		use crate::echo::EchoOutbound;
+
+		#[derive(Clone)]
+		struct EchoManager;
+
+		impl InboundCallback for EchoManager {
+			async fn handle_tcpstream(
+				&self,
+				target_addr: TargetAddr,
+				stream: impl AbstractTcpStream + 'static,
+			) -> eyre::Result<()> {
+				EchoOutbound.handle_tcp(target_addr, stream, None::<EchoOutbound>).await
+			}
+
+			async fn handle_udpsocket(&self, socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+				EchoOutbound.handle_udp(socket, None::<EchoOutbound>).await
+			}
+		}
+
+		let listen_addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+			.await
+			.unwrap()
+			.local_addr()
+			.unwrap();
+		let ctx = Arc::new(wind_core::AppContext::default());
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             true,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			ctx.listen_token.child_token(),
+		)
+		.await
+		.unwrap();
+		ctx.tasks.spawn(async move { inbound.listen(&EchoManager).await });
+		tokio::task::yield_now().await;
+
+		// Nothing listens at the target, the reply can only come from the outbound
+		let backing_socket = TcpStream::connect(listen_addr).await.unwrap();
+		let socket = Socks5Datagram::bind(backing_socket, "127.0.0.1:0".parse::<SocketAddr>().unwrap())
+			.await
+			.unwrap();
+		for payload in [&b"ping"[..], &[0xAB; 1200]] {
+			socket.send_to(payload, ("192.0.2.1", 9)).await.unwrap();
+			let mut buf = vec![0u8; 2048];
+			let (len, _) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buf))
+				.await
+				.expect("echo reply timed out")
+				.unwrap();
+			assert_eq!(&buf[..len], payload);
+		}
+
+		ctx.token.cancel();
+		let _ = tokio::time::timeout(Duration::from_secs(5), ctx.tasks.wait()).await;
+	}
+
 	// =========================================================================
 	// Proxy Tests - Advanced (Large Packets & Fragmentation)
 	// =========================================================================
@@ -0,0 +1,170 @@
+//! Destinations a TUIC user may reach
+
+use std::{net::IpAddr, ops::RangeInclusive, str::FromStr};
+
+use wind_core::types::TargetAddr;
+
+/// Allow and deny lists for one user
+///
+/// A target is permitted when no `deny` rule matches it and, if there are
+/// `allow` rules, one of them does.
+#[derive(Debug, Clone, Default)]
+pub struct UserAcl {
+	pub allow: Vec<DestinationRule>,
+	pub deny:  Vec<DestinationRule>,
+}
+
+impl UserAcl {
+	pub fn permits(&self, target_addr: &TargetAddr) -> bool {
+		!self.deny.iter().any(|rule| rule.matches(target_addr))
+			&& (self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(target_addr)))
+	}
+}
+
+/// Targets matched by an entry of a [`UserAcl`]
+///
+/// A rule matches when the target is within one of `domains` or `networks`
+/// (if either has any) and on one of `ports` (if any).
+#[derive(Debug, Clone, Default)]
+pub struct DestinationRule {
+	/// The domain itself and its subdomains
+	pub domains:  Vec<String>,
+	pub networks: Vec<Network>,
+	pub ports:    Vec<RangeInclusive<u16>>,
+}
+
+impl DestinationRule {
+	fn matches(&self, target_addr: &TargetAddr) -> bool {
+		let (host_matches, port) = match target_addr {
+			TargetAddr::Domain(domain, port) => (self.domains.iter().any(|within| is_within(domain, within)), *port),
+			TargetAddr::IPv4(ip, port) => (self.networks.iter().any(|net| net.contains(IpAddr::V4(*ip))), *port),
+			TargetAddr::IPv6(ip, port) => (self.networks.iter().any(|net| net.contains(IpAddr::V6(*ip))), *port),
+		};
+		(host_matches || (self.domains.is_empty() && self.networks.is_empty()))
+			&& (self.ports.is_empty() || self.ports.iter().any(|ports| ports.contains(&port)))
+	}
+}
+
+/// Addresses within `addr/prefix`, parsed from eg. `10.0.0.0/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+	addr:   IpAddr,
+	prefix: u8,
+}
+
+impl Network {
+	/// Fails when `prefix` is longer than the address, which would otherwise
+	/// match every address or none
+	pub fn new(addr: IpAddr, prefix: u8) -> eyre::Result<Self> {
+		let max = if addr.is_ipv4() { 32 } else { 128 };
+		if prefix > max {
+			eyre::bail!("invalid prefix length /{prefix} for {addr}");
+		}
+		Ok(Self { addr, prefix })
+	}
+
+	pub fn addr(&self) -> IpAddr {
+		self.addr
+	}
+
+	pub fn prefix(&self) -> u8 {
+		self.prefix
+	}
+
+	fn contains(&self, ip: IpAddr) -> bool {
+		match (ip, self.addr) {
+			(IpAddr::V4(ip), IpAddr::V4(network)) => {
+				let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+				u32::from(ip) & mask == u32::from(network) & mask
+			}
+			(IpAddr::V6(ip), IpAddr::V6(network)) => {
+				let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+				u128::from(ip) & mask == u128::from(network) & mask
+			}
+			_ => false,
+		}
+	}
+}
+
+impl FromStr for Network {
+	type Err = eyre::Report;
+
+	/// A bare address is a single host
+	fn from_str(s: &str) -> eyre::Result<Self> {
+		let (addr, prefix) = match s.split_once('/') {
+			Some((addr, prefix)) => (addr, Some(prefix)),
+			None => (s, None),
+		};
+		let addr = addr.parse::<IpAddr>().map_err(|_| eyre::eyre!("invalid network {s}"))?;
+		let prefix = match prefix {
+			Some(prefix) => prefix
+				.parse::<u8>()
+				.map_err(|_| eyre::eyre!("invalid prefix length in {s}"))?,
+			None if addr.is_ipv4() => 32,
+			None => 128,
+		};
+		Self::new(addr, prefix)
+	}
+}
+
+/// Whether `host` is `domain` or one of its subdomains, ignoring case and a
+/// trailing dot
+fn is_within(host: &str, domain: &str) -> bool {
+	let host = host.trim_end_matches('.').as_bytes();
+	let domain = domain.trim_end_matches('.').as_bytes();
+	match host.len().checked_sub(domain.len()) {
+		Some(0) => host.eq_ignore_ascii_case(domain),
+		Some(prefix) => host[prefix - 1] == b'.' && host[prefix..].eq_ignore_ascii_case(domain),
+		None => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+
+	use super::*;
+
+	#[test]
+	fn test_user_acl() {
+		let acl = UserAcl {
+			allow: vec![
+				DestinationRule {
+					domains: vec!["example.com".into()],
+					ports: vec![443..=443],
+					..Default::default()
+				},
+				DestinationRule {
+					networks: vec!["10.0.0.0/8".parse().unwrap()],
+					..Default::default()
+				},
+			],
+			deny:  vec![DestinationRule {
+				networks: vec!["10.0.0.1".parse().unwrap()],
+				..Default::default()
+			}],
+		};
+		assert!(acl.permits(&TargetAddr::Domain("www.Example.com.".into(), 443)));
+		assert!(!acl.permits(&TargetAddr::Domain("www.example.com".into(), 80)));
+		assert!(!acl.permits(&TargetAddr::Domain("notexample.com".into(), 443)));
+		assert!(acl.permits(&TargetAddr::IPv4(Ipv4Addr::new(10, 1, 2, 3), 22)));
+		assert!(!acl.permits(&TargetAddr::IPv4(Ipv4Addr::new(10, 0, 0, 1), 22)));
+		assert!(!acl.permits(&TargetAddr::IPv4(Ipv4Addr::new(192, 168, 0, 1), 443)));
+		assert!(UserAcl::default().permits(&TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 1)));
+	}
+
+	#[test]
+	fn test_network_prefix_validated() {
+		assert!("10.0.0.0/33".parse::<Network>().is_err());
+		assert!("::/129".parse::<Network>().is_err());
+		assert!(Network::new(Ipv4Addr::UNSPECIFIED.into(), 33).is_err());
+		assert!("10.0.0.0/x".parse::<Network>().is_err());
+
+		let any: Network = "0.0.0.0/0".parse().unwrap();
+		assert!(any.contains(Ipv4Addr::new(192, 168, 0, 1).into()));
+		let host: Network = "::1".parse().unwrap();
+		assert_eq!(host.prefix(), 128);
+		assert!(host.contains(std::net::Ipv6Addr::LOCALHOST.into()));
+		assert!(!host.contains(Ipv4Addr::LOCALHOST.into()));
+	}
+}
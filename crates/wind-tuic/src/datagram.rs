@@ -0,0 +1,113 @@
+//! Reading QUIC datagrams while keeping count of those quinn drops
+
+use std::{
+	fmt,
+	sync::{
+		Arc,
+		atomic::{AtomicU64, Ordering},
+	},
+	time::Duration,
+};
+
+use bytes::Bytes;
+use futures_util::FutureExt as _;
+use wind_core::{log::LogLimiter, warn};
+
+/// Incoming datagram bytes a connection buffers until read, quinn's default.
+///
+/// Every connection may hold this much, so raising it trades memory for
+/// fewer drops when UDP bursts outpace the reader.
+pub const DATAGRAM_RECEIVE_BUFFER: usize = 1_250_000;
+
+/// Datagrams quinn discarded because its receive buffer was full
+///
+/// quinn drops the oldest buffered datagram without telling anyone, so
+/// [`DatagramReader`] works the drops out from the connection's stats.
+pub struct DatagramDrops {
+	dropped: AtomicU64,
+	log:     LogLimiter,
+}
+
+impl Default for DatagramDrops {
+	fn default() -> Self {
+		Self {
+			dropped: AtomicU64::new(0),
+			log:     LogLimiter::new(Duration::from_secs(5)),
+		}
+	}
+}
+
+impl fmt::Debug for DatagramDrops {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("DatagramDrops").field("dropped", &self.dropped()).finish()
+	}
+}
+
+impl DatagramDrops {
+	/// Datagrams dropped so far, over all connections counted here
+	pub fn dropped(&self) -> u64 {
+		self.dropped.load(Ordering::Relaxed)
+	}
+
+	fn record(&self, dropped: u64, conn: &quinn::Connection) {
+		let total = self.dropped.fetch_add(dropped, Ordering::Relaxed) + dropped;
+		if let Some(suppressed) = self.log.check() {
+			warn!(
+				"Datagram receive buffer overflowed, dropped {dropped} datagram(s) from {} ({total} in total, {suppressed} \
+				 more reports suppressed)",
+				conn.remote_address()
+			);
+		}
+	}
+}
+
+/// The only reader of a connection's datagrams, adding what got dropped to
+/// [`DatagramDrops`]
+pub struct DatagramReader {
+	conn:      quinn::Connection,
+	drops:     Arc<DatagramDrops>,
+	read:      AtomicU64,
+	/// Drops already recorded
+	accounted: AtomicU64,
+}
+
+impl DatagramReader {
+	pub fn new(conn: quinn::Connection, drops: Arc<DatagramDrops>) -> Self {
+		Self {
+			conn,
+			drops,
+			read: AtomicU64::new(0),
+			accounted: AtomicU64::new(0),
+		}
+	}
+
+	pub async fn read(&self) -> Result<Bytes, quinn::ConnectionError> {
+		let res = match self.conn.read_datagram().now_or_never() {
+			Some(res) => res,
+			None => {
+				// Nothing is buffered once the read would wait, so every datagram received
+				// before that was either read or dropped
+				let received = self.conn.stats().frame_rx.datagram;
+				match self.conn.read_datagram().now_or_never() {
+					Some(res) => res,
+					None => {
+						self.account(received);
+						self.conn.read_datagram().await
+					}
+				}
+			}
+		};
+		if res.is_ok() {
+			self.read.fetch_add(1, Ordering::Relaxed);
+		}
+		res
+	}
+
+	fn account(&self, received: u64) {
+		let dropped = received.saturating_sub(self.read.load(Ordering::Relaxed));
+		let new = dropped.saturating_sub(self.accounted.swap(dropped, Ordering::Relaxed));
+		if new > 0 {
+			self.drops.record(new, &self.conn);
+		}
+	}
+}
@@ -4,17 +4,25 @@
 //! incoming QUIC connections and handle TCP and UDP traffic relaying.
 use std::{
 	collections::HashMap,
-	net::SocketAddr,
+	io::IoSliceMut,
+	net::{Ipv4Addr, SocketAddr},
+	path::{Path, PathBuf},
 	pin::Pin,
-	sync::Arc,
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicBool, Ordering},
+	},
 	task::{Context as TaskContext, Poll},
 	time::Duration,
 };
 
-use bytes::BytesMut;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use bytes::{Bytes, BytesMut};
+use crossfire::{MAsyncRx, MAsyncTx, TrySendError, stream::AsyncStream};
 use eyre::{Context, ContextCompat};
-use moka::future::Cache;
-use quinn::{Endpoint, EndpointConfig, IdleTimeout, ServerConfig, TokioRuntime, TransportConfig, VarInt};
+use quinn::{
+	Endpoint, EndpointConfig, IdleTimeout, ServerConfig, TokioRuntime, TransportConfig, VarInt, crypto::rustls::HandshakeData,
+};
 use rustls::{
 	ServerConfig as RustlsServerConfig,
 	pki_types::{CertificateDer, PrivateKeyDer},
@@ -25,15 +33,40 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use wind_core::{AbstractInbound, AppContext, InboundCallback, error, info, warn};
+use wind_core::{
+	AbstractInbound, AppContext, ConnInfo, Decision, InboundCallback, debug, error,
+	events::{Event, EventBus},
+	info,
+	log::{ConnId, tracing::Instrument as _},
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+	udp::{AbstractUdpSocket, EcnCodepoint, RecvMeta, Transmit, UdpPacket, UdpPollHelper, UdpPoller},
+	warn,
+};
+
+use crate::{
+	acl::UserAcl,
+	datagram::{DATAGRAM_RECEIVE_BUFFER, DatagramDrops, DatagramReader},
+	proto::{
+		Address, AddressType, CloseExt as _, CloseReason, CmdType, Command, UdpStream, UdpStreamConfig, connect_error_code,
+	},
+	tls::{SniCertResolver, SniCertificate},
+	users::{UserTable, load_users},
+};
 
-use crate::proto::{CmdType, Command};
+/// Packets queued per UDP association in either direction before more are
+/// dropped
+const UDP_SESSION_QUEUE: usize = 128;
 
 /// Wrapper to combine quinn's SendStream and RecvStream into a single
 /// bidirectional stream
 struct QuicBidiStream {
-	send: quinn::SendStream,
-	recv: quinn::RecvStream,
+	send:        quinn::SendStream,
+	recv:        quinn::RecvStream,
+	client_addr: SocketAddr,
+	local_addr:  SocketAddr,
+	/// The outbound reported reaching the target
+	connected:   bool,
 }
 
 impl AsyncRead for QuicBidiStream {
@@ -66,6 +99,29 @@ impl AsyncWrite for QuicBidiStream {
 	}
 }
 
+impl AbstractTcpStream for QuicBidiStream {
+	/// A target that can't be reached resets the stream with the reason, for
+	/// clients waiting on a verdict before answering theirs
+	fn on_connect(&mut self, result: Result<(), ConnectError>) -> impl Future<Output = std::io::Result<()>> + Send + Sync {
+		match result {
+			Ok(()) => self.connected = true,
+			Err(err) if !self.connected => {
+				let _ = self.send.reset(connect_error_code(err));
+			}
+			Err(_) => {}
+		}
+		std::future::ready(Ok(()))
+	}
+
+	fn client_addr(&self) -> Option<SocketAddr> {
+		Some(self.client_addr)
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		Some(self.local_addr)
+	}
+}
+
 pub struct TuicInboundOpts {
 	/// Server bind address
 	pub listen_addr: SocketAddr,
@@ -76,12 +132,29 @@ pub struct TuicInboundOpts {
 	/// TLS private key
 	pub private_key: PrivateKeyDer<'static>,
 
+	/// Certificates presented instead of `certificate` to clients whose SNI
+	/// matches, the first match wins
+	pub sni_certificates: Vec<SniCertificate>,
+
 	/// ALPN protocols
 	pub alpn: Vec<String>,
 
+	/// Those of `alpn` that are served as TUIC, all of them when empty
+	pub tuic_alpn: Vec<String>,
+
+	/// What happens to connections that negotiated an ALPN outside `tuic_alpn`
+	pub alpn_fallback: AlpnFallback,
+
 	/// Authentication credentials: UUID -> password
 	pub users: HashMap<Uuid, String>,
 
+	/// File of further `uuid:password` lines, read again on
+	/// [`TuicInbound::reload_users`] and, on Unix, on SIGHUP
+	pub users_file: Option<PathBuf>,
+
+	/// Destinations each user may reach, users without an entry may reach any
+	pub acls: HashMap<Uuid, UserAcl>,
+
 	/// Authentication timeout
 	pub auth_timeout: Duration,
 
@@ -111,6 +184,14 @@ pub struct TuicInboundOpts {
 
 	/// Enable GSO (Generic Segmentation Offload)
 	pub gso: bool,
+
+	/// Reassembly of fragmented packets from clients and fragmentation of
+	/// replies
+	pub udp_stream: UdpStreamConfig,
+
+	/// Datagram bytes buffered per connection until read, older datagrams are
+	/// dropped beyond it. See [`DATAGRAM_RECEIVE_BUFFER`]
+	pub datagram_receive_buffer: usize,
 }
 
 impl Default for TuicInboundOpts {
@@ -119,8 +200,13 @@ impl Default for TuicInboundOpts {
 			listen_addr: "0.0.0.0:443".parse().unwrap(),
 			certificate: Vec::new(),
 			private_key: PrivateKeyDer::Pkcs8(vec![].into()),
+			sni_certificates: Vec::new(),
 			alpn: vec!["h3".to_string()],
+			tuic_alpn: Vec::new(),
+			alpn_fallback: AlpnFallback::Reject,
 			users: HashMap::new(),
+			users_file: None,
+			acls: HashMap::new(),
 			auth_timeout: Duration::from_secs(3),
 			max_idle_time: Duration::from_secs(15),
 			max_concurrent_bi_streams: 32,
@@ -131,36 +217,83 @@ impl Default for TuicInboundOpts {
 			initial_mtu: 1200,
 			min_mtu: 1200,
 			gso: true,
+			udp_stream: UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
 		}
 	}
 }
 
-/// TUIC inbound server
+/// Handling of connections that share the port with TUIC under another ALPN
+pub enum AlpnFallback {
+	/// Close them with [`CloseReason::UnsupportedAlpn`]
+	Reject,
+	/// Hand them over to another QUIC service, e.g. an HTTP/3 server
+	Forward(Arc<dyn Fn(quinn::Connection) + Send + Sync>),
+}
+
+/// TUIC inbound server, clones share its users and cancellation
+#[derive(Clone)]
 pub struct TuicInbound {
-	pub ctx: Arc<AppContext>,
-	opts:    TuicInboundOpts,
-	cancel:  CancellationToken,
+	pub ctx:            Arc<AppContext>,
+	/// Datagrams from clients dropped on any connection
+	pub datagram_drops: Arc<DatagramDrops>,
+	opts:               Arc<TuicInboundOpts>,
+	/// `opts.users` along with those of `opts.users_file`, and `opts.acls`
+	users:              Arc<ArcSwap<UserTable>>,
+	cancel:             CancellationToken,
 }
 
 impl TuicInbound {
 	pub fn new(ctx: Arc<AppContext>, opts: TuicInboundOpts) -> Self {
 		Self {
-			opts,
-			cancel: ctx.token.child_token(),
+			users: Arc::new(ArcSwap::from_pointee(UserTable {
+				passwords: opts.users.clone(),
+				acls:      opts.acls.clone(),
+			})),
+			opts: Arc::new(opts),
+			cancel: ctx.listen_token.child_token(),
 			ctx,
+			datagram_drops: Arc::default(),
 		}
 	}
 
+	/// Read `users_file` again, new connections authenticate against the
+	/// result. Returns how many users there are now
+	///
+	/// The users in use stay as they were if the file can't be read or parsed.
+	pub fn reload_users(&self) -> eyre::Result<usize> {
+		reload_users(&self.users, &self.opts.users, self.opts.users_file.as_deref())
+	}
+
+	/// The users new connections authenticate against
+	pub fn users(&self) -> Arc<UserTable> {
+		self.users.load_full()
+	}
+
 	fn create_server_config(&self) -> eyre::Result<ServerConfig> {
 		// Setup TLS configuration
-		let mut crypto = RustlsServerConfig::builder_with_protocol_versions(&[&rustls::version::TLS13])
-			.with_no_client_auth()
-			.with_single_cert(self.opts.certificate.clone(), self.opts.private_key.clone_key())
-			.wrap_err("Failed to configure TLS certificate")?;
+		let provider = crate::tls::ensure_crypto_provider()?;
+		let builder = RustlsServerConfig::builder_with_provider(provider.clone())
+			.with_protocol_versions(&[&rustls::version::TLS13])?
+			.with_no_client_auth();
+		let default = (self.opts.certificate.clone(), self.opts.private_key.clone_key());
+		let mut crypto = if self.opts.sni_certificates.is_empty() {
+			builder
+				.with_single_cert(default.0, default.1)
+				.wrap_err("Failed to configure TLS certificate")?
+		} else {
+			builder.with_cert_resolver(Arc::new(SniCertResolver::new(
+				&provider,
+				default,
+				&self.opts.sni_certificates,
+			)?))
+		};
 
 		crypto.alpn_protocols = self.opts.alpn.iter().map(|alpn| alpn.as_bytes().to_vec()).collect();
 
-		// Enable 0-RTT if configured
+		// Enable 0-RTT if configured. Replays are left to rustls, whose default
+		// session cache hands out each ticket once, so a replayed flight resumes
+		// nothing and its early data is dropped
 		if self.opts.zero_rtt {
 			crypto.max_early_data_size = u32::MAX;
 			crypto.send_half_rtt_data = true;
@@ -183,7 +316,8 @@ impl TuicInbound {
 			))
 			.initial_mtu(self.opts.initial_mtu)
 			.min_mtu(self.opts.min_mtu)
-			.enable_segmentation_offload(self.opts.gso);
+			.enable_segmentation_offload(self.opts.gso)
+			.datagram_receive_buffer_size(Some(self.opts.datagram_receive_buffer));
 
 		config.transport_config(Arc::new(transport));
 
@@ -193,46 +327,73 @@ impl TuicInbound {
 
 impl AbstractInbound for TuicInbound {
 	async fn listen(&self, cb: &impl InboundCallback) -> eyre::Result<()> {
+		self.opts.udp_stream.validate()?;
 		let config = self.create_server_config()?;
+		if self.opts.users_file.is_some() {
+			info!("Loaded {} TUIC users", self.reload_users()?);
+		}
+		#[cfg(unix)]
+		if let Some(path) = self.opts.users_file.clone() {
+			let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+				.wrap_err("Failed to listen for SIGHUP")?;
+			let (users, configured, cancel) = (self.users.clone(), self.opts.users.clone(), self.cancel.clone());
+			self.ctx.spawn("tuic-users-reload", async move {
+				loop {
+					tokio::select! {
+						_ = cancel.cancelled() => break,
+						signal = hangup.recv() => {
+							if signal.is_none() {
+								break;
+							}
+							match reload_users(&users, &configured, Some(&path)) {
+								Ok(count) => info!("Reloaded TUIC users, {count} in total"),
+								Err(err) => error!("Failed to reload TUIC users, keeping the previous ones: {err:#}"),
+							}
+						}
+					}
+				}
+			});
+		}
 
-		// Bind socket
-		let socket = std::net::UdpSocket::bind(self.opts.listen_addr)
-			.with_context(|| format!("Failed to bind socket on {}", self.opts.listen_addr))?;
+		// Bind socket, unless the service manager passed one in
+		#[cfg(unix)]
+		let inherited = wind_core::systemd::take_udp_socket(self.opts.listen_addr)?;
+		#[cfg(not(unix))]
+		let inherited = None;
+		let socket = match inherited {
+			Some(socket) => socket,
+			None => std::net::UdpSocket::bind(self.opts.listen_addr)
+				.with_context(|| format!("Failed to bind socket on {}", self.opts.listen_addr))?,
+		};
 
 		// Create endpoint
 		let endpoint = Endpoint::new(EndpointConfig::default(), Some(config), socket, Arc::new(TokioRuntime))
 			.wrap_err("Failed to create QUIC endpoint")?;
 
-		info!("TUIC server listening on {}", endpoint.local_addr().unwrap());
-
-		// NOTE: Currently handles connections sequentially due to callback lifetime
-		// constraints. Each QUIC connection runs in a loop processing
-		// streams/datagrams until the connection closes. This means only one
-		// connection can be active at a time, which is a limitation.
-		//
-		// To support concurrent connections, the InboundCallback trait would need to be
-		// Clone + 'static, or the listen() method signature would need to change to
-		// take ownership/Arc of the callback.
+		let local_addr = endpoint.local_addr()?;
+		info!("TUIC server listening on {}", local_addr);
 
 		// Accept connections loop
 		loop {
 			tokio::select! {
 				_ = self.cancel.cancelled() => {
 					info!("TUIC server shutting down");
+					let reason = CloseReason::ServerShutdown;
+					endpoint.close(reason.code(), reason.phrase().as_bytes());
 					break;
 				}
 				Some(incoming) = endpoint.accept() => {
-					let opts = &self.opts;
-					let users = opts.users.clone();
-					let auth_timeout = opts.auth_timeout;
-					let zero_rtt = opts.zero_rtt;
-
-					// Handle connection directly (blocking until connection closes)
-					// This limits the server to one active connection at a time
-					match handle_connection(incoming, users, auth_timeout, zero_rtt, cb).await {
-						Ok(_) => {}
-						Err(err) => error!("Connection handler error: {:?}", err),
-					}
+					// Each connection runs on its own until it closes
+					let (inbound, cb) = (self.clone(), cb.clone());
+					self.ctx.spawn(
+						"tuic-connection",
+						async move {
+							if let Err(err) = handle_connection(incoming, local_addr, &inbound, &cb).await {
+								error!("Connection handler error: {:?}", err);
+							}
+						}
+						.instrument(ConnId::next().span()),
+					);
 				}
 			}
 		}
@@ -241,29 +402,326 @@ impl AbstractInbound for TuicInbound {
 	}
 }
 
+/// Replace the passwords of `users` with `configured` plus the users of
+/// `path`
+fn reload_users(users: &ArcSwap<UserTable>, configured: &HashMap<Uuid, String>, path: Option<&Path>) -> eyre::Result<usize> {
+	let mut passwords = configured.clone();
+	if let Some(path) = path {
+		passwords.extend(load_users(path)?);
+	}
+	let count = passwords.len();
+	users.store(Arc::new(UserTable {
+		passwords,
+		acls: users.load().acls.clone(),
+	}));
+	Ok(count)
+}
+
+/// Whether a connection that negotiated `alpn` speaks TUIC. Clients that
+/// offered no ALPN are taken as TUIC
+fn is_tuic_alpn(alpn: Option<&[u8]>, tuic_alpn: &[String]) -> bool {
+	match alpn {
+		None => true,
+		Some(alpn) => tuic_alpn.is_empty() || tuic_alpn.iter().any(|tuic| tuic.as_bytes() == alpn),
+	}
+}
+
 /// Represents an authenticated connection
 struct InboundCtx {
 	conn:         quinn::Connection,
+	/// Address of the endpoint the connection came in on
+	local_addr:   SocketAddr,
 	uuid:         Arc<RwLock<Option<Uuid>>>,
-	users:        HashMap<Uuid, String>,
+	/// Cancelled once `uuid` is set
+	authed:       CancellationToken,
+	/// How long streams and datagrams wait for `authed`
+	auth_timeout: Duration,
+	users:        Arc<UserTable>,
 	udp_sessions: Arc<RwLock<HashMap<u16, UdpSession>>>,
+	udp_stream:   UdpStreamConfig,
+	early_data:   EarlyData,
+	datagrams:    DatagramReader,
+	/// [`AppContext::udp_enabled`], packets are refused while it's off
+	udp_enabled:  Arc<AtomicBool>,
+	/// [`AppContext::events`], failed authentications are reported there
+	events:       EventBus,
+	/// Cancelled once the connection ends, ending its UDP associations
+	cancel:       CancellationToken,
+}
+
+impl InboundCtx {
+	/// Where the client reached us, the address it was sent to when the
+	/// platform tells and the endpoint's otherwise
+	fn local_addr(&self) -> SocketAddr {
+		match self.conn.local_ip() {
+			Some(ip) => SocketAddr::new(ip, self.local_addr.port()),
+			None => self.local_addr,
+		}
+	}
+
+	/// Whether the client has authenticated, nothing but `Auth` and
+	/// `Heartbeat` is served before. Its other streams and datagrams may
+	/// overtake the `Auth` stream, so they wait for it up to the auth timeout
+	async fn wait_authenticated(&self) -> bool {
+		tokio::select! {
+			biased;
+			_ = self.authed.cancelled() => true,
+			_ = self.cancel.cancelled() => false,
+			_ = tokio::time::sleep(self.auth_timeout) => false,
+		}
+	}
+
+	/// Whether the authenticated user may reach `target_addr`
+	async fn permits(&self, target_addr: &TargetAddr) -> bool {
+		self.uuid
+			.read()
+			.await
+			.is_some_and(|uuid| self.users.permits(&uuid, target_addr))
+	}
+
+	/// What [`InboundCallback::authorize`] is told of this connection
+	async fn conn_info(&self, target: Option<TargetAddr>) -> ConnInfo {
+		ConnInfo {
+			client_addr: Some(self.conn.remote_address()),
+			target,
+			user: self.uuid.read().await.map(|uuid| uuid.to_string()),
+		}
+	}
+
+	/// The association `assoc_id`, handing a socket for it to `callback`
+	/// when it is new. `None` when `callback` denies a new association, which
+	/// is asked again on its next packet
+	async fn udp_session<C: InboundCallback>(&self, assoc_id: u16, callback: &C) -> Option<Arc<UdpStream>> {
+		if let Some(session) = self.udp_sessions.read().await.get(&assoc_id) {
+			return Some(session.stream.clone());
+		}
+		// Not holding the lock, the callback may take its time
+		if callback.authorize(&self.conn_info(None).await).await == Decision::Deny {
+			return None;
+		}
+		let mut sessions = self.udp_sessions.write().await;
+		if let Some(session) = sessions.get(&assoc_id) {
+			return Some(session.stream.clone());
+		}
+
+		let (receive_tx, receive_rx) = crossfire::mpmc::bounded_async(UDP_SESSION_QUEUE);
+		let (reply_tx, reply_rx) = crossfire::mpmc::bounded_async(UDP_SESSION_QUEUE);
+		let stream = Arc::new(UdpStream::new(self.conn.clone(), assoc_id, receive_tx, self.udp_stream));
+		let token = self.cancel.child_token();
+		let socket = TuicInboundUdpSocket {
+			incoming:    Mutex::new(receive_rx.into_stream()),
+			replies:     reply_tx,
+			client_addr: self.conn.remote_address(),
+			last_target: ArcSwapOption::empty(),
+			token:       token.clone(),
+		};
+		info!("Opened UDP session {:#06x}", assoc_id);
+		tokio::spawn(send_replies(stream.clone(), reply_rx, token.clone()).in_current_span());
+
+		let callback = callback.clone();
+		let sessions_ref = self.udp_sessions.clone();
+		let session_stream = stream.clone();
+		tokio::spawn(
+			async move {
+				if let Err(e) = callback.handle_udpsocket(socket).await {
+					warn!("UDP session {:#06x} ended: {:?}", assoc_id, e);
+				}
+				// The id may have been dissociated and reused meanwhile
+				let mut sessions = sessions_ref.write().await;
+				if sessions
+					.get(&assoc_id)
+					.is_some_and(|session| Arc::ptr_eq(&session.stream, &session_stream))
+					&& let Some(session) = sessions.remove(&assoc_id)
+				{
+					session.token.cancel();
+				}
+			}
+			.in_current_span(),
+		);
+
+		sessions.insert(
+			assoc_id,
+			UdpSession {
+				stream: stream.clone(),
+				token,
+			},
+		);
+		Some(stream)
+	}
+}
+
+/// Whether a connection's data may still arrive on 0-RTT keys, which unlike
+/// 1-RTT data can be replayed by anyone who captured it
+#[derive(Debug, Clone)]
+struct EarlyData(CancellationToken);
+
+impl EarlyData {
+	/// For connections that completed the handshake before any data
+	fn confirmed() -> Self {
+		let token = CancellationToken::new();
+		token.cancel();
+		Self(token)
+	}
+
+	/// For connections accepted with 0-RTT, until [`Self::confirm`]
+	fn pending() -> Self {
+		Self(CancellationToken::new())
+	}
+
+	fn confirm(&self) {
+		self.0.cancel();
+	}
+
+	fn is_early(&self) -> bool {
+		!self.0.is_cancelled()
+	}
+
+	/// Wait for the handshake to complete
+	async fn confirmed_wait(&self) {
+		self.0.cancelled().await
+	}
 }
 
-/// UDP session tracking
-#[allow(dead_code)]
+/// A client's UDP association, fed by the connection's read loop
 struct UdpSession {
-	assoc_id:  u16,
-	// Track packet fragments if needed
-	fragments: Cache<u16, Vec<u8>>,
+	stream: Arc<UdpStream>,
+	/// The association's, see [`AbstractUdpSocket::association_token`]
+	token:  CancellationToken,
+}
+
+/// Send the replies queued on an association's socket to the client
+async fn send_replies(stream: Arc<UdpStream>, replies: MAsyncRx<UdpPacket>, token: CancellationToken) {
+	loop {
+		let packet = tokio::select! {
+			_ = token.cancelled() => break,
+			packet = replies.recv() => match packet {
+				Ok(packet) => packet,
+				Err(_) => break,
+			},
+		};
+		if let Err(e) = stream.send_packet(packet).await {
+			warn!("Failed to send UDP reply: {:?}", e);
+		}
+	}
+}
+
+/// A client's UDP association as a socket: it receives the packets the
+/// client sends, reassembled, and sends replies back as `Packet` commands
+///
+/// TUIC carries no ECN, so packets only keep their codepoint within this
+/// process. Replies addressed to the unspecified address go out as coming
+/// from the last target the client sent to.
+pub struct TuicInboundUdpSocket {
+	incoming:    Mutex<AsyncStream<UdpPacket>>,
+	replies:     MAsyncTx<UdpPacket>,
+	client_addr: SocketAddr,
+	last_target: ArcSwapOption<TargetAddr>,
+	token:       CancellationToken,
+}
+
+impl TuicInboundUdpSocket {
+	fn reply(&self, buf: &[u8], source: SocketAddr, ecn: Option<EcnCodepoint>) -> std::io::Result<UdpPacket> {
+		let target = if source.ip().is_unspecified() {
+			self.last_target
+				.load_full()
+				.map(|target| (*target).clone())
+				.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::AddrNotAvailable, "no packet to reply to yet"))?
+		} else {
+			source.into()
+		};
+		Ok(UdpPacket {
+			source: None,
+			target,
+			payload: Bytes::copy_from_slice(buf),
+			ecn,
+		})
+	}
+}
+
+impl AbstractUdpSocket for TuicInboundUdpSocket {
+	fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+		// Replies are queued, so the socket is always writable
+		Box::pin(UdpPollHelper::new(|| async { Ok(()) }))
+	}
+
+	fn try_send(&self, transmit: &Transmit) -> std::io::Result<()> {
+		let packet = self.reply(transmit.contents, transmit.destination, transmit.ecn)?;
+		self.replies.try_send(packet).map_err(|e| match e {
+			TrySendError::Full(_) => std::io::ErrorKind::WouldBlock.into(),
+			TrySendError::Disconnected(_) => std::io::ErrorKind::BrokenPipe.into(),
+		})
+	}
+
+	fn poll_recv(
+		&self,
+		cx: &mut TaskContext,
+		bufs: &mut [IoSliceMut<'_>],
+		meta: &mut [RecvMeta],
+	) -> Poll<std::io::Result<usize>> {
+		let mut incoming = self.incoming.lock().unwrap();
+		let mut count = 0;
+		while count < bufs.len().min(meta.len()) {
+			let packet = match incoming.poll_item(cx) {
+				Poll::Ready(Some(packet)) => packet,
+				Poll::Ready(None) if count == 0 => {
+					return Poll::Ready(Err(std::io::Error::new(
+						std::io::ErrorKind::ConnectionAborted,
+						"UDP session closed",
+					)));
+				}
+				Poll::Pending if count == 0 => return Poll::Pending,
+				Poll::Ready(None) | Poll::Pending => break,
+			};
+			let len = packet.payload.len();
+			if len > bufs[count].len() {
+				warn!(
+					"Dropped {} byte UDP packet to {}, larger than the {} byte receive buffer",
+					len,
+					packet.target,
+					bufs[count].len()
+				);
+				continue;
+			}
+			bufs[count][..len].copy_from_slice(&packet.payload);
+			meta[count] = RecvMeta {
+				addr: self.client_addr,
+				len,
+				stride: len,
+				ecn: packet.ecn,
+				dst_ip: None,
+				destination: Some(packet.target.clone()),
+			};
+			self.last_target.store(Some(Arc::new(packet.target)));
+			count += 1;
+		}
+		Poll::Ready(Ok(count))
+	}
+
+	fn local_addr(&self) -> std::io::Result<SocketAddr> {
+		Ok(self.client_addr)
+	}
+
+	fn association_token(&self) -> CancellationToken {
+		self.token.clone()
+	}
+
+	async fn send_ecn(&self, buf: &[u8], target: SocketAddr, ecn: Option<EcnCodepoint>) -> std::io::Result<usize> {
+		let packet = self.reply(buf, target, ecn)?;
+		self.replies
+			.send(packet)
+			.await
+			.map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+		Ok(buf.len())
+	}
 }
 
 async fn handle_connection<C: InboundCallback>(
 	incoming: quinn::Incoming,
-	users: HashMap<Uuid, String>,
-	auth_timeout: Duration,
-	zero_rtt: bool,
+	local_addr: SocketAddr,
+	inbound: &TuicInbound,
 	callback: &C,
 ) -> eyre::Result<()> {
+	let (opts, users) = (&inbound.opts, inbound.users());
 	let remote_addr = incoming.remote_address();
 
 	let connecting = match incoming.accept() {
@@ -275,45 +733,115 @@ async fn handle_connection<C: InboundCallback>(
 	};
 
 	// Accept connection with optional 0-RTT
-	let conn = if zero_rtt {
+	let (conn, early_data) = if opts.zero_rtt {
 		match connecting.into_0rtt() {
-			Ok((conn, _)) => {
+			Ok((conn, accepted)) => {
 				info!("Accepted 0-RTT connection from {}", remote_addr);
-				conn
+				let early_data = EarlyData::pending();
+				let handshake = early_data.clone();
+				let handshake_conn = conn.clone();
+				tokio::spawn(async move {
+					// Resolves once the handshake completes or fails. On a server its value
+					// says nothing about either, only a still open connection does
+					accepted.await;
+					if handshake_conn.close_reason().is_none() {
+						handshake.confirm();
+					}
+				});
+				(conn, early_data)
 			}
 			Err(connecting) => {
 				let conn = connecting.await.wrap_err("Failed to establish QUIC connection")?;
 				info!("Accepted 1-RTT connection from {}", remote_addr);
-				conn
+				(conn, EarlyData::confirmed())
 			}
 		}
 	} else {
 		let conn = connecting.await.wrap_err("Failed to establish QUIC connection")?;
 		info!("Accepted connection from {}", remote_addr);
-		conn
+		(conn, EarlyData::confirmed())
 	};
 
+	let alpn = conn
+		.handshake_data()
+		.and_then(|data| data.downcast::<HandshakeData>().ok())
+		.and_then(|data| data.protocol);
+	info!(
+		"Connection from {} negotiated ALPN {}",
+		remote_addr,
+		alpn.as_deref().map_or("(none)".into(), String::from_utf8_lossy)
+	);
+	if !is_tuic_alpn(alpn.as_deref(), &opts.tuic_alpn) {
+		match &opts.alpn_fallback {
+			AlpnFallback::Reject => conn.close_with(CloseReason::UnsupportedAlpn),
+			AlpnFallback::Forward(forward) => forward(conn),
+		}
+		return Ok(());
+	}
+
+	let auth_timeout = opts.auth_timeout;
 	let connection = Arc::new(InboundCtx {
 		conn: conn.clone(),
+		local_addr,
 		uuid: Arc::new(RwLock::new(None)),
+		authed: CancellationToken::new(),
+		auth_timeout,
 		users,
 		udp_sessions: Arc::new(RwLock::new(HashMap::new())),
+		udp_stream: opts.udp_stream,
+		early_data,
+		datagrams: DatagramReader::new(conn.clone(), inbound.datagram_drops.clone()),
+		udp_enabled: inbound.ctx.udp_enabled.clone(),
+		events: inbound.ctx.events.clone(),
+		cancel: inbound.cancel.child_token(),
 	});
 
 	// Spawn authentication timeout task
 	let conn_auth = connection.clone();
-	tokio::spawn(async move {
-		tokio::time::sleep(auth_timeout).await;
-		let uuid = conn_auth.uuid.read().await;
-		if uuid.is_none() {
-			warn!("Connection from {} authentication timeout", remote_addr);
-			conn_auth.conn.close(VarInt::from_u32(0), b"auth timeout");
+	tokio::spawn(
+		async move {
+			tokio::time::sleep(auth_timeout).await;
+			let uuid = conn_auth.uuid.read().await;
+			if uuid.is_none() {
+				warn!("Connection from {} authentication timeout", remote_addr);
+				conn_auth.conn.close_with(CloseReason::AuthTimeout);
+			}
 		}
-	});
+		.in_current_span(),
+	);
+
+	// Datagrams are read on their own, in order, while streams are accepted
+	let datagram_conn = connection.clone();
+	let datagram_callback = callback.clone();
+	tokio::spawn(
+		async move {
+			loop {
+				let datagram = tokio::select! {
+					_ = datagram_conn.cancel.cancelled() => break,
+					result = datagram_conn.datagrams.read() => match result {
+						Err(e) => {
+							error!("Read datagram error: {:?}", e);
+							break;
+						}
+						Ok(datagram) => datagram,
+					},
+				};
+
+				if let Err(e) = handle_datagram(datagram_conn.clone(), datagram, &datagram_callback).await {
+					error!("Datagram error: {:?}", e);
+				}
+			}
+		}
+		.in_current_span(),
+	);
 
-	// Handle incoming streams and datagrams
+	// Handle incoming streams, each on its own so a slow one holds up no other
 	loop {
 		tokio::select! {
+			_ = inbound.cancel.cancelled() => {
+				connection.conn.close_with(CloseReason::ServerShutdown);
+				break;
+			}
 			// Handle unidirectional streams
 			result = connection.conn.accept_uni() => {
 				let recv = match result {
@@ -323,11 +851,16 @@ async fn handle_connection<C: InboundCallback>(
 					}
 					Ok(recv) => recv,
 				};
-				
-				let conn = connection.clone();
-				if let Err(e) = handle_uni_stream(conn, recv, callback).await {
-					error!("Uni stream error: {:?}", e);
-				}
+
+				let (conn, callback) = (connection.clone(), callback.clone());
+				tokio::spawn(
+					async move {
+						if let Err(e) = handle_uni_stream(conn, recv, &callback).await {
+							error!("Uni stream error: {:?}", e);
+						}
+					}
+					.in_current_span(),
+				);
 			}
 			// Handle bidirectional streams
 			result = connection.conn.accept_bi() => {
@@ -338,29 +871,20 @@ async fn handle_connection<C: InboundCallback>(
 					}
 					Ok(streams) => streams,
 				};
-				
-				let conn = connection.clone();
-				if let Err(e) = handle_bi_stream(conn, send, recv, callback).await {
-					error!("Bi stream error: {:?}", e);
-				}
-			}
-			// Handle datagrams
-			result = connection.conn.read_datagram() => {
-				let datagram = match result {
-					Err(e) => {
-						error!("Read datagram error: {:?}", e);
-						break;
+
+				let (conn, callback) = (connection.clone(), callback.clone());
+				tokio::spawn(
+					async move {
+						if let Err(e) = handle_bi_stream(conn, send, recv, &callback).await {
+							error!("Bi stream error: {:?}", e);
+						}
 					}
-					Ok(datagram) => datagram,
-				};
-				
-				let conn = connection.clone();
-				if let Err(e) = handle_datagram(conn, datagram, callback).await {
-					error!("Datagram error: {:?}", e);
-				}
+					.in_current_span(),
+				);
 			}
 		}
 	}
+	connection.cancel.cancel();
 
 	Ok(())
 }
@@ -379,21 +903,29 @@ async fn handle_uni_stream<C: InboundCallback>(
 	let mut buf = BytesMut::from(&data[..]);
 
 	// Decode header and command using helper functions
-	let header = crate::proto::decode_header(&mut buf, "uni stream")?;
-	let cmd = crate::proto::decode_command(header.command, &mut buf, "uni stream")?;
+	let header =
+		crate::proto::decode_header(&mut buf, "uni stream").inspect_err(|_| ctx.conn.close_with(CloseReason::ProtocolError))?;
+	let cmd = crate::proto::decode_command(header.command, &mut buf, "uni stream")
+		.inspect_err(|_| ctx.conn.close_with(CloseReason::ProtocolError))?;
 
 	match cmd {
 		Command::Auth { uuid, token } => {
-			handle_auth(&ctx, uuid, token).await?;
+			handle_auth(&ctx, uuid, token).await.inspect_err(|_| {
+				ctx.events.publish(Event::AuthFailed {
+					inbound:     "tuic",
+					client_addr: Some(ctx.conn.remote_address()),
+				});
+				ctx.conn.close_with(CloseReason::AuthFailure);
+			})?;
+		}
+		Command::Packet { .. } | Command::Dissociate { .. } if !ctx.wait_authenticated().await => {
+			warn!("Unauthenticated {:?} on uni stream", cmd);
 		}
-		Command::Packet { assoc_id, size, .. } => {
+		Command::Packet { size, .. } => {
 			// Decode address
 			let addr = crate::proto::decode_address(&mut buf, "uni stream packet")?;
 			let payload = buf.split_to(size as usize).freeze();
-			
-			// Convert address to TargetAddr using helper function
-			let target_addr = crate::proto::address_to_target(addr)?;
-			handle_udp_packet(&ctx, assoc_id, target_addr, payload, callback).await?;
+			handle_udp_packet(&ctx, cmd, addr, payload, callback).await?;
 		}
 		Command::Dissociate { assoc_id } => {
 			handle_dissociate(&ctx, assoc_id).await?;
@@ -413,17 +945,15 @@ async fn handle_uni_stream<C: InboundCallback>(
 /// Handle bidirectional stream (Connect for TCP relay)
 async fn handle_bi_stream<C: InboundCallback>(
 	connection: Arc<InboundCtx>,
-	send: quinn::SendStream,
+	mut send: quinn::SendStream,
 	mut recv: quinn::RecvStream,
 	callback: &C,
 ) -> eyre::Result<()> {
 	// Check if authenticated - guard clause
-	let uuid = connection.uuid.read().await;
-	if uuid.is_none() {
+	if !connection.wait_authenticated().await {
 		warn!("Unauthenticated bi stream attempt");
 		return Ok(());
 	}
-	drop(uuid);
 
 	// Read header and command
 	let mut header_buf = vec![0u8; 2];
@@ -432,28 +962,55 @@ async fn handle_bi_stream<C: InboundCallback>(
 		.map_err(|e| eyre::eyre!("Failed to read header: {}", e))?;
 	let mut buf = BytesMut::from(&header_buf[..]);
 
-	let header = crate::proto::decode_header(&mut buf, "bi stream")?;
+	let header = crate::proto::decode_header(&mut buf, "bi stream")
+		.inspect_err(|_| connection.conn.close_with(CloseReason::ProtocolError))?;
 
 	match header.command {
 		CmdType::Connect => {
 			// Decode command (Connect has no additional fields)
 			let _cmd = crate::proto::decode_command(CmdType::Connect, &mut BytesMut::new(), "bi stream")?;
 
-			// Read address
-			let addr_data = recv
-				.read_to_end(512)
-				.await
-				.map_err(|e| eyre::eyre!("Failed to read address: {}", e))?;
-			let mut addr_buf = BytesMut::from(&addr_data[..]);
-			let addr = crate::proto::decode_address(&mut addr_buf, "bi stream")?;
+			let addr = read_address(&mut recv).await?;
 
 			// Convert address to TargetAddr using helper function
 			let target_addr = crate::proto::address_to_target(addr)?;
 
 			info!("TCP connect to {}", target_addr);
+			if !connection.permits(&target_addr).await {
+				warn!("Refused TCP connect to {}, not permitted for this user", target_addr);
+				let _ = send.reset(connect_error_code(ConnectError::NotAllowed));
+				return Ok(());
+			}
+			let info = connection.conn_info(Some(target_addr.clone())).await;
+			if callback.authorize(&info).await == Decision::Deny {
+				warn!("Refused TCP connect to {}, denied by the callback", target_addr);
+				let _ = send.reset(connect_error_code(ConnectError::NotAllowed));
+				return Ok(());
+			}
+
+			// Opening an upstream isn't idempotent, so a replayed 0-RTT Connect must not
+			// reach the outbound before the handshake proves the client is live
+			if connection.early_data.is_early() {
+				info!("Deferring 0-RTT connect to {} until the handshake completes", target_addr);
+				tokio::select! {
+					_ = connection.early_data.confirmed_wait() => {}
+					reason = connection.conn.closed() => {
+						return Err(eyre::eyre!("Connection closed before confirming 0-RTT connect: {}", reason));
+					}
+				}
+			}
+
+			// The client only schedules the upload, downloads are scheduled here
+			send.set_priority(crate::proto::stream_priority(&target_addr))?;
 
 			// Create bidirectional stream from quinn's send/recv pair
-			let stream = QuicBidiStream { send, recv };
+			let stream = QuicBidiStream {
+				send,
+				recv,
+				client_addr: connection.conn.remote_address(),
+				local_addr: connection.local_addr(),
+				connected: false,
+			};
 
 			// Forward to callback for outbound handling
 			callback.handle_tcpstream(target_addr, stream).await?;
@@ -466,6 +1023,35 @@ async fn handle_bi_stream<C: InboundCallback>(
 	Ok(())
 }
 
+/// Read the address of a `Connect`, no further since the client relays right
+/// behind it
+async fn read_address(recv: &mut quinn::RecvStream) -> eyre::Result<Address> {
+	let mut addr_buf = BytesMut::zeroed(1);
+	recv.read_exact(&mut addr_buf)
+		.await
+		.map_err(|e| eyre::eyre!("Failed to read address: {}", e))?;
+	let rest = match AddressType::from(addr_buf[0]) {
+		AddressType::IPv4 => 4 + 2,
+		AddressType::IPv6 => 16 + 2,
+		AddressType::Domain => {
+			let mut len = [0u8; 1];
+			recv.read_exact(&mut len)
+				.await
+				.map_err(|e| eyre::eyre!("Failed to read address: {}", e))?;
+			addr_buf.extend_from_slice(&len);
+			len[0] as usize + 2
+		}
+		// Left to the decoder to reject
+		AddressType::None | AddressType::Other(_) => 0,
+	};
+	let start = addr_buf.len();
+	addr_buf.resize(start + rest, 0);
+	recv.read_exact(&mut addr_buf[start..])
+		.await
+		.map_err(|e| eyre::eyre!("Failed to read address: {}", e))?;
+	crate::proto::decode_address(&mut addr_buf, "bi stream")
+}
+
 /// Handle datagram (for UDP packets)
 async fn handle_datagram<C: InboundCallback>(
 	connection: Arc<InboundCtx>,
@@ -473,11 +1059,9 @@ async fn handle_datagram<C: InboundCallback>(
 	callback: &C,
 ) -> eyre::Result<()> {
 	// Check if authenticated - guard clause
-	let uuid = connection.uuid.read().await;
-	if uuid.is_none() {
+	if !connection.wait_authenticated().await {
 		return Ok(());
 	}
-	drop(uuid);
 
 	let mut buf = BytesMut::from(data.as_ref());
 
@@ -488,13 +1072,10 @@ async fn handle_datagram<C: InboundCallback>(
 		CmdType::Packet => {
 			let cmd = crate::proto::decode_command(CmdType::Packet, &mut buf, "datagram")?;
 
-			if let Command::Packet { assoc_id, size, .. } = cmd {
+			if let Command::Packet { size, .. } = cmd {
 				let addr = crate::proto::decode_address(&mut buf, "datagram packet")?;
 				let payload = buf.split_to(size as usize).freeze();
-				
-				// Convert address to TargetAddr using helper function
-				let target_addr = crate::proto::address_to_target(addr)?;
-				handle_udp_packet(&connection, assoc_id, target_addr, payload, callback).await?;
+				handle_udp_packet(&connection, cmd, addr, payload, callback).await?;
 			}
 		}
 		CmdType::Heartbeat => {
@@ -511,7 +1092,7 @@ async fn handle_auth(connection: &InboundCtx, uuid: Uuid, token: [u8; 32]) -> ey
 	// Check if user exists
 	let password = connection
 		.users
-		.get(&uuid)
+		.password(&uuid)
 		.with_context(|| format!("Unknown user: {}", uuid))?;
 
 	// Verify token
@@ -527,46 +1108,246 @@ async fn handle_auth(connection: &InboundCtx, uuid: Uuid, token: [u8; 32]) -> ey
 
 	// Mark as authenticated
 	*connection.uuid.write().await = Some(uuid);
+	connection.authed.cancel();
 	info!("Connection authenticated as {}", uuid);
 
 	Ok(())
 }
 
-/// Handle UDP packet
+/// Hand a packet from the client to its association, opening the
+/// association on its first packet
 async fn handle_udp_packet<C: InboundCallback>(
-	_connection: &InboundCtx,
-	assoc_id: u16,
-	target_addr: wind_core::types::TargetAddr,
-	payload: bytes::Bytes,
-	_callback: &C,
+	connection: &InboundCtx,
+	cmd: Command,
+	addr: Address,
+	payload: Bytes,
+	callback: &C,
 ) -> eyre::Result<()> {
-	// TODO: Complete UDP packet handling
-	// Full implementation requires:
-	// 1. Creating a virtual UDP socket that maps TUIC packets to UDP datagrams
-	// 2. Handling bidirectional packet flow (inbound packets from client, outbound
-	//    packets to client)
-	// 3. Managing UDP sessions per assoc_id
-	// 4. Handling packet fragmentation
-	//
-	// For now, we log the received packet
-	info!(
-		"Received UDP packet for session {} to {} ({} bytes)",
+	let Command::Packet {
 		assoc_id,
-		target_addr,
-		payload.len()
-	);
-	// callback.handle_udpsocket(socket)
-	// The proper implementation would involve creating a TuicUdpSocket similar to
-	// Socks5UdpSocket that implements AbstractUdpSocket and handles the packet
-	// translation
-	warn!("UDP relay not yet fully implemented - packet received but not forwarded");
+		pkt_id,
+		frag_total,
+		frag_id,
+		..
+	} = cmd
+	else {
+		eyre::bail!("Expected a Packet command, got {:?}", cmd);
+	};
+	if !connection.udp_enabled.load(Ordering::Relaxed) {
+		debug!("Dropped UDP packet for association {:#06x}, UDP is switched off", assoc_id);
+		return Ok(());
+	}
+	// Only the first fragment of a packet carries its address
+	let target = match crate::proto::address_to_target(addr) {
+		Ok(target) if !connection.permits(&target).await => {
+			// Later fragments of the packet have nowhere to go and expire
+			debug!("Dropped UDP packet to {}, not permitted for this user", target);
+			return Ok(());
+		}
+		Ok(target) => target,
+		Err(_) => TargetAddr::IPv4(Ipv4Addr::UNSPECIFIED, 0),
+	};
+
+	let Some(stream) = connection.udp_session(assoc_id, callback).await else {
+		debug!("Dropped UDP packet for association {:#06x}, denied by the callback", assoc_id);
+		return Ok(());
+	};
+	if frag_total > 1 {
+		// Reassembly can't be awaited on the connection's loop, whose future
+		// must stay `Sync`
+		tokio::spawn(
+			async move {
+				if let Some(packet) = stream
+					.process_fragment(assoc_id, pkt_id, frag_total, frag_id, payload, None, target)
+					.await && let Err(e) = stream.receive_packet(packet)
+				{
+					warn!("Failed to queue UDP packet for session {:#06x}: {:?}", assoc_id, e);
+				}
+			}
+			.in_current_span(),
+		);
+	} else if let Some(payload) = stream.strip_checksum(pkt_id, payload) {
+		stream.receive_packet(UdpPacket {
+			source: None,
+			target,
+			payload,
+			ecn: None,
+		})?;
+	}
 	Ok(())
 }
 
 /// Handle UDP dissociate
 async fn handle_dissociate(connection: &InboundCtx, assoc_id: u16) -> eyre::Result<()> {
-	let mut sessions = connection.udp_sessions.write().await;
-	sessions.remove(&assoc_id);
-	info!("Dissociated UDP session {}", assoc_id);
+	if let Some(session) = connection.udp_sessions.write().await.remove(&assoc_id) {
+		session.token.cancel();
+	}
+	info!("Dissociated UDP session {:#06x}", assoc_id);
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_early_connect_deferred() {
+		let early_data = EarlyData::pending();
+		let connect = tokio::spawn({
+			let early_data = early_data.clone();
+			async move { early_data.confirmed_wait().await }
+		});
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert!(early_data.is_early());
+		assert!(!connect.is_finished());
+
+		early_data.confirm();
+		tokio::time::timeout(Duration::from_secs(1), connect).await.unwrap().unwrap();
+		assert!(!early_data.is_early());
+		assert!(!EarlyData::confirmed().is_early());
+	}
+
+	#[tokio::test]
+	async fn test_udp_socket_drops_oversized_packets() {
+		let (incoming_tx, incoming_rx) = crossfire::mpmc::bounded_async(UDP_SESSION_QUEUE);
+		let (replies, _replies_rx) = crossfire::mpmc::bounded_async(UDP_SESSION_QUEUE);
+		let socket = TuicInboundUdpSocket {
+			incoming: Mutex::new(incoming_rx.into_stream()),
+			replies,
+			client_addr: "127.0.0.1:1".parse().unwrap(),
+			last_target: ArcSwapOption::empty(),
+			token: CancellationToken::new(),
+		};
+		let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 9);
+		for payload in [&b"too large"[..], b"one", b"two"] {
+			incoming_tx
+				.send(UdpPacket {
+					source:  None,
+					target:  target.clone(),
+					payload: Bytes::copy_from_slice(payload),
+					ecn:     None,
+				})
+				.await
+				.unwrap();
+		}
+
+		let (mut first, mut second) = ([0u8; 4], [0u8; 4]);
+		let mut meta = [RecvMeta::default(), RecvMeta::default()];
+		let count = socket
+			.recv(&mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)], &mut meta)
+			.await
+			.unwrap();
+		assert_eq!(count, 2);
+		assert_eq!(&first[..meta[0].len], b"one");
+		assert_eq!(&second[..meta[1].len], b"two");
+	}
+
+	#[test]
+	fn test_users_file_reload() {
+		let path = std::env::temp_dir().join(format!("wind-tuic-users-{}", std::process::id()));
+		let configured = Uuid::from_u128(1);
+		std::fs::write(&path, "00000000-0000-0000-0000-000000000002:second\n").unwrap();
+		let inbound = TuicInbound::new(
+			Arc::new(AppContext::default()),
+			TuicInboundOpts {
+				users: HashMap::from([(configured, "first".to_string())]),
+				users_file: Some(path.clone()),
+				..Default::default()
+			},
+		);
+		assert_eq!(inbound.reload_users().unwrap(), 2);
+		assert_eq!(inbound.users().password(&Uuid::from_u128(2)), Some("second"));
+
+		std::fs::write(
+			&path,
+			"00000000-0000-0000-0000-000000000002:second\n00000000-0000-0000-0000-000000000003:third\n",
+		)
+		.unwrap();
+		assert_eq!(inbound.reload_users().unwrap(), 3);
+		assert_eq!(inbound.users().password(&Uuid::from_u128(3)), Some("third"));
+		assert_eq!(inbound.users().password(&configured), Some("first"));
+
+		// A broken file leaves the users as they were
+		std::fs::write(&path, "garbage\n").unwrap();
+		let err = inbound.reload_users().unwrap_err();
+		assert!(format!("{err:#}").contains("line 1"), "{err:#}");
+		assert_eq!(inbound.users().len(), 3);
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_datagram_receive_buffer_configured() {
+		crate::tls::ensure_crypto_provider().unwrap();
+		let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+		let inbound = TuicInbound::new(
+			Arc::new(AppContext::default()),
+			TuicInboundOpts {
+				certificate: vec![cert.cert.der().clone()],
+				private_key: PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into()),
+				datagram_receive_buffer: 8 << 20,
+				..Default::default()
+			},
+		);
+		let config = inbound.create_server_config().unwrap();
+		assert!(format!("{:?}", config.transport).contains("datagram_receive_buffer_size: Some(8388608)"));
+	}
+
+	#[tokio::test]
+	async fn test_certificate_by_sni() {
+		crate::tls::ensure_crypto_provider().unwrap();
+		let cert = |name: &str| rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+		let (default, a, b) = (cert("localhost"), cert("a.test"), cert("x.b.test"));
+		let sni_certificate = |pattern: &str, cert: &rcgen::CertifiedKey| SniCertificate {
+			pattern:     pattern.to_string(),
+			certificate: vec![cert.cert.der().clone()],
+			private_key: PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into()),
+		};
+		let inbound = TuicInbound::new(
+			Arc::new(AppContext::default()),
+			TuicInboundOpts {
+				certificate: vec![default.cert.der().clone()],
+				private_key: PrivateKeyDer::Pkcs8(default.key_pair.serialize_der().into()),
+				sni_certificates: vec![sni_certificate("A.test", &a), sni_certificate("*.b.test", &b)],
+				..Default::default()
+			},
+		);
+		let server = Endpoint::server(inbound.create_server_config().unwrap(), (Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+		let server_addr = server.local_addr().unwrap();
+		tokio::spawn(async move {
+			let mut held = Vec::new();
+			while let Some(incoming) = server.accept().await {
+				held.extend(incoming.await);
+			}
+		});
+
+		let mut client = Endpoint::client((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+		let mut crypto = crate::tls::client_config(true, &[&rustls::version::TLS13]).unwrap();
+		crypto.alpn_protocols = vec![b"h3".to_vec()];
+		client.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+			quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap(),
+		)));
+		for (server_name, expected) in [
+			("a.test", &a),
+			("x.b.test", &b),
+			("b.test", &default),
+			("other.test", &default),
+		] {
+			let connection = client.connect(server_addr, server_name).unwrap().await.unwrap();
+			let chain = connection
+				.peer_identity()
+				.unwrap()
+				.downcast::<Vec<CertificateDer<'static>>>()
+				.unwrap();
+			assert_eq!(chain[0], *expected.cert.der(), "{server_name}");
+		}
+	}
+
+	#[test]
+	fn test_alpn_routing() {
+		let tuic_alpn = ["h3".to_string()];
+		assert!(is_tuic_alpn(Some(b"h3".as_slice()), &tuic_alpn));
+		assert!(!is_tuic_alpn(Some(b"h3-29".as_slice()), &tuic_alpn));
+		assert!(is_tuic_alpn(None, &tuic_alpn));
+		assert!(is_tuic_alpn(Some(b"h3-29".as_slice()), &[]));
+	}
+}
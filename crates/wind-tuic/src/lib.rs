@@ -1,11 +1,16 @@
 #![feature(error_generic_member_access)]
 
+#[cfg(feature = "server")]
+pub mod acl;
+pub mod datagram;
 pub mod proto;
 mod task;
 pub mod tls;
 
 #[cfg(feature = "server")]
 pub mod inbound;
+#[cfg(feature = "server")]
+pub mod users;
 
 #[cfg(feature = "client")]
 pub mod outbound;
@@ -1,40 +1,175 @@
 use std::{
-	io::IoSliceMut,
-	net::{IpAddr, Ipv4Addr, SocketAddr},
-	sync::{Arc, atomic::AtomicU16},
-	time::Duration,
+	backtrace::Backtrace,
+	io,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	sync::{
+		Arc, LazyLock,
+		atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering},
+	},
+	time::{Duration, Instant},
 };
 
-use eyre::ensure;
+use arc_swap::ArcSwap;
+use bytes::BytesMut;
+use crossfire::{MAsyncRx, MAsyncTx, RecvError, SendError};
 use moka::future::Cache;
-use quinn::TokioRuntime;
-use tokio::net::UdpSocket;
-use tokio_util::sync::CancellationToken;
+use quinn::{ConnectionError, MtuDiscoveryConfig, TokioRuntime, TransportErrorCode, crypto::rustls::HandshakeData};
+use snafu::Snafu;
+use tokio::{io::Interest, net::UdpSocket};
+use tokio_util::{codec::Encoder as _, sync::CancellationToken, task::TaskTracker};
 use uuid::Uuid;
 use wind_core::{
-	AbstractOutbound, AppContext, info,
+	AbstractOutbound, AppContext, debug,
+	dns::bind_in_port_range,
+	error,
+	events::Event,
+	info,
+	log::{LogLimiter, PacketTally},
 	tcp::AbstractTcpStream,
+	trace,
 	types::TargetAddr,
-	udp::{AbstractUdpSocket, RecvMeta, UdpPacket},
+	udp::{AbstractUdpSocket, BufferPool, UdpPacket},
 	warn,
 };
 
 use crate::{
 	Error,
-	proto::{ClientProtoExt, UdpStream},
+	datagram::DatagramDrops,
+	proto::{ClientProtoExt, CloseReason, CmdType, Header, HeaderCodec, HeartbeatMode, UdpStream, UdpStreamConfig},
 	task::ClientTaskExt,
 };
 
+/// TLS alert a server sends when it supports none of the offered ALPN
+/// protocols
+const NO_APPLICATION_PROTOCOL: u8 = 120;
+
+/// Handshake failures [`TuicOutbound::new`] can tell apart from the rest
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum HandshakeError {
+	/// The server accepts none of the ALPN protocols offered
+	#[snafu(display(
+		"{peer_addr} accepts none of the offered ALPN protocols [{}], it selected {}",
+		offered.join(", "),
+		accepted.as_deref().unwrap_or("none")
+	))]
+	AlpnMismatch {
+		peer_addr: SocketAddr,
+		offered:   Vec<String>,
+		/// What the server selected, when the handshake got that far
+		accepted:  Option<String>,
+		backtrace: Backtrace,
+	},
+	/// Nothing answered the handshake before the idle timeout
+	#[snafu(display(
+		"{peer_addr} did not answer the QUIC handshake, check the address and port, and that UDP isn't blocked on the way"
+	))]
+	TimedOut { peer_addr: SocketAddr, backtrace: Backtrace },
+	/// The server's host reported over ICMP that nothing listens on the port
+	#[snafu(display("{peer_addr} is unreachable, nothing listens on that port"))]
+	Unreachable { peer_addr: SocketAddr, backtrace: Backtrace },
+	/// The server supports none of the QUIC versions offered
+	#[snafu(display("{peer_addr} speaks none of the offered QUIC versions, is it a QUIC server at all?"))]
+	VersionMismatch { peer_addr: SocketAddr, backtrace: Backtrace },
+	/// The server answered with a stateless reset, as it does for connections
+	/// it has no state for
+	#[snafu(display(
+		"{peer_addr} reset the connection, the server likely restarted or is behind a load balancer that lost track of it"
+	))]
+	StatelessReset { peer_addr: SocketAddr, backtrace: Backtrace },
+	/// TLS failed for a reason other than ALPN, on either side, eg. a
+	/// certificate that doesn't verify
+	#[snafu(display("TLS handshake with {peer_addr} failed ({reason}), check `sni` and the server's certificate"))]
+	Tls {
+		peer_addr: SocketAddr,
+		reason:    String,
+		backtrace: Backtrace,
+	},
+}
+
+impl HandshakeError {
+	/// Tell apart what went wrong in a failed handshake with `peer_addr`,
+	/// `None` for failures with nothing more actionable to say than `err`
+	fn classify(peer_addr: SocketAddr, err: &ConnectionError) -> Option<Self> {
+		let tls = |code: TransportErrorCode, reason: String| {
+			(0x100..0x200)
+				.contains(&u64::from(code))
+				.then(|| TlsSnafu { peer_addr, reason }.build())
+		};
+		match err {
+			ConnectionError::TimedOut => Some(TimedOutSnafu { peer_addr }.build()),
+			ConnectionError::VersionMismatch => Some(VersionMismatchSnafu { peer_addr }.build()),
+			ConnectionError::Reset => Some(StatelessResetSnafu { peer_addr }.build()),
+			ConnectionError::TransportError(err) => tls(err.code, err.to_string()),
+			ConnectionError::ConnectionClosed(close) => tls(close.error_code, close.to_string()),
+			_ => None,
+		}
+	}
+}
+
 pub struct TuicOutboundOpts {
-	pub peer_addr:          SocketAddr,
-	pub sni:                String,
-	pub auth:               (Uuid, Arc<[u8]>),
-	pub zero_rtt_handshake: bool,
-	pub heartbeat:          Duration,
-	pub gc_interval:        Duration,
-	pub gc_lifetime:        Duration,
-	pub skip_cert_verify:   bool,
-	pub alpn:               Vec<String>,
+	pub peer_addr:               SocketAddr,
+	pub sni:                     String,
+	pub auth:                    (Uuid, Arc<[u8]>),
+	pub zero_rtt_handshake:      bool,
+	pub heartbeat:               Duration,
+	pub gc_interval:             Duration,
+	pub gc_lifetime:             Duration,
+	pub skip_cert_verify:        bool,
+	pub alpn:                    Vec<String>,
+	/// Carry the ECN codepoint of each relayed UDP packet onto its send. TUIC
+	/// has no field for it, so marks don't cross the connection to the server
+	pub ecn:                     bool,
+	/// Tear down UDP associations without traffic in either direction for this
+	/// long
+	pub udp_idle_timeout:        Duration,
+	/// Send the server a heartbeat after this long without traffic on an
+	/// association, so NAT mappings on the path outlive quiet stretches. It
+	/// doesn't count as traffic for `udp_idle_timeout`
+	pub udp_keepalive:           Option<Duration>,
+	/// Largest datagram read from a local UDP socket, longer ones are truncated
+	pub udp_recv_buffer:         usize,
+	/// Payload bytes queued per association on their way to the remote, beyond
+	/// which packets are dropped. Bounds memory when a local client sends
+	/// faster than the connection carries
+	pub udp_send_queue_bytes:    usize,
+	/// Replace the connection after this long regardless of activity. New
+	/// streams and associations move to the fresh connection while those on
+	/// the old one drain
+	pub max_connection_lifetime: Option<Duration>,
+	/// Offer [`CHECKSUM_ALPN`](crate::proto::CHECKSUM_ALPN) so UDP payloads
+	/// carry a CRC-32, for diagnosing corruption in fragmentation
+	pub udp_checksum:            bool,
+	/// Limits of UDP fragmentation and reassembly
+	pub udp_stream:              UdpStreamConfig,
+	/// Datagram bytes buffered until read, older datagrams are dropped beyond
+	/// it. See [`DATAGRAM_RECEIVE_BUFFER`](crate::datagram::DATAGRAM_RECEIVE_BUFFER)
+	pub datagram_receive_buffer: usize,
+	/// MTU assumed when the connection starts. UDP packets that don't fit a
+	/// datagram at the current MTU are fragmented, so a larger MTU means fewer
+	/// fragments, but paths that can't carry it lose every datagram until
+	/// quinn falls back to `min_mtu`
+	pub initial_mtu:             u16,
+	/// MTU the path is known to carry, never probed below
+	pub min_mtu:                 u16,
+	/// Probe for a larger MTU (PLPMTUD) once connected, so fragments grow on
+	/// paths that allow it. Off keeps datagrams at `initial_mtu`
+	pub mtu_discovery:           bool,
+	/// Enable GSO (Generic Segmentation Offload). quinn only lets GSO be
+	/// turned off, GRO stays on whenever the kernel has it
+	pub gso:                     bool,
+	/// Bind the UDP socket to a port of this range, both ends included, for
+	/// egress firewalls that only let some source ports out. `None` leaves
+	/// the port to the OS
+	pub local_port_range:        Option<(u16, u16)>,
+	/// Congestion window the connection starts with, see [`INITIAL_WINDOW`]
+	/// for sensible values
+	pub initial_window:          u64,
+	/// How long a TCP relay waits for the server to refuse its target before
+	/// telling the inbound it connected. TUIC has no acknowledgement, so only
+	/// refusals within it reach the client, and clients that wait for the
+	/// reply before sending wait that long. Zero reports success right away
+	pub connect_verdict:         Duration,
 }
 
 pub struct TuicOutbound {
@@ -43,10 +178,194 @@ pub struct TuicOutbound {
 	pub peer_addr:         SocketAddr,
 	pub sni:               String,
 	pub opts:              TuicOutboundOpts,
-	pub connection:        quinn::Connection,
+	/// The connection new streams open on, swapped on rotation
+	pub connection:        Arc<ArcSwap<quinn::Connection>>,
 	pub udp_assoc_counter: AtomicU16,
 	pub token:             CancellationToken,
 	pub udp_session:       Cache<u16, Arc<UdpStream>>,
+	/// Recently closed associations, so replies still in flight are told apart
+	/// from ids the server made up
+	pub udp_tombstones:    Cache<u16, ()>,
+	/// Receive buffers shared by all UDP associations
+	pub udp_recv_pool:     Arc<BufferPool>,
+	pub heartbeat_status:  Arc<HeartbeatStatus>,
+	/// Tasks spawned for this outbound, awaited by [`Self::shutdown`]
+	pub tasks:             TaskTracker,
+	/// Datagrams from the server dropped on any of the outbound's connections
+	pub datagram_drops:    Arc<DatagramDrops>,
+	/// Packets from the server for associations the outbound doesn't know
+	pub unknown_assocs:    Arc<UnknownAssocs>,
+}
+
+/// How heartbeats to the server are going
+#[derive(Debug, Default)]
+pub struct HeartbeatStatus {
+	over_stream: AtomicBool,
+	failures:    AtomicUsize,
+}
+
+impl HeartbeatStatus {
+	/// How the last heartbeat was sent
+	pub fn mode(&self) -> HeartbeatMode {
+		if self.over_stream.load(Ordering::Relaxed) {
+			HeartbeatMode::Stream
+		} else {
+			HeartbeatMode::Datagram
+		}
+	}
+
+	/// Heartbeats failed in a row
+	pub fn failures(&self) -> usize {
+		self.failures.load(Ordering::Relaxed)
+	}
+}
+
+/// Unknown-association packets between two reports of a likely bug
+pub const UNKNOWN_ASSOC_ALERT: u64 = 1000;
+
+/// Packets the server sent for associations never opened here, or closed too
+/// long ago to be remembered
+///
+/// A few follow restarts or slow replies, a steady stream of them points at
+/// association ids being handed out twice.
+#[derive(Debug, Default)]
+pub struct UnknownAssocs {
+	count: AtomicU64,
+}
+
+impl UnknownAssocs {
+	/// Packets dropped so far
+	pub fn count(&self) -> u64 {
+		self.count.load(Ordering::Relaxed)
+	}
+
+	fn record(&self, assoc_id: u16) {
+		static UNKNOWN_ASSOC: LazyLock<LogLimiter> = LazyLock::new(|| LogLimiter::new(UDP_REPORT_PERIOD));
+		let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+		if count.is_multiple_of(UNKNOWN_ASSOC_ALERT) {
+			error!(target: "[OUT]", "{} UDP packets for unknown associations so far, association ids may be reused while still open", count);
+		} else if let Some(suppressed) = UNKNOWN_ASSOC.check() {
+			warn!(target: "[OUT]", "Received UDP packet for unknown association {:#06x} ({} similar suppressed)", assoc_id, suppressed);
+		}
+	}
+}
+
+/// Replies buffered per association for the local socket, beyond which they
+/// are dropped
+pub const UDP_RECEIVE_QUEUE: usize = 128;
+
+/// Packets queued per association on their way to the remote, beyond which
+/// the local socket stops being read
+const UDP_SEND_QUEUE: usize = 128;
+
+/// Default of [`TuicOutboundOpts::udp_send_queue_bytes`], a fraction of what
+/// [`UDP_SEND_QUEUE`] maximum-size packets would take
+pub const UDP_SEND_QUEUE_BYTES: usize = 1 << 20;
+
+/// Sending half of a UDP packet queue bounded by the payload bytes it holds,
+/// on top of the packet count of the channel underneath
+#[derive(Clone)]
+struct ByteBoundedTx {
+	tx:        MAsyncTx<UdpPacket>,
+	queued:    Arc<AtomicUsize>,
+	max_bytes: usize,
+}
+
+struct ByteBoundedRx {
+	rx:     MAsyncRx<UdpPacket>,
+	queued: Arc<AtomicUsize>,
+}
+
+fn byte_bounded_queue(packets: usize, max_bytes: usize) -> (ByteBoundedTx, ByteBoundedRx) {
+	let (tx, rx) = crossfire::mpmc::bounded_async(packets);
+	let queued = Arc::new(AtomicUsize::new(0));
+	(
+		ByteBoundedTx {
+			tx,
+			queued: queued.clone(),
+			max_bytes,
+		},
+		ByteBoundedRx { rx, queued },
+	)
+}
+
+impl ByteBoundedTx {
+	/// Queue `packet`, waiting while the channel is full. Returns whether it
+	/// was queued, packets that would take the queue past `max_bytes` are
+	/// dropped unless it is empty
+	async fn send(&self, packet: UdpPacket) -> Result<bool, SendError<UdpPacket>> {
+		let len = packet.payload.len();
+		let before = self.queued.fetch_add(len, Ordering::Relaxed);
+		if before > 0 && before + len > self.max_bytes {
+			self.queued.fetch_sub(len, Ordering::Relaxed);
+			return Ok(false);
+		}
+		self.tx.send(packet).await.inspect_err(|_| {
+			self.queued.fetch_sub(len, Ordering::Relaxed);
+		})?;
+		Ok(true)
+	}
+
+	/// Payload bytes currently queued
+	fn queued(&self) -> usize {
+		self.queued.load(Ordering::Relaxed)
+	}
+}
+
+impl ByteBoundedRx {
+	async fn recv(&self) -> Result<UdpPacket, RecvError> {
+		let packet = self.rx.recv().await?;
+		self.queued.fetch_sub(packet.payload.len(), Ordering::Relaxed);
+		Ok(packet)
+	}
+}
+
+/// Bytes the connection may have in flight before its first ack, quinn's
+/// BBR default of 200 datagrams of 1200 bytes
+///
+/// Long fat networks fill their bandwidth-delay product sooner with more,
+/// eg. 1-4 MB on a 100 ms path at a few hundred Mbit/s. Going past the
+/// path's product only makes the first burst lose packets, and below
+/// 14720 bytes (RFC 9002) the connection starts slower than TCP would.
+pub const INITIAL_WINDOW: u64 = 240_000;
+
+/// How long [`TuicOutbound::probe`] waits for the server to acknowledge
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval of the aggregate UDP traffic logs
+const UDP_REPORT_PERIOD: Duration = Duration::from_secs(5);
+
+/// How long a closed association's id is remembered
+const UDP_TOMBSTONE_TTL: Duration = Duration::from_secs(30);
+
+/// Where replies to the local socket are sent, inbounds override it with their
+/// client's address
+const UNSPECIFIED_V4: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+enum AssocLookup<T> {
+	Open(T),
+	/// Closed within [`UDP_TOMBSTONE_TTL`]
+	Closed,
+	Unknown,
+}
+
+async fn lookup_assoc<T: Clone + Send + Sync + 'static>(
+	sessions: &Cache<u16, T>,
+	tombstones: &Cache<u16, ()>,
+	assoc_id: u16,
+) -> AssocLookup<T> {
+	if let Some(session) = sessions.get(&assoc_id).await {
+		AssocLookup::Open(session)
+	} else if tombstones.contains_key(&assoc_id) {
+		AssocLookup::Closed
+	} else {
+		AssocLookup::Unknown
+	}
+}
+
+async fn close_assoc<T: Clone + Send + Sync + 'static>(sessions: &Cache<u16, T>, tombstones: &Cache<u16, ()>, assoc_id: u16) {
+	tombstones.insert(assoc_id, ()).await;
+	sessions.invalidate(&assoc_id).await;
 }
 
 impl TuicOutbound {
@@ -54,14 +373,8 @@ impl TuicOutbound {
 		let peer_addr = opts.peer_addr;
 		let server_name = opts.sni.clone();
 
-		// TODO move to top-level initialization
-		{
-			#[cfg(feature = "aws-lc-rs")]
-			let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
-			#[cfg(feature = "ring")]
-			let _ = rustls::crypto::ring::default_provider().install_default();
-		}
 		info!(target: "[OUT]", "Creating a new outboud");
+		opts.udp_stream.validate()?;
 		let client_config = {
 			let tls_config = super::tls::tls_config(&server_name, &opts)?;
 
@@ -70,26 +383,40 @@ impl TuicOutbound {
 			));
 			let mut transport_config = quinn::TransportConfig::default();
 			transport_config
-				.congestion_controller_factory(Arc::new(quinn::congestion::BbrConfig::default()))
-				.keep_alive_interval(None);
+				.congestion_controller_factory(Arc::new({
+					let mut bbr = quinn::congestion::BbrConfig::default();
+					bbr.initial_window(opts.initial_window);
+					bbr
+				}))
+				.keep_alive_interval(None)
+				.initial_mtu(opts.initial_mtu)
+				.min_mtu(opts.min_mtu)
+				.mtu_discovery_config(opts.mtu_discovery.then(MtuDiscoveryConfig::default))
+				.enable_segmentation_offload(opts.gso)
+				.datagram_receive_buffer_size(Some(opts.datagram_receive_buffer));
 
 			client_config.transport_config(Arc::new(transport_config));
 			client_config
 		};
-		let socket_addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
-		let socket = UdpSocket::bind(&socket_addr)
-			.await
-			.map_err(|e| eyre::eyre!("Failed to bind socket to {}: {}", socket_addr, e))?
-			.into_std()?;
+		let socket = match opts.local_port_range {
+			Some(range) => {
+				let socket = bind_in_port_range(Ipv4Addr::UNSPECIFIED.into(), range, std::net::UdpSocket::bind)
+					.map_err(|e| eyre::eyre!("Failed to bind socket within ports {}-{}: {}", range.0, range.1, e))?;
+				socket.set_nonblocking(true)?;
+				socket
+			}
+			None => {
+				let socket_addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+				UdpSocket::bind(&socket_addr)
+					.await
+					.map_err(|e| eyre::eyre!("Failed to bind socket to {}: {}", socket_addr, e))?
+					.into_std()?
+			}
+		};
 
 		let mut endpoint = quinn::Endpoint::new(quinn::EndpointConfig::default(), None, socket, Arc::new(TokioRuntime))?;
 		endpoint.set_default_client_config(client_config);
-		let connection = endpoint
-			.connect(peer_addr, &server_name)
-			.map_err(|e| eyre::eyre!("Failed to connect to {} ({}): {}", peer_addr, server_name, e))?
-			.await?;
-
-		connection.send_auth(&opts.auth.0, &opts.auth.1).await?;
+		let connection = connect(&endpoint, peer_addr, &server_name, &opts.alpn, &opts.auth).await?;
 
 		Ok(Self {
 			token: ctx.token.child_token(),
@@ -98,28 +425,270 @@ impl TuicOutbound {
 			peer_addr,
 			sni: server_name,
 			opts,
-			connection,
+			connection: Arc::new(ArcSwap::from_pointee(connection)),
 			udp_assoc_counter: AtomicU16::new(0),
 			udp_session: Cache::new(u16::MAX.into()),
+			udp_tombstones: Cache::builder()
+				.max_capacity(u16::MAX.into())
+				.time_to_live(UDP_TOMBSTONE_TTL)
+				.build(),
+			udp_recv_pool: BufferPool::new(64),
+			datagram_drops: Arc::default(),
+			unknown_assocs: Arc::default(),
+			heartbeat_status: Arc::default(),
+			tasks: TaskTracker::new(),
 		})
 	}
 
 	pub async fn start_poll(&self) -> eyre::Result<()> {
 		// Monitor cancellation token for shutdown
-		let cancel_token = self.ctx.token.child_token();
-		let connection = self.connection.clone();
+		let cancel_token = self.token.child_token();
+		let poller = ConnectionPoller {
+			ctx:            self.ctx.clone(),
+			tasks:          self.tasks.clone(),
+			heartbeat:      self.opts.heartbeat,
+			udp_session:    self.udp_session.clone(),
+			udp_tombstones: self.udp_tombstones.clone(),
+			status:         self.heartbeat_status.clone(),
+			datagram_drops: self.datagram_drops.clone(),
+			unknown_assocs: self.unknown_assocs.clone(),
+		};
+		let mut retired = CancellationToken::new();
+		poller
+			.spawn(self.connection.load_full(), cancel_token.clone(), retired.clone())
+			.await?;
+
+		let Some(lifetime) = self.opts.max_connection_lifetime else {
+			return Ok(());
+		};
+		let endpoint = self.endpoint.clone();
+		let (peer_addr, sni, alpn, auth) = (
+			self.peer_addr,
+			self.sni.clone(),
+			self.opts.alpn.clone(),
+			self.opts.auth.clone(),
+		);
+		let current = self.connection.clone();
+		let events = self.ctx.events.clone();
+		let task = self.tasks.token();
+		self.ctx.spawn("tuic-rotation", async move {
+			let _task = task;
+			loop {
+				tokio::select! {
+					_ = cancel_token.cancelled() => return eyre::Ok(()),
+					_ = tokio::time::sleep(lifetime) => {}
+				}
+				events.publish(Event::ReconnectStarted { peer_addr });
+				let fresh = match connect(&endpoint, peer_addr, &sni, &alpn, &auth).await {
+					Ok(fresh) => Arc::new(fresh),
+					Err(e) => {
+						warn!(target: "[OUT]", "Failed to rotate connection to {}, keeping the current one: {}", peer_addr, e);
+						continue;
+					}
+				};
+				let next_retired = CancellationToken::new();
+				poller.spawn(fresh.clone(), cancel_token.clone(), next_retired.clone()).await?;
+				let old = current.swap(fresh);
+				retired.cancel();
+				retired = next_retired;
+				info!(target: "[OUT]", "Rotated connection to {} after {:?}, draining the old one ({})", peer_addr, lifetime, old.stable_id());
+			}
+		});
+
+		Ok(())
+	}
+
+	/// Round trip to the server over the current connection, for picking
+	/// among outbounds by more than whether theirs has failed yet
+	///
+	/// Sends a heartbeat on a stream of its own and waits for the server to
+	/// acknowledge it, so relays on the connection go on undisturbed. Fails
+	/// once the connection is closed, or after [`PROBE_TIMEOUT`].
+	pub async fn probe(&self) -> Result<Duration, Error> {
+		let connection = self.connection.load_full();
+		let started = Instant::now();
+		tokio::time::timeout(PROBE_TIMEOUT, async {
+			let mut heartbeat = BytesMut::with_capacity(2);
+			HeaderCodec.encode(Header::new(CmdType::Heartbeat), &mut heartbeat)?;
+			let mut send = connection.open_uni().await?;
+			send.write_all(&heartbeat).await?;
+			send.finish()?;
+			send.stopped().await?;
+			Ok(started.elapsed())
+		})
+		.await
+		.map_err(|_| eyre::eyre!("{} did not acknowledge the probe within {:?}", self.peer_addr, PROBE_TIMEOUT))?
+	}
+
+	/// Shut down this outbound alone: stop its tasks, drop its UDP
+	/// associations and close its connections, leaving the rest of the app
+	/// running
+	pub async fn shutdown(self) {
+		self.token.cancel();
+		// Associations whose handler was dropped without cleaning up
+		for (assoc_id, stream) in self.udp_session.iter() {
+			if let Err(err) = stream.dissociate().await {
+				info!(target: "[OUT]", "Error dropping UDP association {:#06x}: {}", *assoc_id, err);
+			}
+		}
+		self.udp_session.invalidate_all();
+		self.tasks.close();
+		self.tasks.wait().await;
+
+		let reason = CloseReason::ClientShutdown;
+		self.endpoint.close(reason.code(), reason.phrase().as_bytes());
+		self.endpoint.wait_idle().await;
+		info!(target: "[OUT]", "Outbound to {} shut down", self.peer_addr);
+	}
+}
+
+/// Dropping stops the outbound's tasks and closes its connections right
+/// away. The server then drops the connections' UDP associations on its own,
+/// but without hearing about each through a dissociate, nor waiting for the
+/// close to reach it. [`TuicOutbound::shutdown`] does both, so prefer it
+/// whenever there is a chance to await.
+impl Drop for TuicOutbound {
+	fn drop(&mut self) {
+		self.token.cancel();
+		self.tasks.close();
+		let reason = CloseReason::ClientShutdown;
+		self.endpoint.close(reason.code(), reason.phrase().as_bytes());
+	}
+}
+
+/// Connect and authenticate to the server, which may pick one of `alpn` or
+/// none at all, but nothing else
+async fn connect(
+	endpoint: &quinn::Endpoint,
+	peer_addr: SocketAddr,
+	server_name: &str,
+	alpn: &[String],
+	auth: &(Uuid, Arc<[u8]>),
+) -> Result<quinn::Connection, Error> {
+	let alpn_mismatch = |accepted| AlpnMismatchSnafu {
+		peer_addr,
+		offered: alpn.to_vec(),
+		accepted,
+	};
+	let connecting = endpoint
+		.connect(peer_addr, server_name)
+		.map_err(|e| eyre::eyre!("Failed to connect to {} ({}): {}", peer_addr, server_name, e))?;
+	let result = tokio::select! {
+		result = connecting => result,
+		() = port_unreachable(peer_addr) => return Err(UnreachableSnafu { peer_addr }.build().into()),
+	};
+	let connection = match result {
+		Ok(connection) => connection,
+		Err(err) if is_alpn_alert(&err) => return Err(alpn_mismatch(None).build().into()),
+		Err(err) => return Err(HandshakeError::classify(peer_addr, &err).map_or_else(|| err.into(), Into::into)),
+	};
+
+	let accepted = connection
+		.handshake_data()
+		.and_then(|data| data.downcast::<HandshakeData>().ok())
+		.and_then(|data| data.protocol);
+	debug!(
+		target: "[OUT]",
+		"{} negotiated ALPN {}",
+		peer_addr,
+		accepted.as_deref().map_or("(none)".into(), String::from_utf8_lossy)
+	);
+	if let Some(accepted) = accepted
+		&& !alpn.iter().any(|alpn| alpn.as_bytes() == accepted)
+	{
+		let reason = CloseReason::UnsupportedAlpn;
+		connection.close(reason.code(), reason.phrase().as_bytes());
+		return Err(alpn_mismatch(Some(String::from_utf8_lossy(&accepted).into_owned()))
+			.build()
+			.into());
+	}
+
+	connection.send_auth(&auth.0, &auth.1).await?;
+	if connection.max_datagram_size().is_none() {
+		warn!(target: "[OUT]", "{} does not accept datagrams, relaying UDP over QUIC streams", peer_addr);
+	}
+	Ok(connection)
+}
+
+/// Resolve once the host at `peer_addr` reports over ICMP that nothing
+/// listens on the port, which the endpoint's unconnected socket never hears
+/// about. The single byte sent to find out is too short for a QUIC server to
+/// take for a packet
+async fn port_unreachable(peer_addr: SocketAddr) {
+	let probe = async {
+		let local: SocketAddr = if peer_addr.is_ipv4() {
+			(Ipv4Addr::UNSPECIFIED, 0).into()
+		} else {
+			(Ipv6Addr::UNSPECIFIED, 0).into()
+		};
+		let socket = UdpSocket::bind(local).await?;
+		socket.connect(peer_addr).await?;
+		socket.send(&[0]).await?;
+		// The ICMP error doesn't make the socket readable
+		socket.ready(Interest::ERROR).await?;
+		socket.take_error()?.map_or(Ok(()), Err)
+	};
+	match probe.await {
+		Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {}
+		_ => std::future::pending().await,
+	}
+}
+
+/// Whether the handshake failed on a `no_application_protocol` alert, from
+/// either side
+fn is_alpn_alert(err: &ConnectionError) -> bool {
+	let code = match err {
+		ConnectionError::ConnectionClosed(close) => close.error_code,
+		ConnectionError::TransportError(err) => err.code,
+		_ => return false,
+	};
+	code == TransportErrorCode::crypto(NO_APPLICATION_PROTOCOL)
+}
+
+/// Keeps a connection alive and dispatches what the server sends on it
+#[derive(Clone)]
+struct ConnectionPoller {
+	ctx:            Arc<AppContext>,
+	tasks:          TaskTracker,
+	heartbeat:      Duration,
+	udp_session:    Cache<u16, Arc<UdpStream>>,
+	udp_tombstones: Cache<u16, ()>,
+	status:         Arc<HeartbeatStatus>,
+	datagram_drops: Arc<DatagramDrops>,
+	unknown_assocs: Arc<UnknownAssocs>,
+}
+
+impl ConnectionPoller {
+	/// Poll `connection` until `cancel_token`. Once `retired`, heartbeats stop
+	/// so the connection closes when idle, and its close is no longer an error
+	async fn spawn(
+		&self,
+		connection: Arc<quinn::Connection>,
+		cancel_token: CancellationToken,
+		retired: CancellationToken,
+	) -> eyre::Result<()> {
 		let udp_session = self.udp_session.clone();
+		let udp_tombstones = self.udp_tombstones.clone();
+		let unknown_assocs = self.unknown_assocs.clone();
+		let status = self.status.clone();
+		let (ctx, tasks) = (self.ctx.clone(), self.tasks.clone());
 
-		let mut hb_interval = tokio::time::interval(self.opts.heartbeat);
+		let mut hb_interval = tokio::time::interval(self.heartbeat);
 		const HEARTBEAT_MAX_FAILURES: usize = 3;
 
-		let (datagram_rx, bi_rx, uni_rx) = self
-			.connection
-			.handle_incoming(self.ctx.clone(), cancel_token.clone())
+		let (datagram_rx, bi_rx, uni_rx) = connection
+			.handle_incoming(
+				self.ctx.clone(),
+				self.tasks.clone(),
+				cancel_token.clone(),
+				self.datagram_drops.clone(),
+			)
 			.await?;
 
-		self.ctx.tasks.spawn(async move {
-			let mut hb_failures = 0;
+		let task = self.tasks.token();
+		self.ctx.spawn("tuic-heartbeat", async move {
+			let _task = task;
+			status.failures.store(0, Ordering::Relaxed);
 			hb_interval.tick().await;
 
 			loop {
@@ -128,118 +697,71 @@ impl TuicOutbound {
 						info!(target: "[OUT]", "Heartbeat poll cancelled");
 						return Ok(());
 					}
-					_ = hb_interval.tick() => {
-						if let Err(e) = connection.send_heartbeat().await {
-							hb_failures += 1;
-							info!(target: "[OUT]", "Heartbeat failed ({}/{}): {}", hb_failures, HEARTBEAT_MAX_FAILURES, e);
+					_ = hb_interval.tick(), if !retired.is_cancelled() => {
+						match connection.send_heartbeat().await {
+							Err(e) => {
+								let hb_failures = status.failures.fetch_add(1, Ordering::Relaxed) + 1;
+								info!(target: "[OUT]", "Heartbeat failed ({}/{}): {}", hb_failures, HEARTBEAT_MAX_FAILURES, e);
 
-							if hb_failures >= HEARTBEAT_MAX_FAILURES {
-								return Err(eyre::eyre!("Too many heartbeat failures ({}/{})", hb_failures, HEARTBEAT_MAX_FAILURES));
+								if hb_failures >= HEARTBEAT_MAX_FAILURES {
+									return Err(eyre::eyre!("Too many heartbeat failures ({}/{})", hb_failures, HEARTBEAT_MAX_FAILURES));
+								}
+							}
+							Ok(mode) => {
+								let over_stream = mode == HeartbeatMode::Stream;
+								if status.over_stream.swap(over_stream, Ordering::Relaxed) != over_stream {
+									info!(target: "[OUT]", "Heartbeats are now sent as {:?}", mode);
+								}
+								let hb_failures = status.failures.swap(0, Ordering::Relaxed);
+								if hb_failures > 0 {
+									info!(target: "[OUT]", "Heartbeat succeeded after {} failures", hb_failures);
+								}
 							}
-						} else if hb_failures > 0 {
-							info!(target: "[OUT]", "Heartbeat succeeded after {} failures", hb_failures);
-							hb_failures = 0;
 						}
 					}
+					err = connection.closed() => {
+						if retired.is_cancelled() {
+							info!(target: "[OUT]", "Rotated-out connection ({}) drained: {}", connection.stable_id(), err);
+							return Ok(());
+						}
+						match CloseReason::from_error(&err) {
+							Some(CloseReason::AuthFailure) => {
+								error!(target: "[OUT]", "Server rejected authentication, check the uuid and password")
+							}
+							Some(reason) => warn!(target: "[OUT]", "Server closed the connection: {}", reason),
+							None => warn!(target: "[OUT]", "Connection lost: {}", err),
+						}
+						cancel_token.cancel();
+						return Err(err.into());
+					}
 					Ok(_) = bi_rx.recv() => {
 						warn!(target: "[OUT]", "Received bi-directional stream on Outbound");
 					}
 					Ok(bytes) = datagram_rx.recv() => {
-						info!(target: "[OUT]", "Received datagram: {} bytes", bytes.len());
-						// Process the received datagram
-						use bytes::Buf;
-
-						let mut buf = bytes::BytesMut::from(bytes.as_ref());
-
-						// Parse header, command, and address using helper functions
-						let header = match crate::proto::decode_header(&mut buf, "datagram") {
-							Ok(h) => h,
-							Err(e) => {
-								warn!(target: "[OUT]", "Failed to decode header: {}", e);
-								continue;
-							}
-						};
-
-						let cmd = match crate::proto::decode_command(header.command, &mut buf, "datagram") {
-							Ok(c) => c,
-							Err(e) => {
-								warn!(target: "[OUT]", "Failed to decode command: {}", e);
-								continue;
-							}
-						};
-
-						// Process UDP packet
-						if let crate::proto::Command::Packet {
-							assoc_id,
-							pkt_id,
-							frag_total,
-							frag_id,
-							size,
-						} = cmd {
-							// Parse address
-							let addr = match crate::proto::decode_address(&mut buf, "UDP packet") {
-								Ok(a) => a,
-								Err(e) => {
-									warn!(target: "[OUT]", "Failed to decode address: {}", e);
-									continue;
-								}
+						trace!(target: "[OUT]", "Received datagram: {} bytes", bytes.len());
+						dispatch_packet(&udp_session, &udp_tombstones, &unknown_assocs, &bytes, "datagram").await;
+					}
+					Ok(mut recv) = uni_rx.recv() => {
+						trace!(target: "[OUT]", "Received uni-directional stream");
+						// Packets come this way when the connection has no datagrams. Read each on
+						// its own so a slow one doesn't hold up datagrams and heartbeats, the
+						// server's stream credit bounds how many are in flight
+						let (udp_session, udp_tombstones, unknown_assocs) =
+							(udp_session.clone(), udp_tombstones.clone(), unknown_assocs.clone());
+						let (cancel_token, task) = (cancel_token.clone(), tasks.token());
+						ctx.spawn("tuic-uni-packet", async move {
+							let _task = task;
+							let res = tokio::select! {
+								res = recv.read_to_end(u16::MAX as usize) => res,
+								_ = cancel_token.cancelled() => return,
 							};
-
-							// Extract payload
-							let payload = buf.copy_to_bytes(size as usize);
-
-							// Convert address to TargetAddr and handle logging
-							// Note: For fragmented packets, only the first fragment contains the address
-							// Subsequent fragments will have Address::None, which is handled in process_fragment
-							let (target, has_address) = match crate::proto::address_to_target(addr) {
-								Ok(t) => (t, true),
-								Err(_) => {
-									// For non-first fragments (Address::None), use a placeholder address
-									// The actual address will be retrieved from the first fragment during reassembly
-									(TargetAddr::IPv4(std::net::Ipv4Addr::UNSPECIFIED, 0), false)
+							match res {
+								Ok(bytes) => {
+									dispatch_packet(&udp_session, &udp_tombstones, &unknown_assocs, &bytes, "uni stream").await
 								}
-							};
-
-							// Log differently for fragments with and without address
-							if has_address {
-								info!(target: "[OUT]", "Received UDP packet: assoc={:#06x}, pkt={}, frag={}/{}, size={}, target={}",
-									assoc_id, pkt_id, frag_id + 1, frag_total, size, target);
-							} else {
-								info!(target: "[OUT]", "Received UDP fragment: assoc={:#06x}, pkt={}, frag={}/{}, size={} (no address - non-first fragment)",
-									assoc_id, pkt_id, frag_id + 1, frag_total, size);
+								Err(e) => warn!(target: "[OUT]", "Failed to read uni-directional stream: {}", e),
 							}
-
-							// Find the corresponding UDP session
-							if let Some(udp_stream) = udp_session.get(&assoc_id).await {
-								// Use process_fragment to handle fragmented packets
-								// This will return Some(packet) when all fragments are received and reassembled
-								let complete_packet = if frag_total > 1 {
-									// Fragmented packet - use process_fragment for reassembly
-									udp_stream.process_fragment(assoc_id, pkt_id, frag_total, frag_id, payload, None, target).await
-								} else {
-									// Single packet (no fragmentation)
-									Some(wind_core::udp::UdpPacket {
-										source: None, // TODO: Add source address tracking
-										target,
-										payload,
-									})
-								};
-
-							// If we have a complete packet, send it to the receive channel
-							if let Some(packet) = complete_packet
-								&& let Err(e) = udp_stream.receive_packet(packet).await {
-									warn!(target: "[OUT]", "Failed to send packet to UDP session {:#06x}: {}", assoc_id, e);
-								}
-						} else {
-								warn!(target: "[OUT]", "Received UDP packet for unknown association {:#06x}", assoc_id);
-							}
-						} else {
-							warn!(target: "[OUT]", "Received non-Packet command in datagram: {:?}", cmd);
-						}
-					}
-
-					Ok(_recv) = uni_rx.recv() => {
-						info!(target: "[OUT]", "Received uni-directional stream");
+						});
 					}
 				}
 			}
@@ -249,6 +771,122 @@ impl TuicOutbound {
 	}
 }
 
+/// Route a `Packet` command received from the server to its association
+async fn dispatch_packet(
+	udp_session: &Cache<u16, Arc<UdpStream>>,
+	udp_tombstones: &Cache<u16, ()>,
+	unknown_assocs: &UnknownAssocs,
+	bytes: &[u8],
+	via: &str,
+) {
+	use bytes::Buf;
+
+	let mut buf = bytes::BytesMut::from(bytes);
+
+	// Parse header, command, and address using helper functions
+	let header = match crate::proto::decode_header(&mut buf, via) {
+		Ok(h) => h,
+		Err(e) => {
+			warn!(target: "[OUT]", "Failed to decode header: {}", e);
+			return;
+		}
+	};
+
+	let cmd = match crate::proto::decode_command(header.command, &mut buf, via) {
+		Ok(c) => c,
+		Err(e) => {
+			warn!(target: "[OUT]", "Failed to decode command: {}", e);
+			return;
+		}
+	};
+
+	let crate::proto::Command::Packet {
+		assoc_id,
+		pkt_id,
+		frag_total,
+		frag_id,
+		size,
+	} = cmd
+	else {
+		warn!(target: "[OUT]", "Received non-Packet command in {}: {:?}", via, cmd);
+		return;
+	};
+
+	// Parse address
+	let addr = match crate::proto::decode_address(&mut buf, "UDP packet") {
+		Ok(a) => a,
+		Err(e) => {
+			warn!(target: "[OUT]", "Failed to decode address: {}", e);
+			return;
+		}
+	};
+
+	// Extract payload
+	let payload = buf.copy_to_bytes(size as usize);
+
+	// Convert address to TargetAddr and handle logging
+	// Note: For fragmented packets, only the first fragment contains the address
+	// Subsequent fragments will have Address::None, which is handled in
+	// process_fragment
+	let (target, has_address) = match crate::proto::address_to_target(addr) {
+		Ok(t) => (t, true),
+		Err(_) => {
+			// For non-first fragments (Address::None), use a placeholder address
+			// The actual address will be retrieved from the first fragment during
+			// reassembly
+			(TargetAddr::IPv4(std::net::Ipv4Addr::UNSPECIFIED, 0), false)
+		}
+	};
+
+	// Log differently for fragments with and without address
+	if has_address {
+		trace!(target: "[OUT]", "Received UDP packet: assoc={:#06x}, pkt={}, frag={}/{}, size={}, target={}",
+			assoc_id, pkt_id, frag_id + 1, frag_total, size, target);
+	} else {
+		trace!(target: "[OUT]", "Received UDP fragment: assoc={:#06x}, pkt={}, frag={}/{}, size={} (no address - non-first fragment)",
+			assoc_id, pkt_id, frag_id + 1, frag_total, size);
+	}
+
+	// Find the corresponding UDP session
+	let udp_stream = match lookup_assoc(udp_session, udp_tombstones, assoc_id).await {
+		AssocLookup::Open(udp_stream) => udp_stream,
+		AssocLookup::Closed => {
+			debug!(target: "[OUT]", "Discarding late UDP packet for closed association {:#06x}", assoc_id);
+			return;
+		}
+		AssocLookup::Unknown => {
+			unknown_assocs.record(assoc_id);
+			return;
+		}
+	};
+
+	// Use process_fragment to handle fragmented packets
+	// This will return Some(packet) when all fragments are received and reassembled
+	let complete_packet = if frag_total > 1 {
+		// Fragmented packet - use process_fragment for reassembly
+		udp_stream
+			.process_fragment(assoc_id, pkt_id, frag_total, frag_id, payload, None, target)
+			.await
+	} else {
+		// Single packet (no fragmentation)
+		udp_stream
+			.strip_checksum(pkt_id, payload)
+			.map(|payload| wind_core::udp::UdpPacket {
+				source: None, // TODO: Add source address tracking
+				target,
+				payload,
+				ecn: None,
+			})
+	};
+
+	// If we have a complete packet, send it to the receive channel
+	if let Some(packet) = complete_packet
+		&& let Err(e) = udp_stream.receive_packet(packet)
+	{
+		warn!(target: "[OUT]", "Failed to send packet to UDP session {:#06x}: {}", assoc_id, e);
+	}
+}
+
 pub struct TuicTcpStream;
 
 impl AbstractOutbound for TuicOutbound {
@@ -258,35 +896,63 @@ impl AbstractOutbound for TuicOutbound {
 		stream: impl AbstractTcpStream,
 		_dialer: Option<impl AbstractOutbound>,
 	) -> eyre::Result<()> {
-		self.connection.open_tcp(&target_addr, stream).await?;
+		self.connection
+			.load_full()
+			.open_tcp(&target_addr, stream, self.opts.connect_verdict)
+			.await?;
 		Ok(())
 	}
 
+	/// Relay `socket` as a single association: whatever their targets, its
+	/// packets share one assoc_id, dissociated once the socket's association
+	/// ends
 	async fn handle_udp(
 		&self,
 		socket: impl AbstractUdpSocket + 'static,
 		_dialer: Option<impl AbstractOutbound>,
 	) -> eyre::Result<()> {
-		use std::sync::atomic::Ordering;
 		// Create a cancel token for single udp session
 		let cancel = self.token.child_token();
 		// Generate a new UDP association ID
 		let assoc_id = self.udp_assoc_counter.fetch_add(1, Ordering::SeqCst);
 		info!(target: "[OUT]", "Creating new UDP association: {:#06x}", assoc_id);
 
+		let closed = socket.association_token();
 		let socket = Arc::new(socket);
-		let connection = self.connection.clone();
-		let (send_tx, send_rx) = crossfire::mpmc::bounded_async::<UdpPacket>(128);
-		let (receive_tx, receive_rx) = crossfire::mpmc::bounded_async(128);
-		let udp_stream = Arc::new(UdpStream::new(connection.clone(), assoc_id, receive_tx));
+		// The association stays on this connection even if it is rotated out
+		let connection = self.connection.load_full();
+		let cancel_session = cancel.clone();
+		let (send_tx, send_rx) = byte_bounded_queue(UDP_SEND_QUEUE, self.opts.udp_send_queue_bytes);
+		let (receive_tx, receive_rx) = crossfire::mpmc::bounded_async(UDP_RECEIVE_QUEUE);
+		let udp_stream = Arc::new(UdpStream::new(
+			quinn::Connection::clone(&connection),
+			assoc_id,
+			receive_tx,
+			self.opts.udp_stream,
+		));
+		// The counter may have wrapped onto a recently closed id
+		self.udp_tombstones.invalidate(&assoc_id).await;
 		self.udp_session.insert(assoc_id, udp_stream.clone()).await;
 		let cancel_stream = cancel.clone();
 		let socket_clone = socket.clone();
+		let ecn_enabled = self.opts.ecn;
+		// Milliseconds since `started` at which a packet last flowed
+		let started = Instant::now();
+		let last_activity = Arc::new(AtomicU64::new(0));
+		let last_activity_clone = last_activity.clone();
 
 		let mut gc_interval = tokio::time::interval(self.opts.gc_interval);
 		gc_interval.tick().await;
-		self.ctx.tasks.spawn(async move {
+		let keepalive = self.opts.udp_keepalive;
+		let keepalive_connection = connection.clone();
+		let task = self.tasks.token();
+		self.ctx.spawn("tuic-udp-relay", async move {
+			let _task = task;
+			let (to_local, to_remote) = (PacketTally::new(UDP_REPORT_PERIOD), PacketTally::new(UDP_REPORT_PERIOD));
+			let (local_errors, remote_errors) = (LogLimiter::new(UDP_REPORT_PERIOD), LogLimiter::new(UDP_REPORT_PERIOD));
+			let mut last_keepalive = started;
 			loop {
+				let last = started + Duration::from_millis(last_activity_clone.load(Ordering::Relaxed));
 				tokio::select! {
 					_ = cancel_stream.cancelled() => {
 						info!(target: "[OUT]", "UDP stream sender for association {:#06x} cancelled", assoc_id);
@@ -303,12 +969,19 @@ impl AbstractOutbound for TuicOutbound {
 						};
 						
 						// Received packet from remote, send to local socket
-						// overrided in socks inbound
-						const UNSPECIFIED_V4: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
-						if let Err(e) = socket_clone.send(&packet.payload, UNSPECIFIED_V4).await {
-							warn!(target: "[OUT]", "Failed to send UDP packet to local socket (assoc {:#06x}): {:?}", assoc_id, e);
+						last_activity_clone.store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+						let ecn = if ecn_enabled { packet.ecn } else { None };
+						// The server names where the reply came from, which a domain can't say
+						let origin = packet.target.to_socket_addr().unwrap_or(UNSPECIFIED_V4);
+						if let Err(e) = socket_clone.send_ecn(&packet.payload, origin, ecn).await {
+							if let Some(suppressed) = local_errors.check() {
+								warn!(target: "[OUT]", "Failed to send UDP packet to local socket (assoc {:#06x}): {:?} ({} similar suppressed)", assoc_id, e, suppressed);
+							}
 						} else {
-							info!(target: "[OUT]", "Received UDP packet forward to local ({} bytes, assoc {:#06x})", packet.payload.len(), assoc_id);
+							trace!(target: "[OUT]", "Received UDP packet forward to local ({} bytes, assoc {:#06x})", packet.payload.len(), assoc_id);
+							if let Some((packets, bytes)) = to_local.record(packet.payload.len()) {
+								info!(target: "[OUT]", "UDP association {:#06x} forwarded {} packets ({} bytes) to local in {:?}", assoc_id, packets, bytes, UDP_REPORT_PERIOD);
+							}
 						}
 					}
 					// send queue
@@ -322,26 +995,47 @@ impl AbstractOutbound for TuicOutbound {
 						};
 						
 						// Send packet to remote via UDP stream
+						last_activity_clone.store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
 						let payload_len = packet.payload.len();
 						if let Err(e) = udp_stream.send_packet(packet).await {
-							warn!(target: "[OUT]", "Failed to send UDP packet to remote (assoc {:#06x}): {}", assoc_id, e);
+							if let Some(suppressed) = remote_errors.check() {
+								warn!(target: "[OUT]", "Failed to send UDP packet to remote (assoc {:#06x}): {} ({} similar suppressed)", assoc_id, e, suppressed);
+							}
 						} else {
-							info!(target: "[OUT]", "Sent UDP packet to remote ({} bytes, assoc {:#06x})", payload_len, assoc_id);
+							trace!(target: "[OUT]", "Sent UDP packet to remote ({} bytes, assoc {:#06x})", payload_len, assoc_id);
+							if let Some((packets, bytes)) = to_remote.record(payload_len) {
+								info!(target: "[OUT]", "UDP association {:#06x} sent {} packets ({} bytes) to remote in {:?}", assoc_id, packets, bytes, UDP_REPORT_PERIOD);
+							}
 						}
 					}
 					_ = gc_interval.tick() => {
 						// Perform garbage collection of expired fragments
 						udp_stream.collect_garbage().await;
 					}
+					_ = tokio::time::sleep_until((last.max(last_keepalive) + keepalive.unwrap_or_default()).into()), if keepalive.is_some() => {
+						// Only when nothing flowed while sleeping
+						if last_activity_clone.load(Ordering::Relaxed) == (last - started).as_millis() as u64 {
+							trace!(target: "[OUT]", "Sending keepalive to the server (assoc {:#06x})", assoc_id);
+							if let Err(e) = keepalive_connection.send_heartbeat().await
+								&& let Some(suppressed) = remote_errors.check()
+							{
+								warn!(target: "[OUT]", "Failed to send keepalive to the server (assoc {:#06x}): {} ({} similar suppressed)", assoc_id, e, suppressed);
+							}
+							last_keepalive = Instant::now();
+						}
+					}
 				}
 			}
 			eyre::Ok(())
 		});
 
 		// Spawn task to continuously read from local socket and send to remote
-		self.ctx.tasks.spawn(async move {
-			let mut buf = vec![0u8; u16::MAX as usize];
-
+		let recv_pool = self.udp_recv_pool.clone();
+		let recv_buffer = self.opts.udp_recv_buffer;
+		let task = self.tasks.token();
+		self.ctx.spawn("tuic-udp-local-recv", async move {
+			let _task = task;
+			let overflow_errors = LogLimiter::new(UDP_REPORT_PERIOD);
 			loop {
 				tokio::select! {
 					_ = cancel.cancelled() => {
@@ -349,43 +1043,25 @@ impl AbstractOutbound for TuicOutbound {
 						break;
 					}
 
-					result = async {
-						let mut meta = RecvMeta::default();
-						let result = socket
-							.recv(
-								&mut [IoSliceMut::new(&mut buf)],
-								std::slice::from_mut(&mut meta),
-							)
-							.await?;
-						ensure!(result == 1, "Expected to receive 1 datagram, got {}", result);
-
-					eyre::Ok(meta)
-				} => {
-					let meta = match result {
+					result = recv_pool.recv(socket.as_ref(), recv_buffer) => {
+					let (buf, meta) = match result {
 						Err(e) => {
 							warn!(target: "[OUT]", "Error receiving from UDP socket (assoc {:#06x}): {}", assoc_id, e);
 							break;
 						}
-						Ok(meta) => meta,
+						Ok(received) => received,
 					};
-					
-					// In outbound context, get target address from meta.destination or use meta.addr
-					let target_addr = meta.destination
-						.as_ref()
-						.map(|dest| match dest {
-							TargetAddr::IPv4(ip, port) => SocketAddr::from((*ip, *port)),
-							TargetAddr::IPv6(ip, port) => SocketAddr::from((*ip, *port)),
-							TargetAddr::Domain(_, _) => {
-								warn!(target: "[OUT]", "Cannot convert domain to SocketAddr, using meta.addr for association {:#06x}", assoc_id);
-								meta.addr
-							}
-						})
-						.unwrap_or(meta.addr);
+
+					// In outbound context, get target address from meta.destination or use meta.addr.
+					// TUIC carries domains itself, so they go out as they are
+					let target = meta.destination.clone().unwrap_or_else(|| TargetAddr::from(meta.addr));
 
 					let total_len = meta.len;
 
-					// Convert SocketAddr to TargetAddr using From trait
-					let target = TargetAddr::from(target_addr);
+					let ecn = if ecn_enabled { meta.ecn } else { None };
+					if let Some(codepoint) = ecn {
+						trace!(target: "[OUT]", "Observed ECN {:?} on UDP packet (assoc {:#06x})", codepoint, assoc_id);
+					}
 
 					// Handle GRO (Generic Receive Offload): stride indicates segment size
 					// If stride > 0, the buffer contains multiple segments of that size
@@ -394,7 +1070,7 @@ impl AbstractOutbound for TuicOutbound {
 					if stride > 0 && total_len > stride {
 						// Multiple segments received via GRO, send each separately
 						let num_segments = total_len.div_ceil(stride);
-						info!(target: "[OUT]", "Received {} GRO segments ({} bytes total, stride {}) for assoc {:#06x}",
+						trace!(target: "[OUT]", "Received {} GRO segments ({} bytes total, stride {}) for assoc {:#06x}",
 							num_segments, total_len, stride, assoc_id);
 						for segment_idx in 0..num_segments {
 							let segment_start = segment_idx * stride;
@@ -407,30 +1083,48 @@ impl AbstractOutbound for TuicOutbound {
 								source: None, // TODO: Add source address tracking
 								target: target.clone(),
 								payload,
+								ecn,
 							};
 
-							if let Err(_e) = send_tx.send(packet).await {
-								warn!(target: "[OUT]", "Failed to send UDP segment {}/{} to channel for association {:#06x}: channel closed",
-									segment_idx + 1, num_segments, assoc_id);
-								break;
+							match send_tx.send(packet).await {
+								Ok(true) => {}
+								Ok(false) => {
+									if let Some(suppressed) = overflow_errors.check() {
+										warn!(target: "[OUT]", "UDP send queue of association {:#06x} holds {} bytes, dropping packet ({} similar suppressed)", assoc_id, send_tx.queued(), suppressed);
+									}
+								}
+								Err(_e) => {
+									warn!(target: "[OUT]", "Failed to send UDP segment {}/{} to channel for association {:#06x}: channel closed",
+										segment_idx + 1, num_segments, assoc_id);
+									break;
+								}
 							}
 						}
 					} else {
 						// Single packet (no GRO or single segment)
 						let payload = bytes::Bytes::copy_from_slice(&buf[..total_len]);
 
-						info!(target: "[OUT]", "Sending UDP packet to {}: {} bytes (assoc {:#06x})", target, total_len, assoc_id);
+						trace!(target: "[OUT]", "Sending UDP packet to {}: {} bytes (assoc {:#06x})", target, total_len, assoc_id);
 
 						// Create UdpPacket and send via channel
 						let packet = wind_core::udp::UdpPacket {
 							source: None, // TODO: Add source address tracking
 							target,
 							payload,
+							ecn,
 						};
 
-						if let Err(_e) = send_tx.send(packet).await {
-							warn!(target: "[OUT]", "Failed to send UDP packet to channel for association {:#06x}: channel closed", assoc_id);
-							break;
+						match send_tx.send(packet).await {
+							Ok(true) => {}
+							Ok(false) => {
+								if let Some(suppressed) = overflow_errors.check() {
+									warn!(target: "[OUT]", "UDP send queue of association {:#06x} holds {} bytes, dropping packet ({} similar suppressed)", assoc_id, send_tx.queued(), suppressed);
+								}
+							}
+							Err(_e) => {
+								warn!(target: "[OUT]", "Failed to send UDP packet to channel for association {:#06x}: channel closed", assoc_id);
+								break;
+							}
 						}
 					}
 				}
@@ -439,23 +1133,117 @@ impl AbstractOutbound for TuicOutbound {
 			eyre::Ok(())
 		});
 
-		let cancel_healthy = self.ctx.token.clone();
+		let cancel_healthy = self.token.clone();
+		let idle_timeout = self.opts.udp_idle_timeout;
+		let mut report = tokio::time::interval(Duration::from_secs(30));
+		report.tick().await;
 		loop {
+			let last = started + Duration::from_millis(last_activity.load(Ordering::Relaxed));
 			tokio::select! {
-				_ = tokio::time::sleep(tokio::time::Duration::from_secs(30)) => {
+				_ = report.tick() => {
 					info!(target: "[OUT]", "UDP handler for association {:#06x} active", assoc_id);
 				}
 
+				_ = tokio::time::sleep_until((last + idle_timeout).into()) => {
+					// Traffic may have flowed while sleeping, only reap when it really stayed idle
+					if last_activity.load(Ordering::Relaxed) == (last - started).as_millis() as u64 {
+						info!(target: "[OUT]", "UDP association {:#06x} idle for {:?}, closing", assoc_id, idle_timeout);
+						break;
+					}
+				}
+
+				_ = closed.cancelled() => {
+					info!(target: "[OUT]", "UDP association {:#06x} ended by the inbound", assoc_id);
+					break;
+				}
+
 				_ = cancel_healthy.cancelled() => break,
 			}
 		}
 
+		cancel_session.cancel();
+		close_assoc(&self.udp_session, &self.udp_tombstones, assoc_id).await;
 
 		// Clean up the UDP association before exiting
-		if let Err(err) = self.connection.drop_udp(assoc_id).await {
+		if let Err(err) = connection.drop_udp(assoc_id).await {
 			info!(target: "[OUT]", "Error dropping UDP association {:#06x}: {}", assoc_id, err);
 		}
 
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_send_queue_bounded_by_bytes() {
+		let (tx, rx) = byte_bounded_queue(UDP_SEND_QUEUE, 10_000);
+		let packet = || UdpPacket {
+			source:  None,
+			target:  TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 53),
+			payload: bytes::Bytes::from(vec![0u8; 1500]),
+			ecn:     None,
+		};
+
+		// A flood far below the packet limit but far above the byte cap
+		let mut queued = 0;
+		for _ in 0..64 {
+			if tx.send(packet()).await.unwrap() {
+				queued += 1;
+			}
+			assert!(tx.queued() <= 10_000);
+		}
+		assert_eq!(queued, 6);
+		assert_eq!(tx.queued(), 9000);
+
+		// Draining makes room again
+		rx.recv().await.unwrap();
+		assert_eq!(tx.queued(), 7500);
+		assert!(tx.send(packet()).await.unwrap());
+		for _ in 0..6 {
+			rx.recv().await.unwrap();
+		}
+		assert_eq!(tx.queued(), 0);
+
+		// A packet larger than the cap still gets through an empty queue
+		let (tx, _rx) = byte_bounded_queue(UDP_SEND_QUEUE, 1000);
+		assert!(tx.send(packet()).await.unwrap());
+		assert!(!tx.send(packet()).await.unwrap());
+	}
+
+	#[tokio::test]
+	async fn test_late_reply_after_close() {
+		let sessions: Cache<u16, u32> = Cache::new(16);
+		let tombstones = Cache::builder().time_to_live(Duration::from_millis(100)).build();
+		sessions.insert(1, 1).await;
+		assert!(matches!(lookup_assoc(&sessions, &tombstones, 1).await, AssocLookup::Open(1)));
+
+		// The association is reaped while its reply is still on the way
+		close_assoc(&sessions, &tombstones, 1).await;
+		assert!(matches!(lookup_assoc(&sessions, &tombstones, 1).await, AssocLookup::Closed));
+		assert!(matches!(lookup_assoc(&sessions, &tombstones, 2).await, AssocLookup::Unknown));
+
+		tokio::time::sleep(Duration::from_millis(150)).await;
+		assert!(matches!(lookup_assoc(&sessions, &tombstones, 1).await, AssocLookup::Unknown));
+	}
+
+	#[tokio::test]
+	async fn test_count_unknown_assoc() {
+		let sessions = Cache::new(16);
+		let tombstones = Cache::new(16);
+		let unknown = UnknownAssocs::default();
+		let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 53);
+		let mut packet = crate::proto::encode_packet_head(7, 0, &target, 4).unwrap();
+		packet.extend_from_slice(b"ping");
+
+		dispatch_packet(&sessions, &tombstones, &unknown, &packet, "datagram").await;
+		assert_eq!(unknown.count(), 1);
+
+		// Late replies to a closed association aren't the allocator's fault
+		tombstones.insert(7, ()).await;
+		dispatch_packet(&sessions, &tombstones, &unknown, &packet, "datagram").await;
+		assert_eq!(unknown.count(), 1);
+	}
+}
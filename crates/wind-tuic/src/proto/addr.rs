@@ -11,7 +11,7 @@ use wind_core::types::TargetAddr;
 
 #[cfg(feature = "decode")]
 use crate::proto::ProtoError;
-use crate::proto::{BytesRemainingSnafu, DomainTooLongSnafu, FailParseDomainSnafu, UnknownAddressTypeSnafu};
+use crate::proto::{BytesRemainingSnafu, FailParseDomainSnafu, InvalidDomainSnafu, UnknownAddressTypeSnafu};
 
 //-----------------------------------------------------------------------------
 // Type Definitions
@@ -171,10 +171,8 @@ impl Encoder<Address> for AddressCodec {
 				dst.put_u16(port);
 			}
 			Address::Domain(domain, port) => {
-				// Validate domain length
-				if domain.len() > u8::MAX as usize {
-					return DomainTooLongSnafu { domain }.fail();
-				}
+				// Punycode, lowercase and no trailing dot, which also bounds the length
+				let domain = wind_core::types::normalize_domain(&domain).context(InvalidDomainSnafu)?;
 
 				// Type (1) + Length (1) + Domain + Port (2)
 				dst.reserve(1 + 1 + domain.len() + 2);
@@ -277,6 +275,27 @@ mod test {
 		Ok(())
 	}
 
+	/// Domains go out normalized, and ones too long for the length byte are
+	/// refused
+	#[test_log::test(tokio::test)]
+	async fn test_addr_domain_normalized() -> eyre::Result<()> {
+		let mut writer = FramedWrite::new(Vec::new(), AddressCodec);
+		writer.send(Address::Domain(String::from("Bücher.Example."), 443)).await?;
+		let buffer = writer.get_ref();
+		let mut reader = FramedRead::new(buffer.as_slice(), AddressCodec);
+		assert_eq!(
+			reader.next().await.unwrap()?,
+			Address::Domain(String::from("xn--bcher-kva.example"), 443)
+		);
+
+		let long = format!("{}example", "a.".repeat(125));
+		assert!(matches!(
+			writer.send(Address::Domain(long, 443)).await.unwrap_err(),
+			ProtoError::InvalidDomain { .. }
+		));
+		Ok(())
+	}
+
 	/// Test to generate and inspect hex encoding (useful for debugging)
 	#[test_log::test(tokio::test)]
 	async fn hex_check() -> eyre::Result<()> {
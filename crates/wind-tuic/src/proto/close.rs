@@ -0,0 +1,148 @@
+use std::fmt;
+
+use quinn::{ConnectionError, VarInt};
+use wind_core::tcp::ConnectError;
+
+/// Why a TUIC connection was closed, carried as the QUIC application error
+/// code so the peer can tell the cases apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+	/// The client's token didn't match any user
+	AuthFailure,
+	/// The client didn't authenticate within the server's auth timeout
+	AuthTimeout,
+	/// The peer sent something that isn't valid TUIC
+	ProtocolError,
+	/// Nothing was relayed for too long
+	Idle,
+	/// The server is going away
+	ServerShutdown,
+	/// The two sides negotiated an ALPN the other doesn't speak TUIC on
+	UnsupportedAlpn,
+	/// The client is going away
+	ClientShutdown,
+}
+
+impl CloseReason {
+	pub const fn code(self) -> VarInt {
+		VarInt::from_u32(match self {
+			CloseReason::AuthFailure => 1,
+			CloseReason::AuthTimeout => 2,
+			CloseReason::ProtocolError => 3,
+			CloseReason::Idle => 4,
+			CloseReason::ServerShutdown => 5,
+			CloseReason::UnsupportedAlpn => 6,
+			CloseReason::ClientShutdown => 7,
+		})
+	}
+
+	pub fn from_code(code: VarInt) -> Option<Self> {
+		Some(match code.into_inner() {
+			1 => CloseReason::AuthFailure,
+			2 => CloseReason::AuthTimeout,
+			3 => CloseReason::ProtocolError,
+			4 => CloseReason::Idle,
+			5 => CloseReason::ServerShutdown,
+			6 => CloseReason::UnsupportedAlpn,
+			7 => CloseReason::ClientShutdown,
+			_ => return None,
+		})
+	}
+
+	/// Reason phrase sent along with the code, for humans reading packet dumps
+	pub const fn phrase(self) -> &'static str {
+		match self {
+			CloseReason::AuthFailure => "auth failure",
+			CloseReason::AuthTimeout => "auth timeout",
+			CloseReason::ProtocolError => "protocol error",
+			CloseReason::Idle => "idle",
+			CloseReason::ServerShutdown => "server shutdown",
+			CloseReason::UnsupportedAlpn => "unsupported alpn",
+			CloseReason::ClientShutdown => "client shutdown",
+		}
+	}
+
+	/// The reason the peer gave, if `err` is a close by a TUIC peer
+	pub fn from_error(err: &ConnectionError) -> Option<Self> {
+		match err {
+			ConnectionError::ApplicationClosed(close) => Self::from_code(close.error_code),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for CloseReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.phrase())
+	}
+}
+
+pub trait CloseExt {
+	/// Close the connection, telling the peer why
+	fn close_with(&self, reason: CloseReason);
+}
+
+impl CloseExt for quinn::Connection {
+	fn close_with(&self, reason: CloseReason) {
+		self.close(reason.code(), reason.phrase().as_bytes());
+	}
+}
+
+/// Code a server resets a Connect stream with when it can't reach the target,
+/// the SOCKS5 reply code for `err`
+pub const fn connect_error_code(err: ConnectError) -> VarInt {
+	VarInt::from_u32(match err {
+		ConnectError::General => 1,
+		ConnectError::NotAllowed => 2,
+		ConnectError::NetworkUnreachable => 3,
+		ConnectError::HostUnreachable => 4,
+		ConnectError::ConnectionRefused => 5,
+		ConnectError::TimedOut => 6,
+	})
+}
+
+/// Why the server reset a Connect stream with `code`, servers that don't say
+/// reset with 0
+pub fn connect_error_from_code(code: VarInt) -> ConnectError {
+	match code.into_inner() {
+		2 => ConnectError::NotAllowed,
+		3 => ConnectError::NetworkUnreachable,
+		4 => ConnectError::HostUnreachable,
+		5 => ConnectError::ConnectionRefused,
+		6 => ConnectError::TimedOut,
+		_ => ConnectError::General,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_codes_round_trip() {
+		for reason in [
+			CloseReason::AuthFailure,
+			CloseReason::AuthTimeout,
+			CloseReason::ProtocolError,
+			CloseReason::Idle,
+			CloseReason::ServerShutdown,
+			CloseReason::UnsupportedAlpn,
+			CloseReason::ClientShutdown,
+		] {
+			assert_eq!(CloseReason::from_code(reason.code()), Some(reason));
+		}
+		assert_eq!(CloseReason::from_code(VarInt::from_u32(0)), None);
+
+		for err in [
+			ConnectError::General,
+			ConnectError::NotAllowed,
+			ConnectError::NetworkUnreachable,
+			ConnectError::HostUnreachable,
+			ConnectError::ConnectionRefused,
+			ConnectError::TimedOut,
+		] {
+			assert_eq!(connect_error_from_code(connect_error_code(err)), err);
+		}
+		assert_eq!(connect_error_from_code(VarInt::from_u32(0)), ConnectError::General);
+	}
+}
@@ -27,8 +27,9 @@ pub enum ProtoError {
 		source:    Utf8Error,
 		backtrace: Backtrace,
 	},
-	DomainTooLong {
-		domain:    String,
+	#[snafu(display("Unable to encode address: {source}"))]
+	InvalidDomain {
+		source:    wind_core::types::InvalidDomain,
 		backtrace: Backtrace,
 	},
 	// Caller should yield
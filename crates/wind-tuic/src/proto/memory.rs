@@ -0,0 +1,184 @@
+use std::{
+	io,
+	pin::Pin,
+	sync::{
+		Arc,
+		atomic::{AtomicI32, Ordering},
+	},
+	task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use eyre::eyre;
+use tokio::{
+	io::{AsyncWrite, DuplexStream, ReadHalf, WriteHalf},
+	sync::mpsc,
+};
+
+use super::Transport;
+use crate::Error;
+
+const STREAM_BUFFER: usize = 64 * 1024;
+
+/// In-memory [`Transport`] for exercising the client protocol without QUIC
+///
+/// Streams are [`tokio::io::duplex`] pairs whose other end shows up on the
+/// [`MemoryPeer`], datagrams are passed through a channel.
+pub struct MemoryTransport {
+	bi:        mpsc::UnboundedSender<(DuplexStream, StreamPriority)>,
+	uni:       mpsc::UnboundedSender<(DuplexStream, StreamPriority)>,
+	datagram:  mpsc::UnboundedSender<Bytes>,
+	/// Stands in for the TLS exporter secret
+	secret:    Bytes,
+	/// Whether the peer accepts datagrams
+	datagrams: bool,
+}
+
+/// Server side of a [`MemoryTransport`]
+pub struct MemoryPeer {
+	bi:       mpsc::UnboundedReceiver<(DuplexStream, StreamPriority)>,
+	uni:      mpsc::UnboundedReceiver<(DuplexStream, StreamPriority)>,
+	datagram: mpsc::UnboundedReceiver<Bytes>,
+	secret:   Bytes,
+}
+
+impl MemoryTransport {
+	pub fn pair() -> (Self, MemoryPeer) {
+		let (bi_tx, bi_rx) = mpsc::unbounded_channel();
+		let (uni_tx, uni_rx) = mpsc::unbounded_channel();
+		let (datagram_tx, datagram_rx) = mpsc::unbounded_channel();
+		let secret = Bytes::from_static(b"wind memory transport");
+		(
+			Self {
+				bi:        bi_tx,
+				uni:       uni_tx,
+				datagram:  datagram_tx,
+				secret:    secret.clone(),
+				datagrams: true,
+			},
+			MemoryPeer {
+				bi: bi_rx,
+				uni: uni_rx,
+				datagram: datagram_rx,
+				secret,
+			},
+		)
+	}
+
+	/// Act like a peer that doesn't accept datagrams, so sending one fails
+	pub fn without_datagrams(mut self) -> Self {
+		self.datagrams = false;
+		self
+	}
+
+	fn open(
+		&self,
+		tx: &mpsc::UnboundedSender<(DuplexStream, StreamPriority)>,
+	) -> Result<(DuplexStream, StreamPriority), Error> {
+		let (local, remote) = tokio::io::duplex(STREAM_BUFFER);
+		let priority = StreamPriority::default();
+		tx.send((remote, priority.clone()))
+			.map_err(|_| eyre!("memory peer is closed"))?;
+		Ok((local, priority))
+	}
+}
+
+/// Priority the client gave a memory stream, as seen by the peer
+#[derive(Debug, Clone, Default)]
+pub struct StreamPriority(Arc<AtomicI32>);
+
+impl StreamPriority {
+	pub fn get(&self) -> i32 {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+/// Sending half of a memory stream
+pub struct MemorySendStream {
+	inner:    WriteHalf<DuplexStream>,
+	priority: StreamPriority,
+}
+
+impl AsyncWrite for MemorySendStream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+	}
+}
+
+impl Transport for MemoryTransport {
+	type RecvStream = ReadHalf<DuplexStream>;
+	type SendStream = MemorySendStream;
+
+	async fn open_bi(&self) -> Result<(Self::SendStream, Self::RecvStream), Error> {
+		let (stream, priority) = self.open(&self.bi)?;
+		let (recv, inner) = tokio::io::split(stream);
+		Ok((MemorySendStream { inner, priority }, recv))
+	}
+
+	async fn open_uni(&self) -> Result<Self::SendStream, Error> {
+		let (stream, priority) = self.open(&self.uni)?;
+		let inner = tokio::io::split(stream).1;
+		Ok(MemorySendStream { inner, priority })
+	}
+
+	fn send_datagram(&self, data: Bytes) -> Result<(), Error> {
+		if !self.datagrams {
+			return Err(eyre!("memory peer doesn't accept datagrams"));
+		}
+		self.datagram.send(data).map_err(|_| eyre!("memory peer is closed"))
+	}
+
+	fn max_datagram_size(&self) -> Option<usize> {
+		self.datagrams.then_some(u16::MAX as usize)
+	}
+
+	fn set_priority(stream: &Self::SendStream, priority: i32) -> Result<(), Error> {
+		stream.priority.0.store(priority, Ordering::Relaxed);
+		Ok(())
+	}
+
+	fn export_keying_material(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), Error> {
+		export(&self.secret, output, label, context);
+		Ok(())
+	}
+}
+
+impl MemoryPeer {
+	pub async fn accept_bi(&mut self) -> Option<DuplexStream> {
+		Some(self.accept_bi_with_priority().await?.0)
+	}
+
+	/// Also hand out the priority the client sets on the stream
+	pub async fn accept_bi_with_priority(&mut self) -> Option<(DuplexStream, StreamPriority)> {
+		self.bi.recv().await
+	}
+
+	pub async fn accept_uni(&mut self) -> Option<DuplexStream> {
+		Some(self.uni.recv().await?.0)
+	}
+
+	pub async fn read_datagram(&mut self) -> Option<Bytes> {
+		self.datagram.recv().await
+	}
+
+	/// What the client derived for the same `label` and `context`
+	pub fn export_keying_material(&self, output: &mut [u8], label: &[u8], context: &[u8]) {
+		export(&self.secret, output, label, context);
+	}
+}
+
+/// Deterministic, not cryptographically meaningful
+fn export(secret: &[u8], output: &mut [u8], label: &[u8], context: &[u8]) {
+	let input: Vec<u8> = secret.iter().chain(label).chain(context).copied().collect();
+	for (i, byte) in output.iter_mut().enumerate() {
+		*byte = input[i % input.len()].wrapping_add(i as u8);
+	}
+}
@@ -3,6 +3,8 @@ pub use error::*;
 
 mod header;
 
+use std::{sync::LazyLock, time::Duration};
+
 use bytes::{Buf, BytesMut};
 use eyre::eyre;
 pub use header::*;
@@ -16,30 +18,86 @@ mod addr;
 pub use addr::*;
 
 mod udp_stream;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
 use tokio_util::codec::{Decoder, Encoder};
 pub use udp_stream::*;
-use wind_core::{io::quinn::QuinnCompat, tcp::AbstractTcpStream, types::TargetAddr};
+use wind_core::{
+	debug,
+	log::LogLimiter,
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+};
+
+mod transport;
+pub use transport::*;
+
+#[cfg(any(test, feature = "test-util"))]
+mod memory;
+#[cfg(any(test, feature = "test-util"))]
+pub use memory::*;
+
+mod close;
+pub use close::*;
 
 use crate::Error;
 
 pub const VER: u8 = 5;
 
+/// Longest wait for the server to let the authentication stream open
+pub const AUTH_OPEN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Most a relay reads from the server while waiting for its connect verdict
+const EARLY_READ: usize = 16 * 1024;
+
+/// Why the server failed a relay stream, by its reset code if it gave one
+fn connect_error_of(err: &std::io::Error) -> ConnectError {
+	match err.get_ref().and_then(|inner| inner.downcast_ref::<quinn::ReadError>()) {
+		Some(quinn::ReadError::Reset(code)) => connect_error_from_code(*code),
+		_ => ConnectError::from(err),
+	}
+}
+
 /// Helper function to decode header with better error reporting
 pub fn decode_header(buf: &mut BytesMut, context: &str) -> Result<Header, Error> {
-	HeaderCodec.decode(buf)?
-		.ok_or_else(|| eyre!("Incomplete header in {}", context).into())
+	let header = HeaderCodec
+		.decode(buf)?
+		.ok_or_else(|| eyre!("Incomplete header in {}", context))?;
+	trace_decoded(context, &header);
+	Ok(header)
 }
 
 /// Helper function to decode command with better error reporting
 pub fn decode_command(cmd_type: CmdType, buf: &mut BytesMut, context: &str) -> Result<Command, Error> {
-	CmdCodec(cmd_type).decode(buf)?
-		.ok_or_else(|| eyre!("Incomplete command in {}", context).into())
+	let command = CmdCodec(cmd_type)
+		.decode(buf)?
+		.ok_or_else(|| eyre!("Incomplete command in {}", context))?;
+	match command {
+		// Keep the credentials out of the trace
+		Command::Auth { .. } => trace_decoded(context, &format_args!("Auth {{ .. }}")),
+		ref command => trace_decoded(context, command),
+	}
+	Ok(command)
 }
 
 /// Helper function to decode address with better error reporting
 pub fn decode_address(buf: &mut BytesMut, context: &str) -> Result<Address, Error> {
-	AddressCodec.decode(buf)?
-		.ok_or_else(|| eyre!("Incomplete address in {}", context).into())
+	let address = AddressCodec
+		.decode(buf)?
+		.ok_or_else(|| eyre!("Incomplete address in {}", context))?;
+	trace_decoded(context, &address);
+	Ok(address)
+}
+
+/// Log a decoded header, command or address while payload tracing is on, a
+/// few a second at most as every UDP packet and heartbeat decodes three
+fn trace_decoded(context: &str, decoded: &dyn std::fmt::Debug) {
+	static LIMITER: LazyLock<LogLimiter> = LazyLock::new(|| LogLimiter::new(Duration::from_millis(100)));
+	if !wind_core::log::tracing_payloads() {
+		return;
+	}
+	if let Some(suppressed) = LIMITER.check() {
+		wind_core::info!(target: "[TRACE]", "{context}: {decoded:?} ({suppressed} more suppressed)");
+	}
 }
 
 /// Helper function to convert Address to TargetAddr
@@ -54,7 +112,7 @@ pub fn address_to_target(addr: Address) -> Result<TargetAddr, Error> {
 
 /// Helper function to encode and send data via unidirectional stream
 pub async fn encode_and_send_uni(
-	conn: &quinn::Connection,
+	conn: &impl Transport,
 	cmd_type: CmdType,
 	command: Command,
 	address: Option<Address>,
@@ -65,18 +123,76 @@ pub async fn encode_and_send_uni(
 	if let Some(addr) = address {
 		AddressCodec.encode(addr, &mut buf)?;
 	}
+	send_uni(conn, &buf).await
+}
+
+/// Header, command and address of an unfragmented UDP packet carrying `len`
+/// payload bytes
+pub(crate) fn encode_packet_head(assoc_id: u16, pkt_id: u16, addr: &TargetAddr, len: usize) -> Result<BytesMut, Error> {
+	let mut buf = BytesMut::with_capacity(12);
+	HeaderCodec.encode(Header::new(CmdType::Packet), &mut buf)?;
+	CmdCodec(CmdType::Packet).encode(
+		Command::Packet {
+			assoc_id,
+			pkt_id,
+			frag_total: 1,
+			frag_id: 0,
+			size: len as u16,
+		},
+		&mut buf,
+	)?;
+	AddressCodec.encode(addr.to_owned().into(), &mut buf)?;
+	Ok(buf)
+}
+
+/// Send `buf` as a whole unidirectional stream
+async fn send_uni(conn: &impl Transport, buf: &[u8]) -> Result<(), Error> {
 	let mut send = conn.open_uni().await?;
-	send.write_chunk(buf.into()).await?;
+	send.write_all(buf).await?;
+	send.shutdown().await?;
 	Ok(())
 }
 
+/// Stream priority of relays likely to be interactive, e.g. SSH
+pub const INTERACTIVE_PRIORITY: i32 = 1;
+/// Stream priority of every other relay, quinn's default
+pub const BULK_PRIORITY: i32 = 0;
+
+/// Priority of the relay stream to `addr`, so interactive sessions aren't
+/// queued behind bulk transfers on the same connection
+///
+/// Well-known ports count as interactive, except HTTP(S) which carries most
+/// bulk downloads.
+pub fn stream_priority(addr: &TargetAddr) -> i32 {
+	let port = match addr {
+		TargetAddr::Domain(_, port) | TargetAddr::IPv4(_, port) | TargetAddr::IPv6(_, port) => *port,
+	};
+	match port {
+		80 | 443 => BULK_PRIORITY,
+		port if port < 1024 => INTERACTIVE_PRIORITY,
+		_ => BULK_PRIORITY,
+	}
+}
+
+/// How a heartbeat reached the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatMode {
+	Datagram,
+	/// On a unidirectional stream, as datagrams are unavailable or failed
+	Stream,
+}
+
 pub trait ClientProtoExt {
 	fn send_auth(&self, uuid: &uuid::Uuid, secret: &[u8]) -> impl Future<Output = Result<(), Error>> + Send;
-	fn send_heartbeat(&self) -> impl Future<Output = Result<(), Error>> + Send;
+	fn send_heartbeat(&self) -> impl Future<Output = Result<HeartbeatMode, Error>> + Send;
+	/// Relay `stream` to `addr`. TUIC has no connect acknowledgement, so
+	/// `stream` is told the connect succeeded once the server sends anything,
+	/// or after `verdict` passes without it resetting the relay stream
 	fn open_tcp(
 		&self,
 		addr: &TargetAddr,
 		stream: impl AbstractTcpStream,
+		verdict: Duration,
 	) -> impl Future<Output = Result<(usize, usize), Error>> + Send;
 	fn send_udp(
 		&self,
@@ -89,12 +205,11 @@ pub trait ClientProtoExt {
 	fn drop_udp(&self, assoc_id: u16) -> impl Future<Output = Result<(), Error>> + Send;
 }
 
-impl ClientProtoExt for quinn::Connection {
+impl<T: Transport> ClientProtoExt for T {
 	async fn send_auth(&self, uuid: &uuid::Uuid, secret: &[u8]) -> Result<(), Error> {
 		// Generate the authentication token
 		let mut token = [0u8; 32];
-		self.export_keying_material(&mut token, uuid.as_bytes(), secret)
-			.map_err(|_| eyre!("export_keying_material requested output length is too large."))?;
+		self.export_keying_material(&mut token, uuid.as_bytes(), secret)?;
 
 		// Create and encode the auth command
 		let auth_cmd = Command::Auth { uuid: *uuid, token };
@@ -107,21 +222,56 @@ impl ClientProtoExt for quinn::Connection {
 		HeaderCodec.encode(Header::new(CmdType::Auth), &mut buf)?;
 		CmdCodec(CmdType::Auth).encode(auth_cmd, &mut buf)?;
 
-		// Open a unidirectional stream and send the data
-		let mut send = self.open_uni().await?;
-		send.write_chunk(buf.into()).await?;
-
-		Ok(())
+		// Out of stream credit, opening waits until the server grants more. Bound
+		// that wait, as an unauthenticated connection is of no use
+		tokio::time::timeout(AUTH_OPEN_TIMEOUT, send_uni(self, &buf))
+			.await
+			.map_err(|_| eyre!("Timed out opening the authentication stream after {AUTH_OPEN_TIMEOUT:?}"))?
 	}
 
-	async fn open_tcp(&self, addr: &TargetAddr, mut stream: impl AbstractTcpStream) -> Result<(usize, usize), Error> {
-		let (mut send, recv) = self.open_bi().await?;
-		let mut buf = BytesMut::with_capacity(9);
-		HeaderCodec.encode(Header::new(CmdType::Connect), &mut buf)?;
-		CmdCodec(CmdType::Connect).encode(Command::Connect, &mut buf)?;
-		AddressCodec.encode(addr.to_owned().into(), &mut buf)?;
-		send.write_chunk(buf.into()).await?;
-		let (a, b, err) = wind_core::io::copy_io(&mut stream, &mut QuinnCompat::new(send, recv)).await;
+	async fn open_tcp(
+		&self,
+		addr: &TargetAddr,
+		mut stream: impl AbstractTcpStream,
+		verdict: Duration,
+	) -> Result<(usize, usize), Error> {
+		let connect = async {
+			let (mut send, recv) = self.open_bi().await?;
+			T::set_priority(&send, stream_priority(addr))?;
+			let mut buf = BytesMut::with_capacity(9);
+			HeaderCodec.encode(Header::new(CmdType::Connect), &mut buf)?;
+			CmdCodec(CmdType::Connect).encode(Command::Connect, &mut buf)?;
+			AddressCodec.encode(addr.to_owned().into(), &mut buf)?;
+			send.write_all(&buf).await?;
+			Ok::<_, Error>((send, recv))
+		};
+		let (send, mut recv) = match connect.await {
+			Ok(streams) => streams,
+			Err(e) => {
+				stream.on_connect(Err(ConnectError::from_report(&e))).await?;
+				return Err(e);
+			}
+		};
+		// A server that can't reach the target resets the stream, anything it
+		// sends means it did
+		let mut early = Vec::new();
+		if !verdict.is_zero() {
+			early.resize(EARLY_READ, 0);
+			let read = tokio::time::timeout(verdict, recv.read(&mut early)).await;
+			match read {
+				Ok(Ok(n)) => early.truncate(n),
+				Ok(Err(e)) => {
+					stream.on_connect(Err(connect_error_of(&e))).await?;
+					return Err(e.into());
+				}
+				Err(_) => early.clear(),
+			}
+		}
+		stream.on_connect(Ok(())).await?;
+		if !early.is_empty() {
+			stream.write_all(&early).await?;
+		}
+		let (a, b, err) = wind_core::io::copy_io(&mut stream, &mut tokio::io::join(recv, send)).await;
 		// Guard clause: return early if there's an error
 		if let Some(e) = err {
 			return Err(e.into());
@@ -137,49 +287,43 @@ impl ClientProtoExt for quinn::Connection {
 		payload: bytes::Bytes,
 		datagram: bool,
 	) -> Result<(), Error> {
-		let mut buf = BytesMut::with_capacity(12);
-		HeaderCodec.encode(Header::new(CmdType::Packet), &mut buf)?;
-		CmdCodec(CmdType::Packet).encode(
-			Command::Packet {
-				assoc_id,
-				pkt_id,
-				frag_total: 1,
-				frag_id: 0,
-				size: payload.len() as u16,
-			},
-			&mut buf,
-		)?;
-		AddressCodec.encode(addr.to_owned().into(), &mut buf)?;
+		let buf = encode_packet_head(assoc_id, pkt_id, addr, payload.len())?;
 		if datagram {
 			let mut combined = buf.freeze().chain(payload);
 			self.send_datagram(combined.copy_to_bytes(combined.remaining()))?;
 		} else {
 			let mut send = self.open_uni().await?;
-			send.write_all_chunks(&mut [buf.into(), payload]).await?;
+			send.write_all(&buf).await?;
+			send.write_all(&payload).await?;
+			send.shutdown().await?;
 		}
 		Ok(())
 	}
 
 	async fn drop_udp(&self, assoc_id: u16) -> Result<(), Error> {
-		let mut send = self.open_uni().await?;
 		let mut buf = BytesMut::with_capacity(4);
 		HeaderCodec.encode(Header::new(CmdType::Dissociate), &mut buf)?;
 		CmdCodec(CmdType::Dissociate).encode(Command::Dissociate { assoc_id }, &mut buf)?;
-		send.write_chunk(buf.into()).await?;
-		Ok(())
+		send_uni(self, &buf).await
 	}
 
-	async fn send_heartbeat(&self) -> Result<(), Error> {
+	async fn send_heartbeat(&self) -> Result<HeartbeatMode, Error> {
 		// Pre-allocate the exact size needed for the heartbeat: 2 bytes (version +
 		// command)
 		let mut buf = BytesMut::with_capacity(2);
 
 		// Encode the heartbeat command header (no additional payload needed)
 		HeaderCodec.encode(Header::new(CmdType::Heartbeat), &mut buf)?;
+		let buf = buf.freeze();
 
-		// Send it as a datagram for lowest latency
-		self.send_datagram(buf.freeze())?;
-
-		Ok(())
+		// Send it as a datagram for lowest latency, unless the server takes none
+		if self.max_datagram_size().is_some() {
+			match self.send_datagram(buf.clone()) {
+				Ok(()) => return Ok(HeartbeatMode::Datagram),
+				Err(e) => debug!(target: "[OUT]", "Heartbeat datagram failed, sending it on a stream: {}", e),
+			}
+		}
+		send_uni(self, &buf).await?;
+		Ok(HeartbeatMode::Stream)
 	}
 }
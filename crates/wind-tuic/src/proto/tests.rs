@@ -1,12 +1,17 @@
 #[cfg(test)]
 mod test {
-	use std::net::Ipv4Addr;
+	use std::{net::Ipv4Addr, time::Duration};
 
-	use bytes::BytesMut;
+	use bytes::{Bytes, BytesMut};
+	use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
 	use tokio_util::codec::Encoder as _;
 	use uuid::Uuid;
+	use wind_core::types::TargetAddr;
 
-	use crate::proto::{Address, AddressCodec, CmdCodec, CmdType, Command, Header, HeaderCodec};
+	use crate::proto::{
+		Address, AddressCodec, BULK_PRIORITY, ClientProtoExt as _, CmdCodec, CmdType, Command, Header, HeaderCodec,
+		HeartbeatMode, INTERACTIVE_PRIORITY, MemoryTransport, decode_address, decode_command, decode_header,
+	};
 
 	#[test_log::test(tokio::test)]
 	async fn hex_check_connect_encode() -> eyre::Result<()> {
@@ -34,4 +39,136 @@ mod test {
 		);
 		Ok(())
 	}
+
+	#[test_log::test(tokio::test)]
+	async fn memory_connect_flow() -> eyre::Result<()> {
+		let (client, mut peer) = MemoryTransport::pair();
+		let (inbound, mut app) = tokio::io::duplex(1024);
+		let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 80);
+		let relay = tokio::spawn(async move { client.open_tcp(&target, inbound, Duration::ZERO).await });
+
+		let mut remote = peer.accept_bi().await.unwrap();
+		let mut head = [0u8; 9];
+		remote.read_exact(&mut head).await?;
+		assert_eq!("0501017f0000010050", hex::encode(head));
+
+		app.write_all(b"ping").await?;
+		let mut buf = [0u8; 4];
+		remote.read_exact(&mut buf).await?;
+		assert_eq!(&buf, b"ping");
+		remote.write_all(b"pong").await?;
+		app.read_exact(&mut buf).await?;
+		assert_eq!(&buf, b"pong");
+
+		drop(app);
+		drop(remote);
+		let (up, down) = relay.await??;
+		assert_eq!((up, down), (4, 4));
+		Ok(())
+	}
+
+	#[test_log::test(tokio::test)]
+	async fn memory_stream_priorities() -> eyre::Result<()> {
+		let (client, mut peer) = MemoryTransport::pair();
+		let client = std::sync::Arc::new(client);
+		let mut relays = Vec::new();
+		let mut apps = Vec::new();
+		for port in [22, 8443] {
+			let (inbound, app) = tokio::io::duplex(1024);
+			let client = client.clone();
+			let target = TargetAddr::Domain("example.com".into(), port);
+			relays.push(tokio::spawn(async move { client.open_tcp(&target, inbound, Duration::ZERO).await }));
+			apps.push(app);
+		}
+
+		// Both relays stay open while the priorities are checked
+		let mut seen = Vec::new();
+		for _ in 0..2 {
+			let (mut remote, priority) = peer.accept_bi_with_priority().await.unwrap();
+			let mut head = vec![0u8; 2 + 1 + 1 + "example.com".len() + 2];
+			remote.read_exact(&mut head).await?;
+			let port = u16::from_be_bytes([head[head.len() - 2], head[head.len() - 1]]);
+			seen.push((port, priority.get(), remote));
+		}
+		seen.sort_by_key(|(port, ..)| *port);
+		assert_eq!((seen[0].0, seen[0].1), (22, INTERACTIVE_PRIORITY));
+		assert_eq!((seen[1].0, seen[1].1), (8443, BULK_PRIORITY));
+
+		drop(apps);
+		drop(seen);
+		for relay in relays {
+			relay.await??;
+		}
+		Ok(())
+	}
+
+	#[test_log::test(tokio::test)]
+	async fn memory_heartbeat_falls_back_to_stream() -> eyre::Result<()> {
+		let (client, mut peer) = MemoryTransport::pair();
+		assert_eq!(client.send_heartbeat().await?, HeartbeatMode::Datagram);
+		assert_eq!("0504", hex::encode(peer.read_datagram().await.unwrap()));
+
+		let (client, mut peer) = MemoryTransport::pair();
+		let client = client.without_datagrams();
+		assert_eq!(client.send_heartbeat().await?, HeartbeatMode::Stream);
+		let mut heartbeat = Vec::new();
+		peer.accept_uni().await.unwrap().read_to_end(&mut heartbeat).await?;
+		assert_eq!("0504", hex::encode(heartbeat));
+		Ok(())
+	}
+
+	#[test_log::test(tokio::test)]
+	async fn memory_packet_flow() -> eyre::Result<()> {
+		let (client, mut peer) = MemoryTransport::pair();
+		let target = TargetAddr::Domain("example.com".into(), 53);
+		let payload = Bytes::from_static(b"query");
+
+		for datagram in [true, false] {
+			client.send_udp(7, 1, &target, payload.clone(), datagram).await?;
+			let mut buf = if datagram {
+				BytesMut::from(&peer.read_datagram().await.unwrap()[..])
+			} else {
+				let mut raw = Vec::new();
+				peer.accept_uni().await.unwrap().read_to_end(&mut raw).await?;
+				BytesMut::from(&raw[..])
+			};
+
+			let header = decode_header(&mut buf, "test")?;
+			assert_eq!(header.command, CmdType::Packet);
+			let Command::Packet {
+				assoc_id,
+				pkt_id,
+				frag_total,
+				frag_id,
+				size,
+			} = decode_command(CmdType::Packet, &mut buf, "test")?
+			else {
+				panic!("not a packet command");
+			};
+			assert_eq!((assoc_id, pkt_id, frag_total, frag_id), (7, 1, 1, 0));
+			assert_eq!(size as usize, payload.len());
+			assert_eq!(decode_address(&mut buf, "test")?, Address::Domain("example.com".into(), 53));
+			assert_eq!(&buf[..], &payload[..]);
+		}
+		Ok(())
+	}
+
+	#[test_log::test(tokio::test)]
+	async fn memory_auth_flow() -> eyre::Result<()> {
+		let (client, mut peer) = MemoryTransport::pair();
+		let uuid = Uuid::from_u128(1);
+		client.send_auth(&uuid, b"secret").await?;
+
+		let mut raw = Vec::new();
+		peer.accept_uni().await.unwrap().read_to_end(&mut raw).await?;
+		let mut buf = BytesMut::from(&raw[..]);
+		decode_header(&mut buf, "test")?;
+		let Command::Auth { uuid: got, token } = decode_command(CmdType::Auth, &mut buf, "test")? else {
+			panic!("not an auth command");
+		};
+		let mut expected = [0u8; 32];
+		peer.export_keying_material(&mut expected, uuid.as_bytes(), b"secret");
+		assert_eq!((got, token), (uuid, expected));
+		Ok(())
+	}
 }
@@ -0,0 +1,58 @@
+use bytes::Bytes;
+use eyre::eyre;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::Error;
+
+/// The parts of a QUIC connection the client protocol runs over
+///
+/// Implemented by [`quinn::Connection`] and, with the `test-util` feature, by
+/// an in-memory `MemoryTransport` for tests.
+pub trait Transport: Send + Sync {
+	type SendStream: AsyncWrite + Send + Sync + Unpin;
+	type RecvStream: AsyncRead + Send + Sync + Unpin;
+
+	fn open_bi(&self) -> impl Future<Output = Result<(Self::SendStream, Self::RecvStream), Error>> + Send;
+	fn open_uni(&self) -> impl Future<Output = Result<Self::SendStream, Error>> + Send;
+	fn send_datagram(&self, data: Bytes) -> Result<(), Error>;
+	/// Largest datagram the peer accepts, `None` when it takes none
+	fn max_datagram_size(&self) -> Option<usize>;
+	/// Schedule `stream` ahead of streams with a lower priority, see
+	/// [`quinn::SendStream::set_priority`]. Ignored by default, for transports
+	/// that don't schedule their streams
+	fn set_priority(_stream: &Self::SendStream, _priority: i32) -> Result<(), Error> {
+		Ok(())
+	}
+	/// Derive keying material from the session, as in RFC 5705
+	fn export_keying_material(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), Error>;
+}
+
+impl Transport for quinn::Connection {
+	type RecvStream = quinn::RecvStream;
+	type SendStream = quinn::SendStream;
+
+	async fn open_bi(&self) -> Result<(Self::SendStream, Self::RecvStream), Error> {
+		Ok(quinn::Connection::open_bi(self).await?)
+	}
+
+	async fn open_uni(&self) -> Result<Self::SendStream, Error> {
+		Ok(quinn::Connection::open_uni(self).await?)
+	}
+
+	fn send_datagram(&self, data: Bytes) -> Result<(), Error> {
+		Ok(quinn::Connection::send_datagram(self, data)?)
+	}
+
+	fn max_datagram_size(&self) -> Option<usize> {
+		quinn::Connection::max_datagram_size(self)
+	}
+
+	fn set_priority(stream: &Self::SendStream, priority: i32) -> Result<(), Error> {
+		Ok(stream.set_priority(priority)?)
+	}
+
+	fn export_keying_material(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), Error> {
+		quinn::Connection::export_keying_material(self, output, label, context)
+			.map_err(|_| eyre!("export_keying_material requested output length is too large."))
+	}
+}
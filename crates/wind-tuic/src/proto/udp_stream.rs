@@ -1,6 +1,7 @@
 use std::{
+	collections::VecDeque,
 	sync::{
-		Arc, OnceLock,
+		Arc, Mutex, OnceLock,
 		atomic::{AtomicU16, AtomicU64, Ordering},
 	},
 	time::{Duration, Instant},
@@ -8,19 +9,119 @@ use std::{
 
 use arc_swap::{ArcSwap, ArcSwapOption};
 use bytes::{BufMut, Bytes, BytesMut};
-use crossfire::MAsyncTx;
+use crossfire::{MAsyncTx, TrySendError};
+use eyre::eyre;
+use futures_util::FutureExt as _;
 use moka::future::Cache;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
 use tokio_util::codec::Encoder;
-use wind_core::{types::TargetAddr, udp::UdpPacket};
+use wind_core::{log::LogLimiter, types::TargetAddr, udp::UdpPacket};
 
-use crate::proto::{Address, AddressCodec, ClientProtoExt as _, CmdCodec, CmdType, Command, Header, HeaderCodec};
+use crate::{
+	Error,
+	proto::{Address, AddressCodec, ClientProtoExt as _, CmdCodec, CmdType, Command, Header, HeaderCodec},
+};
+
+/// ALPN a peer offers to have UDP payloads carry a CRC-32 trailer
+///
+/// The trailer is only added once the server picked this protocol, so peers
+/// that don't know it keep getting plain payloads.
+pub const CHECKSUM_ALPN: &str = "tuic-crc32";
+
+/// Limits of UDP fragmentation and reassembly, trading memory for latency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpStreamConfig {
+	/// Packets that need more fragments than this aren't sent
+	pub max_fragments:       u8,
+	/// Partially received packets are discarded after this long
+	pub fragment_timeout:    Duration,
+	/// Packets of an association in reassembly at once, beyond which some are
+	/// evicted
+	pub reassembly_capacity: u64,
+	/// Packets of an association sent over streams the peer hasn't yet
+	/// acknowledged, beyond which sending waits. Keeps a flood from taking
+	/// every stream the connection may open, which heartbeats and other
+	/// associations need too
+	pub stream_window:       usize,
+}
+
+impl Default for UdpStreamConfig {
+	fn default() -> Self {
+		Self {
+			max_fragments:       255,
+			fragment_timeout:    Duration::from_secs(30),
+			reassembly_capacity: 1000,
+			stream_window:       16,
+		}
+	}
+}
 
-// Define MTU sizes for UDP segmentation
-const MAX_FRAGMENTS: u8 = 255; // Maximum number of fragments allowed
-const FRAGMENT_TIMEOUT_MS: u64 = 30000; // 30 seconds timeout for fragment reassembly
+impl UdpStreamConfig {
+	/// Fails on a zero `stream_window`, which would let no packet out over
+	/// streams
+	pub fn validate(&self) -> Result<(), Error> {
+		if self.stream_window == 0 {
+			return Err(eyre!("stream_window must be at least 1"));
+		}
+		Ok(())
+	}
+}
 
 static INIT_TIME: OnceLock<Instant> = OnceLock::new();
 
+/// CRC-32 (IEEE) of `data`
+fn crc32(data: &[u8]) -> u32 {
+	!data.iter().fold(!0u32, |crc, byte| {
+		(0..8).fold(crc ^ u32::from(*byte), |crc, _| {
+			(crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+		})
+	})
+}
+
+/// Append the CRC-32 of `payload` to it
+fn seal_checksum(payload: &[u8]) -> Bytes {
+	let mut sealed = BytesMut::with_capacity(payload.len() + 4);
+	sealed.put_slice(payload);
+	sealed.put_u32(crc32(payload));
+	sealed.freeze()
+}
+
+/// Checksum carried by a payload that doesn't match its content
+#[derive(Debug, PartialEq, Eq)]
+struct ChecksumMismatch {
+	carried:  Option<u32>,
+	computed: u32,
+}
+
+/// Verify and strip the CRC-32 trailer of `payload`
+fn open_checksum(mut payload: Bytes) -> Result<Bytes, ChecksumMismatch> {
+	if payload.len() < 4 {
+		return Err(ChecksumMismatch {
+			carried:  None,
+			computed: crc32(&payload),
+		});
+	}
+	let trailer = payload.split_off(payload.len() - 4);
+	let carried = u32::from_be_bytes(trailer[..].try_into().unwrap());
+	let computed = crc32(&payload);
+	if carried != computed {
+		return Err(ChecksumMismatch {
+			carried: Some(carried),
+			computed,
+		});
+	}
+	Ok(payload)
+}
+
+/// Whether the connection negotiated [`CHECKSUM_ALPN`]
+fn negotiated_checksum(connection: &quinn::Connection) -> bool {
+	connection
+		.handshake_data()
+		.and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+		.and_then(|data| data.protocol)
+		.is_some_and(|alpn| alpn == CHECKSUM_ALPN.as_bytes())
+}
+
 fn init_time() -> &'static Instant {
 	INIT_TIME.get_or_init(Instant::now)
 }
@@ -42,6 +143,23 @@ pub struct UdpStream {
 	next_pkt_id:     AtomicU16, // Track packet IDs for fragmentation
 	// Fragment reassembly state (wrapped in Mutex for interior mutability)
 	fragment_buffer: FragmentReassemblyBuffer,
+	// Send packets over unidirectional streams instead of datagrams
+	over_stream:     bool,
+	// Permits for packets on streams not yet acknowledged, see
+	// `UdpStreamConfig::stream_window`
+	stream_window:   Arc<Semaphore>,
+	window_size:     usize,
+	// Streams sent and not yet seen acknowledged, each holding its permit
+	in_flight:       Mutex<VecDeque<(quinn::SendStream, OwnedSemaphorePermit)>>,
+	// Wakes senders waiting on `in_flight`, when a stream shows up in it or
+	// permits came back from it
+	pushed:          Notify,
+	// Payloads carry a CRC-32 trailer, see `CHECKSUM_ALPN`
+	checksum:        bool,
+	max_fragments:   u8,
+	// Replies dropped because the local side fell behind
+	dropped:         AtomicU64,
+	drop_log:        LogLimiter,
 }
 
 /// Structure to track fragments of a packet for reassembly
@@ -56,16 +174,26 @@ struct FragmentMetadata {
 /// Buffer for reassembling fragmented packets
 struct FragmentReassemblyBuffer {
 	fragments: Cache<(u16, u16), Arc<FragmentMetadata>>, // (assoc_id, pkt_id) -> fragment metadata
+	timeout:   Duration,
+	// Verify and strip the CRC-32 trailer of reassembled payloads
+	checksum:  bool,
 }
 
 impl FragmentReassemblyBuffer {
 	/// Create a new fragment reassembly buffer
-	fn new() -> Self {
+	fn new(config: &UdpStreamConfig) -> Self {
 		Self {
-			fragments: Cache::new(1000),
+			fragments: Cache::new(config.reassembly_capacity),
+			timeout:   config.fragment_timeout,
+			checksum:  false,
 		}
 	}
 
+	fn with_checksum(mut self, checksum: bool) -> Self {
+		self.checksum = checksum;
+		self
+	}
+
 	/// Add a fragment to the buffer
 	async fn add_fragment(&self, info: FragmentInfo, payload: Bytes) -> Option<UdpPacket> {
 		let FragmentInfo {
@@ -126,9 +254,9 @@ impl FragmentReassemblyBuffer {
 
 	/// Clean up expired fragments
 	fn cleanup_expired(&self) {
+		let timeout = self.timeout;
 		let _ = self.fragments.invalidate_entries_if(move |_, meta| {
-			init_time().elapsed() - Duration::from_secs(meta.last_updated.load(Ordering::Relaxed))
-				>= Duration::from_millis(FRAGMENT_TIMEOUT_MS)
+			init_time().elapsed() - Duration::from_secs(meta.last_updated.load(Ordering::Relaxed)) >= timeout
 		});
 	}
 
@@ -146,11 +274,13 @@ impl FragmentReassemblyBuffer {
 				}
 			}
 			let mut buffer = BytesMut::with_capacity(total_size);
+			let mut sizes = Vec::with_capacity(meta.frag_total.into());
 
 			// Combine fragments in order
 			for i in 0..meta.frag_total {
 				if let Some(fragment) = meta.fragments.get(&i).await {
 					buffer.put_slice(&fragment);
+					sizes.push((i, fragment.len()));
 				} else {
 					// Missing fragment, this shouldn't happen if we checked properly
 					return None;
@@ -160,11 +290,25 @@ impl FragmentReassemblyBuffer {
 			// Return the reassembled packet
 			let source = meta.source.load().as_ref().map(|arc| (**arc).clone());
 			let target = (**meta.target.load()).clone();
+			let payload = if self.checksum {
+				match open_checksum(buffer.freeze()) {
+					Ok(payload) => payload,
+					Err(ChecksumMismatch { carried, computed }) => {
+						let (assoc_id, pkt_id) = key;
+						wind_core::warn!(target: "[UDP]", "Checksum mismatch on packet {} of association {:#06x}: carried {:08x?}, computed {:08x}, fragments (id, size) {:?}",
+							pkt_id, assoc_id, carried, computed, sizes);
+						return None;
+					}
+				}
+			} else {
+				buffer.freeze()
+			};
 
 			Some(UdpPacket {
 				source,
 				target,
-				payload: buffer.freeze(),
+				payload,
+				ecn: None,
 			})
 		} else {
 			None
@@ -173,17 +317,31 @@ impl FragmentReassemblyBuffer {
 }
 
 impl UdpStream {
-	pub fn new(connection: quinn::Connection, assoc_id: u16, receive_tx: MAsyncTx<UdpPacket>) -> Self {
+	pub fn new(connection: quinn::Connection, assoc_id: u16, receive_tx: MAsyncTx<UdpPacket>, config: UdpStreamConfig) -> Self {
+		let checksum = negotiated_checksum(&connection);
 		Self {
+			// Peers that don't accept datagrams still take packets on streams
+			over_stream: connection.max_datagram_size().is_none(),
+			stream_window: Arc::new(Semaphore::new(config.stream_window)),
+			window_size: config.stream_window,
+			in_flight: Mutex::default(),
+			pushed: Notify::new(),
+			checksum,
+			max_fragments: config.max_fragments,
 			connection,
 			assoc_id,
 			receive_tx,
 			next_pkt_id: AtomicU16::new(0),
-			fragment_buffer: FragmentReassemblyBuffer::new(),
+			fragment_buffer: FragmentReassemblyBuffer::new(&config).with_checksum(checksum),
+			dropped: AtomicU64::new(0),
+			drop_log: LogLimiter::new(Duration::from_secs(5)),
 		}
 	}
 
-	pub async fn send_packet(&self, packet: UdpPacket) -> eyre::Result<()> {
+	pub async fn send_packet(&self, mut packet: UdpPacket) -> eyre::Result<()> {
+		if self.checksum {
+			packet.payload = seal_checksum(&packet.payload);
+		}
 		let payload_len = packet.payload.len();
 
 		let addr_size = match packet.target {
@@ -201,7 +359,9 @@ impl UdpStream {
 		// Calculate header overhead for single packet sending
 		// Header (2 bytes) + Command (8 bytes) + Address
 		let header_overhead = 10 + addr_size; // If payload fits within the MTU, send as a single packet
-		// TODO handle the case datagram not supported
+		if self.over_stream {
+			return self.send_over_stream(packet).await;
+		}
 		if payload_len <= self.connection.max_datagram_size().unwrap_or(1200) - header_overhead {
 			// Send UDP data with association ID
 			self.connection
@@ -223,6 +383,57 @@ impl UdpStream {
 		self.send_fragmented_packet(packet).await
 	}
 
+	/// Send the whole packet on a stream of its own, whatever its size
+	async fn send_over_stream(&self, packet: UdpPacket) -> eyre::Result<()> {
+		self.reap_acknowledged();
+		let permit = loop {
+			let pushed = self.pushed.notified();
+			let mut pushed = std::pin::pin!(pushed);
+			pushed.as_mut().enable();
+			if let Ok(permit) = self.stream_window.clone().try_acquire_owned() {
+				break permit;
+			}
+			// The window is full, take over the permit of the oldest stream once the peer
+			// acknowledged it
+			let oldest = self.in_flight.lock().unwrap().pop_front();
+			match oldest {
+				Some((send, permit)) => {
+					let _ = send.stopped().await;
+					break permit;
+				}
+				// Every permit is with a send still opening its stream
+				None => pushed.await,
+			}
+		};
+		let pkt_id = self.next_pkt_id.fetch_add(1, Ordering::Relaxed);
+		let head = super::encode_packet_head(self.assoc_id, pkt_id, &packet.target, packet.payload.len())?;
+		let mut send = self.connection.open_uni().await?;
+		send.write_all(&head).await?;
+		send.write_all(&packet.payload).await?;
+		send.finish()?;
+		// The permit comes back once the peer acknowledged all of it, or the stream
+		// or connection is gone
+		self.in_flight.lock().unwrap().push_back((send, permit));
+		self.pushed.notify_waiters();
+		Ok(())
+	}
+
+	/// Give back the permits of the streams acknowledged so far
+	fn reap_acknowledged(&self) {
+		let mut in_flight = self.in_flight.lock().unwrap();
+		let before = in_flight.len();
+		in_flight.retain(|(send, _)| send.stopped().now_or_never().is_none());
+		if in_flight.len() < before {
+			self.pushed.notify_waiters();
+		}
+	}
+
+	/// Packets sent over streams and not yet acknowledged
+	pub fn streams_in_flight(&self) -> usize {
+		self.reap_acknowledged();
+		self.window_size - self.stream_window.available_permits()
+	}
+
 	async fn send_fragmented_packet(&self, packet: UdpPacket) -> eyre::Result<()> {
 		let payload_len = packet.payload.len();
 
@@ -243,7 +454,7 @@ impl UdpStream {
 		let first_frag_max_payload = max_datagram_size.saturating_sub(first_frag_header_overhead);
 		let subsequent_frag_max_payload = max_datagram_size.saturating_sub(subsequent_frag_header_overhead);
 
-		wind_core::info!(target: "[UDP]", "Fragmentation params: payload={}, first_frag_overhead={}, subsequent_frag_overhead={}, max_datagram={}, first_frag_max={}, subsequent_frag_max={}",
+		wind_core::trace!(target: "[UDP]", "Fragmentation params: payload={}, first_frag_overhead={}, subsequent_frag_overhead={}, max_datagram={}, first_frag_max={}, subsequent_frag_max={}",
 			payload_len, first_frag_header_overhead, subsequent_frag_header_overhead, max_datagram_size, first_frag_max_payload, subsequent_frag_max_payload);
 
 		// Calculate number of fragments needed
@@ -256,7 +467,7 @@ impl UdpStream {
 			remaining_payload -= first_frag_max_payload;
 			1 + remaining_payload.div_ceil(subsequent_frag_max_payload)
 		};
-		if fragment_count > MAX_FRAGMENTS as usize {
+		if fragment_count > self.max_fragments as usize {
 			return Err(eyre::eyre!(
 				"Packet too large for fragmentation, exceeds maximum fragment count"
 			));
@@ -316,7 +527,7 @@ impl UdpStream {
 				wind_core::warn!(target: "[UDP]", "Fragment too large: {} bytes > {} bytes max (frag {}/{})", 
 					datagram_size, max_allowed, frag_id + 1, frag_total);
 			} else {
-				wind_core::info!(target: "[UDP]", "Sending fragment {}/{}: {} bytes", frag_id + 1, frag_total, datagram_size);
+				wind_core::trace!(target: "[UDP]", "Sending fragment {}/{}: {} bytes", frag_id + 1, frag_total, datagram_size);
 			}
 
 			// Send using datagram
@@ -359,13 +570,48 @@ impl UdpStream {
 			.await
 	}
 
+	/// Verify and strip the checksum of a packet that came in one piece,
+	/// `None` if it doesn't match
+	pub fn strip_checksum(&self, pkt_id: u16, payload: Bytes) -> Option<Bytes> {
+		if !self.checksum {
+			return Some(payload);
+		}
+		open_checksum(payload)
+			.inspect_err(|ChecksumMismatch { carried, computed }| {
+				wind_core::warn!(target: "[UDP]", "Checksum mismatch on packet {} of association {:#06x}: carried {:08x?}, computed {:08x}, unfragmented",
+					pkt_id, self.assoc_id, carried, computed);
+			})
+			.ok()
+	}
+
 	/// Receive a complete packet from remote server
 	/// This will forward the packet to the local receive channel
-	pub async fn receive_packet(&self, packet: UdpPacket) -> eyre::Result<()> {
-		self.receive_tx
-			.send(packet)
-			.await
-			.map_err(|e| eyre::eyre!("Failed to send packet to receive channel: {:?}", e))
+	///
+	/// Packets of every association share the connection's read loop, so when
+	/// the channel is full the packet is dropped and counted in
+	/// [`Self::dropped`] instead of stalling the other associations.
+	pub fn receive_packet(&self, packet: UdpPacket) -> eyre::Result<()> {
+		match self.receive_tx.try_send(packet) {
+			Ok(()) => Ok(()),
+			Err(TrySendError::Full(_)) => {
+				let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+				if self.drop_log.check().is_some() {
+					wind_core::warn!(target: "[UDP]", "Local side of association {:#06x} is falling behind, {} packets dropped so far", self.assoc_id, dropped);
+				}
+				Ok(())
+			}
+			Err(e) => Err(eyre::eyre!("Failed to send packet to receive channel: {:?}", e)),
+		}
+	}
+
+	/// Number of packets dropped because the receive channel was full
+	pub fn dropped(&self) -> u64 {
+		self.dropped.load(Ordering::Relaxed)
+	}
+
+	/// Tell the server this association is gone
+	pub async fn dissociate(&self) -> eyre::Result<()> {
+		self.connection.drop_udp(self.assoc_id).await
 	}
 
 	pub async fn collect_garbage(&self) {
@@ -440,6 +686,16 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn test_zero_stream_window_refused() {
+		assert!(UdpStreamConfig::default().validate().is_ok());
+		let config = UdpStreamConfig {
+			stream_window: 0,
+			..Default::default()
+		};
+		assert!(config.validate().is_err());
+	}
+
 	/// SPEC.md Section 8.7: Implementation Constraints - Fragment count must
 	/// not exceed 255
 	#[test]
@@ -481,7 +737,7 @@ mod tests {
 	/// Test fragment reassembly buffer
 	#[test_log::test(tokio::test)]
 	async fn test_fragment_reassembly_single_fragment() {
-		let buffer = FragmentReassemblyBuffer::new();
+		let buffer = FragmentReassemblyBuffer::new(&UdpStreamConfig::default());
 		let target = TargetAddr::IPv4(Ipv4Addr::new(127, 0, 0, 1), 8080);
 		let payload = Bytes::from("test payload");
 
@@ -508,7 +764,7 @@ mod tests {
 	/// Test fragment reassembly with multiple fragments
 	#[test_log::test(tokio::test)]
 	async fn test_fragment_reassembly_multiple_fragments() {
-		let buffer = FragmentReassemblyBuffer::new();
+		let buffer = FragmentReassemblyBuffer::new(&UdpStreamConfig::default());
 		let target = TargetAddr::IPv4(Ipv4Addr::new(127, 0, 0, 1), 8080);
 
 		let frag1 = Bytes::from("Hello ");
@@ -553,7 +809,7 @@ mod tests {
 	/// Test fragment reassembly with out-of-order fragments
 	#[test_log::test(tokio::test)]
 	async fn test_fragment_reassembly_out_of_order() {
-		let buffer = FragmentReassemblyBuffer::new();
+		let buffer = FragmentReassemblyBuffer::new(&UdpStreamConfig::default());
 		let target = TargetAddr::IPv4(Ipv4Addr::new(127, 0, 0, 1), 8080);
 
 		let frag0 = Bytes::from("A");
@@ -616,7 +872,7 @@ mod tests {
 	/// Test multiple simultaneous fragmentations
 	#[test_log::test(tokio::test)]
 	async fn test_multiple_simultaneous_fragmentations() {
-		let buffer = FragmentReassemblyBuffer::new();
+		let buffer = FragmentReassemblyBuffer::new(&UdpStreamConfig::default());
 		let target = TargetAddr::IPv4(Ipv4Addr::new(127, 0, 0, 1), 8080);
 
 		// Start two different packets
@@ -685,7 +941,7 @@ mod tests {
 	/// Test fragment cleanup (expired fragments)
 	#[test_log::test(tokio::test)]
 	async fn test_fragment_cleanup() {
-		let buffer = FragmentReassemblyBuffer::new();
+		let buffer = FragmentReassemblyBuffer::new(&UdpStreamConfig::default());
 		let target = TargetAddr::IPv4(Ipv4Addr::new(127, 0, 0, 1), 8080);
 
 		// Add incomplete fragment
@@ -714,6 +970,84 @@ mod tests {
 		assert_eq!(buffer.fragments.entry_count(), 0, "Fragments should be cleaned up");
 	}
 
+	#[test]
+	fn test_crc32() {
+		assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+		assert_eq!(crc32(b""), 0);
+	}
+
+	/// A corrupted fragment fails the checksum of the reassembled packet
+	#[test_log::test(tokio::test)]
+	async fn test_corrupted_fragment_detected() {
+		let buffer = FragmentReassemblyBuffer::new(&UdpStreamConfig::default()).with_checksum(true);
+		let target = TargetAddr::IPv4(Ipv4Addr::new(127, 0, 0, 1), 8080);
+		let payload = Bytes::from((0..3000u32).map(|i| i as u8).collect::<Vec<_>>());
+		let sealed = seal_checksum(&payload);
+
+		for (pkt_id, corrupt) in [(500, true), (501, false)] {
+			let mut result = None;
+			for (frag_id, chunk) in sealed.chunks(1100).enumerate() {
+				let mut chunk = chunk.to_vec();
+				if corrupt && frag_id == 1 {
+					chunk[42] ^= 0x01;
+				}
+				result = buffer
+					.add_fragment(
+						FragmentInfo {
+							assoc_id: 1,
+							pkt_id,
+							frag_total: 3,
+							frag_id: frag_id as u8,
+							source: None,
+							target: target.clone(),
+						},
+						Bytes::from(chunk),
+					)
+					.await;
+			}
+			if corrupt {
+				assert!(result.is_none(), "Corrupted packet should be dropped");
+			} else {
+				assert_eq!(result.unwrap().payload, payload);
+			}
+		}
+	}
+
+	/// A full reassembly buffer evicts packets instead of growing
+	#[test_log::test(tokio::test)]
+	async fn test_reassembly_capacity_evicts() {
+		let buffer = FragmentReassemblyBuffer::new(&UdpStreamConfig {
+			reassembly_capacity: 2,
+			..Default::default()
+		});
+		let target = TargetAddr::IPv4(Ipv4Addr::new(127, 0, 0, 1), 8080);
+
+		// Only the first of two fragments ever arrives
+		for pkt_id in 600..610 {
+			let result = buffer
+				.add_fragment(
+					FragmentInfo {
+						assoc_id: 1,
+						pkt_id,
+						frag_total: 2,
+						frag_id: 0,
+						source: None,
+						target: target.clone(),
+					},
+					Bytes::from("partial"),
+				)
+				.await;
+			assert!(result.is_none());
+		}
+
+		buffer.fragments.run_pending_tasks().await;
+		assert!(
+			buffer.fragments.entry_count() <= 2,
+			"Reassembly buffer should stay within its capacity, holds {}",
+			buffer.fragments.entry_count()
+		);
+	}
+
 	/// Verify saturating_sub prevents underflow as mentioned in SPEC.md Section
 	/// 8.7
 	#[test]
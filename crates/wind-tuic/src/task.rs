@@ -3,10 +3,13 @@ use std::{sync::Arc, time::Duration};
 use bytes::Bytes;
 use crossfire::AsyncRx;
 use quinn::{RecvStream, SendStream};
-use tokio_util::sync::CancellationToken;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use wind_core::{AppContext, info};
 
-use crate::Error;
+use crate::{
+	Error,
+	datagram::{DatagramDrops, DatagramReader},
+};
 
 /// Size of the single-producer single-consumer buffer for QUIC streams
 /// This controls how many elements can be buffered in the channel
@@ -19,6 +22,7 @@ type IncomingRx = (AsyncRx<Bytes>, AsyncRx<(SendStream, RecvStream)>, AsyncRx<Re
 /// and forwards them to a channel
 async fn spawn_handler<T, F, Fut>(
 	ctx: Arc<AppContext>,
+	tasks: TaskTracker,
 	connection: quinn::Connection,
 	cancel_token: CancellationToken,
 	accept_fn: F,
@@ -31,12 +35,18 @@ where
 {
 	let (tx, rx) = crossfire::spsc::bounded_async(SPSC_BUFFER_SIZE);
 
-	ctx.tasks.spawn(async move {
+	let task = tasks.token();
+	ctx.spawn("tuic-incoming", async move {
+		let _task = task;
 		loop {
 			tokio::select! {
 				res = accept_fn(connection.clone()) => {
 					let item = match res {
-						Err(e) => unimplemented!("unhandled error {e:?}"),
+						// The connection is gone, `start_poll` reports why
+						Err(e) => {
+							info!("Stopped accepting {}: {}", name, e);
+							break;
+						}
 						Ok(item) => item,
 					};
 					
@@ -57,17 +67,34 @@ where
 }
 
 pub trait ClientTaskExt {
-	async fn handle_incoming(&self, ctx: Arc<AppContext>, cancel_token: CancellationToken) -> Result<IncomingRx, Error>;
+	async fn handle_incoming(
+		&self,
+		ctx: Arc<AppContext>,
+		tasks: TaskTracker,
+		cancel_token: CancellationToken,
+		datagram_drops: Arc<DatagramDrops>,
+	) -> Result<IncomingRx, Error>;
 }
 
 impl ClientTaskExt for quinn::Connection {
-	async fn handle_incoming(&self, ctx: Arc<AppContext>, cancel_token: CancellationToken) -> Result<IncomingRx, Error> {
+	async fn handle_incoming(
+		&self,
+		ctx: Arc<AppContext>,
+		tasks: TaskTracker,
+		cancel_token: CancellationToken,
+		datagram_drops: Arc<DatagramDrops>,
+	) -> Result<IncomingRx, Error> {
 		// Spawn task for handling datagrams
+		let reader = Arc::new(DatagramReader::new(self.clone(), datagram_drops));
 		let datagram_rx = spawn_handler(
 			ctx.clone(),
+			tasks.clone(),
 			self.clone(),
 			cancel_token.clone(),
-			|conn| async move { conn.read_datagram().await },
+			move |_| {
+				let reader = reader.clone();
+				async move { reader.read().await }
+			},
 			"datagram",
 		)
 		.await;
@@ -75,6 +102,7 @@ impl ClientTaskExt for quinn::Connection {
 		// Spawn task for handling bidirectional streams
 		let bi_rx = spawn_handler(
 			ctx.clone(),
+			tasks.clone(),
 			self.clone(),
 			cancel_token.clone(),
 			|conn| async move { conn.accept_bi().await },
@@ -85,6 +113,7 @@ impl ClientTaskExt for quinn::Connection {
 		// Spawn task for handling unidirectional streams
 		let uni_rx = spawn_handler(
 			ctx.clone(),
+			tasks.clone(),
 			self.clone(),
 			cancel_token,
 			|conn| async move { conn.accept_uni().await },
@@ -1,47 +1,224 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
+use eyre::Context as _;
 use rustls::{
+	ClientConfig, SupportedProtocolVersion,
 	crypto::CryptoProvider,
-	pki_types::{CertificateDer, ServerName, UnixTime},
+	pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+	server::{ClientHello, ResolvesServerCert},
+	sign::CertifiedKey,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsConnector, client::TlsStream};
+use wind_core::{
+	AbstractOutbound, debug,
+	tcp::{AbstractTcpStream, ConnectError},
+	types::TargetAddr,
+	udp::AbstractUdpSocket,
 };
 
 use crate::{Error, outbound::TuicOutboundOpts};
 
+/// The process-wide rustls crypto provider, installing the one this crate is
+/// built with when there is none yet
+///
+/// Can be called any number of times, also alongside code installing a
+/// provider of its own: whichever got installed first is used.
+pub fn ensure_crypto_provider() -> Result<Arc<CryptoProvider>, Error> {
+	if let Some(provider) = CryptoProvider::get_default() {
+		return Ok(provider.clone());
+	}
+	#[cfg(feature = "aws-lc-rs")]
+	let provider = Some(rustls::crypto::aws_lc_rs::default_provider());
+	#[cfg(all(feature = "ring", not(feature = "aws-lc-rs")))]
+	let provider = Some(rustls::crypto::ring::default_provider());
+	#[cfg(not(any(feature = "aws-lc-rs", feature = "ring")))]
+	let provider: Option<CryptoProvider> = None;
+	if let Some(provider) = provider {
+		// Only fails when another thread installed one first, which is used instead
+		let _ = provider.install_default();
+	}
+	CryptoProvider::get_default().cloned().ok_or_else(|| {
+		eyre::eyre!("No rustls crypto provider installed, enable the `aws-lc-rs` or `ring` feature or install one at startup")
+	})
+}
+
 #[allow(clippy::result_large_err)]
 pub(crate) fn tls_config(_servername: &str, opts: &TuicOutboundOpts) -> Result<rustls::ClientConfig, Error> {
-	use rustls::ClientConfig;
+	let mut config = client_config(opts.skip_cert_verify, &[&rustls::version::TLS13])?;
+	config.alpn_protocols = opts.alpn.iter().map(|alpn| alpn.as_bytes().to_vec()).collect();
+	if opts.udp_checksum {
+		config
+			.alpn_protocols
+			.insert(0, crate::proto::CHECKSUM_ALPN.as_bytes().to_vec());
+	}
+
+	Ok(config)
+}
+
+/// Client config verifying the server with the platform verifier, or not at
+/// all with `skip_cert_verify`
+#[allow(clippy::result_large_err)]
+pub(crate) fn client_config(
+	skip_cert_verify: bool,
+	versions: &[&'static SupportedProtocolVersion],
+) -> Result<ClientConfig, Error> {
 	use rustls_platform_verifier::BuilderVerifierExt;
 
-	let arc_crypto_provider = CryptoProvider::get_default().expect("Unable to find default crypto provider");
-	let mut config = if opts.skip_cert_verify {
-		ClientConfig::builder()
+	let provider = ensure_crypto_provider()?;
+	let config = if skip_cert_verify {
+		ClientConfig::builder_with_provider(provider.clone())
+			.with_protocol_versions(versions)?
 			.dangerous()
-			.with_custom_certificate_verifier(SkipServerVerification::new())
+			.with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
 			.with_no_client_auth()
 	} else {
-		ClientConfig::builder_with_provider(arc_crypto_provider.clone())
-			.with_protocol_versions(&[&rustls::version::TLS13])?
+		ClientConfig::builder_with_provider(provider)
+			.with_protocol_versions(versions)?
 			.with_platform_verifier()?
 			.with_no_client_auth()
 	};
-	config.alpn_protocols = vec![String::from("h3")].into_iter().map(|alpn| alpn.into_bytes()).collect();
-
 	Ok(config)
 }
 
+/// Certificate a server presents to clients asking for a server name that
+/// matches `pattern`
+pub struct SniCertificate {
+	/// A server name, or `*.` followed by a domain for any name under it
+	pub pattern:     String,
+	pub certificate: Vec<CertificateDer<'static>>,
+	pub private_key: PrivateKeyDer<'static>,
+}
+
+/// Picks the certificate of the first [`SniCertificate`] matching the SNI of
+/// the ClientHello, or the default one for clients sending no SNI or one
+/// nothing matches
 #[derive(Debug)]
-struct SkipServerVerification(Arc<rustls::crypto::CryptoProvider>);
+pub struct SniCertResolver {
+	/// Lowercased patterns, in the configured order
+	by_sni:  Vec<(String, Arc<CertifiedKey>)>,
+	default: Arc<CertifiedKey>,
+}
+
+impl SniCertResolver {
+	pub fn new(
+		provider: &CryptoProvider,
+		default: (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>),
+		by_sni: &[SniCertificate],
+	) -> Result<Self, Error> {
+		let by_sni = by_sni
+			.iter()
+			.map(|entry| {
+				let key = CertifiedKey::from_der(entry.certificate.clone(), entry.private_key.clone_key(), provider)
+					.wrap_err_with(|| format!("Invalid TLS certificate for {}", entry.pattern))?;
+				Ok((entry.pattern.to_ascii_lowercase(), Arc::new(key)))
+			})
+			.collect::<Result<_, Error>>()?;
+		let default = CertifiedKey::from_der(default.0, default.1, provider).wrap_err("Invalid default TLS certificate")?;
+		Ok(Self {
+			by_sni,
+			default: Arc::new(default),
+		})
+	}
+
+	fn matches(pattern: &str, server_name: &str) -> bool {
+		match pattern.strip_prefix('*') {
+			Some(suffix) => server_name
+				.strip_suffix(suffix)
+				.is_some_and(|sub| !sub.is_empty() && suffix.starts_with('.')),
+			None => pattern == server_name,
+		}
+	}
+}
+
+impl ResolvesServerCert for SniCertResolver {
+	fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+		let Some(server_name) = client_hello.server_name().map(str::to_ascii_lowercase) else {
+			return Some(self.default.clone());
+		};
+		let key = self
+			.by_sni
+			.iter()
+			.find(|(pattern, _)| Self::matches(pattern, &server_name))
+			.map_or(&self.default, |(_, key)| key);
+		Some(key.clone())
+	}
+}
+
+pub struct TlsOutboundOpts {
+	/// TLS endpoint every connection is relayed to
+	pub peer_addr:        SocketAddr,
+	/// Sent in the ClientHello and verified against the certificate, so it may
+	/// differ from the host actually dialed
+	pub sni:              String,
+	pub alpn:             Vec<String>,
+	pub skip_cert_verify: bool,
+}
+
+/// Relays each connection as raw bytes over a fresh TLS session to a fixed
+/// peer, whatever its target
+///
+/// Also the transport for protocols framing their own requests inside TLS,
+/// through [`connect`](Self::connect).
+pub struct TlsOutbound {
+	peer_addr:   SocketAddr,
+	server_name: ServerName<'static>,
+	connector:   TlsConnector,
+}
+
+impl TlsOutbound {
+	pub fn new(opts: TlsOutboundOpts) -> eyre::Result<Self> {
+		let mut config = client_config(opts.skip_cert_verify, rustls::DEFAULT_VERSIONS)?;
+		config.alpn_protocols = opts.alpn.iter().map(|alpn| alpn.as_bytes().to_vec()).collect();
+		Ok(Self {
+			peer_addr:   opts.peer_addr,
+			server_name: ServerName::try_from(opts.sni)?,
+			connector:   TlsConnector::from(Arc::new(config)),
+		})
+	}
 
-impl SkipServerVerification {
-	fn new() -> Arc<Self> {
-		Arc::new(Self(
-			rustls::crypto::CryptoProvider::get_default()
-				.expect("CryptoProvider not found")
-				.clone(),
-		))
+	/// Open a TLS session to the peer
+	pub async fn connect(&self) -> eyre::Result<TlsStream<TcpStream>> {
+		let tcp = TcpStream::connect(self.peer_addr).await?;
+		Ok(self.connector.connect(self.server_name.clone(), tcp).await?)
 	}
 }
 
+impl AbstractOutbound for TlsOutbound {
+	async fn handle_tcp(
+		&self,
+		target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		debug!(target: "[OUT] TLS", "relaying {target_addr} through {}", self.peer_addr);
+		let mut tls = match self.connect().await {
+			Ok(tls) => tls,
+			Err(err) => {
+				stream.on_connect(Err(ConnectError::from_report(&err))).await?;
+				return Err(err);
+			}
+		};
+		stream.on_connect(Ok(())).await?;
+		let (_, _, err) = wind_core::io::copy_io(&mut stream, &mut tls).await;
+		if let Some(err) = err {
+			return Err(err.into());
+		}
+		Ok(())
+	}
+
+	async fn handle_udp(
+		&self,
+		_socket: impl AbstractUdpSocket + 'static,
+		_via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		eyre::bail!("TLS outbound does not relay UDP")
+	}
+}
+
+#[derive(Debug)]
+struct SkipServerVerification(Arc<rustls::crypto::CryptoProvider>);
+
 impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
 	fn verify_server_cert(
 		&self,
@@ -0,0 +1,97 @@
+//! TUIC users kept in a file of `uuid:password` lines
+//!
+//! Blank lines and lines starting with `#` are skipped. The password is
+//! everything after the first `:`, so it may contain colons itself.
+
+use std::{collections::HashMap, path::Path};
+
+use eyre::{Context as _, bail};
+use uuid::Uuid;
+use wind_core::types::TargetAddr;
+
+use crate::acl::UserAcl;
+
+/// The users connections authenticate as, along with what each may reach
+#[derive(Debug, Default)]
+pub struct UserTable {
+	/// UUID -> password
+	pub passwords: HashMap<Uuid, String>,
+	pub acls:      HashMap<Uuid, UserAcl>,
+}
+
+impl UserTable {
+	pub fn len(&self) -> usize {
+		self.passwords.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.passwords.is_empty()
+	}
+
+	pub fn password(&self, uuid: &Uuid) -> Option<&str> {
+		self.passwords.get(uuid).map(String::as_str)
+	}
+
+	/// Whether `uuid` may reach `target_addr`, users without an ACL may reach
+	/// any target
+	pub fn permits(&self, uuid: &Uuid, target_addr: &TargetAddr) -> bool {
+		self.acls.get(uuid).is_none_or(|acl| acl.permits(target_addr))
+	}
+}
+
+/// Parse the contents of a users file, naming the offending line on errors
+pub fn parse_users(content: &str) -> eyre::Result<HashMap<Uuid, String>> {
+	let mut users = HashMap::new();
+	for (index, line) in content.lines().enumerate() {
+		let line_no = index + 1;
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let Some((uuid, password)) = line.split_once(':') else {
+			bail!("line {line_no}: expected `uuid:password`");
+		};
+		let uuid = Uuid::parse_str(uuid.trim()).with_context(|| format!("line {line_no}: invalid UUID {:?}", uuid.trim()))?;
+		if password.is_empty() {
+			bail!("line {line_no}: empty password for {uuid}");
+		}
+		if users.insert(uuid, password.to_owned()).is_some() {
+			bail!("line {line_no}: duplicate user {uuid}");
+		}
+	}
+	Ok(users)
+}
+
+/// Read and parse the users file at `path`
+pub fn load_users(path: &Path) -> eyre::Result<HashMap<Uuid, String>> {
+	let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read users file {}", path.display()))?;
+	parse_users(&content).with_context(|| format!("Invalid users file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_users() {
+		let users = parse_users(
+			"# staff\n\n00000000-0000-0000-0000-000000000001:first\n  00000000-0000-0000-0000-000000000002:with:colon\n",
+		)
+		.unwrap();
+		assert_eq!(users.len(), 2);
+		assert_eq!(users[&Uuid::from_u128(1)], "first");
+		assert_eq!(users[&Uuid::from_u128(2)], "with:colon");
+	}
+
+	#[test]
+	fn test_parse_users_reports_line() {
+		let err = parse_users("00000000-0000-0000-0000-000000000001:first\nnot-a-uuid:second\n").unwrap_err();
+		assert!(format!("{err:#}").starts_with("line 2: invalid UUID"), "{err:#}");
+
+		let err = parse_users("\n\n00000000-0000-0000-0000-000000000001\n").unwrap_err();
+		assert_eq!(err.to_string(), "line 3: expected `uuid:password`");
+
+		let err = parse_users("00000000-0000-0000-0000-000000000001:a\n00000000-0000-0000-0000-000000000001:b\n").unwrap_err();
+		assert!(err.to_string().starts_with("line 2: duplicate user"), "{err}");
+	}
+}
@@ -2,21 +2,47 @@
 //!
 //! Tests TCP and UDP proxying through TUIC server and client
 
-use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+	collections::HashMap,
+	io::IoSliceMut,
+	net::{Ipv4Addr, SocketAddr},
+	pin::Pin,
+	sync::{
+		Arc,
+		atomic::{AtomicUsize, Ordering},
+	},
+	task::{Context, Poll},
+	time::Duration,
+};
 
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
 use tokio::{
 	io::{AsyncReadExt, AsyncWriteExt},
-	net::{TcpListener, TcpStream, UdpSocket},
+	net::{TcpListener, UdpSocket},
 	time::timeout,
 };
+use tokio_util::{
+	codec::{Decoder as _, Encoder as _},
+	sync::CancellationToken,
+};
 use uuid::Uuid;
 use wind_core::{
-	AbstractInbound, AppContext, InboundCallback, tcp::AbstractTcpStream, types::TargetAddr, udp::AbstractUdpSocket,
+	AbstractInbound, AbstractOutbound, AppContext, InboundCallback,
+	tcp::{AbstractTcpStream, ConnectError},
+	test_util::DirectCallback,
+	types::TargetAddr,
+	udp::{AbstractUdpSocket, EcnCodepoint, RecvMeta, Transmit, UdpPacket, UdpPoller},
 };
 use wind_tuic::{
+	acl::{DestinationRule, UserAcl},
+	datagram::{DATAGRAM_RECEIVE_BUFFER, DatagramDrops, DatagramReader},
 	inbound::{TuicInbound, TuicInboundOpts},
-	outbound::{TuicOutbound, TuicOutboundOpts},
+	outbound::{HandshakeError, INITIAL_WINDOW, TuicOutbound, TuicOutboundOpts, UDP_RECEIVE_QUEUE},
+	proto::{
+		AUTH_OPEN_TIMEOUT, Address, AddressCodec, ClientProtoExt, CloseReason, CmdCodec, CmdType, Command, Header, HeaderCodec,
+		HeartbeatMode, UdpStream, UdpStreamConfig, decode_command, decode_header,
+	},
+	tls::{TlsOutbound, TlsOutboundOpts, ensure_crypto_provider},
 };
 
 /// Generate a self-signed certificate for testing
@@ -28,30 +54,101 @@ fn generate_self_signed_cert() -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'
 	(vec![cert_der], PrivateKeyDer::Pkcs8(key_der))
 }
 
-/// Simple callback that forwards TCP connections directly to target
-#[derive(Clone)]
-struct DirectCallback;
-
-impl InboundCallback for DirectCallback {
-	async fn handle_tcpstream(&self, target_addr: TargetAddr, mut client_stream: impl AbstractTcpStream) -> eyre::Result<()> {
-		// Connect to the actual target
-		let target_socket_addr = match target_addr {
-			TargetAddr::IPv4(ip, port) => SocketAddr::new(std::net::IpAddr::V4(ip), port),
-			TargetAddr::IPv6(ip, port) => SocketAddr::new(std::net::IpAddr::V6(ip), port),
-			TargetAddr::Domain(domain, port) => format!("{}:{}", domain, port).parse()?,
-		};
+/// A QUIC endpoint speaking no TUIC, for driving the client by hand
+fn bare_server(transport: quinn::TransportConfig) -> eyre::Result<quinn::Endpoint> {
+	let (cert, key) = generate_self_signed_cert();
+	let mut crypto = rustls::ServerConfig::builder_with_protocol_versions(&[&rustls::version::TLS13])
+		.with_no_client_auth()
+		.with_single_cert(cert, key)?;
+	crypto.alpn_protocols = vec![b"h3".to_vec()];
+	let mut server_config =
+		quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto)?));
+	server_config.transport_config(Arc::new(transport));
+	Ok(quinn::Endpoint::server(server_config, "127.0.0.1:0".parse()?)?)
+}
+
+/// Local socket that takes a while to accept each reply, its association
+/// ends with `token`
+struct ThrottledSocket {
+	inner:     Arc<wind_core::udp::TokioUdpSocket>,
+	delivered: Arc<AtomicUsize>,
+	token:     CancellationToken,
+}
 
-		let mut target_stream = TcpStream::connect(target_socket_addr).await?;
+impl ThrottledSocket {
+	fn bind() -> eyre::Result<Self> {
+		let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+		Ok(Self {
+			inner:     Arc::new(wind_core::udp::TokioUdpSocket::new(socket)?),
+			delivered: Arc::default(),
+			token:     CancellationToken::new(),
+		})
+	}
+}
 
-		tokio::io::copy_bidirectional(&mut client_stream, &mut target_stream).await?;
+impl AbstractUdpSocket for ThrottledSocket {
+	fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+		self.inner.clone().create_io_poller()
+	}
 
-		Ok(())
+	fn try_send(&self, transmit: &Transmit) -> std::io::Result<()> {
+		self.inner.try_send(transmit)
 	}
 
-	async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
-		// UDP handling - forward packets to actual targets
-		// TODO: Implement UDP relay
-		Ok(())
+	fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<std::io::Result<usize>> {
+		self.inner.poll_recv(cx, bufs, meta)
+	}
+
+	fn local_addr(&self) -> std::io::Result<SocketAddr> {
+		self.inner.local_addr()
+	}
+
+	fn association_token(&self) -> CancellationToken {
+		self.token.clone()
+	}
+
+	async fn send_ecn(&self, buf: &[u8], _target: SocketAddr, _ecn: Option<EcnCodepoint>) -> std::io::Result<usize> {
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		self.delivered.fetch_add(1, Ordering::Relaxed);
+		Ok(buf.len())
+	}
+}
+
+/// Local socket relaying whatever an application sends it to `target`, and
+/// the replies back to the application
+struct ForwardSocket {
+	inner:  Arc<wind_core::udp::TokioUdpSocket>,
+	target: TargetAddr,
+	app:    std::sync::Mutex<Option<SocketAddr>>,
+}
+
+impl AbstractUdpSocket for ForwardSocket {
+	fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+		self.inner.clone().create_io_poller()
+	}
+
+	fn try_send(&self, transmit: &Transmit) -> std::io::Result<()> {
+		let app = self.app.lock().unwrap().ok_or(std::io::ErrorKind::NotConnected)?;
+		self.inner.try_send(&Transmit {
+			destination:  app,
+			ecn:          transmit.ecn,
+			contents:     transmit.contents,
+			segment_size: None,
+			src_ip:       None,
+		})
+	}
+
+	fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<std::io::Result<usize>> {
+		let received = std::task::ready!(self.inner.poll_recv(cx, bufs, meta))?;
+		for meta in &mut meta[..received] {
+			*self.app.lock().unwrap() = Some(meta.addr);
+			meta.destination = Some(self.target.clone());
+		}
+		Poll::Ready(Ok(received))
+	}
+
+	fn local_addr(&self) -> std::io::Result<SocketAddr> {
+		self.inner.local_addr()
 	}
 }
 
@@ -60,10 +157,7 @@ async fn test_tuic_tcp_proxy() -> eyre::Result<()> {
 	tracing::info!("\n========== TUIC TCP Proxy Test ==========");
 
 	// Initialize crypto provider
-	#[cfg(feature = "aws-lc-rs")]
-	let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
-	#[cfg(feature = "ring")]
-	let _ = rustls::crypto::ring::default_provider().install_default();
+	ensure_crypto_provider()?;
 
 	// Setup test echo server
 	let echo_server = TcpListener::bind("127.0.0.1:0").await?;
@@ -131,15 +225,31 @@ async fn test_tuic_tcp_proxy() -> eyre::Result<()> {
 
 	// Setup TUIC client (outbound)
 	let client_opts = TuicOutboundOpts {
-		peer_addr:          actual_server_addr,
-		sni:                "localhost".to_string(),
-		auth:               (user_uuid, Arc::from(password.as_bytes())),
-		zero_rtt_handshake: false,
-		heartbeat:          Duration::from_secs(3),
-		gc_interval:        Duration::from_secs(3),
-		gc_lifetime:        Duration::from_secs(15),
-		skip_cert_verify:   true,
-		alpn:               vec!["h3".to_string()],
+		peer_addr:               actual_server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (user_uuid, Arc::from(password.as_bytes())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
 	};
 
 	tracing::info!("✓ Connecting TUIC client to server...");
@@ -180,10 +290,7 @@ async fn test_tuic_udp_proxy() -> eyre::Result<()> {
 	tracing::info!("\n========== TUIC UDP Proxy Test ==========");
 
 	// Initialize crypto provider
-	#[cfg(feature = "aws-lc-rs")]
-	let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
-	#[cfg(feature = "ring")]
-	let _ = rustls::crypto::ring::default_provider().install_default();
+	ensure_crypto_provider()?;
 
 	// Setup test UDP echo server
 	let echo_socket = UdpSocket::bind("127.0.0.1:0").await?;
@@ -251,15 +358,31 @@ async fn test_tuic_udp_proxy() -> eyre::Result<()> {
 	// Setup TUIC client (outbound)
 	let ctx = Arc::new(AppContext::default());
 	let client_opts = TuicOutboundOpts {
-		peer_addr:          actual_server_addr,
-		sni:                "localhost".to_string(),
-		auth:               (user_uuid, Arc::from(password.as_bytes())),
-		zero_rtt_handshake: false,
-		heartbeat:          Duration::from_secs(3),
-		gc_interval:        Duration::from_secs(3),
-		gc_lifetime:        Duration::from_secs(15),
-		skip_cert_verify:   true,
-		alpn:               vec!["h3".to_string()],
+		peer_addr:               actual_server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (user_uuid, Arc::from(password.as_bytes())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
 	};
 
 	tracing::info!("✓ Connecting TUIC client to server...");
@@ -277,17 +400,25 @@ async fn test_tuic_udp_proxy() -> eyre::Result<()> {
 
 	// Test UDP proxy through TUIC
 	tracing::info!("\n--- Testing UDP Proxy ---");
-	tracing::info!("⚠ Note: UDP proxy test is currently TODO - needs full UDP session implementation");
-
-	// TODO: Implement full UDP test once UDP session management is complete
-	// For now, we'll skip the detailed UDP test
-	//
-	// The following would be needed:
-	// 1. Create a UDP socket wrapper that works with TUIC
-	// 2. Send packets through the TUIC tunnel
-	// 3. Receive and verify echoed packets
-
-	tracing::info!("✓ UDP test skipped (not yet fully implemented)");
+	let socket = ForwardSocket {
+		inner:  Arc::new(wind_core::udp::TokioUdpSocket::new(std::net::UdpSocket::bind(
+			"127.0.0.1:0",
+		)?)?),
+		target: echo_addr.into(),
+		app:    std::sync::Mutex::new(None),
+	};
+	let relay_addr = socket.local_addr()?;
+	let client_udp = client.clone();
+	tokio::spawn(async move { client_udp.handle_udp(socket, None::<TuicOutbound>).await });
+
+	let app = UdpSocket::bind("127.0.0.1:0").await?;
+	let mut buf = [0u8; 64];
+	for message in [b"first".as_slice(), b"second"] {
+		app.send_to(message, relay_addr).await?;
+		let len = timeout(Duration::from_secs(5), app.recv(&mut buf)).await??;
+		assert_eq!(&buf[..len], message);
+	}
+	tracing::info!("✓ UDP packets echoed through the TUIC server");
 
 	// Clean up
 	server_cancel.cancel();
@@ -297,15 +428,220 @@ async fn test_tuic_udp_proxy() -> eyre::Result<()> {
 	Ok(())
 }
 
+#[test_log::test(tokio::test)]
+async fn test_tuic_udp_reply_keeps_own_ecn() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	// Echoes without marking its replies
+	let echo_socket = UdpSocket::bind("127.0.0.1:0").await?;
+	let echo_addr = echo_socket.local_addr()?;
+	tokio::spawn(async move {
+		let mut buf = vec![0u8; 65536];
+		while let Ok((n, peer)) = echo_socket.recv_from(&mut buf).await {
+			let _ = echo_socket.send_to(&buf[..n], peer).await;
+		}
+	});
+
+	let (cert, key) = generate_self_signed_cert();
+	let user_uuid = Uuid::new_v4();
+	let password = "test_password";
+	let server_addr = std::net::UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+	let server = TuicInbound::new(
+		Arc::new(AppContext::default()),
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users: HashMap::from([(user_uuid, password.to_string())]),
+			..Default::default()
+		},
+	);
+	tokio::spawn(async move { server.listen(&DirectCallback).await });
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let client = Arc::new(
+		TuicOutbound::new(
+			Arc::new(AppContext::default()),
+			TuicOutboundOpts {
+				peer_addr:               server_addr,
+				sni:                     "localhost".to_string(),
+				auth:                    (user_uuid, Arc::from(password.as_bytes())),
+				zero_rtt_handshake:      false,
+				heartbeat:               Duration::from_secs(3),
+				gc_interval:             Duration::from_secs(3),
+				gc_lifetime:             Duration::from_secs(15),
+				skip_cert_verify:        true,
+				alpn:                    vec!["h3".to_string()],
+				ecn:                     true,
+				udp_idle_timeout:        Duration::from_secs(60),
+				udp_keepalive:           None,
+				udp_recv_buffer:         4096,
+				udp_send_queue_bytes:    1 << 20,
+				max_connection_lifetime: None,
+				udp_checksum:            false,
+				udp_stream:              UdpStreamConfig::default(),
+				datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+				initial_mtu:             1200,
+				min_mtu:                 1200,
+				mtu_discovery:           true,
+				gso:                     true,
+				local_port_range:        None,
+				initial_window:          INITIAL_WINDOW,
+				connect_verdict:         Duration::ZERO,
+			},
+		)
+		.await?,
+	);
+	let client_poll = client.clone();
+	tokio::spawn(async move { client_poll.start_poll().await });
+	let socket = ForwardSocket {
+		inner:  Arc::new(wind_core::udp::TokioUdpSocket::new(std::net::UdpSocket::bind(
+			"127.0.0.1:0",
+		)?)?),
+		target: echo_addr.into(),
+		app:    std::sync::Mutex::new(None),
+	};
+	let relay_addr = socket.local_addr()?;
+	tokio::spawn(async move { client.handle_udp(socket, None::<TuicOutbound>).await });
+
+	// The application marks what it sends, the echo marks nothing
+	let app = Arc::new(wind_core::udp::TokioUdpSocket::new(std::net::UdpSocket::bind(
+		"127.0.0.1:0",
+	)?)?);
+	let mut poller = app.clone().create_io_poller();
+	std::future::poll_fn(|cx| poller.as_mut().poll_writable(cx)).await?;
+	app.try_send(&Transmit {
+		destination:  relay_addr,
+		ecn:          Some(EcnCodepoint::Ect0),
+		contents:     b"ping",
+		segment_size: None,
+		src_ip:       None,
+	})?;
+	let mut buf = [0u8; 64];
+	let mut meta = [RecvMeta::default()];
+	timeout(Duration::from_secs(5), app.recv(&mut [IoSliceMut::new(&mut buf)], &mut meta)).await??;
+	assert_eq!(&buf[..meta[0].len], b"ping");
+	// The reply is not dressed in the application's marking
+	assert_eq!(meta[0].ecn, None);
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_user_acl() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	// Echo servers counting what reaches them
+	let mut echoes = Vec::new();
+	for _ in 0..2 {
+		let echo_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+		let received = Arc::new(AtomicUsize::new(0));
+		echoes.push((echo_socket.local_addr()?, received.clone()));
+		tokio::spawn(async move {
+			let mut buf = vec![0u8; 65536];
+			while let Ok((n, peer)) = echo_socket.recv_from(&mut buf).await {
+				received.fetch_add(1, Ordering::Relaxed);
+				let _ = echo_socket.send_to(&buf[..n], peer).await;
+			}
+		});
+	}
+	let [(allowed_addr, allowed_received), (blocked_addr, blocked_received)] = <[_; 2]>::try_from(echoes).unwrap();
+
+	let (cert, key) = generate_self_signed_cert();
+	let user_uuid = Uuid::new_v4();
+	let password = "restricted_password";
+	let server_addr = std::net::UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+	let server_ctx = Arc::new(AppContext::default());
+	let server = TuicInbound::new(
+		server_ctx.clone(),
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			users: HashMap::from([(user_uuid, password.to_string())]),
+			acls: HashMap::from([(
+				user_uuid,
+				UserAcl {
+					allow: vec![DestinationRule {
+						ports: vec![allowed_addr.port()..=allowed_addr.port()],
+						..Default::default()
+					}],
+					deny:  vec![],
+				},
+			)]),
+			..Default::default()
+		},
+	);
+	tokio::spawn(async move { server.listen(&DirectCallback).await });
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let ctx = Arc::new(AppContext::default());
+	let client_opts = TuicOutboundOpts {
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (user_uuid, Arc::from(password.as_bytes())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
+	};
+	let client = Arc::new(TuicOutbound::new(ctx.clone(), client_opts).await?);
+	let client_poll = client.clone();
+	tokio::spawn(async move { client_poll.start_poll().await });
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let mut relays = Vec::new();
+	for target in [allowed_addr, blocked_addr] {
+		let local = std::net::UdpSocket::bind("127.0.0.1:0")?;
+		let socket = ForwardSocket {
+			inner:  Arc::new(wind_core::udp::TokioUdpSocket::new(local)?),
+			target: target.into(),
+			app:    std::sync::Mutex::new(None),
+		};
+		relays.push(socket.local_addr()?);
+		let client = client.clone();
+		tokio::spawn(async move { client.handle_udp(socket, None::<TuicOutbound>).await });
+	}
+
+	let app = UdpSocket::bind("127.0.0.1:0").await?;
+	let mut buf = [0u8; 64];
+	app.send_to(b"allowed", relays[0]).await?;
+	let len = timeout(Duration::from_secs(5), app.recv(&mut buf)).await??;
+	assert_eq!(&buf[..len], b"allowed");
+
+	app.send_to(b"blocked", relays[1]).await?;
+	assert!(timeout(Duration::from_millis(500), app.recv(&mut buf)).await.is_err());
+	assert_eq!(allowed_received.load(Ordering::Relaxed), 1);
+	assert_eq!(blocked_received.load(Ordering::Relaxed), 0);
+
+	server_ctx.token.cancel();
+	Ok(())
+}
+
 #[test_log::test(tokio::test)]
 async fn test_tuic_connection_and_auth() -> eyre::Result<()> {
 	tracing::info!("\n========== TUIC Connection & Authentication Test ==========");
 
 	// Initialize crypto provider
-	#[cfg(feature = "aws-lc-rs")]
-	let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
-	#[cfg(feature = "ring")]
-	let _ = rustls::crypto::ring::default_provider().install_default();
+	ensure_crypto_provider()?;
 
 	// Generate certificate
 	let (cert, key) = generate_self_signed_cert();
@@ -354,15 +690,31 @@ async fn test_tuic_connection_and_auth() -> eyre::Result<()> {
 	tracing::info!("\n--- Testing Successful Authentication ---");
 	let ctx = Arc::new(AppContext::default());
 	let client_opts = TuicOutboundOpts {
-		peer_addr:          server_addr,
-		sni:                "localhost".to_string(),
-		auth:               (user_uuid, Arc::from(password.as_bytes())),
-		zero_rtt_handshake: false,
-		heartbeat:          Duration::from_secs(3),
-		gc_interval:        Duration::from_secs(3),
-		gc_lifetime:        Duration::from_secs(15),
-		skip_cert_verify:   true,
-		alpn:               vec!["h3".to_string()],
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (user_uuid, Arc::from(password.as_bytes())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
 	};
 
 	let client = TuicOutbound::new(ctx.clone(), client_opts).await;
@@ -373,15 +725,31 @@ async fn test_tuic_connection_and_auth() -> eyre::Result<()> {
 	tracing::info!("\n--- Testing Failed Authentication (Wrong Password) ---");
 	let ctx2 = Arc::new(AppContext::default());
 	let bad_client_opts = TuicOutboundOpts {
-		peer_addr:          server_addr,
-		sni:                "localhost".to_string(),
-		auth:               (user_uuid, Arc::from(b"wrong_password".to_vec())),
-		zero_rtt_handshake: false,
-		heartbeat:          Duration::from_secs(3),
-		gc_interval:        Duration::from_secs(3),
-		gc_lifetime:        Duration::from_secs(15),
-		skip_cert_verify:   true,
-		alpn:               vec!["h3".to_string()],
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (user_uuid, Arc::from(b"wrong_password".to_vec())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
 	};
 
 	// Create client but don't verify connection yet
@@ -398,20 +766,2218 @@ async fn test_tuic_connection_and_auth() -> eyre::Result<()> {
 
 #[test_log::test(tokio::test)]
 async fn test_tuic_multiple_connections() -> eyre::Result<()> {
-	tracing::info!("\n========== TUIC Multiple Connections Test ==========");
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let user_uuid = Uuid::new_v4();
+	let mut users = HashMap::new();
+	users.insert(user_uuid, "slow_password".to_string());
+	let server_addr = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+	let ctx = Arc::new(AppContext::default());
+	let server = TuicInbound::new(
+		ctx.clone(),
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			..Default::default()
+		},
+	);
+	tokio::spawn(async move { server.listen(&SlowCallback).await });
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	// Two clients, so two connections, each relaying while the other waits
+	let mut clients = Vec::new();
+	for _ in 0..2 {
+		let client_opts = TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (user_uuid, Arc::from(b"slow_password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_secs(3),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: None,
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		};
+		let client = Arc::new(TuicOutbound::new(ctx.clone(), client_opts).await?);
+		let client_poll = client.clone();
+		tokio::spawn(async move { client_poll.start_poll().await });
+		clients.push(client);
+	}
+
+	let relay = |client: Arc<TuicOutbound>| async move {
+		let (stream, mut peer) = tokio::io::duplex(64);
+		let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 9);
+		tokio::spawn(async move { client.handle_tcp(target, stream, None::<TuicOutbound>).await });
+		let mut body = [0u8; 4];
+		peer.read_exact(&mut body).await?;
+		eyre::ensure!(&body == b"done", "unexpected reply {body:?}");
+		eyre::Ok(())
+	};
+	let started = tokio::time::Instant::now();
+	let (first, second) = timeout(Duration::from_secs(5), async {
+		tokio::join!(relay(clients[0].clone()), relay(clients[1].clone()))
+	})
+	.await?;
+	first?;
+	second?;
+	// One after the other would take twice the delay
+	assert!(started.elapsed() < SLOW_DELAY * 2, "took {:?}", started.elapsed());
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+/// How long [`SlowCallback`] takes to answer
+const SLOW_DELAY: Duration = Duration::from_millis(500);
+
+/// Answers each connection after [`SLOW_DELAY`] without dialing anything
+#[derive(Clone)]
+struct SlowCallback;
+
+impl InboundCallback for SlowCallback {
+	async fn handle_tcpstream(
+		&self,
+		_target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream + 'static,
+	) -> eyre::Result<()> {
+		stream.on_connect(Ok(())).await?;
+		tokio::time::sleep(SLOW_DELAY).await;
+		stream.write_all(b"done").await?;
+		stream.shutdown().await?;
+		Ok(())
+	}
+
+	async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+		Ok(())
+	}
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_udp_idle_reap() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let user_uuid = Uuid::new_v4();
+	let password = "idle_password";
+	let mut users = HashMap::new();
+	users.insert(user_uuid, password.to_string());
+
+	let server_socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+	let server_addr = server_socket.local_addr()?;
+	drop(server_socket);
+
+	let server_ctx = Arc::new(AppContext::default());
+	let server_cancel = server_ctx.token.clone();
+	let server = TuicInbound::new(
+		server_ctx,
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			auth_timeout: Duration::from_secs(5),
+			max_idle_time: Duration::from_secs(30),
+			zero_rtt: false,
+			..Default::default()
+		},
+	);
+	let server_handle = tokio::spawn(async move {
+		let _ = server.listen(&DirectCallback).await;
+	});
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let ctx = Arc::new(AppContext::default());
+	let client = TuicOutbound::new(
+		ctx.clone(),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (user_uuid, Arc::from(password.as_bytes())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_secs(3),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_millis(300),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: None,
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await?;
+	client.start_poll().await?;
+
+	// Nothing is ever sent, so the association must be reaped on its own
+	let socket = wind_core::udp::TokioUdpSocket::new(std::net::UdpSocket::bind("127.0.0.1:0")?)?;
+	timeout(Duration::from_secs(5), client.handle_udp(socket, None::<TuicOutbound>)).await??;
+	assert!(client.udp_session.get(&0).await.is_none());
+
+	ctx.token.cancel();
+	server_cancel.cancel();
+	let _ = timeout(Duration::from_secs(2), server_handle).await;
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_udp_keepalive_on_idle_association() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let ctx = Arc::new(AppContext::default());
+	let client = Arc::new(
+		TuicOutbound::new(
+			ctx.clone(),
+			TuicOutboundOpts {
+				peer_addr:               server_addr,
+				sni:                     "localhost".to_string(),
+				auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+				zero_rtt_handshake:      false,
+				heartbeat:               Duration::from_secs(3),
+				gc_interval:             Duration::from_secs(3),
+				gc_lifetime:             Duration::from_secs(15),
+				skip_cert_verify:        true,
+				alpn:                    vec!["h3".to_string()],
+				ecn:                     false,
+				udp_idle_timeout:        Duration::from_secs(60),
+				udp_keepalive:           Some(Duration::from_millis(100)),
+				udp_recv_buffer:         4096,
+				udp_send_queue_bytes:    1 << 20,
+				max_connection_lifetime: None,
+				udp_checksum:            false,
+				udp_stream:              UdpStreamConfig::default(),
+				datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+				initial_mtu:             1200,
+				min_mtu:                 1200,
+				mtu_discovery:           true,
+				gso:                     true,
+				local_port_range:        None,
+				initial_window:          INITIAL_WINDOW,
+				connect_verdict:         Duration::ZERO,
+			},
+		)
+		.await?,
+	);
+	let conn = accept.await??;
+
+	// No traffic at all and no polling, so every heartbeat is a keepalive
+	let socket = ThrottledSocket::bind()?;
+	let (delivered, token) = (socket.delivered.clone(), socket.token.clone());
+	let client_clone = client.clone();
+	let started = std::time::Instant::now();
+	let relay = tokio::spawn(async move { client_clone.handle_udp(socket, None::<TuicOutbound>).await });
+	for _ in 0..3 {
+		let heartbeat = timeout(Duration::from_secs(5), conn.read_datagram()).await??;
+		assert_eq!(heartbeat[..], [5, u8::from(CmdType::Heartbeat)]);
+	}
+	// Each one waited out the quiet stretch after the last
+	assert!(started.elapsed() >= Duration::from_millis(300));
+	// The local client never sees them
+	assert_eq!(delivered.load(Ordering::Relaxed), 0);
+
+	token.cancel();
+	timeout(Duration::from_secs(1), relay).await???;
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_udp_over_stream_without_datagrams() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	// A bare server whose transport refuses datagrams
+	let mut transport = quinn::TransportConfig::default();
+	transport.datagram_receive_buffer_size(None);
+	let server = bare_server(transport)?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let ctx = Arc::new(AppContext::default());
+	let client = Arc::new(
+		TuicOutbound::new(
+			ctx.clone(),
+			TuicOutboundOpts {
+				peer_addr:               server_addr,
+				sni:                     "localhost".to_string(),
+				auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+				zero_rtt_handshake:      false,
+				heartbeat:               Duration::from_secs(3),
+				gc_interval:             Duration::from_secs(3),
+				gc_lifetime:             Duration::from_secs(15),
+				skip_cert_verify:        true,
+				alpn:                    vec!["h3".to_string()],
+				ecn:                     false,
+				udp_idle_timeout:        Duration::from_secs(60),
+				udp_keepalive:           None,
+				udp_recv_buffer:         4096,
+				udp_send_queue_bytes:    1 << 20,
+				max_connection_lifetime: None,
+				udp_checksum:            false,
+				udp_stream:              UdpStreamConfig::default(),
+				datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+				initial_mtu:             1200,
+				min_mtu:                 1200,
+				mtu_discovery:           true,
+				gso:                     true,
+				local_port_range:        None,
+				initial_window:          INITIAL_WINDOW,
+				connect_verdict:         Duration::ZERO,
+			},
+		)
+		.await?,
+	);
+	let conn = accept.await??;
+	assert_eq!(client.connection.load().max_datagram_size(), None);
+	let auth = conn.accept_uni().await?.read_to_end(1024).await?;
+	assert_eq!(auth[1], u8::from(CmdType::Auth));
+
+	let socket = wind_core::udp::TokioUdpSocket::new(std::net::UdpSocket::bind("127.0.0.1:0")?)?;
+	let relay_addr = socket.local_addr()?;
+	let client_clone = client.clone();
+	tokio::spawn(async move { client_clone.handle_udp(socket, None::<TuicOutbound>).await });
+
+	// The packet arrives on a stream instead of failing as a datagram
+	let app = UdpSocket::bind("127.0.0.1:0").await?;
+	app.send_to(b"ping", relay_addr).await?;
+	let packet = timeout(Duration::from_secs(5), async {
+		conn.accept_uni().await?.read_to_end(1024).await.map_err(eyre::Report::from)
+	})
+	.await??;
+	let mut buf = bytes::BytesMut::from(&packet[..]);
+	assert_eq!(decode_header(&mut buf, "test")?.command, CmdType::Packet);
+	assert!(packet.ends_with(b"ping"));
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_udp_over_stream_window() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	// Without datagrams, and with stream credit too small for a whole packet so
+	// none is acknowledged before the server reads it
+	let mut transport = quinn::TransportConfig::default();
+	transport
+		.datagram_receive_buffer_size(None)
+		.stream_receive_window(16u32.into())
+		.max_concurrent_uni_streams(100u32.into());
+	let server = bare_server(transport)?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move {
+		let conn = server.accept().await.unwrap().await?;
+		conn.accept_uni().await?.read_to_end(1024).await?;
+		eyre::Ok(conn)
+	});
+
+	let ctx = Arc::new(AppContext::default());
+	let client = TuicOutbound::new(
+		ctx.clone(),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_secs(3),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: None,
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await?;
+	let conn = accept.await??;
+
+	let (receive_tx, _receive_rx) = crossfire::mpmc::bounded_async(16);
+	let config = UdpStreamConfig {
+		stream_window: 4,
+		..Default::default()
+	};
+	let stream = Arc::new(UdpStream::new((**client.connection.load()).clone(), 1, receive_tx, config));
+	let flood: Vec<_> = (0..20)
+		.map(|_| {
+			let stream = stream.clone();
+			tokio::spawn(async move {
+				let packet = UdpPacket {
+					source:  None,
+					target:  TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 53),
+					payload: bytes::Bytes::from_static(&[0u8; 64]),
+					ecn:     None,
+				};
+				stream.send_packet(packet).await
+			})
+		})
+		.collect();
+
+	// Only a window's worth of streams is opened while none is read
+	let mut opened = Vec::new();
+	while let Ok(recv) = timeout(Duration::from_millis(300), conn.accept_uni()).await {
+		opened.push(recv?);
+	}
+	assert_eq!(opened.len(), 4);
+	assert_eq!(stream.streams_in_flight(), 4);
+
+	// Reading them lets the rest through, never more than the window at once
+	let mut received = 0;
+	for mut recv in opened {
+		recv.read_to_end(1024).await?;
+		received += 1;
+	}
+	while received < 20 {
+		let mut recv = timeout(Duration::from_secs(5), conn.accept_uni()).await??;
+		assert!(stream.streams_in_flight() <= 4);
+		recv.read_to_end(1024).await?;
+		received += 1;
+	}
+	for send in flood {
+		send.await??;
+	}
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_auth_waits_for_stream_credit_bounded() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	// Never lets the client open a unidirectional stream
+	let mut transport = quinn::TransportConfig::default();
+	transport.max_concurrent_uni_streams(0u8.into());
+	let server = bare_server(transport)?;
+	let server_addr = server.local_addr()?;
+	let _accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let started = std::time::Instant::now();
+	let res = TuicOutbound::new(
+		Arc::new(AppContext::default()),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_secs(3),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: None,
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await;
+	let err = res.err().expect("authenticated without a stream");
+	assert!(
+		format!("{err:#}").contains("Timed out opening the authentication stream"),
+		"{err:#}"
+	);
+	assert!(started.elapsed() < AUTH_OPEN_TIMEOUT * 2);
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_stalled_uni_stream_does_not_block_heartbeats() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let ctx = Arc::new(AppContext::default());
+	let client = TuicOutbound::new(
+		ctx.clone(),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_millis(50),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: None,
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await?;
+	client.start_poll().await?;
+	let conn = accept.await??;
+	let auth = conn.accept_uni().await?.read_to_end(1024).await?;
+	assert_eq!(auth[1], u8::from(CmdType::Auth));
+
+	// Half a packet header, never finished
+	let mut stalled = conn.open_uni().await?;
+	stalled.write_all(&[5]).await?;
+
+	for _ in 0..3 {
+		let heartbeat = timeout(Duration::from_secs(5), conn.read_datagram()).await??;
+		assert_eq!(heartbeat[..], [5, u8::from(CmdType::Heartbeat)]);
+	}
+
+	drop(stalled);
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_heartbeat_over_stream_without_datagrams() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let mut transport = quinn::TransportConfig::default();
+	transport.datagram_receive_buffer_size(None);
+	let server = bare_server(transport)?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let ctx = Arc::new(AppContext::default());
+	let client = TuicOutbound::new(
+		ctx.clone(),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_millis(50),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: None,
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await?;
+	client.start_poll().await?;
+	let conn = accept.await??;
+	let auth = conn.accept_uni().await?.read_to_end(1024).await?;
+	assert_eq!(auth[1], u8::from(CmdType::Auth));
+
+	// Every heartbeat shows up on a stream of its own
+	for _ in 0..3 {
+		let heartbeat = timeout(Duration::from_secs(5), async {
+			conn.accept_uni().await?.read_to_end(1024).await.map_err(eyre::Report::from)
+		})
+		.await??;
+		assert_eq!(heartbeat, [5, u8::from(CmdType::Heartbeat)]);
+	}
+	assert_eq!(client.heartbeat_status.mode(), HeartbeatMode::Stream);
+	assert_eq!(client.heartbeat_status.failures(), 0);
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_probe() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let ctx = Arc::new(AppContext::default());
+	let client = TuicOutbound::new(
+		ctx.clone(),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_secs(60),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: None,
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await?;
+	let conn = accept.await??;
+	let auth = conn.accept_uni().await?.read_to_end(1024).await?;
+	assert_eq!(auth[1], u8::from(CmdType::Auth));
+
+	let latency = client.probe().await?;
+	assert!(latency < Duration::from_secs(1), "{latency:?}");
+	let probe = conn.accept_uni().await?.read_to_end(1024).await?;
+	assert_eq!(probe, [5, u8::from(CmdType::Heartbeat)]);
+
+	conn.close(0u32.into(), b"bye");
+	client.connection.load().closed().await;
+	assert!(client.probe().await.is_err());
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_datagram_receive_buffer() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+	tokio::spawn(async move {
+		while let Some(incoming) = server.accept().await {
+			let _ = tx.send(incoming.await?);
+		}
+		eyre::Ok(())
+	});
+
+	let ctx = Arc::new(AppContext::default());
+	let opts = |datagram_receive_buffer| TuicOutboundOpts {
+		peer_addr: server_addr,
+		sni: "localhost".to_string(),
+		auth: (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+		zero_rtt_handshake: false,
+		heartbeat: Duration::from_secs(3),
+		gc_interval: Duration::from_secs(3),
+		gc_lifetime: Duration::from_secs(15),
+		skip_cert_verify: true,
+		alpn: vec!["h3".to_string()],
+		ecn: false,
+		udp_idle_timeout: Duration::from_secs(60),
+		udp_keepalive: None,
+		udp_recv_buffer: 4096,
+		udp_send_queue_bytes: 1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum: false,
+		udp_stream: UdpStreamConfig::default(),
+		datagram_receive_buffer,
+		initial_mtu: 1200,
+		min_mtu: 1200,
+		mtu_discovery: false,
+		gso: true,
+		local_port_range: None,
+		initial_window: INITIAL_WINDOW,
+		connect_verdict: Duration::ZERO,
+	};
+
+	// A burst the client doesn't read until it is all in, returning how many
+	// datagrams got through and how many were counted as dropped
+	async fn burst(client: &TuicOutbound, server_conn: &quinn::Connection) -> eyre::Result<(u64, u64)> {
+		for _ in 0..20 {
+			server_conn.send_datagram(vec![0u8; 400].into())?;
+		}
+		tokio::time::sleep(Duration::from_millis(200)).await;
+		let drops = Arc::new(DatagramDrops::default());
+		let reader = DatagramReader::new((**client.connection.load()).clone(), drops.clone());
+		let mut read = 0;
+		while timeout(Duration::from_millis(200), reader.read()).await.is_ok() {
+			read += 1;
+		}
+		Ok((read, drops.dropped()))
+	}
+
+	// The peer may not send datagrams larger than the buffer
+	let client = TuicOutbound::new(ctx.clone(), opts(1000)).await?;
+	let server_conn = timeout(Duration::from_secs(5), rx.recv()).await?.unwrap();
+	assert!(server_conn.max_datagram_size().unwrap() < 1000);
+	let (read, dropped) = burst(&client, &server_conn).await?;
+	assert!(read <= 2, "{read}");
+	assert_eq!(read + dropped, 20);
+
+	let client = TuicOutbound::new(ctx.clone(), opts(DATAGRAM_RECEIVE_BUFFER)).await?;
+	let server_conn = timeout(Duration::from_secs(5), rx.recv()).await?.unwrap();
+	assert!(server_conn.max_datagram_size().unwrap() > 1000);
+	assert_eq!(burst(&client, &server_conn).await?, (20, 0));
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_connection_rotation() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	// Reports the first command of every connection, which the client opens with
+	// its auth
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+	tokio::spawn(async move {
+		while let Some(incoming) = server.accept().await {
+			let tx = tx.clone();
+			tokio::spawn(async move {
+				let conn = incoming.await?;
+				let auth = conn.accept_uni().await?.read_to_end(1024).await?;
+				let _ = tx.send((conn, auth[1]));
+				eyre::Ok(())
+			});
+		}
+	});
+
+	let ctx = Arc::new(AppContext::default());
+	let client = TuicOutbound::new(
+		ctx.clone(),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_secs(3),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: Some(Duration::from_millis(300)),
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await?;
+	client.start_poll().await?;
+	let first = client.connection.load().stable_id();
+
+	let (_first_conn, cmd) = timeout(Duration::from_secs(5), rx.recv()).await?.unwrap();
+	assert_eq!(cmd, u8::from(CmdType::Auth));
+	// The rotated connection authenticates on its own
+	let (_second_conn, cmd) = timeout(Duration::from_secs(5), rx.recv()).await?.unwrap();
+	assert_eq!(cmd, u8::from(CmdType::Auth));
+	timeout(Duration::from_secs(1), async {
+		while client.connection.load().stable_id() == first {
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+	})
+	.await?;
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_udp_slow_local_socket_drops() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let ctx = Arc::new(AppContext::default());
+	let client = Arc::new(
+		TuicOutbound::new(
+			ctx.clone(),
+			TuicOutboundOpts {
+				peer_addr:               server_addr,
+				sni:                     "localhost".to_string(),
+				auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+				zero_rtt_handshake:      false,
+				heartbeat:               Duration::from_secs(3),
+				gc_interval:             Duration::from_secs(3),
+				gc_lifetime:             Duration::from_secs(15),
+				skip_cert_verify:        true,
+				alpn:                    vec!["h3".to_string()],
+				ecn:                     false,
+				udp_idle_timeout:        Duration::from_secs(60),
+				udp_keepalive:           None,
+				udp_recv_buffer:         4096,
+				udp_send_queue_bytes:    1 << 20,
+				max_connection_lifetime: None,
+				udp_checksum:            false,
+				udp_stream:              UdpStreamConfig::default(),
+				datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+				initial_mtu:             1200,
+				min_mtu:                 1200,
+				mtu_discovery:           true,
+				gso:                     true,
+				local_port_range:        None,
+				initial_window:          INITIAL_WINDOW,
+				connect_verdict:         Duration::ZERO,
+			},
+		)
+		.await?,
+	);
+	let conn = accept.await??;
+	client.start_poll().await?;
+
+	let socket = ThrottledSocket::bind()?;
+	let delivered = socket.delivered.clone();
+	let client_clone = client.clone();
+	tokio::spawn(async move { client_clone.handle_udp(socket, None::<TuicOutbound>).await });
+	let stream = timeout(Duration::from_secs(5), async {
+		loop {
+			if let Some(stream) = client.udp_session.get(&0).await {
+				break stream;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+	})
+	.await?;
+
+	// Replies arrive far faster than the local socket takes them
+	let source = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 53);
+	let sent = 4 * UDP_RECEIVE_QUEUE;
+	for pkt_id in 0..sent as u16 {
+		conn.send_udp(0, pkt_id, &source, bytes::Bytes::from_static(b"reply"), true)
+			.await?;
+	}
+	timeout(Duration::from_secs(5), async {
+		while stream.dropped() == 0 {
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+	})
+	.await?;
+	assert!(delivered.load(Ordering::Relaxed) < sent);
+
+	// The shared read loop kept going, so later replies are still taken
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	let before = delivered.load(Ordering::Relaxed);
+	tokio::time::sleep(Duration::from_millis(200)).await;
+	assert!(delivered.load(Ordering::Relaxed) > before);
 
-	// NOTE: Due to current implementation limitations with callback lifetimes,
-	// the TUIC inbound server handles connections sequentially rather than
-	// concurrently. This test is skipped because concurrent connection handling is
-	// not yet supported.
-	//
-	// To support true concurrent connections, the InboundCallback trait would need
-	// to be Clone + 'static, or the API would need to change.
+	ctx.token.cancel();
+	Ok(())
+}
+
+/// Local socket whose packets head to each of `targets` in turn, as a SOCKS
+/// client reusing one association would send them
+struct RoundRobinSocket {
+	inner:   Arc<wind_core::udp::TokioUdpSocket>,
+	targets: Vec<TargetAddr>,
+	next:    AtomicUsize,
+	token:   CancellationToken,
+}
 
-	tracing::info!("✓ Test skipped - concurrent connections not yet supported");
-	tracing::info!("  Current implementation handles one connection at a time");
-	tracing::info!("  Each connection can process multiple streams/datagrams concurrently");
+impl AbstractUdpSocket for RoundRobinSocket {
+	fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+		self.inner.clone().create_io_poller()
+	}
 
-	tracing::info!("========== Multiple Connections Test SKIPPED ==========\n");
+	fn try_send(&self, _transmit: &Transmit) -> std::io::Result<()> {
+		Ok(())
+	}
+
+	fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<std::io::Result<usize>> {
+		let received = std::task::ready!(self.inner.poll_recv(cx, bufs, meta))?;
+		for meta in &mut meta[..received] {
+			let next = self.next.fetch_add(1, Ordering::Relaxed);
+			meta.destination = Some(self.targets[next % self.targets.len()].clone());
+		}
+		Poll::Ready(Ok(received))
+	}
+
+	fn local_addr(&self) -> std::io::Result<SocketAddr> {
+		self.inner.local_addr()
+	}
+
+	fn association_token(&self) -> CancellationToken {
+		self.token.clone()
+	}
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_udp_association_reused_across_targets() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let ctx = Arc::new(AppContext::default());
+	let client_opts = TuicOutboundOpts {
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
+	};
+	let client = Arc::new(TuicOutbound::new(ctx.clone(), client_opts).await?);
+	let conn = accept.await??;
+	let auth = conn.accept_uni().await?.read_to_end(1024).await?;
+	assert_eq!(auth[1], u8::from(CmdType::Auth));
+
+	let targets = vec![
+		TargetAddr::IPv4(Ipv4Addr::new(192, 0, 2, 1), 53),
+		TargetAddr::Domain("example.com".into(), 443),
+	];
+	let socket = RoundRobinSocket {
+		inner:   Arc::new(wind_core::udp::TokioUdpSocket::new(std::net::UdpSocket::bind(
+			"127.0.0.1:0",
+		)?)?),
+		targets: targets.clone(),
+		next:    AtomicUsize::new(0),
+		token:   CancellationToken::new(),
+	};
+	let (local_addr, token) = (socket.local_addr()?, socket.token.clone());
+	let client_clone = client.clone();
+	let relay = tokio::spawn(async move { client_clone.handle_udp(socket, None::<TuicOutbound>).await });
+
+	let app = UdpSocket::bind("127.0.0.1:0").await?;
+	let mut seen = Vec::new();
+	for _ in &targets {
+		app.send_to(b"ping", local_addr).await?;
+		let datagram = timeout(Duration::from_secs(5), conn.read_datagram()).await??;
+		let mut buf = bytes::BytesMut::from(&datagram[..]);
+		assert_eq!(decode_header(&mut buf, "test")?.command, CmdType::Packet);
+		let Command::Packet { assoc_id, .. } = decode_command(CmdType::Packet, &mut buf, "test")? else {
+			unreachable!()
+		};
+		let target = AddressCodec.decode(&mut buf)?.unwrap();
+		seen.push((assoc_id, target));
+	}
+	assert_eq!(seen[0].0, seen[1].0);
+	assert_eq!(seen[0].1, Address::from(targets[0].clone()));
+	assert_eq!(seen[1].1, Address::from(targets[1].clone()));
+
+	// One dissociation once the association ends
+	token.cancel();
+	timeout(Duration::from_secs(5), relay).await???;
+	let packet = timeout(Duration::from_secs(5), async {
+		conn.accept_uni().await?.read_to_end(1024).await.map_err(eyre::Report::from)
+	})
+	.await??;
+	let mut buf = bytes::BytesMut::from(&packet[..]);
+	assert_eq!(decode_header(&mut buf, "test")?.command, CmdType::Dissociate);
+	assert_eq!(
+		decode_command(CmdType::Dissociate, &mut buf, "test")?,
+		Command::Dissociate { assoc_id: seen[0].0 }
+	);
+	assert!(timeout(Duration::from_millis(300), conn.accept_uni()).await.is_err());
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_udp_dissociates_when_inbound_ends() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+
+	let ctx = Arc::new(AppContext::default());
+	let client = Arc::new(
+		TuicOutbound::new(
+			ctx.clone(),
+			TuicOutboundOpts {
+				peer_addr:               server_addr,
+				sni:                     "localhost".to_string(),
+				auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+				zero_rtt_handshake:      false,
+				heartbeat:               Duration::from_secs(3),
+				gc_interval:             Duration::from_secs(3),
+				gc_lifetime:             Duration::from_secs(15),
+				skip_cert_verify:        true,
+				alpn:                    vec!["h3".to_string()],
+				ecn:                     false,
+				udp_idle_timeout:        Duration::from_secs(60),
+				udp_keepalive:           None,
+				udp_recv_buffer:         4096,
+				udp_send_queue_bytes:    1 << 20,
+				max_connection_lifetime: None,
+				udp_checksum:            false,
+				udp_stream:              UdpStreamConfig::default(),
+				datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+				initial_mtu:             1200,
+				min_mtu:                 1200,
+				mtu_discovery:           true,
+				gso:                     true,
+				local_port_range:        None,
+				initial_window:          INITIAL_WINDOW,
+				connect_verdict:         Duration::ZERO,
+			},
+		)
+		.await?,
+	);
+	let conn = accept.await??;
+	let auth = conn.accept_uni().await?.read_to_end(1024).await?;
+	assert_eq!(auth[1], u8::from(CmdType::Auth));
+
+	let socket = ThrottledSocket::bind()?;
+	let token = socket.token.clone();
+	let client_clone = client.clone();
+	let relay = tokio::spawn(async move { client_clone.handle_udp(socket, None::<TuicOutbound>).await });
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	assert!(client.udp_session.get(&0).await.is_some());
+
+	// E.g. the SOCKS control connection closed
+	token.cancel();
+	timeout(Duration::from_secs(5), relay).await???;
+	assert!(client.udp_session.get(&0).await.is_none());
+	let packet = timeout(Duration::from_secs(5), async {
+		conn.accept_uni().await?.read_to_end(1024).await.map_err(eyre::Report::from)
+	})
+	.await??;
+	let mut buf = bytes::BytesMut::from(&packet[..]);
+	assert_eq!(decode_header(&mut buf, "test")?.command, CmdType::Dissociate);
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_outbound_shutdown_leaves_others_running() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+	tokio::spawn(async move {
+		while let Some(incoming) = server.accept().await {
+			let tx = tx.clone();
+			tokio::spawn(async move {
+				let conn = incoming.await?;
+				let auth = conn.accept_uni().await?.read_to_end(1024).await?;
+				assert_eq!(auth[1], u8::from(CmdType::Auth));
+				let _ = tx.send(conn);
+				eyre::Ok(())
+			});
+		}
+	});
+
+	let ctx = Arc::new(AppContext::default());
+	let opts = || TuicOutboundOpts {
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_millis(50),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
+	};
+	let first = TuicOutbound::new(ctx.clone(), opts()).await?;
+	let first_conn = timeout(Duration::from_secs(5), rx.recv()).await?.unwrap();
+	let second = TuicOutbound::new(ctx.clone(), opts()).await?;
+	let second_conn = timeout(Duration::from_secs(5), rx.recv()).await?.unwrap();
+	first.start_poll().await?;
+	second.start_poll().await?;
+
+	// The inbound went away without its association being cleaned up
+	let relay = first.handle_udp(ThrottledSocket::bind()?, None::<TuicOutbound>);
+	assert!(timeout(Duration::from_millis(100), relay).await.is_err());
+	assert!(first.udp_session.get(&0).await.is_some());
+
+	timeout(Duration::from_secs(5), first.shutdown()).await?;
+	let packet = timeout(Duration::from_secs(5), async {
+		first_conn
+			.accept_uni()
+			.await?
+			.read_to_end(1024)
+			.await
+			.map_err(eyre::Report::from)
+	})
+	.await??;
+	let mut buf = bytes::BytesMut::from(&packet[..]);
+	assert_eq!(decode_header(&mut buf, "test")?.command, CmdType::Dissociate);
+	let err = timeout(Duration::from_secs(5), first_conn.closed()).await?;
+	assert_eq!(CloseReason::from_error(&err), Some(CloseReason::ClientShutdown));
+
+	// The other outbound keeps its connection and heartbeats
+	assert!(!ctx.token.is_cancelled());
+	for _ in 0..3 {
+		timeout(Duration::from_secs(5), second_conn.read_datagram()).await??;
+	}
+	assert!(second.connection.load().close_reason().is_none());
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_outbound_drop_stops_tasks() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+	tokio::spawn(async move {
+		while let Some(incoming) = server.accept().await {
+			let _ = tx.send(incoming.await?);
+		}
+		eyre::Ok(())
+	});
+
+	let ctx = Arc::new(AppContext::default());
+	let client = TuicOutbound::new(
+		ctx.clone(),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_millis(50),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: Some(Duration::from_secs(60)),
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await?;
+	let server_conn = timeout(Duration::from_secs(5), rx.recv()).await?.unwrap();
+	client.start_poll().await?;
+	let (token, tasks) = (client.token.clone(), client.tasks.clone());
+	assert!(!tasks.is_empty());
+
+	drop(client);
+	assert!(token.is_cancelled());
+	timeout(Duration::from_secs(5), tasks.wait()).await?;
+	let err = timeout(Duration::from_secs(5), server_conn.closed()).await?;
+	assert_eq!(CloseReason::from_error(&err), Some(CloseReason::ClientShutdown));
+	assert!(!ctx.token.is_cancelled());
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_larger_mtu_fragments_less() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+	tokio::spawn(async move {
+		while let Some(incoming) = server.accept().await {
+			let _ = tx.send(incoming.await?);
+		}
+		eyre::Ok(())
+	});
+
+	let ctx = Arc::new(AppContext::default());
+	let opts = |initial_mtu| TuicOutboundOpts {
+		peer_addr: server_addr,
+		sni: "localhost".to_string(),
+		auth: (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+		zero_rtt_handshake: false,
+		heartbeat: Duration::from_secs(3),
+		gc_interval: Duration::from_secs(3),
+		gc_lifetime: Duration::from_secs(15),
+		skip_cert_verify: true,
+		alpn: vec!["h3".to_string()],
+		ecn: false,
+		udp_idle_timeout: Duration::from_secs(60),
+		udp_keepalive: None,
+		udp_recv_buffer: 4096,
+		udp_send_queue_bytes: 1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum: false,
+		udp_stream: UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu,
+		min_mtu: 1200,
+		// Probing would grow both to the same size
+		mtu_discovery: false,
+		gso: true,
+		local_port_range: None,
+		initial_window: INITIAL_WINDOW,
+		connect_verdict: Duration::ZERO,
+	};
+
+	// Datagrams the server receives for one 4000 byte packet
+	let mut sizes = Vec::new();
+	let mut fragments = Vec::new();
+	for initial_mtu in [1200, 1400] {
+		let client = TuicOutbound::new(ctx.clone(), opts(initial_mtu)).await?;
+		let conn = timeout(Duration::from_secs(5), rx.recv()).await?.unwrap();
+		let connection = client.connection.load_full();
+		sizes.push(connection.max_datagram_size().unwrap());
+
+		let (receive_tx, _receive_rx) = crossfire::mpmc::bounded_async(16);
+		let stream = UdpStream::new(
+			quinn::Connection::clone(&connection),
+			0,
+			receive_tx,
+			UdpStreamConfig::default(),
+		);
+		stream
+			.send_packet(UdpPacket {
+				source:  None,
+				target:  TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 53),
+				payload: bytes::Bytes::from(vec![0u8; 4000]),
+				ecn:     None,
+			})
+			.await?;
+
+		let mut count = 0;
+		while let Ok(datagram) = timeout(Duration::from_millis(300), conn.read_datagram()).await {
+			let mut buf = bytes::BytesMut::from(&datagram?[..]);
+			assert_eq!(decode_header(&mut buf, "test")?.command, CmdType::Packet);
+			count += 1;
+		}
+		fragments.push(count);
+	}
+
+	assert!(sizes[1] > sizes[0], "{sizes:?}");
+	assert!(fragments[1] < fragments[0], "{fragments:?}");
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_auth_failure_close_code() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let user_uuid = Uuid::new_v4();
+	let mut users = HashMap::new();
+	users.insert(user_uuid, "right_password".to_string());
+
+	let server_socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+	let server_addr = server_socket.local_addr()?;
+	drop(server_socket);
+
+	let server_ctx = Arc::new(AppContext::default());
+	let server_cancel = server_ctx.token.clone();
+	let server = TuicInbound::new(
+		server_ctx,
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			auth_timeout: Duration::from_secs(5),
+			max_idle_time: Duration::from_secs(30),
+			zero_rtt: false,
+			..Default::default()
+		},
+	);
+	let server_handle = tokio::spawn(async move {
+		let _ = server.listen(&DirectCallback).await;
+	});
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let ctx = Arc::new(AppContext::default());
+	let client = TuicOutbound::new(
+		ctx.clone(),
+		TuicOutboundOpts {
+			peer_addr:               server_addr,
+			sni:                     "localhost".to_string(),
+			auth:                    (user_uuid, Arc::from(b"wrong_password".as_slice())),
+			zero_rtt_handshake:      false,
+			heartbeat:               Duration::from_secs(3),
+			gc_interval:             Duration::from_secs(3),
+			gc_lifetime:             Duration::from_secs(15),
+			skip_cert_verify:        true,
+			alpn:                    vec!["h3".to_string()],
+			ecn:                     false,
+			udp_idle_timeout:        Duration::from_secs(60),
+			udp_keepalive:           None,
+			udp_recv_buffer:         4096,
+			udp_send_queue_bytes:    1 << 20,
+			max_connection_lifetime: None,
+			udp_checksum:            false,
+			udp_stream:              UdpStreamConfig::default(),
+			datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+			initial_mtu:             1200,
+			min_mtu:                 1200,
+			mtu_discovery:           true,
+			gso:                     true,
+			local_port_range:        None,
+			initial_window:          INITIAL_WINDOW,
+			connect_verdict:         Duration::ZERO,
+		},
+	)
+	.await?;
+
+	let err = timeout(Duration::from_secs(5), client.connection.load().closed()).await?;
+	assert_eq!(CloseReason::from_error(&err), Some(CloseReason::AuthFailure));
+
+	ctx.token.cancel();
+	server_cancel.cancel();
+	let _ = timeout(Duration::from_secs(2), server_handle).await;
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_outbounds_share_crypto_provider() -> eyre::Result<()> {
+	// Left to the inbound and outbounds, which each make sure of one
+	let (cert, key) = generate_self_signed_cert();
+	let user_uuid = Uuid::new_v4();
+	let mut users = HashMap::new();
+	users.insert(user_uuid, "provider_password".to_string());
+
+	let server_socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+	let server_addr = server_socket.local_addr()?;
+	drop(server_socket);
+
+	let server_ctx = Arc::new(AppContext::default());
+	let server_cancel = server_ctx.token.clone();
+	let server = TuicInbound::new(
+		server_ctx,
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			..Default::default()
+		},
+	);
+	let server_handle = tokio::spawn(async move {
+		let _ = server.listen(&DirectCallback).await;
+	});
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let ctx = Arc::new(AppContext::default());
+	let opts = || TuicOutboundOpts {
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (user_uuid, Arc::from(b"provider_password".as_slice())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
+	};
+	let first = TuicOutbound::new(ctx.clone(), opts()).await?;
+	assert!(first.connection.load().close_reason().is_none());
+	// The inbound serves one connection at a time
+	drop(first);
+	let second = TuicOutbound::new(ctx.clone(), opts()).await?;
+	assert!(second.connection.load().close_reason().is_none());
+	assert!(Arc::ptr_eq(&ensure_crypto_provider()?, &ensure_crypto_provider()?));
+
+	ctx.token.cancel();
+	server_cancel.cancel();
+	let _ = timeout(Duration::from_secs(2), server_handle).await;
+	Ok(())
+}
+
+#[tokio::test]
+async fn test_tuic_rejects_foreign_alpn() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let mut roots = rustls::RootCertStore::empty();
+	roots.add(cert[0].clone())?;
+
+	let server_socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+	let server_addr = server_socket.local_addr()?;
+	drop(server_socket);
+
+	let server_ctx = Arc::new(AppContext::default());
+	let server_cancel = server_ctx.token.clone();
+	let server = TuicInbound::new(
+		server_ctx,
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string(), "fronted".to_string()],
+			tuic_alpn: vec!["h3".to_string()],
+			..Default::default()
+		},
+	);
+	let server_handle = tokio::spawn(async move {
+		let _ = server.listen(&DirectCallback).await;
+	});
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	// A client of whatever else shares the port, speaking its own ALPN
+	let mut crypto = rustls::ClientConfig::builder()
+		.with_root_certificates(roots)
+		.with_no_client_auth();
+	crypto.alpn_protocols = vec![b"fronted".to_vec()];
+	let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse()?)?;
+	endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+		quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?,
+	)));
+	let conn = endpoint.connect(server_addr, "localhost")?.await?;
+
+	let err = timeout(Duration::from_secs(5), conn.closed()).await?;
+	assert_eq!(CloseReason::from_error(&err), Some(CloseReason::UnsupportedAlpn));
+
+	server_cancel.cancel();
+	let _ = timeout(Duration::from_secs(2), server_handle).await;
+	Ok(())
+}
+
+/// Counts the TCP connections and UDP associations the inbound hands over
+#[derive(Clone, Default)]
+struct CountingCallback {
+	connects:     Arc<AtomicUsize>,
+	associations: Arc<AtomicUsize>,
+}
+
+impl InboundCallback for CountingCallback {
+	async fn handle_tcpstream(&self, _target_addr: TargetAddr, _stream: impl AbstractTcpStream + 'static) -> eyre::Result<()> {
+		self.connects.fetch_add(1, Ordering::SeqCst);
+		Ok(())
+	}
+
+	async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+		self.associations.fetch_add(1, Ordering::SeqCst);
+		Ok(())
+	}
+}
+
+#[tokio::test]
+async fn test_tuic_connect_ahead_of_auth_served() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let mut roots = rustls::RootCertStore::empty();
+	roots.add(cert[0].clone())?;
+	let user_uuid = Uuid::new_v4();
+	let mut users = HashMap::new();
+	users.insert(user_uuid, "early_password".to_string());
+	let server_addr = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+	let ctx = Arc::new(AppContext::default());
+	let server = TuicInbound::new(
+		ctx.clone(),
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			..Default::default()
+		},
+	);
+	let callback = CountingCallback::default();
+	let connects = callback.connects.clone();
+	tokio::spawn(async move { server.listen(&callback).await });
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let mut crypto = rustls::ClientConfig::builder()
+		.with_root_certificates(roots)
+		.with_no_client_auth();
+	crypto.alpn_protocols = vec![b"h3".to_vec()];
+	let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse()?)?;
+	endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+		quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?,
+	)));
+	let conn = endpoint.connect(server_addr, "localhost")?.await?;
+
+	// The `Connect` stream gets there first, as it may on a real network
+	let mut request = bytes::BytesMut::new();
+	HeaderCodec.encode(Header::new(CmdType::Connect), &mut request)?;
+	AddressCodec.encode(Address::IPv4(Ipv4Addr::LOCALHOST, 9), &mut request)?;
+	let (mut send, _recv) = conn.open_bi().await?;
+	send.write_all(&request).await?;
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	assert_eq!(connects.load(Ordering::SeqCst), 0);
+
+	conn.send_auth(&user_uuid, b"early_password").await?;
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[tokio::test]
+async fn test_tuic_unauthenticated_packet_on_stream_refused() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let mut roots = rustls::RootCertStore::empty();
+	roots.add(cert[0].clone())?;
+	let user_uuid = Uuid::new_v4();
+	let mut users = HashMap::new();
+	users.insert(user_uuid, "stream_password".to_string());
+
+	let server_socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+	let server_addr = server_socket.local_addr()?;
+	drop(server_socket);
+
+	let server_ctx = Arc::new(AppContext::default());
+	let server_cancel = server_ctx.token.clone();
+	let server = TuicInbound::new(
+		server_ctx,
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			..Default::default()
+		},
+	);
+	let callback = CountingCallback::default();
+	let associations = callback.associations.clone();
+	let server_handle = tokio::spawn(async move {
+		let _ = server.listen(&callback).await;
+	});
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let mut crypto = rustls::ClientConfig::builder()
+		.with_root_certificates(roots)
+		.with_no_client_auth();
+	crypto.alpn_protocols = vec![b"h3".to_vec()];
+	let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse()?)?;
+	endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+		quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?,
+	)));
+	let conn = endpoint.connect(server_addr, "localhost")?.await?;
+	let target = TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 9);
+
+	// Neither a whole packet nor a later fragment, which carries no address,
+	// may open an association before the client authenticated. They are held
+	// for it instead, as they may have overtaken the `Auth` stream
+	conn.send_udp(1, 0, &target, bytes::Bytes::from_static(b"early"), false)
+		.await?;
+	let mut fragment = bytes::BytesMut::new();
+	HeaderCodec.encode(Header::new(CmdType::Packet), &mut fragment)?;
+	CmdCodec(CmdType::Packet).encode(
+		Command::Packet {
+			assoc_id:   2,
+			pkt_id:     0,
+			frag_total: 2,
+			frag_id:    1,
+			size:       4,
+		},
+		&mut fragment,
+	)?;
+	AddressCodec.encode(Address::None, &mut fragment)?;
+	fragment.extend_from_slice(b"tail");
+	let mut send = conn.open_uni().await?;
+	send.write_all(&fragment).await?;
+	send.finish()?;
+	tokio::time::sleep(Duration::from_millis(200)).await;
+	assert_eq!(associations.load(Ordering::SeqCst), 0);
+
+	conn.send_auth(&user_uuid, b"stream_password").await?;
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	assert_eq!(associations.load(Ordering::SeqCst), 2);
+
+	server_cancel.cancel();
+	let _ = timeout(Duration::from_secs(2), server_handle).await;
+	Ok(())
+}
+
+#[tokio::test]
+async fn test_tuic_0rtt_connect_relayed() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let mut roots = rustls::RootCertStore::empty();
+	roots.add(cert[0].clone())?;
+	let user_uuid = Uuid::new_v4();
+	let mut users = HashMap::new();
+	users.insert(user_uuid, "zero_rtt_password".to_string());
+
+	let greeter = TcpListener::bind("127.0.0.1:0").await?;
+	let greeter_addr = greeter.local_addr()?;
+	tokio::spawn(async move {
+		let (mut stream, _) = greeter.accept().await?;
+		stream.write_all(b"hello").await?;
+		eyre::Ok(())
+	});
+
+	let server_socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+	let server_addr = server_socket.local_addr()?;
+	drop(server_socket);
+
+	let server_ctx = Arc::new(AppContext::default());
+	let server_cancel = server_ctx.token.clone();
+	let server = TuicInbound::new(
+		server_ctx,
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			zero_rtt: true,
+			..Default::default()
+		},
+	);
+	let server_handle = tokio::spawn(async move {
+		let _ = server.listen(&DirectCallback).await;
+	});
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let mut crypto = rustls::ClientConfig::builder()
+		.with_root_certificates(roots)
+		.with_no_client_auth();
+	crypto.alpn_protocols = vec![b"h3".to_vec()];
+	crypto.enable_early_data = true;
+	let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse()?)?;
+	endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+		quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?,
+	)));
+
+	// A full handshake first, for a ticket to resume with
+	let first = endpoint.connect(server_addr, "localhost")?.await?;
+	first.send_auth(&user_uuid, b"zero_rtt_password").await?;
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	first.close(0u32.into(), b"");
+
+	let Ok((conn, accepted)) = endpoint.connect(server_addr, "localhost")?.into_0rtt() else {
+		panic!("no ticket to resume the session with");
+	};
+	// The token is exported from the completed handshake
+	assert!(timeout(Duration::from_secs(5), accepted).await?, "0-RTT rejected");
+	conn.send_auth(&user_uuid, b"zero_rtt_password").await?;
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	let (mut send, mut recv) = conn.open_bi().await?;
+	let mut request = bytes::BytesMut::new();
+	HeaderCodec.encode(Header::new(CmdType::Connect), &mut request)?;
+	AddressCodec.encode(TargetAddr::from(greeter_addr).into(), &mut request)?;
+	send.write_all(&request).await?;
+	send.finish()?;
+
+	// Connects on a connection the inbound accepted with 0-RTT are held until its
+	// handshake completes, which must not stall them for good
+	let greeting = timeout(Duration::from_secs(5), recv.read_to_end(64)).await??;
+	assert_eq!(greeting, b"hello");
+
+	server_cancel.cancel();
+	let _ = timeout(Duration::from_secs(2), server_handle).await;
+	Ok(())
+}
+
+#[tokio::test]
+async fn test_tuic_replayed_0rtt_session_opens_nothing() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let mut roots = rustls::RootCertStore::empty();
+	roots.add(cert[0].clone())?;
+	let user_uuid = Uuid::new_v4();
+	let mut users = HashMap::new();
+	users.insert(user_uuid, "replay_password".to_string());
+
+	let server_socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+	let server_addr = server_socket.local_addr()?;
+	drop(server_socket);
+
+	let server_ctx = Arc::new(AppContext::default());
+	let server_cancel = server_ctx.token.clone();
+	let server = TuicInbound::new(
+		server_ctx,
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			zero_rtt: true,
+			..Default::default()
+		},
+	);
+	let callback = CountingCallback::default();
+	let connects = callback.connects.clone();
+	let server_handle = tokio::spawn(async move {
+		let _ = server.listen(&callback).await;
+	});
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	// Sits between the client and the server, keeping what the client sends
+	let relay = UdpSocket::bind("127.0.0.1:0").await?;
+	let relay_addr = relay.local_addr()?;
+	let upstream = UdpSocket::bind("127.0.0.1:0").await?;
+	upstream.connect(server_addr).await?;
+	let captured = Arc::new(std::sync::Mutex::new(Vec::new()));
+	tokio::spawn({
+		let captured = captured.clone();
+		async move {
+			let (mut request, mut reply, mut client_addr) = (vec![0u8; 65536], vec![0u8; 65536], None);
+			loop {
+				tokio::select! {
+					Ok((len, from)) = relay.recv_from(&mut request) => {
+						client_addr = Some(from);
+						captured.lock().unwrap().push(request[..len].to_vec());
+						let _ = upstream.send(&request[..len]).await;
+					}
+					Ok(len) = upstream.recv(&mut reply) => {
+						if let Some(client_addr) = client_addr {
+							let _ = relay.send_to(&reply[..len], client_addr).await;
+						}
+					}
+					else => break,
+				}
+			}
+		}
+	});
+
+	let mut crypto = rustls::ClientConfig::builder()
+		.with_root_certificates(roots)
+		.with_no_client_auth();
+	crypto.alpn_protocols = vec![b"h3".to_vec()];
+	crypto.enable_early_data = true;
+	let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse()?)?;
+	endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+		quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?,
+	)));
+
+	// A full handshake first, for a ticket to resume with
+	let first = endpoint.connect(server_addr, "localhost")?.await?;
+	first.send_auth(&user_uuid, b"replay_password").await?;
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	first.close(0u32.into(), b"");
+
+	// The session to capture: resumed with 0-RTT, then relaying a connection
+	let Ok((conn, accepted)) = endpoint.connect(relay_addr, "localhost")?.into_0rtt() else {
+		panic!("no ticket to resume the session with");
+	};
+	assert!(timeout(Duration::from_secs(5), accepted).await?, "0-RTT rejected");
+	conn.send_auth(&user_uuid, b"replay_password").await?;
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	let (mut send, _recv) = conn.open_bi().await?;
+	let mut request = bytes::BytesMut::new();
+	HeaderCodec.encode(Header::new(CmdType::Connect), &mut request)?;
+	AddressCodec.encode(TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 9).into(), &mut request)?;
+	send.write_all(&request).await?;
+	send.finish()?;
+	tokio::time::sleep(Duration::from_millis(200)).await;
+	assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+	// Sending Auth again is harmless, the connection stays up
+	conn.send_auth(&user_uuid, b"replay_password").await?;
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	assert!(conn.close_reason().is_none(), "{:?}", conn.close_reason());
+
+	// What an attacker who recorded the session would send. The ticket was used
+	// up, so nothing of it resumes and nothing reaches the callback
+	let replayer = UdpSocket::bind("127.0.0.1:0").await?;
+	let captured = captured.lock().unwrap().clone();
+	for datagram in captured {
+		replayer.send_to(&datagram, server_addr).await?;
+	}
+	tokio::time::sleep(Duration::from_millis(500)).await;
+	assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+	server_cancel.cancel();
+	let _ = timeout(Duration::from_secs(2), server_handle).await;
+	Ok(())
+}
+
+#[tokio::test]
+async fn test_tls_outbound_relays_to_echo_server() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let mut server_config = rustls::ServerConfig::builder()
+		.with_no_client_auth()
+		.with_single_cert(cert, key)?;
+	server_config.alpn_protocols = vec![b"relay".to_vec()];
+	let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let peer_addr = listener.local_addr()?;
+	tokio::spawn(async move {
+		let (tcp, _) = listener.accept().await.unwrap();
+		let mut tls = acceptor.accept(tcp).await.unwrap();
+		assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"relay"[..]));
+		let mut buf = [0u8; 4];
+		tls.read_exact(&mut buf).await.unwrap();
+		tls.write_all(&buf).await.unwrap();
+		tls.shutdown().await.unwrap();
+	});
+
+	let outbound = TlsOutbound::new(TlsOutboundOpts {
+		peer_addr,
+		sni: "localhost".to_string(),
+		alpn: vec!["relay".to_string()],
+		skip_cert_verify: true,
+	})?;
+	let (mut client, stream) = tokio::io::duplex(1024);
+	let relay = tokio::spawn(async move {
+		outbound
+			.handle_tcp(TargetAddr::Domain("example.com".into(), 443), stream, None::<TlsOutbound>)
+			.await
+	});
+
+	client.write_all(b"ping").await?;
+	let mut buf = [0u8; 4];
+	timeout(Duration::from_secs(5), client.read_exact(&mut buf)).await??;
+	assert_eq!(&buf, b"ping");
+	drop(client);
+	timeout(Duration::from_secs(5), relay).await???;
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_alpn_mismatch() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	// Offers only `h3`
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	let accept = tokio::spawn(async move {
+		if let Some(incoming) = server.accept().await {
+			let _ = incoming.await;
+		}
+	});
+
+	let ctx = Arc::new(AppContext::default());
+	let client_opts = TuicOutboundOpts {
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["tuic".to_string(), "h2".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
+	};
+	let err = match timeout(Duration::from_secs(5), TuicOutbound::new(ctx.clone(), client_opts)).await? {
+		Ok(_) => eyre::bail!("handshake succeeded without a common ALPN protocol"),
+		Err(err) => err,
+	};
+	match err.downcast_ref::<HandshakeError>() {
+		Some(HandshakeError::AlpnMismatch {
+			peer_addr,
+			offered,
+			accepted,
+			..
+		}) => {
+			assert_eq!(*peer_addr, server_addr);
+			assert_eq!(offered, &["tuic", "h2"]);
+			assert_eq!(*accepted, None);
+		}
+		_ => panic!("expected an ALPN mismatch, got {err:?}"),
+	}
+	assert!(err.to_string().contains("[tuic, h2]"), "{err}");
+
+	ctx.token.cancel();
+	let _ = timeout(Duration::from_secs(2), accept).await;
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_closed_port_unreachable() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	// Bound and dropped, so nothing listens there
+	let server_addr = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+	let ctx = Arc::new(AppContext::default());
+	let client_opts = TuicOutboundOpts {
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::ZERO,
+	};
+	// Loopback answers with an ICMP port unreachable, long before the
+	// handshake would time out
+	let err = match timeout(Duration::from_secs(5), TuicOutbound::new(ctx.clone(), client_opts)).await? {
+		Ok(_) => eyre::bail!("connected to a closed port"),
+		Err(err) => err,
+	};
+	match err.downcast_ref::<HandshakeError>() {
+		Some(HandshakeError::Unreachable { peer_addr, .. }) => assert_eq!(*peer_addr, server_addr),
+		_ => panic!("expected the port to be unreachable, got {err:?}"),
+	}
+	assert!(err.to_string().contains("unreachable"), "{err}");
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_initial_window() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let server = bare_server(quinn::TransportConfig::default())?;
+	let server_addr = server.local_addr()?;
+	tokio::spawn(async move {
+		let mut held = Vec::new();
+		while let Some(incoming) = server.accept().await {
+			held.push(incoming.await?);
+		}
+		eyre::Ok(())
+	});
+
+	let ctx = Arc::new(AppContext::default());
+	let opts = |initial_window| TuicOutboundOpts {
+		peer_addr: server_addr,
+		sni: "localhost".to_string(),
+		auth: (Uuid::new_v4(), Arc::from(b"password".as_slice())),
+		zero_rtt_handshake: false,
+		heartbeat: Duration::from_secs(60),
+		gc_interval: Duration::from_secs(3),
+		gc_lifetime: Duration::from_secs(15),
+		skip_cert_verify: true,
+		alpn: vec!["h3".to_string()],
+		ecn: false,
+		udp_idle_timeout: Duration::from_secs(60),
+		udp_keepalive: None,
+		udp_recv_buffer: 4096,
+		udp_send_queue_bytes: 1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum: false,
+		udp_stream: UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu: 1200,
+		min_mtu: 1200,
+		mtu_discovery: false,
+		gso: true,
+		local_port_range: None,
+		initial_window,
+		connect_verdict: Duration::ZERO,
+	};
+
+	let default = TuicOutbound::new(ctx.clone(), opts(INITIAL_WINDOW)).await?;
+	let default_cwnd = default.connection.load().stats().path.cwnd;
+	assert!(default_cwnd < 4_000_000, "{default_cwnd}");
+
+	// The window a long fat network would want
+	let client = TuicOutbound::new(ctx.clone(), opts(4_000_000)).await?;
+	let cwnd = client.connection.load().stats().path.cwnd;
+	assert!(cwnd >= 4_000_000, "{cwnd}");
+	client.probe().await?;
+
+	ctx.token.cancel();
+	Ok(())
+}
+
+/// A client stream keeping the verdict its outbound reported
+struct VerdictStream {
+	inner:   tokio::io::DuplexStream,
+	verdict: Option<tokio::sync::oneshot::Sender<Result<(), ConnectError>>>,
+}
+
+impl tokio::io::AsyncRead for VerdictStream {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut tokio::io::ReadBuf<'_>,
+	) -> Poll<std::io::Result<()>> {
+		Pin::new(&mut self.inner).poll_read(cx, buf)
+	}
+}
+
+impl tokio::io::AsyncWrite for VerdictStream {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+		Pin::new(&mut self.inner).poll_shutdown(cx)
+	}
+}
+
+impl AbstractTcpStream for VerdictStream {
+	fn on_connect(&mut self, result: Result<(), ConnectError>) -> impl Future<Output = std::io::Result<()>> + Send + Sync {
+		if let Some(verdict) = self.verdict.take() {
+			let _ = verdict.send(result);
+		}
+		std::future::ready(Ok(()))
+	}
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tuic_connect_verdict() -> eyre::Result<()> {
+	ensure_crypto_provider()?;
+
+	let (cert, key) = generate_self_signed_cert();
+	let user_uuid = Uuid::new_v4();
+	let mut users = HashMap::new();
+	users.insert(user_uuid, "verdict_password".to_string());
+	let server_addr = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+	let ctx = Arc::new(AppContext::default());
+	let server = TuicInbound::new(
+		ctx.clone(),
+		TuicInboundOpts {
+			listen_addr: server_addr,
+			certificate: cert,
+			private_key: key,
+			alpn: vec!["h3".to_string()],
+			users,
+			..Default::default()
+		},
+	);
+	tokio::spawn(async move { server.listen(&DirectCallback).await });
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	let client_opts = TuicOutboundOpts {
+		peer_addr:               server_addr,
+		sni:                     "localhost".to_string(),
+		auth:                    (user_uuid, Arc::from(b"verdict_password".as_slice())),
+		zero_rtt_handshake:      false,
+		heartbeat:               Duration::from_secs(3),
+		gc_interval:             Duration::from_secs(3),
+		gc_lifetime:             Duration::from_secs(15),
+		skip_cert_verify:        true,
+		alpn:                    vec!["h3".to_string()],
+		ecn:                     false,
+		udp_idle_timeout:        Duration::from_secs(60),
+		udp_keepalive:           None,
+		udp_recv_buffer:         4096,
+		udp_send_queue_bytes:    1 << 20,
+		max_connection_lifetime: None,
+		udp_checksum:            false,
+		udp_stream:              UdpStreamConfig::default(),
+		datagram_receive_buffer: DATAGRAM_RECEIVE_BUFFER,
+		initial_mtu:             1200,
+		min_mtu:                 1200,
+		mtu_discovery:           true,
+		gso:                     true,
+		local_port_range:        None,
+		initial_window:          INITIAL_WINDOW,
+		connect_verdict:         Duration::from_secs(5),
+	};
+	let client = Arc::new(TuicOutbound::new(ctx.clone(), client_opts).await?);
+	let client_poll = client.clone();
+	tokio::spawn(async move { client_poll.start_poll().await });
+
+	// The server resets the stream with the reason it couldn't dial, well
+	// before the verdict times out into an optimistic success
+	let closed = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+	let (verdict, refused) = tokio::sync::oneshot::channel();
+	let (stream, _peer) = tokio::io::duplex(1024);
+	let stream = VerdictStream {
+		inner:   stream,
+		verdict: Some(verdict),
+	};
+	let relay = client.handle_tcp(TargetAddr::from(closed), stream, None::<TuicOutbound>);
+	assert!(timeout(Duration::from_secs(3), relay).await?.is_err());
+	assert_eq!(refused.await?, Err(ConnectError::ConnectionRefused));
+
+	// A target that answers first is reported reached, its greeting relayed
+	let greeter = TcpListener::bind("127.0.0.1:0").await?;
+	let greeter_addr = greeter.local_addr()?;
+	tokio::spawn(async move {
+		let (mut stream, _) = greeter.accept().await?;
+		stream.write_all(b"hello").await?;
+		eyre::Ok(())
+	});
+	let (verdict, reached) = tokio::sync::oneshot::channel();
+	let (stream, mut peer) = tokio::io::duplex(1024);
+	let stream = VerdictStream {
+		inner:   stream,
+		verdict: Some(verdict),
+	};
+	let relay_client = client.clone();
+	tokio::spawn(async move {
+		relay_client
+			.handle_tcp(TargetAddr::from(greeter_addr), stream, None::<TuicOutbound>)
+			.await
+	});
+	assert_eq!(timeout(Duration::from_secs(3), reached).await??, Ok(()));
+	let mut greeting = [0u8; 5];
+	timeout(Duration::from_secs(3), peer.read_exact(&mut greeting)).await??;
+	assert_eq!(&greeting, b"hello");
+
+	ctx.token.cancel();
 	Ok(())
 }
@@ -0,0 +1,22 @@
+//! Run wind inside another program
+//!
+//! ```sh
+//! cargo run --example embed -- config.toml [override.toml...]
+//! ```
+
+use std::time::Duration;
+
+use wind::{Wind, conf::persistent::PersistentConfig};
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+	let config = PersistentConfig::load(std::env::args().skip(1).collect(), None)?;
+
+	let handle = Wind::from_config(config)?.start().await?;
+	println!("wind is running, stopping in 10 seconds");
+
+	tokio::time::sleep(Duration::from_secs(10)).await;
+	let report = handle.shutdown().await;
+	println!("wind stopped, {report}");
+	Ok(())
+}
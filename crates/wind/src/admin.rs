@@ -0,0 +1,237 @@
+//! Token-protected HTTP API to list the relayed connections and close them
+//!
+//! - `GET /connections` answers a JSON array of the open connections
+//! - `DELETE /connections/{id}` cancels one, `404` if it's gone already
+//! - `GET /udp` answers whether UDP is relayed, `PUT /udp/on` and `PUT
+//!   /udp/off` switch it, refusing new UDP associations while off
+//!
+//! Every request needs `Authorization: Bearer <token>`. Each connection
+//! serves a single request.
+
+use std::{
+	net::SocketAddr,
+	sync::{Arc, atomic::Ordering},
+	time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{
+	io::{AsyncReadExt as _, AsyncWriteExt as _},
+	net::{TcpListener, TcpStream},
+};
+use tokio_util::sync::CancellationToken;
+use wind_core::{AppContext, debug, info, registry::ConnectionInfo};
+
+/// Longest request head accepted, admin requests carry no body
+const MAX_REQUEST: usize = 8 * 1024;
+/// Time a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct AdminConfig {
+	pub listen_addr: SocketAddr,
+	/// Expected as `Authorization: Bearer <token>`
+	pub token:       String,
+}
+
+/// One connection in the `GET /connections` listing
+#[derive(Debug, Serialize)]
+struct ConnectionEntry {
+	id:         u64,
+	target:     String,
+	outbound:   String,
+	uploaded:   u64,
+	downloaded: u64,
+	/// Seconds since the connection was registered
+	age:        f64,
+}
+
+impl From<ConnectionInfo> for ConnectionEntry {
+	fn from(info: ConnectionInfo) -> Self {
+		Self {
+			id:         info.id,
+			target:     info.target.to_string(),
+			outbound:   info.outbound,
+			uploaded:   info.uploaded,
+			downloaded: info.downloaded,
+			age:        info.started.elapsed().as_secs_f64(),
+		}
+	}
+}
+
+pub struct AdminServer {
+	listener: TcpListener,
+	token:    Arc<str>,
+}
+
+impl AdminServer {
+	pub async fn bind(config: AdminConfig) -> eyre::Result<Self> {
+		eyre::ensure!(!config.token.is_empty(), "the admin API needs a token");
+		let listener = TcpListener::bind(config.listen_addr).await?;
+		info!(target: "[ADMIN]", "Admin API listening on {}", listener.local_addr()?);
+		Ok(Self {
+			listener,
+			token: config.token.into(),
+		})
+	}
+
+	pub fn local_addr(&self) -> eyre::Result<SocketAddr> {
+		Ok(self.listener.local_addr()?)
+	}
+
+	/// Answer admin requests about `ctx`'s connections until `cancel` fires
+	pub async fn serve(self, ctx: Arc<AppContext>, cancel: CancellationToken) -> eyre::Result<()> {
+		loop {
+			let (stream, peer) = tokio::select! {
+				res = self.listener.accept() => res?,
+				_ = cancel.cancelled() => return Ok(()),
+			};
+			let ctx = ctx.clone();
+			let token = self.token.clone();
+			ctx.clone().spawn("admin-request", async move {
+				if let Err(err) = handle(stream, &ctx, &token).await {
+					debug!(target: "[ADMIN]", "request from {peer} failed: {err}");
+				}
+			});
+		}
+	}
+}
+
+async fn handle(mut stream: TcpStream, ctx: &AppContext, token: &str) -> eyre::Result<()> {
+	let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream)).await??;
+	let (status, body) = respond(&head, ctx, token)?;
+	let response = format!(
+		"HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		body.len()
+	);
+	stream.write_all(response.as_bytes()).await?;
+	stream.shutdown().await?;
+	Ok(())
+}
+
+/// Read up to the blank line ending the request head
+async fn read_head(stream: &mut TcpStream) -> eyre::Result<String> {
+	let mut buf = Vec::with_capacity(1024);
+	let mut chunk = [0u8; 1024];
+	while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+		eyre::ensure!(buf.len() < MAX_REQUEST, "request head too large");
+		let n = stream.read(&mut chunk).await?;
+		eyre::ensure!(n > 0, "connection closed before the request ended");
+		buf.extend_from_slice(&chunk[..n]);
+	}
+	Ok(String::from_utf8(buf)?)
+}
+
+fn respond(head: &str, ctx: &AppContext, token: &str) -> eyre::Result<(&'static str, String)> {
+	let mut lines = head.split("\r\n");
+	let mut request_line = lines.next().unwrap_or_default().split(' ');
+	let (method, path) = (
+		request_line.next().unwrap_or_default(),
+		request_line.next().unwrap_or_default(),
+	);
+
+	let authorized = lines
+		.filter_map(|line| line.split_once(':'))
+		.filter(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+		.filter_map(|(_, value)| value.trim().strip_prefix("Bearer "))
+		.any(|given| token_matches(given.trim(), token));
+	if !authorized {
+		return Ok(("401 Unauthorized", error_body("missing or wrong token")));
+	}
+
+	if let Some(rest) = path.strip_prefix("/udp")
+		&& (rest.is_empty() || rest.starts_with('/'))
+	{
+		return respond_udp(method, rest, ctx);
+	}
+	let rest = match path.strip_prefix("/connections") {
+		Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+		_ => return Ok(("404 Not Found", error_body("no such endpoint"))),
+	};
+	match (method, rest.strip_prefix('/').filter(|id| !id.is_empty())) {
+		("GET", None) => {
+			let mut connections: Vec<ConnectionEntry> =
+				ctx.connections.snapshot().into_iter().map(ConnectionEntry::from).collect();
+			connections.sort_by_key(|conn| conn.id);
+			Ok(("200 OK", serde_json::to_string(&connections)?))
+		}
+		("DELETE", Some(id)) => {
+			let Ok(id) = id.parse() else {
+				return Ok(("400 Bad Request", error_body("connection ids are integers")));
+			};
+			if ctx.connections.kill(id) {
+				info!(target: "[ADMIN]", "Closing connection {id}");
+				Ok(("200 OK", serde_json::json!({ "killed": id }).to_string()))
+			} else {
+				Ok(("404 Not Found", error_body("no such connection")))
+			}
+		}
+		_ => Ok(("405 Method Not Allowed", error_body("unsupported method"))),
+	}
+}
+
+/// Answer the `/udp` endpoints, `rest` is the path past `/udp`
+fn respond_udp(method: &str, rest: &str, ctx: &AppContext) -> eyre::Result<(&'static str, String)> {
+	let enabled = match (method, rest) {
+		("GET", "") => ctx.udp_enabled.load(Ordering::Relaxed),
+		("PUT", "/on" | "/off") => {
+			let enabled = rest == "/on";
+			ctx.udp_enabled.store(enabled, Ordering::Relaxed);
+			info!(target: "[ADMIN]", "UDP relay switched {}", if enabled { "on" } else { "off" });
+			enabled
+		}
+		(_, "" | "/on" | "/off") => return Ok(("405 Method Not Allowed", error_body("unsupported method"))),
+		_ => return Ok(("404 Not Found", error_body("no such endpoint"))),
+	};
+	Ok(("200 OK", serde_json::json!({ "enabled": enabled }).to_string()))
+}
+
+fn error_body(message: &str) -> String {
+	serde_json::json!({ "error": message }).to_string()
+}
+
+/// Compare without bailing out at the first differing byte
+fn token_matches(given: &str, expected: &str) -> bool {
+	given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	/// Send `req` and return the status line and body of the answer
+	pub(crate) async fn request(addr: SocketAddr, req: &str) -> (String, String) {
+		let mut stream = TcpStream::connect(addr).await.unwrap();
+		stream.write_all(req.as_bytes()).await.unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).await.unwrap();
+		let (head, body) = response.split_once("\r\n\r\n").unwrap();
+		(head.lines().next().unwrap().to_owned(), body.to_owned())
+	}
+
+	/// Serve on a free port with the token `secret`
+	pub(crate) async fn start(ctx: &Arc<AppContext>) -> SocketAddr {
+		let server = AdminServer::bind(AdminConfig {
+			listen_addr: "127.0.0.1:0".parse().unwrap(),
+			token:       "secret".into(),
+		})
+		.await
+		.unwrap();
+		let addr = server.local_addr().unwrap();
+		tokio::spawn(server.serve(ctx.clone(), ctx.token.child_token()));
+		addr
+	}
+
+	#[tokio::test]
+	async fn test_admin_requires_token() {
+		let ctx = Arc::new(AppContext::default());
+		let addr = start(&ctx).await;
+
+		let (status, _) = request(addr, "GET /connections HTTP/1.1\r\n\r\n").await;
+		assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+		let (status, _) = request(addr, "GET /connections HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n").await;
+		assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+		let (status, _) = request(addr, "GET /other HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n").await;
+		assert_eq!(status, "HTTP/1.1 404 Not Found");
+		ctx.token.cancel();
+	}
+}
@@ -5,9 +5,10 @@ use clap::{ArgAction, Parser, Subcommand};
 #[derive(Parser)]
 #[command(about, long_about = None)]
 pub struct Cli {
-	/// Set a custom config
-	#[arg(short, visible_short_alias = 'f', long, value_name = "FILE/BASE64-TEXT")]
-	pub config: Option<String>,
+	/// Set a custom config, may be repeated. Later files override earlier ones,
+	/// a directory contributes its *.toml/*.yaml files in lexical order
+	#[arg(short, visible_short_alias = 'f', long, value_name = "FILE/DIR")]
+	pub config: Vec<String>,
 
 	/// Set configuration directory
 	#[arg(short = 'C', visible_short_alias = 'd', long, value_name = "PATH")]
@@ -17,6 +18,11 @@ pub struct Cli {
 	#[arg(short = 'D', long, value_name = "PATH")]
 	pub work_dir: Option<PathBuf>,
 
+	/// Listen on the sockets passed in by systemd (`LISTEN_FDS`) instead of
+	/// binding. Implied when systemd activated this process
+	#[arg(long, action = ArgAction::SetTrue)]
+	pub systemd: bool,
+
 	/// Show current version
 	#[arg(short = 'v', visible_short_alias = 'V', long, action = ArgAction::SetTrue)]
 	pub version: bool,
@@ -33,10 +39,44 @@ pub enum Commands {
 		#[arg(short, long, value_enum, default_value = "yaml")]
 		format: ConfigFormat,
 	},
+	/// Inspect the configuration
+	Config {
+		#[command(subcommand)]
+		command: ConfigCommands,
+	},
 }
 
-#[derive(clap::ValueEnum, Clone, Debug)]
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+	/// Print the effective configuration after merging files, environment
+	/// variables and `${VAR}` references, with secrets masked
+	Dump {
+		/// Specify the output format
+		#[arg(short, long, value_enum, default_value = "yaml")]
+		format: ConfigFormat,
+	},
+	/// Encrypt a secret read from stdin into an `encrypted:` value, to be
+	/// decrypted with the same key through `secret_key_file`
+	Encrypt {
+		/// File holding the 32-byte key, raw or as hex or base64 text
+		#[arg(short, long, value_name = "FILE")]
+		key_file: PathBuf,
+	},
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
 pub enum ConfigFormat {
 	Yaml,
 	Toml,
+	Json,
+}
+
+impl ConfigFormat {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			ConfigFormat::Yaml => "yaml",
+			ConfigFormat::Toml => "toml",
+			ConfigFormat::Json => "json",
+		}
+	}
 }
@@ -1,2 +1,3 @@
 pub mod persistent;
 pub mod runtime;
+pub mod secret;
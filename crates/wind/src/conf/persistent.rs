@@ -1,6 +1,6 @@
 use std::{
 	net::{Ipv4Addr, SocketAddr},
-	path::PathBuf,
+	path::{Path, PathBuf},
 	time::Duration,
 };
 
@@ -8,16 +8,161 @@ use educe::Educe;
 use figment::{
 	Figment,
 	providers::{Env, Format, Toml, Yaml},
+	value::Value,
 };
 use serde::{Deserialize, Serialize};
-use wind_core::types::TargetAddr;
-use wind_socks::inbound::AuthMode;
+use wind_core::{
+	BalanceStrategy, breaker::BreakerConfig, dns::IpPolicy, metrics::StatsdConfig, tcp::KeepaliveConfig,
+	throttle::RateLimitConfig, types::TargetAddr,
+};
+use wind_http::inbound::AuthMode as HttpAuthMode;
+use wind_socks::inbound::{AuthMode, UdpBindFamily};
+
+use crate::{
+	conf::secret::{ENCRYPTED_PREFIX, SecretKey},
+	hosts::HostEntry,
+	route::{DomainPattern, Rule},
+};
 
 #[derive(Debug, Deserialize, Serialize, Educe)]
 #[educe(Default)]
 pub struct PersistentConfig {
 	pub socks_opt: SocksOpt,
 	pub tuic_opt:  TuicOpt,
+
+	/// Further inbounds, served alongside `socks_opt` through the same
+	/// outbounds
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	#[educe(Default(expression = Vec::new()))]
+	pub inbounds: Vec<InboundConfig>,
+
+	/// Spread connections over several TUIC servers instead of `tuic_opt`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub tuic_group: Option<TuicGroupOpt>,
+
+	/// Fail over between several TUIC servers instead of `tuic_opt`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub tuic_fallback: Option<TuicFallbackOpt>,
+
+	/// Tunnel through an upstream HTTP proxy with `CONNECT` instead of
+	/// `tuic_opt`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub http_upstream: Option<HttpUpstreamOpt>,
+
+	/// Chain through an upstream SOCKS5 proxy instead of `tuic_opt`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub socks_upstream: Option<SocksUpstreamOpt>,
+
+	/// Routing rules, the first matching one picks the outbound: `proxy`,
+	/// `direct` or `block`
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	#[educe(Default(expression = Vec::new()))]
+	pub rules: Vec<RuleOpt>,
+
+	/// How long shutdown waits for open connections after it stops accepting
+	/// new ones
+	#[serde(default = "default_drain_timeout", with = "humantime_serde")]
+	#[educe(Default(expression = default_drain_timeout()))]
+	pub drain_timeout: Duration,
+
+	/// Refuse new connections while this many are open, unlimited by default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub max_connections: Option<usize>,
+
+	/// Use TCP Fast Open on the SOCKS and HTTP listeners and direct
+	/// connections, where the platform supports it
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub tcp_fast_open: bool,
+
+	/// Probe idle accepted and dialed TCP connections so dead peers are
+	/// noticed, off by default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub tcp_keepalive: Option<KeepaliveOpt>,
+
+	/// Static host mapping applied before dialing, the first matching entry
+	/// wins
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	#[educe(Default(expression = Vec::new()))]
+	pub hosts: Vec<HostOpt>,
+
+	/// Fail direct connections to targets that keep failing right away for a
+	/// while, off by default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub circuit_breaker: Option<CircuitBreakerOpt>,
+
+	/// Relay UDP without GSO/GRO, a workaround for NICs and drivers that
+	/// corrupt or drop offloaded batches
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub disable_offload: bool,
+
+	/// Open direct connections with a PROXY protocol v2 header carrying the
+	/// client address, for backends behind this proxy that want it
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub send_proxy_protocol: bool,
+
+	/// Source ports the TUIC and direct outbounds bind to, `[first, last]`
+	/// both included, for egress firewalls that only let some out. Any port
+	/// by default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub local_port_range: Option<(u16, u16)>,
+
+	/// DNS server the direct outbound looks targets up on, eg. for split DNS.
+	/// The system resolver by default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub direct_dns: Option<SocketAddr>,
+
+	/// Bandwidth caps on relayed TCP connections, unlimited by default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub rate_limit: Option<RateLimitOpt>,
+
+	/// Log a hex dump of the start of streams relayed by the TUIC, TLS and
+	/// HTTP outbounds, and a sample of the TUIC commands decoded, for protocol
+	/// debugging. Direct relays aren't traced. Off by default as it logs what
+	/// clients send
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub trace_payloads: Option<TracePayloadsOpt>,
+
+	/// HTTP API listing the open connections and closing them on request,
+	/// off by default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub admin: Option<AdminOpt>,
+
+	/// Where connection, traffic and authentication counters are pushed,
+	/// nowhere by default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub metrics: Option<MetricsOpt>,
+
+	/// File holding the 32-byte key `encrypted:` values are decrypted with,
+	/// raw or as hex or base64 text, see `wind config encrypt`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub secret_key_file: Option<PathBuf>,
+
+	/// What the `encrypted:` values loaded decrypted to, masked by
+	/// [`Redacted`] under whichever key they appear
+	#[serde(skip)]
+	#[educe(Default(expression = Vec::new()))]
+	decrypted: Vec<String>,
+}
+
+fn default_drain_timeout() -> Duration {
+	Duration::from_secs(30)
 }
 
 #[derive(Debug, Deserialize, Serialize, Educe)]
@@ -26,6 +171,11 @@ pub struct SocksOpt {
 	#[educe(Default(expression = "127.0.0.1:6666".parse().unwrap()))]
 	pub listen_addr: SocketAddr,
 
+	/// Listen on this Unix domain socket instead of `listen_addr`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub listen_path: Option<PathBuf>,
+
 	#[educe(Default = None)]
 	pub public_addr: Option<std::net::IpAddr>,
 
@@ -37,6 +187,77 @@ pub struct SocksOpt {
 
 	#[educe(Default = true)]
 	pub allow_udp: bool,
+
+	/// Answer Tor's `RESOLVE` for names routed to `direct`, refusing the
+	/// rest so no query leaves from this host for them
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub allow_resolve: bool,
+
+	/// Accept IPv4 and IPv6 clients on `[::]` at the port of `listen_addr`
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub dual_stack: bool,
+
+	/// Also accept SOCKS4 and SOCKS4a clients, which can't authenticate
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub allow_socks4: bool,
+
+	/// Expect a PROXY protocol header from a load balancer in front of every
+	/// connection, connections without one are refused
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub accept_proxy_protocol: bool,
+
+	/// Family of the sockets relaying UDP to clients, `ipv4` for hosts whose
+	/// IPv6 sockets open but don't work
+	#[serde(default)]
+	#[educe(Default = UdpBindFamilyConfig::Auto)]
+	pub udp_bind_family: UdpBindFamilyConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UdpBindFamilyConfig {
+	/// Dual-stack IPv6, or IPv4 where the host lacks it
+	#[default]
+	Auto,
+	Ipv4,
+}
+
+impl From<UdpBindFamilyConfig> for UdpBindFamily {
+	fn from(config: UdpBindFamilyConfig) -> Self {
+		match config {
+			UdpBindFamilyConfig::Auto => UdpBindFamily::Auto,
+			UdpBindFamilyConfig::Ipv4 => UdpBindFamily::Ipv4,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum InboundConfig {
+	Socks(SocksOpt),
+	Http(HttpOpt),
+}
+
+#[derive(Debug, Deserialize, Serialize, Educe)]
+#[educe(Default)]
+pub struct HttpOpt {
+	#[educe(Default(expression = "127.0.0.1:8080".parse().unwrap()))]
+	pub listen_addr: SocketAddr,
+
+	/// Checked against `Proxy-Authorization: Basic`
+	#[serde(default)]
+	#[educe(Default = AuthModeConfig::NoAuth)]
+	pub auth: AuthModeConfig,
+
+	/// Tell clients the id their connection is logged under, in an
+	/// `X-Wind-Conn-Id` response header. Off by default as it marks the proxy
+	/// as wind
+	#[serde(default)]
+	pub conn_id_header: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Educe)]
@@ -59,6 +280,15 @@ impl From<AuthModeConfig> for AuthMode {
 	}
 }
 
+impl From<AuthModeConfig> for HttpAuthMode {
+	fn from(config: AuthModeConfig) -> Self {
+		match config {
+			AuthModeConfig::NoAuth => HttpAuthMode::NoAuth,
+			AuthModeConfig::Password { username, password } => HttpAuthMode::Password { username, password },
+		}
+	}
+}
+
 #[derive(Debug, Deserialize, Serialize, Educe)]
 #[educe(Default)]
 pub struct TuicOpt {
@@ -68,9 +298,21 @@ pub struct TuicOpt {
 	#[educe(Default = "localhost")]
 	pub sni: String,
 
+	/// Address families used when `server_addr` is a domain
+	#[serde(default)]
+	#[educe(Default = IpPolicy::Dual)]
+	pub ip_policy: IpPolicy,
+
+	/// DNS server `server_addr` is looked up on, the system resolver by
+	/// default
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub dns: Option<SocketAddr>,
+
 	#[educe(Default = "c1e6dbe2-f417-4890-994c-9ee15b926597".parse().unwrap())]
 	pub uuid: uuid::Uuid,
 
+	/// UTF-8 text, or a binary key prefixed with `base64:` or `hex:`
 	#[educe(Default = "test_passwd")]
 	pub password: String,
 
@@ -89,71 +331,431 @@ pub struct TuicOpt {
 	#[educe(Default(expression = Duration::from_secs(20)))]
 	pub gc_lifetime: Duration,
 
+	#[serde(default = "default_udp_idle_timeout", with = "humantime_serde")]
+	#[educe(Default(expression = default_udp_idle_timeout()))]
+	pub udp_idle_timeout: Duration,
+
+	/// Send the server a heartbeat after this long without traffic on a UDP
+	/// association, keeping NAT mappings on the path open while it idles
+	#[serde(default, with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub udp_keepalive: Option<Duration>,
+
+	/// Largest UDP datagram relayed from local clients, longer ones are
+	/// truncated. Any datagram fits by default
+	#[serde(default = "default_udp_recv_buffer")]
+	#[educe(Default(expression = default_udp_recv_buffer()))]
+	pub udp_recv_buffer: usize,
+
+	/// Payload bytes of UDP packets queued per association on their way to
+	/// the server, beyond which packets are dropped. 1 MiB when unset
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub udp_send_queue_bytes: Option<usize>,
+
+	/// Bytes of QUIC datagrams from the server buffered until read, beyond
+	/// which the oldest are dropped. Heavy UDP traffic may need more, at the
+	/// cost of memory per connection. 1.25 MB when unset
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub datagram_receive_buffer: Option<usize>,
+
 	#[educe(Default = true)]
 	pub skip_cert_verify: bool,
 
 	#[educe(Default(expression = vec![String::from("h3")]))]
 	pub alpn: Vec<String>,
+
+	/// Carry the ECN codepoint of each relayed UDP packet onto its send. TUIC
+	/// has no field for it, so marks don't cross the connection to the server
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub ecn: bool,
+
+	/// Reconnect after this long regardless of activity, letting streams on
+	/// the old connection drain
+	#[serde(default, with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub max_connection_lifetime: Option<Duration>,
+
+	/// Ask the server to add a CRC-32 to UDP payloads, for diagnosing
+	/// corruption. Only takes effect if the server supports it
+	#[serde(default)]
+	#[educe(Default = false)]
+	pub udp_checksum: bool,
+
+	/// Limits of UDP fragmentation and reassembly
+	#[serde(default)]
+	pub udp_reassembly: UdpReassemblyOpt,
+
+	/// Path MTU of the QUIC connection, which sets where UDP packets start
+	/// being fragmented
+	#[serde(default)]
+	pub mtu: MtuOpt,
+
+	/// Bytes in flight allowed before the first ack. Raising it to the path's
+	/// bandwidth-delay product, eg. 1-4 MB, speeds up the first burst on long
+	/// fat networks, while more only loses packets. 240 KB when unset
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub initial_window: Option<u64>,
+
+	/// How long TCP relays wait for the server to refuse their target before
+	/// answering the client they connected. TUIC has no acknowledgement, so
+	/// only refusals within it reach SOCKS and HTTP clients, at the cost of
+	/// that much delay for those waiting on the answer to send. Off by default
+	#[serde(default, with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+	#[educe(Default = None)]
+	pub connect_verdict: Option<Duration>,
 }
 
-impl PersistentConfig {
-	pub fn export_to_file(&self, file_path: &PathBuf, format: &str) -> eyre::Result<()> {
-		use std::{fs, io::Write};
+fn default_udp_idle_timeout() -> Duration {
+	Duration::from_secs(120)
+}
 
-		match format.to_lowercase().as_str() {
-			"yaml" => {
-				let yaml_content = serde_yaml::to_string(&self)?;
-				let mut file = fs::File::create(file_path)?;
-				file.write_all(yaml_content.as_bytes())?;
-			}
-			"toml" => {
-				let toml_content = toml::to_string_pretty(&self)?;
-				let mut file = fs::File::create(file_path)?;
-				file.write_all(toml_content.as_bytes())?;
-			}
-			_ => return Err(eyre::eyre!("Unsupported file format: {}", format)),
+fn default_udp_recv_buffer() -> usize {
+	u16::MAX.into()
+}
+
+/// Limits of UDP fragmentation and reassembly, trading memory for latency
+#[derive(Debug, Deserialize, Serialize, Educe)]
+#[educe(Default)]
+#[serde(default)]
+pub struct UdpReassemblyOpt {
+	/// Most fragments a UDP packet is split into, larger packets are dropped
+	#[educe(Default = 255)]
+	pub max_fragments: u8,
+
+	/// Partially received packets are discarded after this long
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_secs(30)))]
+	pub fragment_timeout: Duration,
+
+	/// Packets per association in reassembly at once
+	#[educe(Default = 1000)]
+	pub capacity: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Educe)]
+#[educe(Default)]
+#[serde(default)]
+pub struct MtuOpt {
+	/// MTU assumed when connecting, larger values fragment UDP packets less
+	/// but lose datagrams on paths that can't carry them
+	#[educe(Default = 1200)]
+	pub initial: u16,
+
+	/// MTU the path is known to carry
+	#[educe(Default = 1200)]
+	pub min: u16,
+
+	/// Probe for a larger MTU once connected
+	#[educe(Default = true)]
+	pub discovery: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Educe)]
+#[educe(Default)]
+#[serde(default)]
+pub struct KeepaliveOpt {
+	/// Idle time before the first probe
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_secs(60)))]
+	pub idle: Duration,
+
+	/// Time between unanswered probes
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_secs(10)))]
+	pub interval: Duration,
+
+	/// Unanswered probes after which the connection is dropped
+	#[educe(Default = 6)]
+	pub retries: u32,
+}
+
+impl From<KeepaliveOpt> for KeepaliveConfig {
+	fn from(opt: KeepaliveOpt) -> Self {
+		KeepaliveConfig {
+			idle:     opt.idle,
+			interval: opt.interval,
+			retries:  opt.retries,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize, Educe)]
+#[educe(Default)]
+#[serde(default)]
+pub struct CircuitBreakerOpt {
+	/// Failures within `window` after which a target is refused
+	#[educe(Default = 5)]
+	pub threshold: u32,
+
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_secs(30)))]
+	pub window: Duration,
+
+	/// How long a target is refused before it is dialed again
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_secs(30)))]
+	pub cooldown: Duration,
+}
+
+impl From<CircuitBreakerOpt> for BreakerConfig {
+	fn from(opt: CircuitBreakerOpt) -> Self {
+		BreakerConfig {
+			threshold: opt.threshold,
+			window:    opt.window,
+			cooldown:  opt.cooldown,
+		}
+	}
+}
+
+/// Limits in bytes per second, uploads and downloads each held to them
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitOpt {
+	/// Shared by all connections
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub global: Option<u64>,
+
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub per_connection: Option<u64>,
+}
+
+impl From<RateLimitOpt> for RateLimitConfig {
+	fn from(opt: RateLimitOpt) -> Self {
+		RateLimitConfig {
+			global:         opt.global,
+			per_connection: opt.per_connection,
 		}
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize, Educe)]
+#[educe(Default)]
+#[serde(default)]
+pub struct TracePayloadsOpt {
+	/// Bytes dumped each way of a stream, the rest is relayed untraced
+	#[educe(Default = 256)]
+	pub max_bytes: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AdminOpt {
+	pub listen_addr: SocketAddr,
+	/// Clients send it as `Authorization: Bearer <token>`
+	pub token:       String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "exporter", rename_all = "lowercase")]
+pub enum MetricsOpt {
+	Statsd(StatsdOpt),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StatsdOpt {
+	/// StatsD server, pushed to over UDP
+	pub addr: SocketAddr,
+
+	/// Put before every metric name, eg. `wind.connections.opened`
+	#[serde(default = "default_statsd_prefix")]
+	pub prefix: String,
+
+	#[serde(default = "default_statsd_interval", with = "humantime_serde")]
+	pub interval: Duration,
+}
+
+fn default_statsd_prefix() -> String {
+	"wind".into()
+}
+
+fn default_statsd_interval() -> Duration {
+	Duration::from_secs(10)
+}
+
+impl From<MetricsOpt> for StatsdConfig {
+	fn from(opt: MetricsOpt) -> Self {
+		match opt {
+			MetricsOpt::Statsd(opt) => StatsdConfig {
+				addr:     opt.addr,
+				prefix:   opt.prefix,
+				interval: opt.interval,
+			},
+		}
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HostOpt {
+	/// A domain, an address or a network such as `10.0.0.0/8`
+	pub pattern: String,
+
+	/// Where matching targets go instead, port 0 keeps the requested port
+	pub target: TargetAddr,
+}
+
+impl TryFrom<HostOpt> for HostEntry {
+	type Error = eyre::Report;
+
+	fn try_from(opt: HostOpt) -> eyre::Result<Self> {
+		Ok(HostEntry {
+			pattern: opt.pattern.parse()?,
+			target:  opt.target,
+		})
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RuleOpt {
+	pub name: String,
+
+	/// Domains matched along with their subdomains, any when empty
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub domains: Vec<String>,
+
+	/// Glob patterns over the whole domain, like `*.ads.*`
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub domain_wildcards: Vec<String>,
+
+	/// Regular expressions matched against the domain
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub domain_regexes: Vec<String>,
+
+	/// Target ports, any when empty
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub ports: Vec<u16>,
+
+	pub outbound: String,
+}
+
+impl TryFrom<RuleOpt> for Rule {
+	type Error = eyre::Report;
+
+	fn try_from(opt: RuleOpt) -> eyre::Result<Self> {
+		let regexes = opt.domain_regexes.iter().map(|pattern| {
+			DomainPattern::regex(pattern).map_err(|err| eyre::eyre!("invalid domain regex in rule {}: {err}", opt.name))
+		});
+		let patterns = opt
+			.domain_wildcards
+			.into_iter()
+			.map(|pattern| Ok(DomainPattern::wildcard(pattern)))
+			.chain(regexes)
+			.collect::<eyre::Result<_>>()?;
+		Ok(Rule {
+			name: opt.name,
+			domains: opt.domains,
+			patterns,
+			ports: opt.ports,
+			outbound: opt.outbound,
+		})
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HttpUpstreamOpt {
+	pub proxy_addr: SocketAddr,
+
+	/// Sent as `Proxy-Authorization: Basic`
+	#[serde(default)]
+	pub auth: AuthModeConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SocksUpstreamOpt {
+	pub proxy_addr: SocketAddr,
+
+	/// Username and password, when the upstream asks for them
+	#[serde(default)]
+	pub auth: AuthModeConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize, Educe)]
+#[educe(Default)]
+pub struct TuicGroupOpt {
+	#[serde(default)]
+	pub strategy: BalanceStrategy,
+
+	pub members: Vec<TuicOpt>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Educe)]
+#[educe(Default)]
+pub struct TuicFallbackOpt {
+	#[educe(Default = TargetAddr::Domain("www.gstatic.com".into(), 80))]
+	pub probe_target: TargetAddr,
+
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_secs(60)))]
+	pub interval: Duration,
 
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_secs(5)))]
+	pub timeout: Duration,
+
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_millis(50)))]
+	pub tolerance: Duration,
+
+	#[serde(with = "humantime_serde")]
+	#[educe(Default(expression = Duration::from_secs(120)))]
+	pub cooldown: Duration,
+
+	pub members: Vec<TuicOpt>,
+}
+
+impl PersistentConfig {
+	pub fn export_to_file(&self, file_path: &PathBuf, format: &str) -> eyre::Result<()> {
+		std::fs::write(file_path, to_string(self, format)?)?;
 		Ok(())
 	}
 
-	pub fn load(config_path: Option<String>, config_dir: Option<PathBuf>) -> eyre::Result<Self> {
+	/// The config as `format`, with passwords, UUIDs, tokens and decrypted
+	/// values masked so it can be shared when debugging
+	pub fn dump(&self, format: &str) -> eyre::Result<String> {
+		to_string(&Redacted(self), format)
+	}
+
+	/// Load the configuration, later sources overriding earlier ones:
+	///
+	/// 1. `config.toml`, then `config.yaml`, in `config_dir` or else the
+	///    working directory
+	/// 2. each of `config_paths` in order, where a directory contributes its
+	///    `*.toml`/`*.yaml`/`*.yml` files in lexical order
+	/// 3. `WIND_` prefixed environment variables
+	///
+	/// `${VAR}` in any string value is then replaced by that environment
+	/// variable, so secrets such as the TUIC password can stay out of files.
+	/// `$${` stands for a literal `${`.
+	/// Last, `encrypted:` values are decrypted with the key in
+	/// `secret_key_file`.
+	pub fn load(config_paths: Vec<String>, config_dir: Option<PathBuf>) -> eyre::Result<Self> {
 		// Start with empty figment (will use default values via serde)
 		let mut figment = Figment::new();
 
 		// Load from default configuration location
-		if let Some(config_dir) = config_dir {
-			let config_file = config_dir.join("config.toml");
+		let default_dir = config_dir.unwrap_or_default();
+		for name in ["config.toml", "config.yaml"] {
+			let config_file = default_dir.join(name);
 			if config_file.exists() {
-				figment = figment.merge(Toml::file(config_file));
-			}
-
-			let config_file = config_dir.join("config.yaml");
-			if config_file.exists() {
-				figment = figment.merge(Yaml::file(config_file));
-			}
-		} else {
-			// Try to load from default locations in current directory
-			let config_toml = std::path::Path::new("config.toml");
-			if config_toml.exists() {
-				figment = figment.merge(Toml::file(config_toml));
-			}
-
-			let config_yaml = std::path::Path::new("config.yaml");
-			if config_yaml.exists() {
-				figment = figment.merge(Yaml::file(config_yaml));
+				figment = merge_file(figment, &config_file);
 			}
 		}
 
-		// If specific config path is provided, use that
-		if let Some(config_path) = config_path {
-			if config_path.ends_with(".toml") {
-				figment = figment.merge(Toml::file(config_path));
-			} else if config_path.ends_with(".yaml") || config_path.ends_with(".yml") {
-				figment = figment.merge(Yaml::file(config_path));
+		for config_path in config_paths {
+			let config_path = PathBuf::from(config_path);
+			if config_path.is_dir() {
+				let mut files = std::fs::read_dir(&config_path)?
+					.map(|entry| entry.map(|entry| entry.path()))
+					.collect::<Result<Vec<_>, _>>()?;
+				files.retain(|file| matches!(file.extension().and_then(|ext| ext.to_str()), Some("toml" | "yaml" | "yml")));
+				files.sort();
+				for file in files {
+					figment = merge_file(figment, &file);
+				}
 			} else {
-				// Assume it's TOML format
-				figment = figment.merge(Toml::file(config_path));
+				figment = merge_file(figment, &config_path);
 			}
 		}
 
@@ -163,6 +765,293 @@ impl PersistentConfig {
 		// Extract the configuration
 		let config: PersistentConfig = figment.extract()?;
 
-		Ok(config)
+		let mut value = Value::serialize(&config)?;
+		interpolate_env(&mut value, &|name| std::env::var(name))?;
+		let key_file = value.find_ref("secret_key_file").and_then(Value::as_str).map(PathBuf::from);
+		let mut decrypted = Vec::new();
+		decrypt_values(&mut value, key_file.as_deref(), &mut None, &mut decrypted)?;
+		Ok(PersistentConfig {
+			decrypted,
+			..value.deserialize()?
+		})
+	}
+}
+
+fn to_string(config: &impl Serialize, format: &str) -> eyre::Result<String> {
+	Ok(match format.to_lowercase().as_str() {
+		"yaml" => serde_yaml::to_string(config)?,
+		"toml" => toml::to_string_pretty(config)?,
+		"json" => serde_json::to_string_pretty(config)?,
+		_ => return Err(eyre::eyre!("Unsupported file format: {}", format)),
+	})
+}
+
+/// Keys whose values are masked by [`Redacted`]
+const SECRET_KEYS: &[&str] = &["password", "uuid", "token"];
+
+/// Serializes as the wrapped config, with [`SECRET_KEYS`] and decrypted
+/// values masked wherever they appear
+pub struct Redacted<'a>(pub &'a PersistentConfig);
+
+impl Serialize for Redacted<'_> {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut value = Value::serialize(self.0).map_err(serde::ser::Error::custom)?;
+		redact(&mut value, &self.0.decrypted);
+		value.serialize(serializer)
+	}
+}
+
+fn redact(value: &mut Value, decrypted: &[String]) {
+	match value {
+		Value::String(_, s) if decrypted.contains(s) => *value = Value::from("<redacted>"),
+		Value::Dict(_, dict) => {
+			for (key, value) in dict.iter_mut() {
+				if SECRET_KEYS.contains(&key.as_str()) {
+					*value = Value::from("<redacted>");
+				} else {
+					redact(value, decrypted);
+				}
+			}
+		}
+		Value::Array(_, array) => array.iter_mut().for_each(|value| redact(value, decrypted)),
+		_ => {}
+	}
+}
+
+/// Replace `${VAR}` in every string with the environment variable `VAR`, as
+/// `lookup` finds it, and `$${` with `${`
+fn interpolate_env(value: &mut Value, lookup: &impl Fn(&str) -> Result<String, std::env::VarError>) -> eyre::Result<()> {
+	match value {
+		Value::String(_, s) if s.contains("${") => {
+			let mut out = String::with_capacity(s.len());
+			let mut rest = s.as_str();
+			while let Some(start) = rest.find('$') {
+				out.push_str(&rest[..start]);
+				rest = &rest[start..];
+				if let Some(after) = rest.strip_prefix("$${") {
+					out.push_str("${");
+					rest = after;
+				} else if let Some(after) = rest.strip_prefix("${") {
+					let Some(len) = after.find('}') else {
+						eyre::bail!("unterminated variable reference in \"{s}\"");
+					};
+					let name = &after[..len];
+					let var = lookup(name).map_err(|e| eyre::eyre!("config references ${{{name}}}: {e}"))?;
+					out.push_str(&var);
+					rest = &after[len + 1..];
+				} else {
+					out.push('$');
+					rest = &rest[1..];
+				}
+			}
+			out.push_str(rest);
+			*s = out;
+		}
+		Value::Dict(_, dict) => dict.values_mut().try_for_each(|value| interpolate_env(value, lookup))?,
+		Value::Array(_, array) => array.iter_mut().try_for_each(|value| interpolate_env(value, lookup))?,
+		_ => {}
+	}
+	Ok(())
+}
+
+/// Decrypt every `encrypted:` string with the key in `key_file`, read on the
+/// first one into `key`, collecting the plaintexts in `decrypted`
+fn decrypt_values(
+	value: &mut Value,
+	key_file: Option<&Path>,
+	key: &mut Option<SecretKey>,
+	decrypted: &mut Vec<String>,
+) -> eyre::Result<()> {
+	match value {
+		Value::String(_, s) if s.starts_with(ENCRYPTED_PREFIX) => {
+			let key = match key {
+				Some(key) => key,
+				None => {
+					let key_file = key_file.ok_or_else(|| eyre::eyre!("config has encrypted values but no secret_key_file"))?;
+					key.insert(SecretKey::from_file(key_file)?)
+				}
+			};
+			*s = key.decrypt(s)?;
+			decrypted.push(s.clone());
+		}
+		Value::Dict(_, dict) => dict
+			.values_mut()
+			.try_for_each(|value| decrypt_values(value, key_file, key, decrypted))?,
+		Value::Array(_, array) => array
+			.iter_mut()
+			.try_for_each(|value| decrypt_values(value, key_file, key, decrypted))?,
+		_ => {}
+	}
+	Ok(())
+}
+
+fn merge_file(figment: Figment, path: &Path) -> Figment {
+	match path.extension().and_then(|ext| ext.to_str()) {
+		Some("yaml" | "yml") => figment.merge(Yaml::file(path)),
+		// Assume it's TOML format
+		_ => figment.merge(Toml::file(path)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_later_files_override() {
+		let dir = std::env::temp_dir().join(format!("wind-conf-{}", std::process::id()));
+		let conf_d = dir.join("conf.d");
+		std::fs::create_dir_all(&conf_d).unwrap();
+
+		let base = dir.join("base.toml");
+		PersistentConfig::default().export_to_file(&base, "toml").unwrap();
+		let over = dir.join("override.yaml");
+		std::fs::write(&over, "socks_opt:\n  listen_addr: 127.0.0.1:7777\n").unwrap();
+		std::fs::write(conf_d.join("10-a.toml"), "[socks_opt]\nlisten_addr = \"127.0.0.1:8888\"\n").unwrap();
+		std::fs::write(conf_d.join("20-b.toml"), "[socks_opt]\nlisten_addr = \"127.0.0.1:9999\"\n").unwrap();
+
+		let path = |p: &Path| p.to_string_lossy().into_owned();
+		let config = PersistentConfig::load(vec![path(&base), path(&over)], Some(dir.clone())).unwrap();
+		assert_eq!(config.socks_opt.listen_addr, "127.0.0.1:7777".parse().unwrap());
+
+		// Directory entries apply in lexical order, after the files before them
+		let config = PersistentConfig::load(vec![path(&base), path(&over), path(&conf_d)], Some(dir.clone())).unwrap();
+		assert_eq!(config.socks_opt.listen_addr, "127.0.0.1:9999".parse().unwrap());
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	/// A config written before any optional setting existed
+	const OLDEST_CONFIG: &str = "
+socks_opt:
+  listen_addr: 127.0.0.1:6666
+  public_addr: null
+  auth: NoAuth
+  skip_auth: false
+  allow_udp: true
+tuic_opt:
+  server_addr: 127.0.0.1:9443
+  sni: localhost
+  uuid: c1e6dbe2-f417-4890-994c-9ee15b926597
+  password: test_passwd
+  zero_rtt_handshake: false
+  heartbeat: 10s
+  gc_interval: 20s
+  gc_lifetime: 20s
+  skip_cert_verify: true
+  alpn: [h3]
+";
+
+	#[test]
+	fn test_oldest_config_loads() {
+		let config: PersistentConfig = Figment::from(Yaml::string(OLDEST_CONFIG)).extract().unwrap();
+		let defaults = PersistentConfig::default();
+		assert!(!config.socks_opt.allow_resolve);
+		assert_eq!(config.drain_timeout, defaults.drain_timeout);
+		assert_eq!(config.tuic_opt.udp_idle_timeout, defaults.tuic_opt.udp_idle_timeout);
+		assert_eq!(config.tuic_opt.udp_recv_buffer, defaults.tuic_opt.udp_recv_buffer);
+	}
+
+	#[test]
+	fn test_statsd_exporter_selected() {
+		let yaml = format!("{OLDEST_CONFIG}metrics:\n  exporter: statsd\n  addr: 127.0.0.1:8125\n");
+		let config: PersistentConfig = Figment::from(Yaml::string(&yaml)).extract().unwrap();
+		let statsd = StatsdConfig::from(config.metrics.unwrap());
+		assert_eq!(statsd.addr, "127.0.0.1:8125".parse().unwrap());
+		assert_eq!((statsd.prefix.as_str(), statsd.interval), ("wind", Duration::from_secs(10)));
+	}
+
+	#[test]
+	fn test_dump_redacts_secrets() {
+		let dir = std::env::temp_dir().join(format!("wind-dump-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let base = dir.join("base.toml");
+		let mut config = PersistentConfig::default();
+		config.tuic_opt.password = "hunter2".into();
+		config.socks_opt.auth = AuthModeConfig::Password {
+			username: "alice".into(),
+			password: "swordfish".into(),
+		};
+		config.admin = Some(AdminOpt {
+			listen_addr: "127.0.0.1:9090".parse().unwrap(),
+			token:       "letmein".into(),
+		});
+		config.export_to_file(&base, "toml").unwrap();
+		let limit = dir.join("limit.toml");
+		std::fs::write(&limit, "max_connections = 42\n").unwrap();
+
+		let paths = [base, limit].map(|path| path.to_string_lossy().into_owned());
+		let config = PersistentConfig::load(paths.to_vec(), Some(dir.clone())).unwrap();
+
+		for format in ["yaml", "toml", "json"] {
+			let dump = config.dump(format).unwrap();
+			assert!(dump.contains("42"), "{format}: {dump}");
+			assert!(dump.contains("alice"), "{format}: {dump}");
+			for secret in ["hunter2", "swordfish", "letmein", &config.tuic_opt.uuid.to_string()] {
+				assert!(!dump.contains(secret), "{format}: {dump}");
+			}
+		}
+		let dumped: serde_json::Value = serde_json::from_str(&config.dump("json").unwrap()).unwrap();
+		assert_eq!(dumped["max_connections"], 42);
+		assert_eq!(dumped["tuic_opt"]["password"], "<redacted>");
+
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_env_interpolation() {
+		let lookup = |name: &str| match name {
+			"WIND_TEST_INTERPOLATED" => Ok("hunter2".to_owned()),
+			_ => Err(std::env::VarError::NotPresent),
+		};
+		let mut config = PersistentConfig::default();
+		config.tuic_opt.password = "${WIND_TEST_INTERPOLATED}".into();
+		config.tuic_opt.sni = "$${literal}-$5".into();
+		let mut value = Value::serialize(&config).unwrap();
+		interpolate_env(&mut value, &lookup).unwrap();
+		let config: PersistentConfig = value.deserialize().unwrap();
+		assert_eq!(config.tuic_opt.password, "hunter2");
+		assert_eq!(config.tuic_opt.sni, "${literal}-$5");
+
+		let mut value = Value::from("${WIND_TEST_MISSING}");
+		let err = interpolate_env(&mut value, &lookup).unwrap_err();
+		assert!(err.to_string().contains("WIND_TEST_MISSING"), "{err}");
+		let err = interpolate_env(&mut Value::from("${WIND_TEST_INTERPOLATED"), &lookup).unwrap_err();
+		assert!(err.to_string().contains("unterminated"), "{err}");
+	}
+
+	#[test]
+	fn test_encrypted_password() {
+		let dir = std::env::temp_dir().join(format!("wind-encrypted-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let key_file = dir.join("secret.key");
+		std::fs::write(&key_file, [7u8; 32]).unwrap();
+		let key = SecretKey::new(&[7u8; 32]).unwrap();
+		let (encrypted, encrypted_sni) = (key.encrypt("hunter2").unwrap(), key.encrypt("hidden.example").unwrap());
+		let base = dir.join("base.toml");
+		PersistentConfig::default().export_to_file(&base, "toml").unwrap();
+		let secret = dir.join("secret.toml");
+		std::fs::write(
+			&secret,
+			format!(
+				"secret_key_file = \"{}\"\n[tuic_opt]\npassword = \"{encrypted}\"\nsni = \"{encrypted_sni}\"\n",
+				key_file.display()
+			),
+		)
+		.unwrap();
+		let paths = vec![base.to_string_lossy().into_owned(), secret.to_string_lossy().into_owned()];
+
+		let loaded = PersistentConfig::load(paths.clone(), Some(dir.clone())).unwrap();
+		assert_eq!(loaded.tuic_opt.password, "hunter2");
+		assert_eq!(loaded.tuic_opt.sni, "hidden.example");
+		// Masked even under keys that don't usually hold secrets
+		let dump = loaded.dump("toml").unwrap();
+		assert!(!dump.contains("hidden.example"), "{dump}");
+
+		std::fs::write(&key_file, [8u8; 32]).unwrap();
+		let err = PersistentConfig::load(paths, Some(dir.clone())).unwrap_err();
+		assert!(err.to_string().contains("wrong key"), "{err}");
+
+		std::fs::remove_dir_all(dir).unwrap();
 	}
 }
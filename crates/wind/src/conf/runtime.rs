@@ -1,33 +1,307 @@
-use wind_socks::inbound::SocksInboundOpt;
-use wind_tuic::outbound::TuicOutboundOpts;
+use std::time::Duration;
 
-use crate::{conf::persistent::PersistentConfig, util::target_addr_to_socket_addr};
+use base64::prelude::*;
+use wind_core::{
+	BalanceStrategy, FallbackOpts,
+	breaker::BreakerConfig,
+	dns::{Resolver, check_port_range},
+	metrics::StatsdConfig,
+	tcp::KeepaliveConfig,
+	throttle::RateLimitConfig,
+};
+use wind_http::{inbound::HttpInboundOpt, outbound::HttpConnectOutboundOpts};
+use wind_socks::{
+	inbound::{Listen, SocksInboundOpt},
+	outbound::SocksOutbound,
+};
+use wind_tuic::{
+	datagram::DATAGRAM_RECEIVE_BUFFER,
+	outbound::{INITIAL_WINDOW, TuicOutboundOpts, UDP_SEND_QUEUE_BYTES},
+	proto::UdpStreamConfig,
+};
+
+use crate::{
+	admin::AdminConfig,
+	conf::persistent::{AuthModeConfig, InboundConfig, PersistentConfig, SocksOpt, TuicOpt},
+	hosts::{HostEntry, HostRewrite},
+	route::Rule,
+	util::target_addr_to_socket_addr,
+};
 
 pub struct Config {
-	pub socks_opt: SocksInboundOpt,
-	pub tuic_opt:  TuicOutboundOpts,
+	/// Served side by side, `socks_opt` first
+	pub inbounds:            Vec<InboundOpts>,
+	pub tuic_opt:            TuicOutboundOpts,
+	pub tuic_group:          Option<TuicGroup>,
+	pub tuic_fallback:       Option<TuicFallback>,
+	/// Takes the place of the TUIC outbound when set
+	pub http_upstream:       Option<HttpConnectOutboundOpts>,
+	/// Takes the place of the TUIC outbound when set
+	pub socks_upstream:      Option<SocksOutbound>,
+	/// Tried in order, unmatched connections go to the TUIC outbound
+	pub rules:               Vec<Rule>,
+	pub drain_timeout:       Duration,
+	/// Global cap on open connections
+	pub max_connections:     Option<usize>,
+	/// Applies to the SOCKS and HTTP inbounds and the direct outbound
+	pub tcp_fast_open:       bool,
+	/// Applies to the SOCKS and HTTP inbounds and the direct outbound
+	pub tcp_keepalive:       Option<KeepaliveConfig>,
+	/// Static mapping applied to targets before they are dialed
+	pub hosts:               HostRewrite,
+	/// Shared by the direct outbound, `None` dials every target as asked
+	pub circuit_breaker:     Option<BreakerConfig>,
+	/// Direct connections open with a PROXY protocol v2 header
+	pub send_proxy_protocol: bool,
+	/// Source ports of direct connections, the TUIC outbounds have theirs in
+	/// their options
+	pub local_port_range:    Option<(u16, u16)>,
+	/// Where direct connections look their targets up
+	pub direct_resolver:     Resolver,
+	/// Bandwidth caps on relayed TCP connections
+	pub rate_limit:          RateLimitConfig,
+	/// Bytes of each relayed stream direction to dump, `None` when tracing
+	/// is off
+	pub trace_payloads:      Option<usize>,
+	/// Serves the admin API when set
+	pub admin:               Option<AdminConfig>,
+	/// Pushes the counters of [`ctx.events`](wind_core::AppContext::events)
+	/// when set
+	pub metrics:             Option<StatsdConfig>,
+}
+
+pub enum InboundOpts {
+	Socks(SocksInboundOpt),
+	Http(HttpInboundOpt),
+}
+
+pub struct TuicGroup {
+	pub strategy: BalanceStrategy,
+	pub members:  Vec<TuicOutboundOpts>,
+}
+
+pub struct TuicFallback {
+	pub opts:    FallbackOpts,
+	pub members: Vec<TuicOutboundOpts>,
 }
+
 impl Config {
-	pub fn from_persist(config: PersistentConfig) -> Self {
-		Self {
-			socks_opt: SocksInboundOpt {
-				listen_addr: config.socks_opt.listen_addr,
-				public_addr: config.socks_opt.public_addr,
-				auth:        config.socks_opt.auth.into(),
-				skip_auth:   config.socks_opt.skip_auth,
-				allow_udp:   config.socks_opt.allow_udp,
-			},
-			tuic_opt:  TuicOutboundOpts {
-				peer_addr:          target_addr_to_socket_addr(&config.tuic_opt.server_addr),
-				sni:                config.tuic_opt.sni.clone(),
-				auth:               (config.tuic_opt.uuid, config.tuic_opt.password.as_bytes().to_vec().into()),
-				zero_rtt_handshake: config.tuic_opt.zero_rtt_handshake,
-				heartbeat:          config.tuic_opt.heartbeat,
-				gc_interval:        config.tuic_opt.gc_interval,
-				gc_lifetime:        config.tuic_opt.gc_lifetime,
-				skip_cert_verify:   config.tuic_opt.skip_cert_verify,
-				alpn:               config.tuic_opt.alpn.clone(),
-			},
+	pub fn from_persist(config: PersistentConfig) -> eyre::Result<Self> {
+		let (disable_offload, local_port_range) = (config.disable_offload, config.local_port_range);
+		if let Some(range) = local_port_range {
+			check_port_range(range)?;
 		}
+		let tuic_group = match config.tuic_group {
+			Some(group) => Some(TuicGroup {
+				strategy: group.strategy,
+				members:  group
+					.members
+					.iter()
+					.map(|opt| tuic_outbound_opts(opt, disable_offload, local_port_range))
+					.collect::<eyre::Result<_>>()?,
+			}),
+			None => None,
+		};
+		let tuic_fallback = match config.tuic_fallback {
+			Some(fallback) => Some(TuicFallback {
+				opts:    FallbackOpts {
+					probe_target: fallback.probe_target,
+					interval:     fallback.interval,
+					timeout:      fallback.timeout,
+					tolerance:    fallback.tolerance,
+					cooldown:     fallback.cooldown,
+				},
+				members: fallback
+					.members
+					.iter()
+					.map(|opt| tuic_outbound_opts(opt, disable_offload, local_port_range))
+					.collect::<eyre::Result<_>>()?,
+			}),
+			None => None,
+		};
+		let tcp_fast_open = config.tcp_fast_open;
+		let tcp_keepalive: Option<KeepaliveConfig> = config.tcp_keepalive.map(Into::into);
+		let mut inbounds = vec![InboundOpts::Socks(socks_inbound_opts(
+			config.socks_opt,
+			tcp_fast_open,
+			tcp_keepalive,
+			disable_offload,
+		))];
+		inbounds.extend(config.inbounds.into_iter().map(|inbound| match inbound {
+			InboundConfig::Socks(opt) => {
+				InboundOpts::Socks(socks_inbound_opts(opt, tcp_fast_open, tcp_keepalive, disable_offload))
+			}
+			InboundConfig::Http(opt) => InboundOpts::Http(HttpInboundOpt {
+				listen_addr: opt.listen_addr,
+				auth: opt.auth.into(),
+				tcp_fast_open,
+				tcp_keepalive,
+				conn_id_header: opt.conn_id_header,
+			}),
+		}));
+		Ok(Self {
+			inbounds,
+			tuic_opt: tuic_outbound_opts(&config.tuic_opt, disable_offload, local_port_range)?,
+			tuic_group,
+			tuic_fallback,
+			http_upstream: config.http_upstream.map(|opt| HttpConnectOutboundOpts {
+				proxy_addr: opt.proxy_addr,
+				auth:       match opt.auth {
+					AuthModeConfig::NoAuth => None,
+					AuthModeConfig::Password { username, password } => Some((username, password)),
+				},
+			}),
+			socks_upstream: config.socks_upstream.map(|opt| match opt.auth {
+				AuthModeConfig::NoAuth => SocksOutbound::new(opt.proxy_addr),
+				AuthModeConfig::Password { username, password } => {
+					SocksOutbound::new(opt.proxy_addr).with_password(username, password)
+				}
+			}),
+			rules: config.rules.into_iter().map(Rule::try_from).collect::<eyre::Result<_>>()?,
+			drain_timeout: config.drain_timeout,
+			max_connections: config.max_connections,
+			tcp_fast_open,
+			tcp_keepalive,
+			hosts: HostRewrite::new(
+				config
+					.hosts
+					.into_iter()
+					.map(HostEntry::try_from)
+					.collect::<eyre::Result<_>>()?,
+			),
+			circuit_breaker: config.circuit_breaker.map(Into::into),
+			send_proxy_protocol: config.send_proxy_protocol,
+			local_port_range,
+			direct_resolver: config.direct_dns.map_or(Resolver::System, Resolver::Server),
+			rate_limit: config.rate_limit.map(Into::into).unwrap_or_default(),
+			trace_payloads: config.trace_payloads.map(|opt| opt.max_bytes),
+			admin: config.admin.map(|opt| AdminConfig {
+				listen_addr: opt.listen_addr,
+				token:       opt.token,
+			}),
+			metrics: config.metrics.map(Into::into),
+		})
+	}
+}
+
+fn socks_inbound_opts(opt: SocksOpt, fast_open: bool, keepalive: Option<KeepaliveConfig>, no_offload: bool) -> SocksInboundOpt {
+	SocksInboundOpt {
+		listen:                match opt.listen_path {
+			Some(path) => Listen::Unix(path),
+			None => Listen::Tcp(opt.listen_addr),
+		},
+		public_addr:           opt.public_addr,
+		auth:                  opt.auth.into(),
+		skip_auth:             opt.skip_auth,
+		allow_udp:             opt.allow_udp,
+		allow_resolve:         opt.allow_resolve,
+		dual_stack:            opt.dual_stack,
+		allow_socks4:          opt.allow_socks4,
+		accept_proxy_protocol: opt.accept_proxy_protocol,
+		udp_bind_family:       opt.udp_bind_family.into(),
+		tcp_fast_open:         fast_open,
+		tcp_keepalive:         keepalive,
+		disable_offload:       no_offload,
+	}
+}
+
+fn tuic_outbound_opts(opt: &TuicOpt, disable_offload: bool, port_range: Option<(u16, u16)>) -> eyre::Result<TuicOutboundOpts> {
+	let resolver = opt.dns.map_or(Resolver::System, Resolver::Server);
+	Ok(TuicOutboundOpts {
+		peer_addr:               target_addr_to_socket_addr(&opt.server_addr, resolver, opt.ip_policy)?,
+		sni:                     opt.sni.clone(),
+		auth:                    (opt.uuid, decode_secret(&opt.password)?.into()),
+		zero_rtt_handshake:      opt.zero_rtt_handshake,
+		heartbeat:               opt.heartbeat,
+		gc_interval:             opt.gc_interval,
+		gc_lifetime:             opt.gc_lifetime,
+		skip_cert_verify:        opt.skip_cert_verify,
+		alpn:                    opt.alpn.clone(),
+		ecn:                     opt.ecn,
+		udp_idle_timeout:        opt.udp_idle_timeout,
+		udp_keepalive:           opt.udp_keepalive,
+		udp_recv_buffer:         opt.udp_recv_buffer,
+		udp_send_queue_bytes:    opt.udp_send_queue_bytes.unwrap_or(UDP_SEND_QUEUE_BYTES),
+		max_connection_lifetime: opt.max_connection_lifetime,
+		udp_checksum:            opt.udp_checksum,
+		udp_stream:              UdpStreamConfig {
+			max_fragments:       opt.udp_reassembly.max_fragments,
+			fragment_timeout:    opt.udp_reassembly.fragment_timeout,
+			reassembly_capacity: opt.udp_reassembly.capacity,
+			stream_window:       UdpStreamConfig::default().stream_window,
+		},
+		datagram_receive_buffer: opt.datagram_receive_buffer.unwrap_or(DATAGRAM_RECEIVE_BUFFER),
+		initial_mtu:             opt.mtu.initial,
+		min_mtu:                 opt.mtu.min,
+		mtu_discovery:           opt.mtu.discovery,
+		gso:                     !disable_offload,
+		local_port_range:        port_range,
+		initial_window:          opt.initial_window.unwrap_or(INITIAL_WINDOW),
+		connect_verdict:         opt.connect_verdict.unwrap_or_default(),
+	})
+}
+
+/// Decode a TUIC password: `base64:` and `hex:` prefixes carry a binary key,
+/// anything else is used as UTF-8
+fn decode_secret(password: &str) -> eyre::Result<Vec<u8>> {
+	if let Some(encoded) = password.strip_prefix("base64:") {
+		return Ok(BASE64_STANDARD.decode(encoded.trim())?);
+	}
+	if let Some(encoded) = password.strip_prefix("hex:") {
+		let encoded = encoded.trim().as_bytes();
+		if encoded.len() % 2 != 0 {
+			eyre::bail!("hex password has an odd number of digits");
+		}
+		return encoded
+			.chunks(2)
+			.map(|pair| {
+				let pair = std::str::from_utf8(pair)?;
+				u8::from_str_radix(pair, 16).map_err(|_| eyre::eyre!("invalid hex digits \"{pair}\" in password"))
+			})
+			.collect();
+	}
+	Ok(password.as_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_secret_encodings() {
+		let key = b"\x00\xffwind\x80";
+		assert_eq!(decode_secret("base64:AP93aW5kgA==").unwrap(), key);
+		assert_eq!(decode_secret("hex:00ff77696e6480").unwrap(), key);
+		assert_eq!(decode_secret("hex:00FF77696E6480").unwrap(), key);
+		assert_eq!(decode_secret("test_passwd").unwrap(), b"test_passwd");
+		assert!(decode_secret("hex:0g").is_err());
+		assert!(decode_secret("base64:!!").is_err());
+	}
+
+	#[test]
+	fn test_uuid_forms() {
+		let canonical = PersistentConfig::default().tuic_opt.uuid;
+		for form in ["c1e6dbe2-f417-4890-994c-9ee15b926597", "c1e6dbe2f4174890994c9ee15b926597"] {
+			assert_eq!(serde_yaml::from_str::<uuid::Uuid>(form).unwrap(), canonical);
+		}
+	}
+
+	#[test]
+	fn test_unresolvable_server_fails_to_load() {
+		// Nothing listens there, so the query is refused right away
+		let dns = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+		let mut config = PersistentConfig::default();
+		config.tuic_opt.server_addr = wind_core::types::TargetAddr::Domain("server.invalid".into(), 443);
+		config.tuic_opt.dns = Some(dns);
+		let err = Config::from_persist(config).err().unwrap();
+		assert!(err.to_string().contains("server.invalid"), "{err}");
+	}
+
+	#[test]
+	fn test_invalid_port_range_fails_to_load() {
+		let mut config = PersistentConfig::default();
+		config.local_port_range = Some((2, 1));
+		let err = Config::from_persist(config).err().unwrap();
+		assert!(err.to_string().contains("invalid local port range 2-1"), "{err}");
 	}
 }
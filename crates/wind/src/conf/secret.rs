@@ -0,0 +1,120 @@
+//! Config values encrypted at rest: `encrypted:` followed by the base64 of a
+//! random nonce and the ChaCha20-Poly1305 sealed value, under a 32-byte key
+//! kept in a file of its own
+//!
+//! This is ChaCha20-Poly1305 with a 96-bit nonce, not XChaCha20-Poly1305, as
+//! ring has no extended-nonce variant. Random 96-bit nonces stay safe for
+//! billions of values sealed under one key, far more than configs hold.
+
+use std::path::Path;
+
+use base64::prelude::*;
+use ring::{
+	aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+	rand::{SecureRandom as _, SystemRandom},
+};
+
+/// Marks a config string as encrypted
+pub const ENCRYPTED_PREFIX: &str = "encrypted:";
+
+/// Length of the key file's content
+pub const KEY_LEN: usize = 32;
+
+pub struct SecretKey(LessSafeKey);
+
+impl SecretKey {
+	/// Read a key file, holding the [`KEY_LEN`] bytes of the key as they are,
+	/// or as hex or base64 text with any surrounding whitespace
+	pub fn from_file(path: &Path) -> eyre::Result<Self> {
+		let content = std::fs::read(path).map_err(|e| eyre::eyre!("failed to read secret key {}: {e}", path.display()))?;
+		decode_key(&content)
+			.and_then(|key| Self::new(&key))
+			.map_err(|e| eyre::eyre!("secret key {}: {e}", path.display()))
+	}
+
+	pub fn new(key: &[u8]) -> eyre::Result<Self> {
+		if key.len() != KEY_LEN {
+			eyre::bail!("expected {KEY_LEN} bytes, got {}", key.len());
+		}
+		let key = UnboundKey::new(&CHACHA20_POLY1305, key).map_err(|_| eyre::eyre!("unusable key"))?;
+		Ok(Self(LessSafeKey::new(key)))
+	}
+
+	/// `secret` as an `encrypted:` config value
+	pub fn encrypt(&self, secret: &str) -> eyre::Result<String> {
+		let mut nonce = [0u8; NONCE_LEN];
+		SystemRandom::new()
+			.fill(&mut nonce)
+			.map_err(|_| eyre::eyre!("no randomness for the nonce"))?;
+		let mut sealed = secret.as_bytes().to_vec();
+		self.0
+			.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+			.map_err(|_| eyre::eyre!("failed to encrypt"))?;
+		let mut raw = nonce.to_vec();
+		raw.extend_from_slice(&sealed);
+		Ok(format!("{ENCRYPTED_PREFIX}{}", BASE64_STANDARD.encode(raw)))
+	}
+
+	/// The secret in an `encrypted:` config value, failing if it was sealed
+	/// under another key or altered
+	pub fn decrypt(&self, value: &str) -> eyre::Result<String> {
+		let encoded = value
+			.strip_prefix(ENCRYPTED_PREFIX)
+			.ok_or_else(|| eyre::eyre!("not an {ENCRYPTED_PREFIX} value"))?;
+		let mut raw = BASE64_STANDARD
+			.decode(encoded.trim())
+			.map_err(|e| eyre::eyre!("encrypted value isn't base64: {e}"))?;
+		if raw.len() < NONCE_LEN {
+			eyre::bail!("encrypted value is too short");
+		}
+		let (nonce, sealed) = raw.split_at_mut(NONCE_LEN);
+		let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| eyre::eyre!("bad nonce"))?;
+		let secret = self
+			.0
+			.open_in_place(nonce, Aad::empty(), sealed)
+			.map_err(|_| eyre::eyre!("failed to decrypt, wrong key or altered value"))?;
+		Ok(String::from_utf8(secret.to_vec())?)
+	}
+}
+
+/// The key in a key file's `content`, exactly [`KEY_LEN`] bytes being the key
+/// itself
+fn decode_key(content: &[u8]) -> eyre::Result<Vec<u8>> {
+	if content.len() == KEY_LEN {
+		return Ok(content.to_vec());
+	}
+	let not_a_key = || eyre::eyre!("expected {KEY_LEN} bytes, or their hex or base64");
+	let text = std::str::from_utf8(content).map_err(|_| not_a_key())?.trim();
+	if text.len() == KEY_LEN * 2 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return Ok((0..text.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&text[i..i + 2], 16).expect("checked hex digits"))
+			.collect());
+	}
+	BASE64_STANDARD.decode(text).map_err(|_| not_a_key())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_key_encodings() {
+		let key = [0xa7u8; KEY_LEN];
+		let hex: String = key.iter().map(|b| format!("{b:02x}")).collect();
+		for content in [
+			key.to_vec(),
+			format!("{hex}\n").into_bytes(),
+			format!("  {}\r\n", BASE64_STANDARD.encode(key)).into_bytes(),
+		] {
+			assert_eq!(decode_key(&content).unwrap(), key);
+		}
+
+		let err = decode_key(b"not a key\n").unwrap_err();
+		assert!(err.to_string().contains("hex or base64"), "{err}");
+		// Decodes, but to a key of the wrong length
+		let short = BASE64_STANDARD.encode([1u8; 16]);
+		let err = SecretKey::new(&decode_key(short.as_bytes()).unwrap()).err().unwrap();
+		assert!(err.to_string().contains("got 16"), "{err}");
+	}
+}
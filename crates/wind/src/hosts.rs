@@ -0,0 +1,254 @@
+use std::{
+	io::{IoSliceMut, Result as IoResult},
+	net::{IpAddr, SocketAddr},
+	pin::Pin,
+	str::FromStr,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use tokio_util::sync::CancellationToken;
+use wind_core::{
+	types::TargetAddr,
+	udp::{AbstractUdpSocket, EcnCodepoint, RecvMeta, Transmit, UdpPoller},
+};
+
+/// Targets a [`HostEntry`] applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPattern {
+	/// Exactly this domain, regardless of case or a trailing dot
+	Domain(String),
+	/// Addresses within `addr/prefix`, a bare address being a single host
+	Cidr { addr: IpAddr, prefix: u8 },
+}
+
+impl HostPattern {
+	fn matches(&self, target_addr: &TargetAddr) -> bool {
+		match (self, target_addr) {
+			(HostPattern::Domain(domain), TargetAddr::Domain(host, _)) => {
+				host.trim_end_matches('.').eq_ignore_ascii_case(domain.trim_end_matches('.'))
+			}
+			(HostPattern::Cidr { addr, prefix }, TargetAddr::IPv4(ip, _)) => in_network(IpAddr::V4(*ip), *addr, *prefix),
+			(HostPattern::Cidr { addr, prefix }, TargetAddr::IPv6(ip, _)) => in_network(IpAddr::V6(*ip), *addr, *prefix),
+			_ => false,
+		}
+	}
+}
+
+impl FromStr for HostPattern {
+	type Err = eyre::Report;
+
+	fn from_str(s: &str) -> eyre::Result<Self> {
+		let (addr, prefix) = match s.split_once('/') {
+			Some((addr, prefix)) => (addr, Some(prefix)),
+			None => (s, None),
+		};
+		let Ok(addr) = addr.parse::<IpAddr>() else {
+			if prefix.is_some() {
+				eyre::bail!("invalid network {s}");
+			}
+			return Ok(HostPattern::Domain(s.to_string()));
+		};
+		let max = if addr.is_ipv4() { 32 } else { 128 };
+		let prefix = match prefix {
+			Some(prefix) => prefix
+				.parse::<u8>()
+				.ok()
+				.filter(|prefix| *prefix <= max)
+				.ok_or_else(|| eyre::eyre!("invalid prefix length in {s}"))?,
+			None => max,
+		};
+		Ok(HostPattern::Cidr { addr, prefix })
+	}
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+	match (ip, network) {
+		(IpAddr::V4(ip), IpAddr::V4(network)) => {
+			let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+			u32::from(ip) & mask == u32::from(network) & mask
+		}
+		(IpAddr::V6(ip), IpAddr::V6(network)) => {
+			let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+			u128::from(ip) & mask == u128::from(network) & mask
+		}
+		_ => false,
+	}
+}
+
+/// Sends targets matching `pattern` to `target` instead
+///
+/// A `target` port of 0 keeps the port originally asked for.
+#[derive(Debug, Clone)]
+pub struct HostEntry {
+	pub pattern: HostPattern,
+	pub target:  TargetAddr,
+}
+
+/// Static host mapping, like `/etc/hosts`, applied to targets before they
+/// are dialed
+///
+/// Entries are tried in order and the first matching one wins. Routing still
+/// sees the target the client asked for.
+#[derive(Debug, Clone, Default)]
+pub struct HostRewrite {
+	entries: Vec<HostEntry>,
+}
+
+impl HostRewrite {
+	pub fn new(entries: Vec<HostEntry>) -> Self {
+		Self { entries }
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// The address to dial for `target_addr`, itself when no entry matches
+	pub fn rewrite(&self, target_addr: TargetAddr) -> TargetAddr {
+		let Some(entry) = self.entries.iter().find(|entry| entry.pattern.matches(&target_addr)) else {
+			return target_addr;
+		};
+		let port = match target_addr {
+			TargetAddr::Domain(_, port) | TargetAddr::IPv4(_, port) | TargetAddr::IPv6(_, port) => port,
+		};
+		match entry.target.clone() {
+			TargetAddr::Domain(domain, 0) => TargetAddr::Domain(domain, port),
+			TargetAddr::IPv4(ip, 0) => TargetAddr::IPv4(ip, port),
+			TargetAddr::IPv6(ip, 0) => TargetAddr::IPv6(ip, port),
+			target => target,
+		}
+	}
+}
+
+/// UDP socket whose datagrams are headed for their rewritten destination
+///
+/// Replies keep the address they came from, so clients see the mapped
+/// address as their source.
+pub struct RewriteUdpSocket<S> {
+	inner: Arc<S>,
+	hosts: Arc<HostRewrite>,
+}
+
+impl<S> RewriteUdpSocket<S> {
+	pub fn new(inner: S, hosts: Arc<HostRewrite>) -> Self {
+		Self {
+			inner: Arc::new(inner),
+			hosts,
+		}
+	}
+}
+
+impl<S: AbstractUdpSocket + 'static> AbstractUdpSocket for RewriteUdpSocket<S> {
+	fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+		self.inner.clone().create_io_poller()
+	}
+
+	fn try_send(&self, transmit: &Transmit) -> IoResult<()> {
+		self.inner.try_send(transmit)
+	}
+
+	fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<IoResult<usize>> {
+		let received = std::task::ready!(self.inner.poll_recv(cx, bufs, meta))?;
+		for meta in &mut meta[..received] {
+			meta.destination = meta.destination.take().map(|destination| self.hosts.rewrite(destination));
+		}
+		Poll::Ready(Ok(received))
+	}
+
+	fn local_addr(&self) -> IoResult<SocketAddr> {
+		self.inner.local_addr()
+	}
+
+	fn max_transmit_segments(&self) -> usize {
+		self.inner.max_transmit_segments()
+	}
+
+	fn max_receive_segments(&self) -> usize {
+		self.inner.max_receive_segments()
+	}
+
+	fn may_fragment(&self) -> bool {
+		self.inner.may_fragment()
+	}
+
+	fn poll_recv_ready(&self, cx: &mut Context) -> Poll<IoResult<()>> {
+		self.inner.poll_recv_ready(cx)
+	}
+
+	fn association_token(&self) -> CancellationToken {
+		self.inner.association_token()
+	}
+
+	fn poll_send_ecn(
+		&self,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+		target: SocketAddr,
+		ecn: Option<EcnCodepoint>,
+	) -> Poll<IoResult<usize>> {
+		self.inner.poll_send_ecn(cx, buf, target, ecn)
+	}
+
+	async fn send_ecn(&self, buf: &[u8], target: SocketAddr, ecn: Option<EcnCodepoint>) -> IoResult<usize> {
+		self.inner.send_ecn(buf, target, ecn).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::{Ipv4Addr, Ipv6Addr};
+
+	use super::*;
+
+	fn entry(pattern: &str, target: TargetAddr) -> HostEntry {
+		HostEntry {
+			pattern: pattern.parse().unwrap(),
+			target,
+		}
+	}
+
+	#[test]
+	fn test_rewrite_first_matching_entry() {
+		let hosts = HostRewrite::new(vec![
+			entry("example.com", TargetAddr::IPv4(Ipv4Addr::new(192, 0, 2, 1), 0)),
+			entry("10.0.0.0/8", TargetAddr::Domain("localhost".into(), 8080)),
+			entry("10.1.0.0/16", TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 0)),
+			entry("::1", TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 0)),
+		]);
+
+		assert_eq!(
+			hosts.rewrite(TargetAddr::Domain("Example.COM.".into(), 443)),
+			TargetAddr::IPv4(Ipv4Addr::new(192, 0, 2, 1), 443)
+		);
+		assert_eq!(
+			hosts.rewrite(TargetAddr::IPv4(Ipv4Addr::new(10, 1, 2, 3), 22)),
+			TargetAddr::Domain("localhost".into(), 8080)
+		);
+		assert_eq!(
+			hosts.rewrite(TargetAddr::IPv6(Ipv6Addr::LOCALHOST, 53)),
+			TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 53)
+		);
+
+		// Subdomains and addresses outside the networks are left alone
+		for untouched in [
+			TargetAddr::Domain("www.example.com".into(), 443),
+			TargetAddr::IPv4(Ipv4Addr::new(11, 0, 0, 1), 80),
+		] {
+			assert_eq!(hosts.rewrite(untouched.clone()), untouched);
+		}
+	}
+
+	#[test]
+	fn test_parse_pattern() {
+		assert_eq!(
+			"0.0.0.0/0".parse::<HostPattern>().unwrap(),
+			HostPattern::Cidr {
+				addr:   Ipv4Addr::UNSPECIFIED.into(),
+				prefix: 0,
+			}
+		);
+		assert!("10.0.0.0/33".parse::<HostPattern>().is_err());
+		assert!("example.com/8".parse::<HostPattern>().is_err());
+	}
+}
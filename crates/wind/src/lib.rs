@@ -0,0 +1,1222 @@
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
+use std::{
+	collections::HashMap,
+	net::SocketAddr,
+	sync::{Arc, atomic::Ordering},
+	time::Duration,
+};
+
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use wind_core::{
+	AbstractOutbound, AppContext, BlackholeOutbound, DirectOutbound, FallbackOutbound, InboundCallback, LoadBalanceOutbound,
+	breaker::CircuitBreaker,
+	debug, error,
+	inbound::AbstractInbound,
+	info,
+	log::tracing::Instrument as _,
+	metrics::{self, StatsdExporter},
+	task::{OnFailure, ShutdownReport},
+	tcp::{AbstractTcpStream, report_failure},
+	throttle::Throttle,
+	types::TargetAddr,
+	udp::AbstractUdpSocket,
+	warn,
+};
+use wind_http::{inbound::HttpInbound, outbound::HttpConnectOutbound};
+use wind_socks::{inbound::SocksInbound, outbound::SocksOutbound};
+use wind_tuic::outbound::{TuicOutbound, TuicOutboundOpts};
+
+use crate::{
+	admin::AdminServer,
+	conf::{
+		persistent::PersistentConfig,
+		runtime::{Config, InboundOpts},
+	},
+	hosts::{HostRewrite, RewriteUdpSocket},
+	route::Router,
+};
+
+pub mod admin;
+pub mod conf;
+pub mod hosts;
+pub mod log;
+pub mod route;
+mod util;
+
+/// Embeddable wind instance
+///
+/// ```no_run
+/// # async fn example() -> eyre::Result<()> {
+/// use wind::{Wind, conf::persistent::PersistentConfig};
+///
+/// let wind = Wind::from_config(PersistentConfig::default())?;
+/// let handle = wind.start().await?;
+/// // ...
+/// handle.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Wind {
+	config: Config,
+	ctx:    Arc<AppContext>,
+}
+
+impl Wind {
+	pub fn from_config(config: PersistentConfig) -> eyre::Result<Self> {
+		Ok(Self::from_runtime(Config::from_persist(config)?))
+	}
+
+	pub fn from_runtime(config: Config) -> Self {
+		Self {
+			config,
+			ctx: Arc::new(AppContext::default()),
+		}
+	}
+
+	/// Run under an existing context, e.g. to share its task tracker
+	pub fn with_context(mut self, ctx: Arc<AppContext>) -> Self {
+		self.ctx = ctx;
+		self
+	}
+
+	/// Connect the outbounds and start accepting connections in the background
+	pub async fn start(self) -> eyre::Result<WindHandle> {
+		let drain_timeout = self.config.drain_timeout;
+		let manager = start_manager(self.ctx.clone(), self.config).await?;
+		Ok(WindHandle {
+			ctx: self.ctx,
+			manager,
+			drain_timeout,
+		})
+	}
+}
+
+/// Handle to a started [`Wind`]
+pub struct WindHandle {
+	ctx:           Arc<AppContext>,
+	/// Tracks the listeners along with the relays, so shutdown drains both
+	manager:       Manager,
+	drain_timeout: Duration,
+}
+
+impl WindHandle {
+	pub fn context(&self) -> &Arc<AppContext> {
+		&self.ctx
+	}
+
+	/// Relay a client accepted outside of wind, eg. by a supervising process,
+	/// to `target_addr` as though one of the inbounds had accepted it
+	///
+	/// `fd` is a connected TCP or Unix domain stream socket, owned by wind
+	/// from now on. Returns once the relay is started, which is then drained
+	/// on shutdown like any other.
+	#[cfg(unix)]
+	pub async fn relay_fd(&self, fd: OwnedFd, target_addr: TargetAddr) -> eyre::Result<()> {
+		self.manager.relay_fd(fd, target_addr).await
+	}
+
+	/// Resolves once everything is being stopped, e.g. after a critical task
+	/// failed
+	pub async fn stopped(&self) {
+		self.ctx.token.cancelled().await
+	}
+
+	/// Stop accepting connections and give open ones up to the drain timeout
+	/// to finish, then cancel every task and wait up to 10 seconds for them
+	///
+	/// The report says which tasks, if any, were still running by then.
+	pub async fn shutdown(self) -> ShutdownReport {
+		self.ctx.listen_token.cancel();
+		let listeners = &self.manager.relays;
+		listeners.close();
+		if tokio::time::timeout(self.drain_timeout, listeners.wait()).await.is_err() {
+			warn!(target: "[MAIN]", "Drain timeout elapsed, closing remaining connections");
+		}
+		self.ctx.token.cancel();
+		let report = self.ctx.drain_tasks(Duration::from_secs(10)).await;
+		if report.is_clean() {
+			info!(target: "[MAIN]", "Shutdown complete, {report}");
+		} else {
+			warn!(target: "[MAIN]", "Shutdown timed out, {report}");
+		}
+		report
+	}
+}
+
+#[derive(Clone)]
+struct Manager {
+	ctx:       Arc<AppContext>,
+	router:    Arc<Router>,
+	/// Every outbound the router may pick, by name
+	outbounds: Arc<HashMap<String, Outbounds>>,
+	hosts:     Arc<HostRewrite>,
+	throttle:  Arc<Throttle>,
+	/// TCP relays in flight, drained on shutdown along with the listeners
+	relays:    TaskTracker,
+}
+
+impl Manager {
+	fn outbound(&self, name: &str) -> eyre::Result<&Outbounds> {
+		self.outbounds
+			.get(name)
+			.ok_or_else(|| eyre::eyre!("no outbound named {name}"))
+	}
+
+	#[cfg(unix)]
+	async fn relay_fd(&self, fd: OwnedFd, target_addr: TargetAddr) -> eyre::Result<()> {
+		let socket = socket2::Socket::from(fd);
+		// Caught here rather than as odd I/O errors once relaying
+		eyre::ensure!(socket.r#type()? == socket2::Type::STREAM, "fd is not a stream socket");
+		socket
+			.peer_addr()
+			.map_err(|err| eyre::eyre!("fd is not a connected socket: {err}"))?;
+		socket.set_nonblocking(true)?;
+		if socket.domain()? == socket2::Domain::UNIX {
+			let stream = tokio::net::UnixStream::from_std(socket.into())?;
+			self.handle_tcpstream(target_addr, stream).await
+		} else {
+			let stream = tokio::net::TcpStream::from_std(socket.into())?;
+			self.handle_tcpstream(target_addr, stream).await
+		}
+	}
+}
+
+impl InboundCallback for Manager {
+	async fn handle_tcpstream(
+		&self,
+		target_addr: TargetAddr,
+		mut stream: impl AbstractTcpStream + 'static,
+	) -> eyre::Result<()> {
+		info!(target: "[TCP-IN] START","target address {target_addr}");
+		let decision = self.router.select(&target_addr);
+		debug!(target: "[ROUTE]", "{target_addr} matched rule {}, routed to {}", decision.rule_name.unwrap_or("(default)"), decision.outbound_name);
+		let rewritten = self.hosts.rewrite(target_addr.clone());
+		if rewritten != target_addr {
+			debug!(target: "[HOSTS]", "{target_addr} rewritten to {rewritten}");
+		}
+		let target_addr = rewritten;
+		let guard = match self.ctx.connections.register(target_addr.clone(), decision.outbound_name) {
+			Ok(guard) => guard,
+			Err(err) => return report_failure(&mut stream, Err(err)).await,
+		};
+
+		// Relay in the background so the inbound can take the next client
+		let manager = self.clone();
+		let outbound_name = decision.outbound_name.to_owned();
+		let relay = async move {
+			let token = guard.token();
+			let mut stream = manager.throttle.wrap(guard.track(stream));
+			let res = match manager.outbound(&outbound_name) {
+				Ok(outbound) => tokio::select! {
+					res = outbound.handle_tcp(target_addr, &mut stream, None::<Outbounds>) => res,
+					_ = token.cancelled() => {
+						debug!(target: "[TCP-IN] KILL", "connection {} closed through the registry", guard.id());
+						Ok(())
+					}
+				},
+				Err(err) => Err(err),
+			};
+			// An outbound that failed without reporting still owes the client an answer
+			if let Err(err) = report_failure(&mut stream, res).await {
+				error!(target: "[TCP-IN] END", "{err}");
+			}
+		};
+		self.ctx.spawn("tcp-relay", self.relays.track_future(relay.in_current_span()));
+		Ok(())
+	}
+
+	async fn handle_udpsocket(&self, socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+		info!(target: "[UDP-IN] START","UDP association started");
+		// Datagrams of one association may go anywhere, so they take the default route
+		let outbound = self.outbound(self.router.default_outbound())?;
+		if self.hosts.is_empty() {
+			outbound.handle_udp(socket, None::<Outbounds>).await?;
+		} else {
+			outbound
+				.handle_udp(RewriteUdpSocket::new(socket, self.hosts.clone()), None::<Outbounds>)
+				.await?;
+		}
+		Ok(())
+	}
+
+	async fn resolve(&self, target_addr: TargetAddr) -> eyre::Result<SocketAddr> {
+		let decision = self.router.select(&target_addr);
+		let target_addr = self.hosts.rewrite(target_addr);
+		match (&target_addr, self.outbound(decision.outbound_name)?) {
+			(TargetAddr::IPv4(ip, port), _) => Ok(SocketAddr::new((*ip).into(), *port)),
+			(TargetAddr::IPv6(ip, port), _) => Ok(SocketAddr::new((*ip).into(), *port)),
+			// Only a direct connection would look the name up from this host anyway
+			(TargetAddr::Domain(..), Outbounds::Direct(direct)) => Ok(direct.resolve(&target_addr).await?),
+			(TargetAddr::Domain(..), _) => eyre::bail!(
+				"{target_addr} is routed to {}, which can't look names up",
+				decision.outbound_name
+			),
+		}
+	}
+}
+
+pub enum Inbounds {
+	Socks(SocksInbound),
+	Http(HttpInbound),
+}
+
+impl Inbounds {
+	/// Stopped by `ctx`'s listen token, UDP follows `ctx.udp_enabled` and
+	/// events go to `ctx.events`
+	async fn new(opts: InboundOpts, ctx: &AppContext) -> eyre::Result<Self> {
+		let cancel = ctx.listen_token.child_token();
+		Ok(match opts {
+			InboundOpts::Socks(opts) => Inbounds::Socks(
+				SocksInbound::new(opts, cancel)
+					.await?
+					.with_udp_enabled(ctx.udp_enabled.clone())
+					.with_events(ctx.events.clone()),
+			),
+			InboundOpts::Http(opts) => Inbounds::Http(HttpInbound::new(opts, cancel).await.with_events(ctx.events.clone())),
+		})
+	}
+}
+
+impl AbstractInbound for Inbounds {
+	async fn listen(&self, cb: &impl InboundCallback) -> eyre::Result<()> {
+		match self {
+			Inbounds::Socks(socks_inbound) => socks_inbound.listen(cb).await,
+			Inbounds::Http(http_inbound) => http_inbound.listen(cb).await,
+		}
+	}
+}
+
+pub enum Outbounds {
+	Tuic(Box<TuicOutbound>),
+	LoadBalance(LoadBalanceOutbound<TuicOutbound>),
+	Fallback(FallbackOutbound<TuicOutbound>),
+	HttpConnect(HttpConnectOutbound),
+	Socks(SocksOutbound),
+	Direct(DirectOutbound),
+	Blackhole(BlackholeOutbound),
+}
+
+impl Outbounds {
+	async fn start_poll(&self) -> eyre::Result<()> {
+		match self {
+			Outbounds::Tuic(tuic_outbound) => tuic_outbound.start_poll().await,
+			Outbounds::LoadBalance(group) => {
+				for member in group.members() {
+					member.start_poll().await?;
+				}
+				Ok(())
+			}
+			Outbounds::Fallback(group) => {
+				for member in group.members() {
+					member.start_poll().await?;
+				}
+				Ok(())
+			}
+			Outbounds::HttpConnect(_) | Outbounds::Socks(_) | Outbounds::Direct(_) | Outbounds::Blackhole(_) => Ok(()),
+		}
+	}
+
+	/// Health-check fallback members until cancelled, no-op for other outbounds
+	async fn run_probes(&self, token: CancellationToken) {
+		if let Outbounds::Fallback(group) = self {
+			group.run_probes(token).await;
+		}
+	}
+}
+
+impl AbstractOutbound for Outbounds {
+	async fn handle_tcp(
+		&self,
+		target_addr: TargetAddr,
+		stream: impl AbstractTcpStream,
+		via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		match &self {
+			Outbounds::Tuic(tuic_outbound) => tuic_outbound.handle_tcp(target_addr, stream, via).await,
+			Outbounds::LoadBalance(group) => group.handle_tcp(target_addr, stream, via).await,
+			Outbounds::Fallback(group) => group.handle_tcp(target_addr, stream, via).await,
+			Outbounds::HttpConnect(http) => http.handle_tcp(target_addr, stream, via).await,
+			Outbounds::Socks(socks) => socks.handle_tcp(target_addr, stream, via).await,
+			Outbounds::Direct(direct) => direct.handle_tcp(target_addr, stream, via).await,
+			Outbounds::Blackhole(blackhole) => blackhole.handle_tcp(target_addr, stream, via).await,
+		}
+	}
+
+	async fn handle_udp(
+		&self,
+		socket: impl AbstractUdpSocket + 'static,
+		via: Option<impl AbstractOutbound + Sized + Send>,
+	) -> eyre::Result<()> {
+		match &self {
+			Outbounds::Tuic(tuic_outbound) => tuic_outbound.handle_udp(socket, via).await,
+			Outbounds::LoadBalance(group) => group.handle_udp(socket, via).await,
+			Outbounds::Fallback(group) => group.handle_udp(socket, via).await,
+			Outbounds::HttpConnect(http) => http.handle_udp(socket, via).await,
+			Outbounds::Socks(socks) => socks.handle_udp(socket, via).await,
+			Outbounds::Direct(direct) => direct.handle_udp(socket, via).await,
+			Outbounds::Blackhole(blackhole) => blackhole.handle_udp(socket, via).await,
+		}
+	}
+}
+
+/// Flip [`AppContext::udp_enabled`] on each SIGUSR1
+#[cfg(unix)]
+fn spawn_udp_toggle(ctx: &Arc<AppContext>) -> eyre::Result<()> {
+	use tokio::signal::unix::{SignalKind, signal};
+
+	let mut toggle = signal(SignalKind::user_defined1())?;
+	let (udp_enabled, token) = (ctx.udp_enabled.clone(), ctx.token.child_token());
+	ctx.spawn("udp-toggle", async move {
+		loop {
+			tokio::select! {
+				_ = token.cancelled() => break,
+				signal = toggle.recv() => {
+					if signal.is_none() {
+						break;
+					}
+					let enabled = !udp_enabled.fetch_xor(true, Ordering::Relaxed);
+					info!(target: "[MAIN]", "UDP relay switched {} by SIGUSR1", if enabled { "on" } else { "off" });
+				}
+			}
+		}
+	});
+	Ok(())
+}
+
+/// Start the configured inbounds and outbounds, returning the tracker of the
+/// listener tasks, which finish once their open connections have
+pub async fn run(ctx: Arc<AppContext>, config: Config) -> eyre::Result<TaskTracker> {
+	Ok(start_manager(ctx, config).await?.relays)
+}
+
+/// [`run`], keeping hold of the manager to hand it relays later
+async fn start_manager(ctx: Arc<AppContext>, config: Config) -> eyre::Result<Manager> {
+	if let Some(max_bytes) = config.trace_payloads.filter(|max_bytes| *max_bytes > 0) {
+		warn!(target: "[MAIN]", "Tracing up to {max_bytes} bytes of relayed payloads, which logs client traffic");
+		wind_core::log::trace_payloads(max_bytes);
+	}
+	let upstream = match (config.http_upstream, config.socks_upstream) {
+		(Some(_), Some(_)) => eyre::bail!("http_upstream and socks_upstream are mutually exclusive"),
+		(Some(opts), None) => Some(Outbounds::HttpConnect(HttpConnectOutbound::new(opts))),
+		(None, Some(socks)) => Some(Outbounds::Socks(socks)),
+		(None, None) => None,
+	};
+	let outbound = match (config.tuic_group, config.tuic_fallback, upstream) {
+		(Some(_), Some(_), _) => eyre::bail!("tuic_group and tuic_fallback are mutually exclusive"),
+		(Some(_), _, Some(_)) | (_, Some(_), Some(_)) => {
+			eyre::bail!("http_upstream and socks_upstream can't be combined with tuic_group or tuic_fallback")
+		}
+		(Some(group), None, None) => Outbounds::LoadBalance(LoadBalanceOutbound::new(
+			tuic_members(&ctx, group.members).await?,
+			group.strategy,
+		)?),
+		(None, Some(fallback), None) => Outbounds::Fallback(FallbackOutbound::new(
+			tuic_members(&ctx, fallback.members).await?,
+			fallback.opts,
+		)?),
+		(None, None, Some(upstream)) => upstream,
+		(None, None, None) => Outbounds::Tuic(Box::new(TuicOutbound::new(ctx.clone(), config.tuic_opt).await?)),
+	};
+	let mut direct = DirectOutbound::new()
+		.with_tcp_fast_open(config.tcp_fast_open)
+		.with_tcp_keepalive(config.tcp_keepalive)
+		.with_proxy_protocol(config.send_proxy_protocol)
+		.with_local_port_range(config.local_port_range)
+		.with_resolver(config.direct_resolver);
+	if let Some(breaker) = config.circuit_breaker {
+		direct = direct.with_circuit_breaker(Arc::new(CircuitBreaker::new(breaker)));
+	}
+	let outbounds = HashMap::from([
+		(route::PROXY.to_string(), outbound),
+		(route::DIRECT.to_string(), Outbounds::Direct(direct)),
+		(route::BLOCK.to_string(), Outbounds::Blackhole(BlackholeOutbound)),
+	]);
+	let router = Router::new(config.rules, route::PROXY);
+	if let Some(unknown) = router.outbounds().find(|name| !outbounds.contains_key(*name)) {
+		eyre::bail!("routing rules refer to unknown outbound {unknown}");
+	}
+	ctx.connections.set_max_connections(config.max_connections);
+	let listeners = TaskTracker::new();
+	let manager = Manager {
+		ctx:       ctx.clone(),
+		router:    Arc::new(router),
+		outbounds: Arc::new(outbounds),
+		hosts:     Arc::new(config.hosts),
+		throttle:  Arc::new(Throttle::new(config.rate_limit)),
+		relays:    listeners.clone(),
+	};
+
+	let manager_clone = manager.clone();
+	// Without their connections the outbounds can't relay anything
+	ctx.spawn_critical("outbound-poll", OnFailure::Shutdown, async move {
+		for outbound in manager_clone.outbounds.values() {
+			outbound.start_poll().await?;
+		}
+		eyre::Ok(())
+	});
+
+	let manager_clone = manager.clone();
+	let token = ctx.token.child_token();
+	// Only the configured outbound can be a fallback group
+	ctx.spawn("fallback-probes", async move {
+		manager_clone.outbounds[route::PROXY].run_probes(token).await
+	});
+
+	if let Some(admin) = config.admin {
+		let server = AdminServer::bind(admin).await?;
+		let token = ctx.token.child_token();
+		ctx.spawn_critical("admin", OnFailure::Log, server.serve(ctx.clone(), token));
+	}
+
+	if let Some(statsd) = config.metrics {
+		let exporter = StatsdExporter::connect(statsd.addr, statsd.prefix).await?;
+		// Subscribed before the inbounds start so no connection goes uncounted
+		let events = ctx.events.subscribe();
+		let token = ctx.token.child_token();
+		ctx.spawn("metrics", metrics::run(exporter, events, statsd.interval, token));
+	}
+
+	#[cfg(unix)]
+	spawn_udp_toggle(&ctx)?;
+
+	let mut inbounds = Vec::with_capacity(config.inbounds.len());
+	for opts in config.inbounds {
+		inbounds.push(Inbounds::new(opts, &ctx).await?);
+	}
+	spawn_inbounds(&ctx, inbounds, manager.clone(), &listeners);
+	Ok(manager)
+}
+
+/// Listen on every inbound in its own task on `listeners`, all feeding `cb`
+fn spawn_inbounds(ctx: &AppContext, inbounds: Vec<Inbounds>, cb: impl InboundCallback, listeners: &TaskTracker) {
+	for inbound in inbounds {
+		let cb = cb.clone();
+		ctx.spawn_critical(
+			"inbound",
+			OnFailure::Shutdown,
+			listeners.track_future(async move { inbound.listen(&cb).await }),
+		);
+	}
+}
+
+async fn tuic_members(ctx: &Arc<AppContext>, opts: Vec<TuicOutboundOpts>) -> eyre::Result<Vec<TuicOutbound>> {
+	let mut members = Vec::with_capacity(opts.len());
+	for opts in opts {
+		members.push(TuicOutbound::new(ctx.clone(), opts).await?);
+	}
+	Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+
+	use tokio::{
+		io::{AsyncReadExt, AsyncWriteExt},
+		net::{TcpListener, TcpStream},
+	};
+	use wind_core::test_util::{DirectCallback, LogCapture, socks5_connect, socks5_request};
+	use wind_http::inbound::{AuthMode as HttpAuthMode, HttpInboundOpt};
+	use wind_socks::inbound::{AuthMode, SocksInboundOpt, UdpBindFamily};
+
+	use super::*;
+
+	/// Answers after a while, unless cancelled first
+	#[derive(Clone)]
+	struct SlowCallback(Arc<AppContext>);
+
+	impl InboundCallback for SlowCallback {
+		async fn handle_tcpstream(
+			&self,
+			_target_addr: TargetAddr,
+			mut stream: impl AbstractTcpStream + 'static,
+		) -> eyre::Result<()> {
+			stream.on_connect(Ok(())).await?;
+			tokio::select! {
+				_ = self.0.token.cancelled() => eyre::bail!("cancelled"),
+				_ = tokio::time::sleep(Duration::from_millis(200)) => {}
+			}
+			stream.write_all(b"done").await?;
+			Ok(())
+		}
+
+		async fn handle_udpsocket(&self, _socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[tokio::test]
+	async fn test_route_decision_logged() {
+		let (logs, _guard) = LogCapture::install(tracing::Level::DEBUG);
+
+		let rule = route::Rule {
+			name:     "ads".into(),
+			domains:  vec!["ads.example".into()],
+			patterns: vec![],
+			ports:    vec![],
+			outbound: route::BLOCK.into(),
+		};
+		let manager = Manager {
+			ctx:       Arc::new(AppContext::default()),
+			router:    Arc::new(Router::new(vec![rule], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([
+				(route::BLOCK.to_string(), Outbounds::Blackhole(BlackholeOutbound)),
+				(route::DIRECT.to_string(), Outbounds::Direct(DirectOutbound::new())),
+			])),
+			hosts:     Arc::default(),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+		let (_client, stream) = tokio::io::duplex(64);
+		manager
+			.handle_tcpstream(TargetAddr::Domain("tracker.ads.example".into(), 443), stream)
+			.await
+			.unwrap();
+
+		let logs = logs.contents();
+		assert!(logs.contains("matched rule ads, routed to block"), "{logs}");
+		assert_eq!(manager.router.connections(route::BLOCK), 1);
+	}
+
+	#[tokio::test]
+	async fn test_connection_logs_share_id() {
+		let (logs, _guard) = LogCapture::install(tracing::Level::DEBUG);
+
+		let refused_port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let ctx = Arc::new(AppContext::default());
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			ctx.listen_token.child_token(),
+		)
+		.await
+		.unwrap();
+		let manager = Manager {
+			ctx:       ctx.clone(),
+			router:    Arc::new(Router::new(vec![], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([(
+				route::DIRECT.to_string(),
+				Outbounds::Direct(DirectOutbound::new()),
+			)])),
+			hosts:     Arc::default(),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+		tokio::spawn(async move { inbound.listen(&manager).await });
+		tokio::task::yield_now().await;
+
+		for _ in 0..2 {
+			let refused = SocketAddr::from((Ipv4Addr::LOCALHOST, refused_port));
+			let (_, reply) = socks5_connect(listen_addr, refused).await.unwrap();
+			assert_ne!(reply, 0);
+		}
+		ctx.listen_token.cancel();
+
+		let logs = logs.contents();
+		let conn_id = |line: &str| Some(line.split_once("conn{id=")?.1.split_once('}')?.0.to_owned());
+		let started: Vec<String> = logs
+			.lines()
+			.filter(|line| line.contains("target address"))
+			.map(|line| conn_id(line).unwrap_or_else(|| panic!("{logs}")))
+			.collect();
+		assert_eq!(started.len(), 2, "{logs}");
+		assert_ne!(started[0], started[1]);
+		// The route decision and the failure of each connection carry its id
+		for id in &started {
+			let lines: Vec<&str> = logs.lines().filter(|line| conn_id(line).as_ref() == Some(id)).collect();
+			assert!(lines.iter().any(|line| line.contains("routed to direct")), "{logs}");
+			assert!(lines.iter().any(|line| line.contains("ERROR")), "{logs}");
+		}
+	}
+
+	#[tokio::test]
+	async fn test_connection_limit_answered() {
+		let manager = Manager {
+			ctx:       Arc::new(AppContext::default()),
+			router:    Arc::new(Router::new(vec![], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([(
+				route::DIRECT.to_string(),
+				Outbounds::Direct(DirectOutbound::new()),
+			)])),
+			hosts:     Arc::default(),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+		manager.ctx.connections.set_max_connections(Some(0));
+
+		// Refused before any relay starts, the client still gets its reply
+		let (mut client, stream) = tokio::io::duplex(64);
+		let stream = wind_socks::stream::SocksTcpStream::new(stream, "127.0.0.1:0".parse().unwrap());
+		let err = manager
+			.handle_tcpstream(TargetAddr::Domain("example.com".into(), 80), stream)
+			.await
+			.unwrap_err();
+		assert!(err.to_string().contains("connection limit"), "{err}");
+		let mut reply = [0u8; 10];
+		client.read_exact(&mut reply).await.unwrap();
+		assert_eq!(reply[..2], [5, 1]);
+	}
+
+	#[tokio::test]
+	async fn test_resolve_follows_route() {
+		let manager = Manager {
+			ctx:       Arc::new(AppContext::default()),
+			router:    Arc::new(Router::new(vec![], route::BLOCK)),
+			outbounds: Arc::new(HashMap::from([
+				(route::DIRECT.to_string(), Outbounds::Direct(DirectOutbound::new())),
+				(route::BLOCK.to_string(), Outbounds::Blackhole(BlackholeOutbound)),
+			])),
+			hosts:     Arc::new(HostRewrite::new(vec![hosts::HostEntry {
+				pattern: "pinned.invalid".parse().unwrap(),
+				target:  TargetAddr::IPv4(std::net::Ipv4Addr::LOCALHOST, 0),
+			}])),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+
+		// Not looked up from this host when the connection wouldn't be
+		let err = manager.resolve(TargetAddr::Domain("localhost".into(), 80)).await.unwrap_err();
+		assert!(err.to_string().contains("routed to block"), "{err}");
+		// A mapped host needs no lookup at all
+		let addr = manager
+			.resolve(TargetAddr::Domain("pinned.invalid".into(), 80))
+			.await
+			.unwrap();
+		assert_eq!(addr, "127.0.0.1:80".parse().unwrap());
+	}
+
+	#[tokio::test]
+	async fn test_mapped_host_dialed_at_mapped_address() {
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = target.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			let (mut stream, _) = target.accept().await.unwrap();
+			stream.write_all(b"pinned").await.unwrap();
+		});
+
+		let manager = Manager {
+			ctx:       Arc::new(AppContext::default()),
+			router:    Arc::new(Router::new(vec![], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([(
+				route::DIRECT.to_string(),
+				Outbounds::Direct(DirectOutbound::new()),
+			)])),
+			hosts:     Arc::new(HostRewrite::new(vec![hosts::HostEntry {
+				pattern: "pinned.invalid".parse().unwrap(),
+				target:  TargetAddr::IPv4(std::net::Ipv4Addr::LOCALHOST, 0),
+			}])),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+
+		let (mut client, stream) = tokio::io::duplex(64);
+		// Nothing to send, so the relay ends once the target hangs up
+		client.shutdown().await.unwrap();
+		manager
+			.handle_tcpstream(TargetAddr::Domain("pinned.invalid".into(), port), stream)
+			.await
+			.unwrap();
+		let mut body = Vec::new();
+		client.read_to_end(&mut body).await.unwrap();
+		assert_eq!(body, b"pinned");
+
+		// `.invalid` never resolves, so only the mapping made the above work
+		let (mut client, stream) = tokio::io::duplex(64);
+		manager
+			.handle_tcpstream(TargetAddr::Domain("other.invalid".into(), port), stream)
+			.await
+			.unwrap();
+		manager.relays.close();
+		manager.relays.wait().await;
+		let mut body = Vec::new();
+		client.read_to_end(&mut body).await.unwrap();
+		assert!(body.is_empty());
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_relay_inherited_fd() {
+		let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let echo_addr = echo.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut stream, _) = echo.accept().await.unwrap();
+			let (mut reader, mut writer) = stream.split();
+			tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+		});
+
+		let manager = Manager {
+			ctx:       Arc::new(AppContext::default()),
+			router:    Arc::new(Router::new(vec![], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([(
+				route::DIRECT.to_string(),
+				Outbounds::Direct(DirectOutbound::new()),
+			)])),
+			hosts:     Arc::default(),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+
+		// What a supervisor accepting clients itself would pass on
+		let (ours, theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+		manager.relay_fd(theirs.into(), echo_addr.into()).await.unwrap();
+
+		let mut client = tokio::net::UnixStream::from_std({
+			ours.set_nonblocking(true).unwrap();
+			ours
+		})
+		.unwrap();
+		client.write_all(b"inherited").await.unwrap();
+		let mut echoed = [0u8; 9];
+		client.read_exact(&mut echoed).await.unwrap();
+		assert_eq!(&echoed, b"inherited");
+		assert_eq!(manager.ctx.connections.len(), 1);
+
+		// Neither is a client to relay
+		let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+		let err = manager.relay_fd(udp.into(), echo_addr.into()).await.unwrap_err();
+		assert!(err.to_string().contains("not a stream socket"), "{err}");
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let err = manager.relay_fd(listener.into(), echo_addr.into()).await.unwrap_err();
+		assert!(err.to_string().contains("not a connected socket"), "{err}");
+		assert_eq!(manager.ctx.connections.len(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_slow_relays_run_concurrently() {
+		// Answers each connection after a while
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target_addr = target.local_addr().unwrap();
+		tokio::spawn(async move {
+			loop {
+				let (mut stream, _) = target.accept().await.unwrap();
+				tokio::spawn(async move {
+					tokio::time::sleep(Duration::from_millis(300)).await;
+					stream.write_all(b"slow").await
+				});
+			}
+		});
+
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let ctx = Arc::new(AppContext::default());
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			ctx.listen_token.child_token(),
+		)
+		.await
+		.unwrap();
+		let manager = Manager {
+			ctx:       ctx.clone(),
+			router:    Arc::new(Router::new(vec![], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([(
+				route::DIRECT.to_string(),
+				Outbounds::Direct(DirectOutbound::new()),
+			)])),
+			hosts:     Arc::default(),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+		tokio::spawn(async move { inbound.listen(&manager).await });
+		tokio::task::yield_now().await;
+
+		let started = tokio::time::Instant::now();
+		let mut clients = Vec::new();
+		for _ in 0..2 {
+			let (client, reply) = socks5_connect(listen_addr, target_addr).await.unwrap();
+			assert_eq!(reply, 0);
+			clients.push(client);
+		}
+		for mut client in clients {
+			let mut body = [0u8; 4];
+			client.read_exact(&mut body).await.unwrap();
+			assert_eq!(&body, b"slow");
+		}
+		// One after the other would take twice the delay
+		assert!(started.elapsed() < Duration::from_millis(550), "{:?}", started.elapsed());
+		ctx.listen_token.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_drain_finishes_open_relay() {
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let ctx = Arc::new(AppContext::default());
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			ctx.listen_token.child_token(),
+		)
+		.await
+		.unwrap();
+		let listeners = TaskTracker::new();
+		let cb = SlowCallback(ctx.clone());
+		ctx.tasks
+			.spawn(listeners.track_future(async move { inbound.listen(&cb).await }));
+		tokio::task::yield_now().await;
+
+		let (mut client, reply) = socks5_connect(listen_addr, SocketAddr::from((Ipv4Addr::LOCALHOST, 80)))
+			.await
+			.unwrap();
+		assert_eq!(reply, 0);
+
+		// The relay is now in flight
+		let handle = WindHandle {
+			manager: Manager {
+				ctx:       ctx.clone(),
+				router:    Arc::new(Router::new(vec![], route::DIRECT)),
+				outbounds: Arc::default(),
+				hosts:     Arc::default(),
+				throttle:  Arc::default(),
+				relays:    listeners,
+			},
+			ctx,
+			drain_timeout: Duration::from_secs(5),
+		};
+		let shutdown = tokio::spawn(handle.shutdown());
+
+		let mut body = Vec::new();
+		client.read_to_end(&mut body).await.unwrap();
+		assert_eq!(body, b"done");
+		assert!(shutdown.await.unwrap().is_clean());
+	}
+
+	#[tokio::test]
+	async fn test_admin_kills_relay() {
+		// Holds every connection open without answering
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target_addr = target.local_addr().unwrap();
+		tokio::spawn(async move {
+			let mut held = Vec::new();
+			loop {
+				held.push(target.accept().await.unwrap().0);
+			}
+		});
+
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let ctx = Arc::new(AppContext::default());
+		let inbound = SocksInbound::new(
+			SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			},
+			ctx.listen_token.child_token(),
+		)
+		.await
+		.unwrap();
+		let manager = Manager {
+			ctx:       ctx.clone(),
+			router:    Arc::new(Router::new(vec![], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([(
+				route::DIRECT.to_string(),
+				Outbounds::Direct(DirectOutbound::new()),
+			)])),
+			hosts:     Arc::default(),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+		tokio::spawn(async move { inbound.listen(&manager).await });
+		let admin_addr = admin::tests::start(&ctx).await;
+		tokio::task::yield_now().await;
+
+		let (mut client, reply) = socks5_connect(listen_addr, target_addr).await.unwrap();
+		assert_eq!(reply, 0);
+
+		let (status, body) = admin::tests::request(
+			admin_addr,
+			"GET /connections HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+		)
+		.await;
+		assert_eq!(status, "HTTP/1.1 200 OK");
+		let listed: serde_json::Value = serde_json::from_str(&body).unwrap();
+		assert_eq!(listed.as_array().unwrap().len(), 1, "{body}");
+		assert_eq!(listed[0]["target"], target_addr.to_string());
+		assert_eq!(listed[0]["outbound"], route::DIRECT);
+		let id = listed[0]["id"].as_u64().unwrap();
+
+		let kill = format!("DELETE /connections/{id} HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+		let (status, _) = admin::tests::request(admin_addr, &kill).await;
+		assert_eq!(status, "HTTP/1.1 200 OK");
+
+		// The relay ends, closing the client side
+		let mut rest = Vec::new();
+		tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest))
+			.await
+			.expect("relay still open after kill")
+			.unwrap();
+		tokio::time::timeout(Duration::from_secs(5), async {
+			while !ctx.connections.is_empty() {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await
+		.unwrap();
+		let (status, _) = admin::tests::request(admin_addr, &kill).await;
+		assert_eq!(status, "HTTP/1.1 404 Not Found");
+		ctx.token.cancel();
+		ctx.listen_token.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_udp_toggle_spares_tcp() {
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target_addr = target.local_addr().unwrap();
+		tokio::spawn(async move {
+			loop {
+				let (mut stream, _) = target.accept().await.unwrap();
+				tokio::spawn(async move {
+					let (mut reader, mut writer) = stream.split();
+					let _ = tokio::io::copy(&mut reader, &mut writer).await;
+				});
+			}
+		});
+
+		let listen_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let ctx = Arc::new(AppContext::default());
+		let inbound = Inbounds::new(
+			InboundOpts::Socks(SocksInboundOpt {
+				listen:                listen_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             true,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			}),
+			&ctx,
+		)
+		.await
+		.unwrap();
+		let manager = Manager {
+			ctx:       ctx.clone(),
+			router:    Arc::new(Router::new(vec![], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([(
+				route::DIRECT.to_string(),
+				Outbounds::Direct(DirectOutbound::new()),
+			)])),
+			hosts:     Arc::default(),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+		tokio::spawn(async move { inbound.listen(&manager).await });
+		let admin_addr = admin::tests::start(&ctx).await;
+		tokio::task::yield_now().await;
+
+		let udp_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+		let (mut tcp, code) = socks5_connect(listen_addr, target_addr).await.unwrap();
+		assert_eq!(code, 0);
+
+		let (status, body) =
+			admin::tests::request(admin_addr, "PUT /udp/off HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n").await;
+		assert_eq!(status, "HTTP/1.1 200 OK");
+		assert_eq!(body, r#"{"enabled":false}"#);
+		let (_, code) = socks5_request(listen_addr, 3, udp_addr).await.unwrap();
+		assert_eq!(code, 7, "UDP ASSOCIATE should be refused with CommandNotSupported");
+
+		// The relay opened before and new TCP ones carry on
+		tcp.write_all(b"ping").await.unwrap();
+		let mut buf = [0u8; 4];
+		tcp.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"ping");
+		let (_, code) = socks5_connect(listen_addr, target_addr).await.unwrap();
+		assert_eq!(code, 0);
+
+		let (_, body) = admin::tests::request(admin_addr, "PUT /udp/on HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n").await;
+		assert_eq!(body, r#"{"enabled":true}"#);
+		let (_, code) = socks5_request(listen_addr, 3, udp_addr).await.unwrap();
+		assert_eq!(code, 0);
+		ctx.token.cancel();
+		ctx.listen_token.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_socks_and_http_inbounds() {
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target_addr = target.local_addr().unwrap();
+		tokio::spawn(async move {
+			loop {
+				let (mut stream, _) = target.accept().await.unwrap();
+				tokio::spawn(async move {
+					let (mut rx, mut tx) = stream.split();
+					tokio::io::copy(&mut rx, &mut tx).await
+				});
+			}
+		});
+
+		let socks_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let http_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let ctx = AppContext::default();
+		let inbounds = vec![
+			Inbounds::new(
+				InboundOpts::Socks(SocksInboundOpt {
+					listen:                socks_addr.into(),
+					public_addr:           None,
+					auth:                  AuthMode::NoAuth,
+					skip_auth:             false,
+					allow_udp:             false,
+					allow_resolve:         false,
+					dual_stack:            false,
+					allow_socks4:          false,
+					tcp_fast_open:         false,
+					tcp_keepalive:         None,
+					disable_offload:       false,
+					accept_proxy_protocol: false,
+					udp_bind_family:       UdpBindFamily::Auto,
+				}),
+				&ctx,
+			)
+			.await
+			.unwrap(),
+			Inbounds::new(
+				InboundOpts::Http(HttpInboundOpt {
+					listen_addr:    http_addr,
+					auth:           HttpAuthMode::NoAuth,
+					tcp_fast_open:  false,
+					tcp_keepalive:  None,
+					conn_id_header: false,
+				}),
+				&ctx,
+			)
+			.await
+			.unwrap(),
+		];
+		spawn_inbounds(&ctx, inbounds, DirectCallback, &TaskTracker::new());
+		tokio::task::yield_now().await;
+
+		let (mut client, reply) = socks5_connect(socks_addr, target_addr).await.unwrap();
+		assert_eq!(reply, 0);
+		client.write_all(b"socks").await.unwrap();
+		let mut echo = [0u8; 5];
+		client.read_exact(&mut echo).await.unwrap();
+		assert_eq!(&echo, b"socks");
+
+		let mut client = TcpStream::connect(http_addr).await.unwrap();
+		client
+			.write_all(format!("CONNECT {target_addr} HTTP/1.1\r\n\r\n").as_bytes())
+			.await
+			.unwrap();
+		// No connection id header unless asked for
+		let mut response = [0u8; 39];
+		client.read_exact(&mut response).await.unwrap();
+		assert_eq!(&response, b"HTTP/1.1 200 Connection Established\r\n\r\n");
+		client.write_all(b"http").await.unwrap();
+		let mut echo = [0u8; 4];
+		client.read_exact(&mut echo).await.unwrap();
+		assert_eq!(&echo, b"http");
+
+		ctx.listen_token.cancel();
+	}
+
+	#[tokio::test]
+	async fn test_socks_upstream_relays() {
+		let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let target_addr = target.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut stream, _) = target.accept().await.unwrap();
+			let (mut rx, mut tx) = stream.split();
+			tokio::io::copy(&mut rx, &mut tx).await.unwrap();
+		});
+
+		let upstream_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let ctx = AppContext::default();
+		let upstream = Inbounds::new(
+			InboundOpts::Socks(SocksInboundOpt {
+				listen:                upstream_addr.into(),
+				public_addr:           None,
+				auth:                  AuthMode::NoAuth,
+				skip_auth:             false,
+				allow_udp:             false,
+				allow_resolve:         false,
+				dual_stack:            false,
+				allow_socks4:          false,
+				tcp_fast_open:         false,
+				tcp_keepalive:         None,
+				disable_offload:       false,
+				accept_proxy_protocol: false,
+				udp_bind_family:       UdpBindFamily::Auto,
+			}),
+			&ctx,
+		)
+		.await
+		.unwrap();
+		spawn_inbounds(&ctx, vec![upstream], DirectCallback, &TaskTracker::new());
+		tokio::task::yield_now().await;
+
+		let manager = Manager {
+			ctx:       Arc::new(AppContext::default()),
+			router:    Arc::new(Router::new(vec![], route::DIRECT)),
+			outbounds: Arc::new(HashMap::from([(
+				route::DIRECT.to_string(),
+				Outbounds::Socks(SocksOutbound::new(upstream_addr)),
+			)])),
+			hosts:     Arc::default(),
+			throttle:  Arc::default(),
+			relays:    TaskTracker::new(),
+		};
+
+		let (mut client, stream) = tokio::io::duplex(64);
+		manager.handle_tcpstream(target_addr.into(), stream).await.unwrap();
+		client.write_all(b"chained").await.unwrap();
+		let mut echoed = [0u8; 7];
+		client.read_exact(&mut echoed).await.unwrap();
+		assert_eq!(&echoed, b"chained");
+
+		ctx.listen_token.cancel();
+	}
+}
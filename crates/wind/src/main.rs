@@ -1,74 +1,19 @@
-use std::{ops::Deref, sync::Arc, time::Duration};
-
 use clap::Parser as _;
 use tracing::Level;
-use wind_core::{
-	AbstractOutbound, AppContext, InboundCallback, inbound::AbstractInbound, info, tcp::AbstractTcpStream, types::TargetAddr,
-	udp::AbstractUdpSocket,
+use wind::{
+	Wind,
+	conf::{persistent::PersistentConfig, runtime::Config, secret::SecretKey},
+	log,
 };
-use wind_socks::inbound::SocksInbound;
-use wind_tuic::outbound::TuicOutbound;
+use wind_core::info;
 
-mod util;
-use crate::{
-	cli::Cli,
-	conf::{persistent::PersistentConfig, runtime::Config},
-};
+use crate::cli::Cli;
 
 mod cli;
-mod conf;
-mod log;
-
-#[derive(Clone)]
-struct Manager {
-	inbound:  Arc<SocksInbound>,
-	outbound: Arc<TuicOutbound>,
-}
-
-impl InboundCallback for Manager {
-	async fn handle_tcpstream(&self, target_addr: TargetAddr, stream: impl AbstractTcpStream) -> eyre::Result<()> {
-		info!(target: "[TCP-IN] START","target address {target_addr}");
-		self.outbound.handle_tcp(target_addr, stream, None::<Outbounds>).await?;
-		Ok(())
-	}
-
-	async fn handle_udpsocket(&self, socket: impl AbstractUdpSocket + 'static) -> eyre::Result<()> {
-		info!(target: "[UDP-IN] START","UDP association started");
-		self.outbound.handle_udp(socket, None::<Outbounds>).await?;
-		Ok(())
-	}
-}
 
-pub enum Outbounds {
-	Tuic(TuicOutbound),
-}
-impl AbstractOutbound for Outbounds {
-	fn handle_tcp(
-		&self,
-		target_addr: TargetAddr,
-		stream: impl AbstractTcpStream,
-		via: Option<impl AbstractOutbound + Sized + Send>,
-	) -> impl Future<Output = eyre::Result<()>> + Send {
-		match &self {
-			Outbounds::Tuic(tuic_outbound) => tuic_outbound.handle_tcp(target_addr, stream, via),
-		}
-	}
-
-	fn handle_udp(
-		&self,
-		socket: impl AbstractUdpSocket + 'static,
-		via: Option<impl AbstractOutbound + Sized + Send>,
-	) -> impl Future<Output = eyre::Result<()>> + Send {
-		match &self {
-			Outbounds::Tuic(tuic_outbound) => tuic_outbound.handle_udp(socket, via),
-		}
-	}
-}
 // curl --socks5 127.0.0.1:6666 https://www.bing.com
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-	log::init_log(Level::TRACE)?;
-	info!(target: "[MAIN]", "Wind starting");
 	let cli = match Cli::try_parse() {
 		Ok(v) => v,
 		Err(err) => {
@@ -93,10 +38,7 @@ async fn main() -> eyre::Result<()> {
 			let default_config = PersistentConfig::default();
 
 			// Determine format and file name
-			let format_str = match format {
-				crate::cli::ConfigFormat::Yaml => "yaml",
-				crate::cli::ConfigFormat::Toml => "toml",
-			};
+			let format_str = format.as_str();
 
 			// Determine the file path
 			let file_name = format!("config.{}", format_str);
@@ -113,44 +55,58 @@ async fn main() -> eyre::Result<()> {
 			println!("Created default configuration at: {}", file_path.display());
 			return Ok(());
 		}
+		Some(crate::cli::Commands::Config {
+			command: crate::cli::ConfigCommands::Dump { format },
+		}) => {
+			let config = PersistentConfig::load(cli.config, cli.config_dir)?;
+			let dump = config.dump(format.as_str())?;
+			// Only print a config `wind` would start with
+			Config::from_persist(config)?;
+			print!("{dump}");
+			return Ok(());
+		}
+		Some(crate::cli::Commands::Config {
+			command: crate::cli::ConfigCommands::Encrypt { key_file },
+		}) => {
+			let key = SecretKey::from_file(key_file)?;
+			let mut secret = String::new();
+			std::io::stdin().read_line(&mut secret)?;
+			println!("{}", key.encrypt(secret.trim_end_matches(['\r', '\n']))?);
+			return Ok(());
+		}
 		None => {}
 	}
 
+	// Logs go to stdout, so only once the commands printing there are done
+	log::init_log(Level::TRACE)?;
+	info!(target: "[MAIN]", "Wind starting");
+
 	// Load configuration using the persistent config module
 	let persistent_config = PersistentConfig::load(cli.config, cli.config_dir)?;
 	info!(target: "[MAIN]", "Configuration loaded successfully");
 
-	// Convert to runtime config
-	let runtime_config = conf::runtime::Config::from_persist(persistent_config);
-	let ctx = Arc::new(AppContext::default());
-	run(ctx.clone(), runtime_config).await?;
-	tokio::signal::ctrl_c().await?;
-	info!(target: "[MAIN]", "Ctrl-C received, shutting down");
-	ctx.token.cancel();
-	ctx.tasks.close();
-	tokio::time::timeout(Duration::from_secs(10), ctx.tasks.wait()).await?;
-
-	info!(target: "[MAIN]", "Shutdown complete");
-	Ok(())
-}
-
-pub async fn run(ctx: Arc<AppContext>, config: Config) -> eyre::Result<()> {
-	let outbound = TuicOutbound::new(ctx.clone(), config.tuic_opt).await?;
-	let inbound = Arc::new(SocksInbound::new(config.socks_opt, ctx.token.child_token()).await);
-	let outbound = Arc::new(outbound);
-	let manager = Manager { inbound, outbound };
-	let manager = Arc::new(manager);
-
-	let manager_clone = manager.clone();
-	ctx.tasks.spawn(async move {
-		manager_clone.outbound.start_poll().await?;
-		eyre::Ok(())
-	});
+	#[cfg(unix)]
+	if cli.systemd || wind_core::systemd::is_activated() {
+		let count = wind_core::systemd::activate()?;
+		info!(target: "[MAIN]", "Adopted {count} socket(s) from systemd");
+	}
 
-	let manager_clone = manager.clone();
-	ctx.tasks.spawn(async move {
-		manager_clone.inbound.listen(manager.deref()).await?;
-		eyre::Ok(())
-	});
+	let handle = Wind::from_config(persistent_config)?.start().await?;
+	let failed = tokio::select! {
+		res = tokio::signal::ctrl_c() => {
+			res?;
+			info!(target: "[MAIN]", "Ctrl-C received, shutting down");
+			false
+		}
+		// The failed task logged why
+		_ = handle.stopped() => true,
+	};
+	let report = handle.shutdown().await;
+	if !report.is_clean() {
+		eyre::bail!("shutdown timed out, {report}");
+	}
+	if failed {
+		eyre::bail!("stopped after a critical task failed");
+	}
 	Ok(())
 }
@@ -0,0 +1,262 @@
+use std::{
+	collections::HashMap,
+	sync::atomic::{AtomicU64, Ordering},
+};
+
+use regex::{Regex, RegexBuilder};
+use wind_core::types::TargetAddr;
+
+/// Name of the configured TUIC outbound, the default route
+pub const PROXY: &str = "proxy";
+/// Name of the built-in outbound connecting from this host
+pub const DIRECT: &str = "direct";
+/// Name of the built-in outbound dropping all traffic
+pub const BLOCK: &str = "block";
+
+/// Sends connections whose target matches to `outbound`
+///
+/// A rule matches when the target matches one of `domains` or `patterns` (if
+/// any) and one of `ports` (if any).
+#[derive(Debug, Clone)]
+pub struct Rule {
+	pub name:     String,
+	/// The domain itself and its subdomains
+	pub domains:  Vec<String>,
+	pub patterns: Vec<DomainPattern>,
+	pub ports:    Vec<u16>,
+	pub outbound: String,
+}
+
+impl Rule {
+	fn matches(&self, target_addr: &TargetAddr) -> bool {
+		let (host, port) = match target_addr {
+			TargetAddr::Domain(domain, port) => (Some(domain.as_str()), *port),
+			TargetAddr::IPv4(_, port) | TargetAddr::IPv6(_, port) => (None, *port),
+		};
+		let domain_matches = (self.domains.is_empty() && self.patterns.is_empty())
+			|| host.is_some_and(|host| {
+				self.domains.iter().any(|domain| is_within(host, domain))
+					|| self.patterns.iter().any(|pattern| pattern.matches(host))
+			});
+		domain_matches && (self.ports.is_empty() || self.ports.contains(&port))
+	}
+}
+
+/// Domains a [`Rule`] matches beyond a domain and its subdomains, ignoring
+/// case and a trailing dot
+#[derive(Debug, Clone)]
+pub enum DomainPattern {
+	/// Glob over the whole domain, `*` standing for any run of characters
+	/// (dots included) and `?` for a single one
+	Wildcard(String),
+	/// Regular expression found anywhere in the domain unless anchored
+	Regex(Regex),
+}
+
+impl DomainPattern {
+	pub fn wildcard(pattern: impl Into<String>) -> Self {
+		DomainPattern::Wildcard(pattern.into())
+	}
+
+	/// Compile `pattern`, failing on invalid syntax
+	pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+		RegexBuilder::new(pattern)
+			.case_insensitive(true)
+			.build()
+			.map(DomainPattern::Regex)
+	}
+
+	fn matches(&self, host: &str) -> bool {
+		let host = host.trim_end_matches('.');
+		match self {
+			DomainPattern::Wildcard(pattern) => glob_matches(pattern.trim_end_matches('.').as_bytes(), host.as_bytes()),
+			DomainPattern::Regex(regex) => regex.is_match(host),
+		}
+	}
+}
+
+/// Whether all of `host` matches the glob `pattern`, ignoring ASCII case
+fn glob_matches(pattern: &[u8], host: &[u8]) -> bool {
+	let (mut p, mut h) = (0, 0);
+	// Position after the last `*` and the host position it's matched up to
+	let mut star = None;
+	while h < host.len() {
+		match pattern.get(p) {
+			Some(b'*') => {
+				star = Some((p + 1, h));
+				p += 1;
+			}
+			Some(c) if *c == b'?' || c.eq_ignore_ascii_case(&host[h]) => {
+				p += 1;
+				h += 1;
+			}
+			_ => match star {
+				// Let the last `*` swallow one more character and retry
+				Some((after, matched)) => {
+					p = after;
+					h = matched + 1;
+					star = Some((after, matched + 1));
+				}
+				None => return false,
+			},
+		}
+	}
+	pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Whether `host` is `domain` or one of its subdomains
+fn is_within(host: &str, domain: &str) -> bool {
+	let host = host.trim_end_matches('.').as_bytes();
+	match host.len().checked_sub(domain.len()) {
+		Some(0) => host.eq_ignore_ascii_case(domain.as_bytes()),
+		Some(prefix) => host[prefix - 1] == b'.' && host[prefix..].eq_ignore_ascii_case(domain.as_bytes()),
+		None => false,
+	}
+}
+
+/// Outcome of [`Router::select`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteDecision<'a> {
+	/// The rule that matched, `None` when falling through to the default
+	pub rule_name:     Option<&'a str>,
+	pub outbound_name: &'a str,
+}
+
+/// Picks the outbound of the first rule matching a connection's target
+pub struct Router {
+	rules:       Vec<Rule>,
+	default:     String,
+	/// Connections routed to each outbound so far
+	connections: HashMap<String, AtomicU64>,
+}
+
+impl Router {
+	pub fn new(rules: Vec<Rule>, default: impl Into<String>) -> Self {
+		let default = default.into();
+		let connections = rules
+			.iter()
+			.map(|rule| rule.outbound.clone())
+			.chain([default.clone()])
+			.map(|outbound| (outbound, AtomicU64::new(0)))
+			.collect();
+		Self {
+			rules,
+			default,
+			connections,
+		}
+	}
+
+	/// Outbounds the rules and the default route to
+	pub fn outbounds(&self) -> impl Iterator<Item = &str> {
+		self.connections.keys().map(String::as_str)
+	}
+
+	pub fn default_outbound(&self) -> &str {
+		&self.default
+	}
+
+	/// Route a connection to `target_addr`, counting it for the chosen outbound
+	pub fn select(&self, target_addr: &TargetAddr) -> RouteDecision<'_> {
+		let decision = match self.rules.iter().find(|rule| rule.matches(target_addr)) {
+			Some(rule) => RouteDecision {
+				rule_name:     Some(&rule.name),
+				outbound_name: &rule.outbound,
+			},
+			None => RouteDecision {
+				rule_name:     None,
+				outbound_name: &self.default,
+			},
+		};
+		if let Some(counter) = self.connections.get(decision.outbound_name) {
+			counter.fetch_add(1, Ordering::Relaxed);
+		}
+		decision
+	}
+
+	/// Number of connections routed to `outbound` so far
+	pub fn connections(&self, outbound: &str) -> u64 {
+		self.connections
+			.get(outbound)
+			.map_or(0, |counter| counter.load(Ordering::Relaxed))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+
+	use super::*;
+
+	#[test]
+	fn test_select_first_matching_rule() {
+		let router = Router::new(
+			vec![
+				Rule {
+					name:     "ads".into(),
+					domains:  vec!["ads.example".into()],
+					patterns: vec![],
+					ports:    vec![],
+					outbound: BLOCK.into(),
+				},
+				Rule {
+					name:     "smtp".into(),
+					domains:  vec![],
+					patterns: vec![],
+					ports:    vec![25],
+					outbound: DIRECT.into(),
+				},
+			],
+			PROXY,
+		);
+
+		let decision = router.select(&TargetAddr::Domain("tracker.ADS.example.".into(), 443));
+		assert_eq!(decision.rule_name, Some("ads"));
+		assert_eq!(decision.outbound_name, BLOCK);
+		assert_eq!(router.select(&TargetAddr::Domain("bads.example".into(), 443)).rule_name, None);
+		assert_eq!(
+			router.select(&TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 25)).outbound_name,
+			DIRECT
+		);
+
+		assert_eq!(router.connections(BLOCK), 1);
+		assert_eq!(router.connections(DIRECT), 1);
+		assert_eq!(router.connections(PROXY), 1);
+	}
+
+	#[test]
+	fn test_domain_patterns() {
+		let router = Router::new(
+			vec![
+				Rule {
+					name:     "ads".into(),
+					domains:  vec![],
+					patterns: vec![DomainPattern::wildcard("*.ads.*")],
+					ports:    vec![],
+					outbound: BLOCK.into(),
+				},
+				Rule {
+					name:     "trackers".into(),
+					domains:  vec![],
+					patterns: vec![DomainPattern::regex(r"^(metrics|telemetry)\d*\.").unwrap()],
+					ports:    vec![443],
+					outbound: BLOCK.into(),
+				},
+			],
+			PROXY,
+		);
+		let rule = |domain: &str, port| router.select(&TargetAddr::Domain(domain.into(), port)).rule_name;
+
+		assert_eq!(rule("cdn.ads.example.com", 443), Some("ads"));
+		assert_eq!(rule("X.ADS.example.", 80), Some("ads"));
+		assert_eq!(rule("ads.example.com", 443), None);
+		assert_eq!(rule("cdn.badads.example", 443), None);
+
+		assert_eq!(rule("telemetry2.example.com", 443), Some("trackers"));
+		assert_eq!(rule("Metrics.example.org", 443), Some("trackers"));
+		assert_eq!(rule("metrics.example.org", 80), None);
+		assert_eq!(rule("app.metrics.example.org", 443), None);
+		assert_eq!(router.select(&TargetAddr::IPv4(Ipv4Addr::LOCALHOST, 443)).rule_name, None);
+
+		assert!(DomainPattern::regex("(unclosed").is_err());
+	}
+}